@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Rates and latencies are calculated over this window
+const WINDOW: Duration = Duration::from_secs(60);
+
+static REQUESTS: AtomicU64 = AtomicU64::new(0);
+static ERRORS: AtomicU64 = AtomicU64::new(0);
+static SAMPLES: Mutex<VecDeque<Sample>> = Mutex::new(VecDeque::new());
+
+struct Sample {
+    at: Instant,
+    latency: Duration,
+    failed: bool,
+}
+
+/// How much the api of the bridge is used, the bridge starts dropping requests if it's used too much
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct ApiUsage {
+    pub bridge: String,
+    pub requests: u64,
+    pub errors: u64,
+    pub requests_last_minute: u64,
+    pub errors_last_minute: u64,
+    pub latency_p50_ms: Option<u64>,
+    pub latency_p95_ms: Option<u64>,
+    pub latency_p99_ms: Option<u64>,
+}
+
+/// Sends a request to the bridge and records how long it took and whether it failed
+pub fn track<T, E>(request: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+    let started_at = Instant::now();
+    let result = request();
+
+    REQUESTS.fetch_add(1, Ordering::Relaxed);
+
+    if result.is_err() {
+        ERRORS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let mut samples = SAMPLES.lock().unwrap_or_else(PoisonError::into_inner);
+
+    while samples
+        .front()
+        .map(|sample| sample.at.elapsed() > WINDOW)
+        .unwrap_or(false)
+    {
+        samples.pop_front();
+    }
+
+    samples.push_back(Sample {
+        at: Instant::now(),
+        latency: started_at.elapsed(),
+        failed: result.is_err(),
+    });
+
+    result
+}
+
+/// Returns the usage of the api since startup and within the last minute
+pub fn get_api_usage(bridge: &str) -> ApiUsage {
+    let samples = SAMPLES.lock().unwrap_or_else(PoisonError::into_inner);
+
+    let recent = samples
+        .iter()
+        .filter(|sample| sample.at.elapsed() <= WINDOW)
+        .collect::<Vec<&Sample>>();
+
+    let mut latencies = recent
+        .iter()
+        .map(|sample| sample.latency.as_millis() as u64)
+        .collect::<Vec<u64>>();
+
+    latencies.sort();
+
+    let percentile = |percent: usize| {
+        latencies
+            .get((latencies.len() * percent / 100).min(latencies.len().saturating_sub(1)))
+            .copied()
+    };
+
+    ApiUsage {
+        bridge: bridge.to_string(),
+        requests: REQUESTS.load(Ordering::Relaxed),
+        errors: ERRORS.load(Ordering::Relaxed),
+        requests_last_minute: recent.len() as u64,
+        errors_last_minute: recent.iter().filter(|sample| sample.failed).count() as u64,
+        latency_p50_ms: percentile(50),
+        latency_p95_ms: percentile(95),
+        latency_p99_ms: percentile(99),
+    }
+}
+
+/// E.g. `Bridge 192.168.1.2: 1520 requests (3 errors), 64 in the last minute (0 errors), latency p50 12ms, p95 40ms, p99 85ms`
+impl fmt::Display for ApiUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Bridge {}: {} requests ({} errors), {} in the last minute ({} errors)",
+            self.bridge,
+            self.requests,
+            self.errors,
+            self.requests_last_minute,
+            self.errors_last_minute
+        )?;
+
+        if let (Some(p50), Some(p95), Some(p99)) = (
+            self.latency_p50_ms,
+            self.latency_p95_ms,
+            self.latency_p99_ms,
+        ) {
+            write!(f, ", latency p50 {}ms, p95 {}ms, p99 {}ms", p50, p95, p99)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,485 @@
+#[cfg(feature = "clip-v2")]
+use crate::clip_v2_resources;
+#[cfg(feature = "deconz")]
+use crate::deconz;
+use crate::engine::Context;
+#[cfg(feature = "home-assistant")]
+use crate::home_assistant::HomeAssistant;
+use crate::info;
+use crate::locks;
+use crate::scene_states::{self, SceneLightState};
+use crate::shutdown;
+use crate::simulation::Simulation;
+#[cfg(feature = "clip-v2")]
+use crate::smart_scenes;
+use crate::utils::Backend;
+#[cfg(feature = "deconz")]
+use crate::watchdog;
+#[cfg(feature = "zigbee2mqtt")]
+use crate::zigbee2mqtt::Zigbee2Mqtt;
+use huelib2::resource::light::{
+    Capabilities, Config as LightConfig, ControlCapabilities, SoftwareUpdate, SoftwareUpdateState,
+    State as LightState, StreamingCapabilities,
+};
+use huelib2::resource::{group, light, Group, Light, Scene, Sensor};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::panic;
+use std::thread;
+use std::time::Duration;
+
+/// Builds a light in the shape of the hue api for backends that don't know about its hue specific details
+pub fn new_light(id: String, name: String, kind: &str, state: LightState) -> Light {
+    Light {
+        unique_id: id.clone(),
+        id,
+        name,
+        kind: kind.to_string(),
+        state,
+        model_id: String::new(),
+        product_id: None,
+        product_name: None,
+        manufacturer_name: None,
+        software_version: String::new(),
+        software_update: SoftwareUpdate {
+            state: SoftwareUpdateState::NoUpdates,
+            last_install: None,
+        },
+        config: LightConfig {
+            arche_type: String::new(),
+            function: "mixed".to_string(),
+            direction: "omnidirectional".to_string(),
+            startup: None,
+        },
+        capabilities: Capabilities {
+            certified: false,
+            control: ControlCapabilities {
+                min_dimlevel: None,
+                max_lumen: None,
+                color_gamut: None,
+                color_gamut_type: None,
+                color_temperature: None,
+            },
+            streaming: StreamingCapabilities {
+                renderer: false,
+                proxy: false,
+            },
+        },
+    }
+}
+
+/// The zigbee2mqtt connection, it's always set up if it's the backend
+#[cfg(feature = "zigbee2mqtt")]
+fn zigbee2mqtt(context: &Context) -> &Zigbee2Mqtt {
+    context
+        .zigbee2mqtt
+        .as_deref()
+        .expect("zigbee2mqtt isn't connected")
+}
+
+/// The Home Assistant connection, it's always set up if it's the backend
+#[cfg(feature = "home-assistant")]
+fn home_assistant(context: &Context) -> &HomeAssistant {
+    context
+        .home_assistant
+        .as_deref()
+        .expect("Home Assistant isn't connected")
+}
+
+/// The simulated bridge, it's always set up in a simulation
+fn simulation(context: &Context) -> &Simulation {
+    context
+        .simulation
+        .as_deref()
+        .expect("there's no simulation")
+}
+
+/// Lights, groups and scenes are read and written through the v2 api, deCONZ, zigbee2mqtt or Home Assistant if configured,
+/// see `BRIDGE_API` and `BRIDGE_BACKEND`
+pub fn get_all_lights(context: &Context) -> Result<Vec<Light>, String> {
+    match context.conf.backend {
+        #[cfg(feature = "deconz")]
+        Backend::Deconz => {
+            return deconz::get_all_lights(&context.bridge).map_err(|err| err.to_string())
+        }
+        #[cfg(feature = "zigbee2mqtt")]
+        Backend::Zigbee2Mqtt => return Ok(zigbee2mqtt(context).get_all_lights()),
+        #[cfg(feature = "home-assistant")]
+        Backend::HomeAssistant => return Ok(home_assistant(context).get_all_lights()),
+        Backend::Simulation => return Ok(simulation(context).get_all_lights()),
+        Backend::Hue => {}
+    }
+
+    #[cfg(feature = "clip-v2")]
+    if context.conf.bridge_api_v2 {
+        return clip_v2_resources::get_all_lights(&context.clip).map_err(|err| err.to_string());
+    }
+
+    context
+        .hue()
+        .get_all_lights()
+        .map_err(|err| format!("{:?}", err))
+}
+
+pub fn get_all_groups(context: &Context) -> Result<Vec<Group>, String> {
+    match context.conf.backend {
+        #[cfg(feature = "deconz")]
+        Backend::Deconz => {
+            return deconz::get_all_groups(&context.bridge).map_err(|err| err.to_string())
+        }
+        #[cfg(feature = "zigbee2mqtt")]
+        Backend::Zigbee2Mqtt => return Ok(zigbee2mqtt(context).get_all_groups()),
+        #[cfg(feature = "home-assistant")]
+        Backend::HomeAssistant => return Ok(home_assistant(context).get_all_groups()),
+        Backend::Simulation => return Ok(simulation(context).get_all_groups()),
+        Backend::Hue => {}
+    }
+
+    #[cfg(feature = "clip-v2")]
+    if context.conf.bridge_api_v2 {
+        return clip_v2_resources::get_all_groups(&context.clip).map_err(|err| err.to_string());
+    }
+
+    context
+        .hue()
+        .get_all_groups()
+        .map_err(|err| format!("{:?}", err))
+}
+
+pub fn get_all_scenes(context: &Context) -> Result<Vec<Scene>, String> {
+    match context.conf.backend {
+        #[cfg(feature = "deconz")]
+        Backend::Deconz => {
+            return deconz::get_all_scenes(&context.bridge).map_err(|err| err.to_string())
+        }
+        #[cfg(feature = "zigbee2mqtt")]
+        Backend::Zigbee2Mqtt => return Ok(zigbee2mqtt(context).get_all_scenes()),
+        #[cfg(feature = "home-assistant")]
+        Backend::HomeAssistant => return Ok(home_assistant(context).get_all_scenes()),
+        Backend::Simulation => return Ok(simulation(context).get_all_scenes()),
+        Backend::Hue => {}
+    }
+
+    #[cfg(feature = "clip-v2")]
+    if context.conf.bridge_api_v2 {
+        return clip_v2_resources::get_all_scenes(&context.clip).map_err(|err| err.to_string());
+    }
+
+    context
+        .hue()
+        .get_all_scenes()
+        .map_err(|err| format!("{:?}", err))
+}
+
+pub fn get_light(context: &Context, light_id: &str) -> Result<Light, String> {
+    match context.conf.backend {
+        Backend::Hue => context
+            .hue()
+            .get_light(light_id)
+            .map_err(|err| err.to_string()),
+        #[cfg(feature = "deconz")]
+        Backend::Deconz => {
+            deconz::get_light(&context.bridge, light_id).map_err(|err| err.to_string())
+        }
+        #[cfg(feature = "zigbee2mqtt")]
+        Backend::Zigbee2Mqtt => zigbee2mqtt(context).get_light(light_id),
+        #[cfg(feature = "home-assistant")]
+        Backend::HomeAssistant => home_assistant(context).get_light(light_id),
+        Backend::Simulation => simulation(context).get_light(light_id),
+    }
+}
+
+/// Sensors are only read from bridges, deCONZ serves them the same way as a hue bridge
+pub fn get_all_sensors(context: &Context) -> Result<Vec<Sensor>, String> {
+    match context.conf.backend {
+        #[cfg(feature = "deconz")]
+        Backend::Deconz => watchdog::track(&context.bridge, |bridge| bridge.get_all_sensors()),
+        _ => context.hue().get_all_sensors(),
+    }
+    .map_err(|err| err.to_string())
+}
+
+/// Commands are refused once the scheduler is stopping, those sent already are waited for
+fn command() -> Result<shutdown::Command, String> {
+    shutdown::command().ok_or("the scheduler is stopping".to_string())
+}
+
+/// Logs a request instead of sending it if this is a dry run, see `DRY_RUN`
+fn dry_run(context: &Context, request: impl FnOnce() -> String) -> bool {
+    if context.conf.dry_run {
+        info!("Dry run: would send {} to {}", request(), context.bridge_ip);
+    }
+
+    context.conf.dry_run
+}
+
+/// Readable body of a request, e.g. `{"on":false}`
+fn body(modifier: &impl Serialize) -> String {
+    serde_json::to_string(modifier).unwrap_or_default()
+}
+
+pub fn set_light_state(
+    context: &Context,
+    light_id: &str,
+    modifier: &light::StateModifier,
+) -> Result<(), String> {
+    if dry_run(context, || {
+        format!("PUT /lights/{}/state {}", light_id, body(modifier))
+    }) {
+        return Ok(());
+    }
+
+    let _command = command()?;
+
+    match context.conf.backend {
+        #[cfg(feature = "zigbee2mqtt")]
+        Backend::Zigbee2Mqtt => zigbee2mqtt(context).set_light_state(light_id, modifier),
+        #[cfg(feature = "home-assistant")]
+        Backend::HomeAssistant => home_assistant(context).set_light_state(light_id, modifier),
+        Backend::Simulation => simulation(context).set_light_state(light_id, modifier),
+        Backend::Hue => {
+            #[cfg(feature = "clip-v2")]
+            if context.conf.bridge_api_v2 {
+                return clip_v2_resources::set_light_state(&context.clip, light_id, modifier);
+            }
+
+            context
+                .hue()
+                .set_light_state(light_id, modifier)
+                .map_err(|err| err.to_string())
+        }
+        #[cfg(feature = "deconz")]
+        Backend::Deconz => {
+            let (light_id, modifier) = (light_id.to_string(), modifier.clone());
+
+            watchdog::track(&context.bridge, move |bridge| {
+                bridge.set_light_state(light_id, &modifier)
+            })
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+        }
+    }
+}
+
+pub fn set_group_state(
+    context: &Context,
+    group_id: &str,
+    modifier: &group::StateModifier,
+) -> Result<(), String> {
+    if dry_run(context, || {
+        format!("PUT /groups/{}/action {}", group_id, body(modifier))
+    }) {
+        return Ok(());
+    }
+
+    let _command = command()?;
+
+    match context.conf.backend {
+        #[cfg(feature = "zigbee2mqtt")]
+        Backend::Zigbee2Mqtt => zigbee2mqtt(context).set_group_state(group_id, modifier),
+        #[cfg(feature = "home-assistant")]
+        Backend::HomeAssistant => home_assistant(context).set_group_state(group_id, modifier),
+        Backend::Simulation => simulation(context).set_group_state(group_id, modifier),
+        Backend::Hue => {
+            #[cfg(feature = "clip-v2")]
+            if context.conf.bridge_api_v2 {
+                return clip_v2_resources::set_group_state(&context.clip, group_id, modifier);
+            }
+
+            context
+                .hue()
+                .set_group_state(group_id, modifier)
+                .map_err(|err| err.to_string())
+        }
+        #[cfg(feature = "deconz")]
+        Backend::Deconz => {
+            let (group_id, modifier) = (group_id.to_string(), modifier.clone());
+
+            let responses = watchdog::track(&context.bridge, move |bridge| {
+                bridge.set_group_state(group_id, &modifier)
+            })
+            .map_err(|err| err.to_string())?;
+
+            // The bridge answers with an error in place of each attribute it couldn't set
+            match responses
+                .into_iter()
+                .find_map(|response| response.into_result().err())
+            {
+                Some(err) => Err(err.to_string()),
+                None => Ok(()),
+            }
+        }
+    }
+}
+
+/// Recalls a scene in its group, deCONZ doesn't accept scenes in the action of a group
+pub fn recall_scene(context: &Context, group_id: &str, scene_id: &str) -> Result<(), String> {
+    match context.conf.backend {
+        #[cfg(feature = "deconz")]
+        Backend::Deconz => {
+            if dry_run(context, || format!("PUT /scenes/{}/recall", scene_id)) {
+                return Ok(());
+            }
+
+            let _command = command()?;
+            deconz::recall_scene(&context.bridge, scene_id).map_err(|err| err.to_string())
+        }
+        _ => set_group_state(
+            context,
+            group_id,
+            &group::StateModifier::new().with_scene(scene_id.to_string()),
+        ),
+    }
+}
+
+/// Takes or extends the advisory lock of a group for `ttl`, see `GROUP_LOCK_TTL`.
+/// Hue bridges store it as resourcelink, other backends keep it in memory so only the homes of this process take part.
+pub fn acquire_lock(
+    context: &Context,
+    group_id: &str,
+    ttl: Duration,
+) -> Result<Option<String>, String> {
+    let holder = &context.conf.lock_holder;
+
+    if dry_run(context, || {
+        format!("POST /resourcelinks group {} lock of {}", group_id, holder)
+    }) {
+        return Ok(None);
+    }
+
+    let _command = command()?;
+
+    match context.conf.backend {
+        Backend::Hue => {
+            locks::acquire(&context.hue(), holder, ttl, group_id).map_err(|err| err.to_string())
+        }
+        _ => Ok(locks::acquire_in_memory(
+            &context.bridge_ip.to_string(),
+            holder,
+            ttl,
+            group_id,
+        )),
+    }
+}
+
+/// Activates a smart scene through the v2 api, see `SMART_SCENES`
+#[cfg(feature = "clip-v2")]
+pub fn activate_smart_scene(context: &Context, smart_scene_id: &str) -> Result<(), String> {
+    if dry_run(context, || {
+        format!(
+            "PUT /clip/v2/resource/smart_scene/{} activate",
+            smart_scene_id
+        )
+    }) {
+        return Ok(());
+    }
+
+    let _command = command()?;
+    smart_scenes::activate(&context.clip, smart_scene_id).map_err(|err| err.to_string())
+}
+
+/// Returns the stored light states of a scene, zigbee2mqtt and Home Assistant don't reveal them
+pub fn get_scene_light_states(
+    context: &Context,
+    scene_id: &str,
+) -> Result<HashMap<String, SceneLightState>, String> {
+    match context.conf.backend {
+        Backend::Hue => scene_states::get_scene_light_states(&context.hue(), scene_id)
+            .map_err(|err| err.to_string()),
+        #[cfg(feature = "deconz")]
+        Backend::Deconz => {
+            deconz::get_scene_light_states(&context.bridge, scene_id).map_err(|err| err.to_string())
+        }
+        #[cfg(feature = "zigbee2mqtt")]
+        Backend::Zigbee2Mqtt => Ok(HashMap::new()),
+        #[cfg(feature = "home-assistant")]
+        Backend::HomeAssistant => Ok(HashMap::new()),
+        Backend::Simulation => Ok(simulation(context).get_scene_light_states(scene_id)),
+    }
+}
+
+/// A request whose result is written to a variable of the caller
+pub type Request<'a> = Box<dyn FnOnce() + Send + 'a>;
+
+/// Sends requests at the same time, at most `limit` at once. Returns once all of them are done.
+pub fn concurrently(limit: usize, requests: Vec<Request>) {
+    let mut requests = requests.into_iter().peekable();
+
+    while requests.peek().is_some() {
+        let batch = requests
+            .by_ref()
+            .take(limit.max(1))
+            .collect::<Vec<Request>>();
+
+        thread::scope(|scope| {
+            let handles = batch
+                .into_iter()
+                .map(|request| scope.spawn(request))
+                .collect::<Vec<_>>();
+
+            // Panics are passed on, polling catches them
+            for handle in handles {
+                if let Err(payload) = handle.join() {
+                    panic::resume_unwind(payload);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::simulation;
+    use serde_json::json;
+
+    /// Context of an empty simulation, it stands in for backends other than a hue bridge
+    fn simulated(variables: &[(&str, &str)]) -> Context {
+        let conf = config::load_test_config(variables);
+        let fixtures = json!({ "lights": {}, "groups": {}, "scenes": {} });
+
+        simulation::test_context(conf, fixtures).0
+    }
+
+    #[test]
+    fn test_simulation() {
+        let context = simulated(&[]);
+
+        assert_eq!(get_all_lights(&context), Ok(vec![]));
+        assert_eq!(
+            recall_scene(&context, "1", "s1"),
+            Err("scene s1 doesn't exist".to_string())
+        );
+
+        // Nothing is sent in a dry run
+        let context = simulated(&[("DRY_RUN", "true")]);
+        assert_eq!(recall_scene(&context, "1", "s1"), Ok(()));
+    }
+
+    #[test]
+    fn test_acquire_lock() {
+        let ttl = Duration::from_secs(60);
+        let context = simulated(&[("LOCK_HOLDER", "flat")]);
+        let bridge = context.bridge_ip.to_string();
+
+        // Backends without resourcelinks share locks between the homes of this process
+        assert_eq!(acquire_lock(&context, "backend-1", ttl), Ok(None));
+        assert_eq!(
+            locks::acquire_in_memory(&bridge, "parents", ttl, "backend-1"),
+            Some("flat".to_string())
+        );
+
+        assert_eq!(
+            locks::acquire_in_memory(&bridge, "parents", ttl, "backend-2"),
+            None
+        );
+        assert_eq!(
+            acquire_lock(&context, "backend-2", ttl),
+            Ok(Some("parents".to_string()))
+        );
+
+        let context = simulated(&[("LOCK_HOLDER", "flat"), ("DRY_RUN", "true")]);
+        assert_eq!(acquire_lock(&context, "backend-2", ttl), Ok(None));
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Longest delay between two attempts while the bridge keeps failing
+const MAX_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Consecutive failures after which a bridge counts as degraded
+pub const DEGRADED_AFTER: u32 = 3;
+
+/// Delays polls of a bridge exponentially while its requests fail, so a rebooting bridge isn't hammered.
+/// Delays are randomized to keep several schedulers from retrying in lockstep.
+pub struct Backoff {
+    interval: Duration,
+    failures: u32,
+}
+
+impl Backoff {
+    pub fn new(interval: Duration) -> Backoff {
+        Backoff {
+            interval,
+            failures: 0,
+        }
+    }
+
+    /// Changes the regular interval, failures so far are kept
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Consecutive failures so far
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Time to wait before the next attempt, the regular interval as long as nothing failed
+    pub fn delay(&self) -> Duration {
+        if self.failures == 0 {
+            return self.interval;
+        }
+
+        let delay = self
+            .interval
+            .saturating_mul(2u32.saturating_pow(self.failures))
+            .min(MAX_DELAY.max(self.interval));
+
+        // Half of the delay is fixed, the other half random
+        let random = RandomState::new().build_hasher().finish() % 1000;
+        (delay / 2 + delay / 2 * random as u32 / 1000).max(self.interval)
+    }
+
+    pub fn fail(&mut self) {
+        self.failures = self.failures.saturating_add(1);
+    }
+
+    /// Resets the delay, returns how many attempts failed before
+    pub fn succeed(&mut self) -> u32 {
+        std::mem::take(&mut self.failures)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let secs = Duration::from_secs;
+        let mut backoff = Backoff::new(secs(10));
+
+        // Delays lie between half and all of the doubled interval
+        let within = |backoff: &Backoff, max: Duration| {
+            (0..100).all(|_| (max / 2..=max).contains(&backoff.delay()))
+        };
+
+        assert_eq!(backoff.delay(), secs(10));
+
+        backoff.fail();
+        assert!(within(&backoff, secs(20)));
+
+        backoff.fail();
+        assert!(within(&backoff, secs(40)));
+
+        backoff.fail();
+        assert!(within(&backoff, secs(80)));
+        assert_eq!(backoff.failures(), DEGRADED_AFTER);
+
+        for _ in 0..100 {
+            backoff.fail();
+        }
+
+        assert!(within(&backoff, MAX_DELAY));
+
+        assert_eq!(backoff.succeed(), 103);
+        assert_eq!(backoff.failures(), 0);
+        assert_eq!(backoff.delay(), secs(10));
+
+        // Intervals longer than the cap aren't shortened
+        let mut backoff = Backoff::new(secs(600));
+        backoff.fail();
+        assert_eq!(backoff.delay(), secs(600));
+    }
+}
@@ -0,0 +1,171 @@
+use crate::backend;
+use crate::debug;
+use crate::engine::Context;
+use crate::scene_states::SceneLightState;
+use huelib2::resource::Light;
+use std::collections::HashMap;
+
+/// Offsets for bulbs that render the same state visibly different, e.g. of older generations
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Calibration {
+    pub hue: i32,
+    pub color_temperature: i32,
+}
+
+/// Largest offsets that still make a difference, hue wraps around at 65536 and color temperatures range from 153 to 500
+const MAX_HUE_OFFSET: i32 = 65535;
+const MAX_COLOR_TEMPERATURE_OFFSET: i32 = 500 - 153;
+
+/// Parses calibrations in the form of `light name:hue offset:color temperature offset`, separated by `;`
+/// # Examples
+/// ```
+/// assert_eq!(
+///   parse_calibrations("Desk:1200:-15"),
+///   Some(HashMap::from([("Desk".to_string(), Calibration { hue: 1200, color_temperature: -15 })]))
+/// );
+/// ```
+pub fn parse_calibrations(str: &str) -> Option<HashMap<String, Calibration>> {
+    str.split(";")
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let parts = entry.split(":").map(str::trim).collect::<Vec<&str>>();
+
+            match parts[..] {
+                [name, hue, color_temperature] => Some((
+                    name.to_string(),
+                    Calibration {
+                        hue: hue
+                            .parse()
+                            .ok()
+                            .filter(|hue: &i32| hue.abs() <= MAX_HUE_OFFSET)?,
+                        color_temperature: color_temperature
+                            .parse()
+                            .ok()
+                            .filter(|ct: &i32| ct.abs() <= MAX_COLOR_TEMPERATURE_OFFSET)?,
+                    },
+                )),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+impl Calibration {
+    /// Returns a stored state with the offsets applied
+    pub fn apply(&self, state: &SceneLightState) -> SceneLightState {
+        SceneLightState {
+            hue: state
+                .hue
+                .map(|hue| (hue as i32 + self.hue).rem_euclid(65536) as u16),
+            ct: state
+                .ct
+                .map(|ct| (ct as i32 + self.color_temperature).clamp(153, 500) as u16),
+            ..state.clone()
+        }
+    }
+}
+
+/// Returns the states the lights of a scene should end up in, calibrated lights have their offsets applied
+pub fn expected_states(
+    calibrations: &HashMap<String, Calibration>,
+    states: &HashMap<String, SceneLightState>,
+    lights: &[Light],
+) -> HashMap<String, SceneLightState> {
+    lights
+        .iter()
+        .filter_map(|light| {
+            let state = states.get(&light.id)?;
+
+            match calibrations.get(&light.name) {
+                Some(calibration) => Some((light.id.clone(), calibration.apply(state))),
+                None => Some((light.id.clone(), state.clone())),
+            }
+        })
+        .collect()
+}
+
+/// Writes the calibrated scene state to all calibrated lights of a scene that was just recalled
+pub fn apply_calibrations(
+    context: &Context,
+    calibrations: &HashMap<String, Calibration>,
+    states: &HashMap<String, SceneLightState>,
+    lights: &[Light],
+) {
+    for light in lights {
+        let (Some(calibration), Some(state)) =
+            (calibrations.get(&light.name), states.get(&light.id))
+        else {
+            continue;
+        };
+
+        let modifier = calibration.apply(state).modifier();
+        debug!("Bridge: PUT /lights/{}/state {:?}", light.id, modifier);
+        context.limiter.wait();
+
+        if let Err(err) = backend::set_light_state(context, &light.id, &modifier) {
+            eprintln!("Failed to calibrate light \"{}\": {}", light.name, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_calibrations() {
+        let c = |hue, color_temperature| Calibration {
+            hue,
+            color_temperature,
+        };
+
+        assert_eq!(parse_calibrations(""), Some(HashMap::new()));
+        assert_eq!(
+            parse_calibrations("Hall 1:1200:-15; Desk Lamp : 0 : 20 ;"),
+            Some(HashMap::from([
+                ("Hall 1".to_string(), c(1200, -15)),
+                ("Desk Lamp".to_string(), c(0, 20)),
+            ]))
+        );
+        assert_eq!(
+            parse_calibrations("Desk:-65535:347"),
+            Some(HashMap::from([("Desk".to_string(), c(-65535, 347))]))
+        );
+
+        // Malformed entries
+        assert_eq!(parse_calibrations("Desk"), None);
+        assert_eq!(parse_calibrations("Desk:1200"), None);
+        assert_eq!(parse_calibrations("Desk:1200:-15:3"), None);
+        assert_eq!(parse_calibrations("Desk:warm:-15"), None);
+        assert_eq!(parse_calibrations("Desk:1200:1.5"), None);
+        assert_eq!(parse_calibrations("Desk:1200:-15;Hall"), None);
+
+        // Out of range
+        assert_eq!(parse_calibrations("Desk:65536:0"), None);
+        assert_eq!(parse_calibrations("Desk:0:-348"), None);
+        assert_eq!(parse_calibrations("Desk:99999999999:0"), None);
+    }
+
+    #[test]
+    fn test_apply() {
+        let state = SceneLightState {
+            on: Some(true),
+            bri: Some(200),
+            hue: Some(65000),
+            sat: None,
+            xy: None,
+            ct: Some(160),
+        };
+
+        let calibrated = Calibration {
+            hue: 1200,
+            color_temperature: -15,
+        }
+        .apply(&state);
+
+        // Hue wraps around, color temperatures stay within what bulbs support
+        assert_eq!(calibrated.hue, Some(664));
+        assert_eq!(calibrated.ct, Some(153));
+        assert_eq!(calibrated.bri, Some(200));
+    }
+}
@@ -0,0 +1,366 @@
+use crate::api_usage;
+use crate::config::Config;
+use crate::debug;
+use crate::discovery;
+use crate::watchdog;
+use base64::Engine;
+use huelib2::Bridge;
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, CertificateError, ClientConfig, RootCertStore, ServerName};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+
+/// Object identifier of the common name within the subject of a certificate
+const OID_COMMON_NAME: [u8; 3] = [0x55, 0x04, 0x03];
+
+/// Reference to another resource of the v2 api
+#[derive(Clone, PartialEq, Debug, Deserialize)]
+pub struct ResourceIdentifier {
+    pub rid: String,
+    pub rtype: String,
+}
+
+#[derive(Deserialize)]
+struct Response<T> {
+    data: Vec<T>,
+}
+
+/// Splits the next DER element off the input, returns its tag, its content and what follows
+fn read_der(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first, mut input) = input.split_first()?;
+
+    let length = match first {
+        0..=0x7f => first as usize,
+        0x81..=0x84 => {
+            let (bytes, rest) = input.split_at_checked((first & 0x7f) as usize)?;
+            input = rest;
+            bytes
+                .iter()
+                .fold(0, |length, byte| (length << 8) | *byte as usize)
+        }
+        _ => return None,
+    };
+
+    let (content, rest) = input.split_at_checked(length)?;
+    Some((tag, content, rest))
+}
+
+/// Returns the common name of the subject of a certificate, bridges use their id
+fn common_name(certificate: &[u8]) -> Option<String> {
+    let (_, certificate, _) = read_der(certificate)?;
+    let (_, mut tbs, _) = read_der(certificate)?;
+
+    // The version is optional, serial number, signature algorithm, issuer and validity come before the subject
+    if tbs.first() == Some(&0xa0) {
+        tbs = read_der(tbs)?.2;
+    }
+
+    for _ in 0..4 {
+        tbs = read_der(tbs)?.2;
+    }
+
+    let (_, mut subject, _) = read_der(tbs)?;
+
+    while !subject.is_empty() {
+        let (_, set, rest) = read_der(subject)?;
+        let (_, attribute, _) = read_der(set)?;
+        let (_, oid, value) = read_der(attribute)?;
+
+        if oid == OID_COMMON_NAME {
+            let (_, value, _) = read_der(value)?;
+            return String::from_utf8(value.to_vec()).ok();
+        }
+
+        subject = rest;
+    }
+
+    None
+}
+
+/// Reads a certificate in PEM or DER format
+fn read_certificate(path: &Path) -> Result<Certificate, String> {
+    let content = fs::read(path).map_err(|err| err.to_string())?;
+
+    if !content.starts_with(b"-----") {
+        return Ok(Certificate(content));
+    }
+
+    let base64 = String::from_utf8_lossy(&content)
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect::<String>();
+
+    base64::engine::general_purpose::STANDARD
+        .decode(base64.trim())
+        .map(Certificate)
+        .map_err(|err| err.to_string())
+}
+
+/// Accepts any certificate, for bridges and emulators with a certificate of their own, see `BRIDGE_TLS_VERIFY`
+struct AcceptBridgeCertificate;
+
+impl ServerCertVerifier for AcceptBridgeCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// The bridge uses a certificate issued for its id, signed by Signify whose root certificate isn't part of any root store.
+/// It's only checked against the root certificate if one is given, see `BRIDGE_CA_FILE`.
+/// The id comes from `BRIDGE_ID` or the discovery service of Philips, never from the bridge, whose answers could be forged just as well.
+struct BridgeCertificateVerifier {
+    /// See `BRIDGE_ID`
+    bridge_id: Option<String>,
+    /// Ids of bridges looked up through discovery by their address
+    discovered: Mutex<HashMap<IpAddr, String>>,
+    root: Option<WebPkiVerifier>,
+}
+
+impl BridgeCertificateVerifier {
+    fn bridge_id(&self, server_name: &ServerName) -> Result<String, rustls::Error> {
+        if let Some(bridge_id) = &self.bridge_id {
+            return Ok(bridge_id.clone());
+        }
+
+        let ServerName::IpAddress(ip) = server_name else {
+            return Err(rustls::Error::General(
+                "bridges are only looked up by their address".to_string(),
+            ));
+        };
+
+        let mut discovered = self
+            .discovered
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        if !discovered.contains_key(ip) {
+            let bridges = discovery::discover().map_err(|err| {
+                rustls::Error::General(format!(
+                    "failed to look up the id of the bridge at {}, set BRIDGE_ID: {}",
+                    ip, err
+                ))
+            })?;
+
+            discovered.extend(bridges.into_iter().map(|(id, ip)| (ip, id)));
+        }
+
+        discovered.get(ip).cloned().ok_or_else(|| {
+            rustls::Error::General(format!(
+                "the bridge at {} isn't known to the discovery service, set BRIDGE_ID",
+                ip
+            ))
+        })
+    }
+}
+
+impl ServerCertVerifier for BridgeCertificateVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let bridge_id = self.bridge_id(server_name)?;
+
+        match common_name(&end_entity.0) {
+            Some(name) if name.eq_ignore_ascii_case(&bridge_id) => {}
+            name => {
+                return Err(rustls::Error::General(format!(
+                    "certificate is issued for {} instead of bridge {}",
+                    name.unwrap_or("nobody".to_string()),
+                    bridge_id
+                )))
+            }
+        }
+
+        let Some(root) = &self.root else {
+            return Ok(ServerCertVerified::assertion());
+        };
+
+        // The name doesn't match the address the bridge is connected to, its id is checked above instead
+        match root.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        ) {
+            Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForName)) => {
+                Ok(ServerCertVerified::assertion())
+            }
+            result => result,
+        }
+    }
+}
+
+/// HTTPS agent checking the certificate of a bridge, see `BRIDGE_TLS_VERIFY`, `BRIDGE_ID` and `BRIDGE_CA_FILE`.
+/// The v1 api is served with the same certificate, see [crate::hue_api::HueApi].
+pub fn tls_agent(
+    bridge_id: Option<String>,
+    tls_verify: bool,
+    ca_file: Option<&Path>,
+) -> Result<ureq::Agent, String> {
+    let verifier: Arc<dyn ServerCertVerifier> = match tls_verify {
+        true => {
+            let root = match ca_file {
+                Some(path) => {
+                    let mut roots = RootCertStore::empty();
+                    let certificate = read_certificate(path).map_err(|err| {
+                        format!("failed to read BRIDGE_CA_FILE {}: {}", path.display(), err)
+                    })?;
+                    roots.add(&certificate).map_err(|err| {
+                        format!("failed to parse BRIDGE_CA_FILE {}: {}", path.display(), err)
+                    })?;
+
+                    Some(WebPkiVerifier::new(roots, None))
+                }
+                None => None,
+            };
+
+            Arc::new(BridgeCertificateVerifier {
+                bridge_id,
+                discovered: Mutex::new(HashMap::new()),
+                root,
+            })
+        }
+        false => Arc::new(AcceptBridgeCertificate),
+    };
+
+    let tls_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(verifier)
+        .with_no_client_auth();
+
+    Ok(ureq::AgentBuilder::new()
+        .tls_config(Arc::new(tls_config))
+        .timeout(watchdog::timeout())
+        .build())
+}
+
+/// Minimal client for the CLIP v2 api, which huelib2 doesn't support
+pub struct ClipV2 {
+    agent: ureq::Agent,
+    base_url: String,
+    application_key: String,
+    /// Ids resources are written through by their v1 address, e.g. the grouped_light of `/groups/1`
+    ids: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl ClipV2 {
+    /// Creates a client for a bridge, the certificate it presents must be issued for `bridge_id` if it's known.
+    /// Fails if `BRIDGE_CA_FILE` can't be read.
+    pub fn new(
+        bridge: &Bridge,
+        bridge_id: Option<String>,
+        conf: &Config,
+    ) -> Result<ClipV2, String> {
+        let agent = tls_agent(
+            bridge_id,
+            conf.bridge_tls_verify,
+            conf.bridge_ca_file.as_deref(),
+        )?;
+
+        Ok(ClipV2::with_agent(agent, bridge))
+    }
+
+    fn with_agent(agent: ureq::Agent, bridge: &Bridge) -> ClipV2 {
+        ClipV2 {
+            agent,
+            base_url: format!("https://{}/clip/v2/resource", bridge.ip_address()),
+            application_key: bridge.username().to_string(),
+            ids: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Same client for the bridge under a new address, its certificate is checked as before
+    pub fn moved_to(&self, bridge: &Bridge) -> ClipV2 {
+        ClipV2 {
+            ids: self.ids.clone(),
+            ..ClipV2::with_agent(self.agent.clone(), bridge)
+        }
+    }
+
+    /// Remembers the id a resource with a v1 address is written through
+    pub fn remember_id(&self, address: String, id: String) {
+        self.ids
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(address, id);
+    }
+
+    /// Returns the id a resource with a v1 address is written through, if it was read before
+    pub fn id_of(&self, address: &str) -> Option<String> {
+        self.ids
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(address)
+            .cloned()
+    }
+
+    /// HTTPS agent with the certificate checks of this client
+    pub fn agent(&self) -> &ureq::Agent {
+        &self.agent
+    }
+
+    /// Returns all resources of a type, e.g. `smart_scene`
+    pub fn get_resources<T: DeserializeOwned>(
+        &self,
+        resource_type: &str,
+    ) -> Result<Vec<T>, Box<ureq::Error>> {
+        debug!("Bridge: GET /clip/v2/resource/{}", resource_type);
+
+        let response = api_usage::track(|| {
+            self.agent
+                .get(&format!("{}/{}", self.base_url, resource_type))
+                .set("hue-application-key", &self.application_key)
+                .call()
+                .map_err(Box::new)
+        })?
+        .into_json::<Response<T>>()
+        .map_err(|err| Box::new(ureq::Error::from(err)))?;
+
+        Ok(response.data)
+    }
+
+    /// Updates a single resource
+    pub fn put_resource(
+        &self,
+        resource_type: &str,
+        id: &str,
+        body: serde_json::Value,
+    ) -> Result<(), Box<ureq::Error>> {
+        debug!(
+            "Bridge: PUT /clip/v2/resource/{}/{} {}",
+            resource_type, id, body
+        );
+
+        api_usage::track(|| {
+            self.agent
+                .put(&format!("{}/{}/{}", self.base_url, resource_type, id))
+                .set("hue-application-key", &self.application_key)
+                .send_json(body)
+                .map_err(Box::new)
+        })?;
+
+        Ok(())
+    }
+}
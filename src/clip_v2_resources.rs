@@ -0,0 +1,460 @@
+use crate::clip_v2::{ClipV2, ResourceIdentifier};
+use huelib2::resource::group::{CreatableKind, Kind as GroupKind, State as GroupState};
+use huelib2::resource::light::{
+    Capabilities, ColorTemperatureCapabilities, Config as LightConfig, ControlCapabilities,
+    SoftwareUpdate, SoftwareUpdateState, State as LightState, StreamingCapabilities,
+};
+use huelib2::resource::scene::{AppData, Kind as SceneKind, Version};
+use huelib2::resource::{group, light, Adjust, ColorMode, Group, Light, Scene};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+struct Metadata {
+    name: String,
+    #[serde(default)]
+    archetype: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct On {
+    on: bool,
+}
+
+#[derive(Deserialize)]
+struct Dimming {
+    brightness: f32,
+}
+
+#[derive(Deserialize)]
+struct MirekSchema {
+    mirek_minimum: usize,
+    mirek_maximum: usize,
+}
+
+#[derive(Deserialize)]
+struct ColorTemperature {
+    mirek: Option<u16>,
+    #[serde(default)]
+    mirek_valid: bool,
+    mirek_schema: Option<MirekSchema>,
+}
+
+#[derive(Deserialize)]
+struct Xy {
+    x: f32,
+    y: f32,
+}
+
+#[derive(Deserialize)]
+struct Color {
+    xy: Xy,
+    gamut_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LightResource {
+    id: String,
+    id_v1: Option<String>,
+    owner: ResourceIdentifier,
+    metadata: Metadata,
+    on: On,
+    dimming: Option<Dimming>,
+    color_temperature: Option<ColorTemperature>,
+    color: Option<Color>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct ProductData {
+    model_id: String,
+    manufacturer_name: Option<String>,
+    product_name: Option<String>,
+    software_version: String,
+}
+
+#[derive(Deserialize)]
+struct DeviceResource {
+    id: String,
+    #[serde(default)]
+    product_data: ProductData,
+}
+
+#[derive(Deserialize)]
+struct ConnectivityResource {
+    owner: ResourceIdentifier,
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct GroupResource {
+    id: String,
+    id_v1: Option<String>,
+    metadata: Metadata,
+    children: Vec<ResourceIdentifier>,
+    #[serde(default)]
+    services: Vec<ResourceIdentifier>,
+}
+
+#[derive(Deserialize)]
+struct SceneAction {
+    target: ResourceIdentifier,
+}
+
+#[derive(Deserialize)]
+struct SceneResource {
+    id: String,
+    id_v1: Option<String>,
+    metadata: Metadata,
+    group: ResourceIdentifier,
+    actions: Vec<SceneAction>,
+}
+
+/// Returns the v1 id of a resource, e.g. `3` for `/lights/3`, so it can still be written through the v1 api.
+/// Resources without one keep their v2 id.
+fn v1_id(id: &str, id_v1: &Option<String>) -> String {
+    id_v1
+        .as_ref()
+        .and_then(|id_v1| id_v1.rsplit("/").next())
+        .filter(|id_v1| !id_v1.is_empty())
+        .unwrap_or(id)
+        .to_string()
+}
+
+/// Address of a resource in the v1 api, e.g. `/groups/1`
+fn address(kind: &str, id: &str) -> String {
+    format!("/{}/{}", kind, id)
+}
+
+/// Returns the v1 ids of all lights by their v2 id
+fn light_ids(clip: &ClipV2) -> Result<HashMap<String, String>, Box<ureq::Error>> {
+    Ok(clip
+        .get_resources::<LightResource>("light")?
+        .into_iter()
+        .map(|light| (light.id.clone(), v1_id(&light.id, &light.id_v1)))
+        .collect())
+}
+
+/// Returns all lights in the shape of the v1 api, they're reachable unless their zigbee connection reports otherwise
+pub fn get_all_lights(clip: &ClipV2) -> Result<Vec<Light>, Box<ureq::Error>> {
+    let lights = clip.get_resources::<LightResource>("light")?;
+    let devices = clip.get_resources::<DeviceResource>("device")?;
+    let connectivity = clip.get_resources::<ConnectivityResource>("zigbee_connectivity")?;
+
+    Ok(lights
+        .into_iter()
+        .map(|light| {
+            let device = devices.iter().find(|device| device.id == light.owner.rid);
+            let product_data = device.map(|device| &device.product_data);
+            let reachable = connectivity
+                .iter()
+                .find(|connectivity| connectivity.owner.rid == light.owner.rid)
+                .map(|connectivity| connectivity.status == "connected")
+                .unwrap_or(true);
+
+            let color_temperature = light
+                .color_temperature
+                .as_ref()
+                .filter(|color_temperature| color_temperature.mirek_valid)
+                .and_then(|color_temperature| color_temperature.mirek);
+
+            let kind = match (&light.color, &light.color_temperature, &light.dimming) {
+                (Some(_), Some(_), _) => "Extended color light",
+                (Some(_), None, _) => "Color light",
+                (None, Some(_), _) => "Color temperature light",
+                (None, None, Some(_)) => "Dimmable light",
+                (None, None, None) => "On/Off plug-in unit",
+            };
+
+            let id = v1_id(&light.id, &light.id_v1);
+            clip.remember_id(address("lights", &id), light.id.clone());
+
+            Light {
+                id,
+                name: light.metadata.name,
+                kind: kind.to_string(),
+                state: LightState {
+                    on: Some(light.on.on),
+                    // Brightness is given in percent instead of 1 to 254
+                    brightness: light.dimming.as_ref().map(|dimming| {
+                        (dimming.brightness / 100.0 * 253.0 + 1.0).clamp(1.0, 254.0) as u8
+                    }),
+                    hue: None,
+                    saturation: None,
+                    color_space_coordinates: light
+                        .color
+                        .as_ref()
+                        .map(|color| (color.xy.x, color.xy.y)),
+                    color_temperature,
+                    alert: None,
+                    effect: None,
+                    color_mode: match (color_temperature, &light.color) {
+                        (Some(_), _) => Some(ColorMode::ColorTemperature),
+                        (None, Some(_)) => Some(ColorMode::ColorSpaceCoordinates),
+                        (None, None) => None,
+                    },
+                    reachable,
+                },
+                model_id: product_data
+                    .map(|data| data.model_id.clone())
+                    .unwrap_or_default(),
+                unique_id: light.id,
+                product_id: None,
+                product_name: product_data.and_then(|data| data.product_name.clone()),
+                manufacturer_name: product_data.and_then(|data| data.manufacturer_name.clone()),
+                software_version: product_data
+                    .map(|data| data.software_version.clone())
+                    .unwrap_or_default(),
+                software_update: SoftwareUpdate {
+                    state: SoftwareUpdateState::NoUpdates,
+                    last_install: None,
+                },
+                config: LightConfig {
+                    arche_type: light.metadata.archetype.unwrap_or_default(),
+                    function: "mixed".to_string(),
+                    direction: "omnidirectional".to_string(),
+                    startup: None,
+                },
+                capabilities: Capabilities {
+                    certified: true,
+                    control: ControlCapabilities {
+                        min_dimlevel: None,
+                        max_lumen: None,
+                        color_gamut: None,
+                        color_gamut_type: light.color.and_then(|color| color.gamut_type),
+                        color_temperature: light
+                            .color_temperature
+                            .and_then(|color_temperature| color_temperature.mirek_schema)
+                            .map(|schema| ColorTemperatureCapabilities {
+                                min: schema.mirek_minimum,
+                                max: schema.mirek_maximum,
+                            }),
+                    },
+                    streaming: StreamingCapabilities {
+                        renderer: false,
+                        proxy: false,
+                    },
+                },
+            }
+        })
+        .collect())
+}
+
+/// Returns all rooms and zones in the shape of the v1 api.
+/// Rooms contain devices instead of lights, they're resolved to the lights of these devices.
+pub fn get_all_groups(clip: &ClipV2) -> Result<Vec<Group>, Box<ureq::Error>> {
+    let lights = clip.get_resources::<LightResource>("light")?;
+    let mut groups = vec![];
+
+    for (resource_type, kind) in [("room", CreatableKind::Room), ("zone", CreatableKind::Zone)] {
+        for group in clip.get_resources::<GroupResource>(resource_type)? {
+            let members = lights
+                .iter()
+                .filter(|light| {
+                    group.children.iter().any(|child| {
+                        (child.rtype == "light" && child.rid == light.id)
+                            || (child.rtype == "device" && child.rid == light.owner.rid)
+                    })
+                })
+                .collect::<Vec<&LightResource>>();
+
+            let id = v1_id(&group.id, &group.id_v1);
+
+            // Rooms and zones are switched through the grouped_light they offer as a service
+            if let Some(service) = group
+                .services
+                .iter()
+                .find(|service| service.rtype == "grouped_light")
+            {
+                clip.remember_id(address("groups", &id), service.rid.clone());
+            }
+
+            groups.push(Group {
+                id,
+                name: group.metadata.name,
+                lights: members
+                    .iter()
+                    .map(|light| v1_id(&light.id, &light.id_v1))
+                    .collect(),
+                sensors: vec![],
+                kind: GroupKind::Creatable(kind),
+                class: group.metadata.archetype,
+                state: Some(GroupState {
+                    any_on: members.iter().any(|light| light.on.on),
+                    all_on: !members.is_empty() && members.iter().all(|light| light.on.on),
+                }),
+                model_id: None,
+                unique_id: None,
+                recycle: None,
+            });
+        }
+    }
+
+    Ok(groups)
+}
+
+/// Returns all scenes in the shape of the v1 api, their lights are the targets of their actions
+pub fn get_all_scenes(clip: &ClipV2) -> Result<Vec<Scene>, Box<ureq::Error>> {
+    let light_ids = light_ids(clip)?;
+    let group_ids = ["room", "zone"]
+        .into_iter()
+        .map(|resource_type| clip.get_resources::<GroupResource>(resource_type))
+        .collect::<Result<Vec<Vec<GroupResource>>, Box<ureq::Error>>>()?
+        .into_iter()
+        .flatten()
+        .map(|group| (group.id.clone(), v1_id(&group.id, &group.id_v1)))
+        .collect::<HashMap<String, String>>();
+
+    Ok(clip
+        .get_resources::<SceneResource>("scene")?
+        .into_iter()
+        .map(|scene| {
+            let id = v1_id(&scene.id, &scene.id_v1);
+            clip.remember_id(address("scenes", &id), scene.id);
+
+            Scene {
+                id,
+                name: scene.metadata.name,
+                kind: SceneKind::GroupScene,
+                group: group_ids.get(&scene.group.rid).cloned(),
+                lights: Some(
+                    scene
+                        .actions
+                        .iter()
+                        .filter_map(|action| light_ids.get(&action.target.rid).cloned())
+                        .collect(),
+                ),
+                owner: None,
+                recycle: false,
+                locked: false,
+                app_data: AppData::default(),
+                picture: None,
+                last_update: None,
+                version: Version::Post,
+            }
+        })
+        .collect())
+}
+
+/// Returns the id a resource is written through, all resources of its kind are read once if it wasn't seen yet
+fn resolve(clip: &ClipV2, kind: &str, id: &str) -> Result<String, String> {
+    let address = address(kind, id);
+
+    if let Some(id) = clip.id_of(&address) {
+        return Ok(id);
+    }
+
+    match kind {
+        "lights" => get_all_lights(clip).map(|_| ()),
+        "groups" => get_all_groups(clip).map(|_| ()),
+        _ => get_all_scenes(clip).map(|_| ()),
+    }
+    .map_err(|err| err.to_string())?;
+
+    clip.id_of(&address)
+        .ok_or(format!("{} isn't available through the v2 api", address))
+}
+
+/// Converts a modifier of the v1 api into the body of a light or grouped_light, only absolute values are supported
+fn body(
+    on: Option<bool>,
+    brightness: &Option<Adjust<u8>>,
+    color_space_coordinates: &Option<Adjust<(f32, f32)>>,
+    color_temperature: &Option<Adjust<u16>>,
+    transition_time: Option<u16>,
+) -> Map<String, Value> {
+    let mut body = Map::new();
+
+    if let Some(on) = on {
+        body.insert("on".to_string(), json!({ "on": on }));
+    }
+
+    // Brightness is given in percent instead of 1 to 254
+    if let Some(Adjust::Override(brightness)) = brightness {
+        body.insert(
+            "dimming".to_string(),
+            json!({ "brightness": ((*brightness as f32 - 1.0) / 253.0 * 100.0).clamp(0.0, 100.0) }),
+        );
+    }
+
+    if let Some(Adjust::Override((x, y))) = color_space_coordinates {
+        body.insert("color".to_string(), json!({ "xy": { "x": x, "y": y } }));
+    }
+
+    if let Some(Adjust::Override(color_temperature)) = color_temperature {
+        body.insert(
+            "color_temperature".to_string(),
+            json!({ "mirek": color_temperature }),
+        );
+    }
+
+    // The v1 api counts in 100ms, the v2 api in milliseconds
+    if let Some(transition_time) = transition_time {
+        body.insert(
+            "dynamics".to_string(),
+            json!({ "duration": transition_time as u32 * 100 }),
+        );
+    }
+
+    body
+}
+
+/// Changes the state of a light by its v1 id
+pub fn set_light_state(
+    clip: &ClipV2,
+    light_id: &str,
+    modifier: &light::StateModifier,
+) -> Result<(), String> {
+    let id = resolve(clip, "lights", light_id)?;
+    let body = body(
+        modifier.on,
+        &modifier.brightness,
+        &modifier.color_space_coordinates,
+        &modifier.color_temperature,
+        modifier.transition_time,
+    );
+
+    clip.put_resource("light", &id, Value::Object(body))
+        .map_err(|err| err.to_string())
+}
+
+/// Changes the state of a room or zone by its v1 id, a scene in the modifier is recalled instead
+pub fn set_group_state(
+    clip: &ClipV2,
+    group_id: &str,
+    modifier: &group::StateModifier,
+) -> Result<(), String> {
+    if let Some(scene_id) = &modifier.scene {
+        return recall_scene(clip, scene_id, modifier.transition_time);
+    }
+
+    let id = resolve(clip, "groups", group_id)?;
+    let body = body(
+        modifier.on,
+        &modifier.brightness,
+        &modifier.color_space_coordinates,
+        &modifier.color_temperature,
+        modifier.transition_time,
+    );
+
+    clip.put_resource("grouped_light", &id, Value::Object(body))
+        .map_err(|err| err.to_string())
+}
+
+/// Recalls a scene by its v1 id, scenes of the v2 api know their room or zone themselves
+pub fn recall_scene(
+    clip: &ClipV2,
+    scene_id: &str,
+    transition_time: Option<u16>,
+) -> Result<(), String> {
+    let id = resolve(clip, "scenes", scene_id)?;
+    let mut recall = json!({ "action": "active" });
+
+    if let Some(transition_time) = transition_time {
+        recall["duration"] = json!(transition_time as u32 * 100);
+    }
+
+    clip.put_resource("scene", &id, json!({ "recall": recall }))
+        .map_err(|err| err.to_string())
+}
@@ -0,0 +1,98 @@
+use crate::config::Config;
+use crate::debug;
+use crate::hue_api::HueApi;
+use crate::utils;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use std::time::Instant;
+
+/// Schedules and rules stored on the bridge fire at its own time, drifting further is worth a warning
+const MAX_CLOCK_DRIFT: i64 = 60;
+
+/// Seconds the local clock may move apart from the monotonic one between two polls before it counts as a jump
+const MAX_CLOCK_JUMP: i64 = 30;
+
+/// Returns how many seconds the clock of the bridge is ahead of the local one, negative if it's behind
+fn get_clock_drift(hue: &HueApi) -> Result<i64, huelib2::Error> {
+    let config = hue.get_config()?;
+    Ok((config.current_time.and_utc() - Utc::now()).num_seconds())
+}
+
+/// Warns if the clocks of bridge and scheduler drifted apart, e.g. because the bridge can't reach its time server
+pub fn check_clock(hue: &HueApi) {
+    let drift = match get_clock_drift(hue) {
+        Ok(drift) => drift,
+        Err(err) => {
+            eprintln!("Failed to retrieve time of the bridge: {}", err);
+            return;
+        }
+    };
+
+    debug!("Bridge: GET /config → clock drift {}s", drift);
+
+    if drift.abs() > MAX_CLOCK_DRIFT {
+        eprintln!(
+            "The clock of the bridge is {}s {}, schedules and rules stored on the bridge will fire at the wrong time",
+            drift.abs(),
+            if drift > 0 { "ahead" } else { "behind" }
+        );
+    }
+}
+
+/// Notices the host waking up from suspend or its clock being corrected, e.g. by NTP.
+/// The monotonic clock stands still while suspended, so timestamps taken before look much more recent than they are.
+#[derive(Default)]
+pub struct JumpDetector {
+    last: Option<(Instant, DateTime<Tz>)>,
+}
+
+impl JumpDetector {
+    /// Returns how many seconds the local clock moved further than the monotonic one since the last call, if it jumped
+    pub fn check(&mut self, conf: &Config) -> Option<i64> {
+        self.observe(utils::instant_now(), utils::now_at_home(conf))
+    }
+
+    fn observe(&mut self, instant: Instant, now: DateTime<Tz>) -> Option<i64> {
+        let (last_instant, last_now) = self.last.replace((instant, now))?;
+
+        let monotonic = instant.saturating_duration_since(last_instant).as_secs() as i64;
+        let jump = (now - last_now).num_seconds() - monotonic;
+
+        (jump.abs() > MAX_CLOCK_JUMP).then_some(jump)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::time::Duration;
+
+    #[test]
+    fn test_clock_jumps() {
+        let instant = Instant::now();
+        let now = Tz::Europe__Berlin
+            .with_ymd_and_hms(2024, 10, 14, 22, 0, 0)
+            .unwrap();
+        let s = |seconds: u64| instant + Duration::from_secs(seconds);
+        let t = |seconds: i64| now + chrono::Duration::seconds(seconds);
+
+        let mut detector = JumpDetector::default();
+        let mut jump = |monotonic: u64, local: i64| detector.observe(s(monotonic), t(local));
+
+        assert_eq!(jump(0, 0), None);
+        assert_eq!(jump(1, 1), None);
+        assert_eq!(jump(2, 3), None);
+
+        // Suspended for an hour, the monotonic clock stood still
+        assert_eq!(jump(3, 3604), Some(3600));
+        assert_eq!(jump(4, 3605), None);
+
+        // Clock set back by NTP
+        assert_eq!(jump(5, 3506), Some(-100));
+
+        // Drifting apart within the limit
+        assert_eq!(jump(35, 3566), None);
+        assert_eq!(jump(36, 3538), None);
+    }
+}
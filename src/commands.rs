@@ -0,0 +1,262 @@
+use crate::scene_states::SceneLightState;
+use crate::utils;
+use huelib2::resource::Light;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Time after which a failed command is sent again, doubled with every attempt
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Command an engine sends to a group, all of them may be sent several times without harm
+#[derive(Clone, PartialEq, Debug)]
+pub enum Command {
+    /// Applies a scene, with the lights that triggered it
+    Scene {
+        group_id: String,
+        scene_id: String,
+        trigger_light_ids: Vec<String>,
+    },
+    /// Turns off a group whose non-attached lights became unreachable
+    GroupOff { group_id: String },
+}
+
+impl Command {
+    pub fn group_id(&self) -> &str {
+        match self {
+            Command::Scene { group_id, .. } | Command::GroupOff { group_id } => group_id,
+        }
+    }
+}
+
+/// Reads back whether a command took effect once the lights had time to settle, see `SCENE_VERIFICATION_DELAY`
+#[derive(Clone, PartialEq, Debug)]
+pub enum Check {
+    /// Re-sends the stored state to lights of a scene which didn't take it
+    Scene {
+        scene_id: String,
+        lights: Vec<Light>,
+        expected: HashMap<String, SceneLightState>,
+    },
+    /// Lights of a group still on count as a failed group-off
+    GroupOff { group_id: String },
+}
+
+struct Pending {
+    command: Command,
+    attempts: u32,
+    due: Instant,
+}
+
+/// Commands that failed and are sent again later, see `COMMAND_RETRIES`.
+/// There's at most one per group, a newer command replaces the one before.
+#[derive(Default)]
+pub struct CommandQueue {
+    pending: HashMap<String, Pending>,
+    /// Commands taken from the queue to be sent again, so their attempts carry over if they fail once more
+    retrying: HashMap<String, Pending>,
+    /// Checks waiting for the lights of a group to settle, each group has its own instead of holding up the others
+    checks: HashMap<String, (Check, Instant)>,
+}
+
+impl CommandQueue {
+    /// Queues a command that failed. Returns when it's sent again, or the attempts made once it's given up on.
+    pub fn failed(&mut self, command: Command, retries: u32) -> Result<Duration, u32> {
+        let group_id = command.group_id().to_string();
+
+        let attempts = self
+            .pending
+            .get(&group_id)
+            .or(self.retrying.get(&group_id))
+            .filter(|pending| pending.command == command)
+            .map(|pending| pending.attempts)
+            .unwrap_or(0)
+            + 1;
+
+        if attempts > retries {
+            self.pending.remove(&group_id);
+            return Err(attempts);
+        }
+
+        let delay = RETRY_DELAY * 2u32.saturating_pow(attempts - 1);
+
+        self.pending.insert(
+            group_id,
+            Pending {
+                command,
+                attempts,
+                due: utils::instant_now() + delay,
+            },
+        );
+
+        Ok(delay)
+    }
+
+    /// Drops the command and check queued for a group once a newer command went through
+    pub fn forget(&mut self, group_id: &str) {
+        self.pending.remove(group_id);
+        self.checks.remove(group_id);
+    }
+
+    /// Reads back a command sent to a group after a delay, replacing the check waiting for it before
+    pub fn check_later(&mut self, group_id: &str, check: Check, delay: Duration) {
+        self.checks
+            .insert(group_id.to_string(), (check, utils::instant_now() + delay));
+    }
+
+    /// Takes the checks whose delay is over
+    pub fn due_checks(&mut self) -> Vec<Check> {
+        let now = utils::instant_now();
+
+        let due_ids = self
+            .checks
+            .iter()
+            .filter(|(_, (_, due))| *due <= now)
+            .map(|(group_id, _)| group_id.clone())
+            .collect::<Vec<String>>();
+
+        due_ids
+            .into_iter()
+            .filter_map(|group_id| self.checks.remove(&group_id))
+            .map(|(check, _)| check)
+            .collect()
+    }
+
+    /// When the next command is due to be sent again or the next check is due
+    pub fn next_due(&self) -> Option<Instant> {
+        self.pending
+            .values()
+            .map(|pending| pending.due)
+            .chain(self.checks.values().map(|(_, due)| *due))
+            .min()
+    }
+
+    /// Takes the commands that are due to be sent again, [CommandQueue::settle] has to be called once they were
+    pub fn due(&mut self) -> Vec<Command> {
+        let now = utils::instant_now();
+
+        let due_ids = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.due <= now)
+            .map(|(group_id, _)| group_id.clone())
+            .collect::<Vec<String>>();
+
+        due_ids
+            .into_iter()
+            .filter_map(|group_id| self.pending.remove_entry(&group_id))
+            .map(|(group_id, pending)| {
+                let command = pending.command.clone();
+                self.retrying.insert(group_id, pending);
+                command
+            })
+            .collect()
+    }
+
+    /// Forgets the attempts of commands sent again, those which failed are queued by now
+    pub fn settle(&mut self) {
+        self.retrying.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene(group_id: &str, scene_id: &str) -> Command {
+        Command::Scene {
+            group_id: group_id.to_string(),
+            scene_id: scene_id.to_string(),
+            trigger_light_ids: vec!["1".to_string()],
+        }
+    }
+
+    fn off(group_id: &str) -> Command {
+        Command::GroupOff {
+            group_id: group_id.to_string(),
+        }
+    }
+
+    /// Makes everything queued due right away instead of waiting for the delays
+    fn expire(queue: &mut CommandQueue) {
+        let now = Instant::now();
+        queue
+            .pending
+            .values_mut()
+            .for_each(|pending| pending.due = now);
+        queue.checks.values_mut().for_each(|(_, due)| *due = now);
+    }
+
+    #[test]
+    fn test_retries() {
+        let secs = Duration::from_secs;
+        let mut queue = CommandQueue::default();
+
+        assert_eq!(queue.next_due(), None);
+        assert_eq!(queue.failed(scene("1", "a"), 3), Ok(secs(5)));
+        assert!(queue.next_due().is_some());
+        assert_eq!(queue.due(), vec![]);
+
+        // Attempts carry over while the same command is sent again
+        for delay in [secs(10), secs(20)] {
+            expire(&mut queue);
+            assert_eq!(queue.due(), vec![scene("1", "a")]);
+            assert_eq!(queue.failed(scene("1", "a"), 3), Ok(delay));
+            queue.settle();
+        }
+
+        expire(&mut queue);
+        assert_eq!(queue.due(), vec![scene("1", "a")]);
+        assert_eq!(queue.failed(scene("1", "a"), 3), Err(4));
+        queue.settle();
+
+        assert_eq!(queue.next_due(), None);
+        assert_eq!(queue.due(), vec![]);
+
+        // Without retries commands are given up on right away
+        assert_eq!(queue.failed(off("1"), 0), Err(1));
+        assert_eq!(queue.next_due(), None);
+    }
+
+    #[test]
+    fn test_one_per_group() {
+        let secs = Duration::from_secs;
+        let mut queue = CommandQueue::default();
+
+        assert_eq!(queue.failed(scene("1", "a"), 3), Ok(secs(5)));
+        assert_eq!(queue.failed(scene("1", "a"), 3), Ok(secs(10)));
+        assert_eq!(queue.failed(scene("2", "a"), 3), Ok(secs(5)));
+
+        // A newer command replaces the one queued for its group and starts over
+        assert_eq!(queue.failed(off("1"), 3), Ok(secs(5)));
+
+        expire(&mut queue);
+        let mut due = queue.due();
+        due.sort_by(|a, b| a.group_id().cmp(b.group_id()));
+        assert_eq!(due, vec![off("1"), scene("2", "a")]);
+        queue.settle();
+
+        // Commands going through drop what's queued for their group
+        assert_eq!(queue.failed(scene("3", "a"), 3), Ok(secs(5)));
+        queue.forget("3");
+        assert_eq!(queue.next_due(), None);
+    }
+
+    #[test]
+    fn test_checks() {
+        let mut queue = CommandQueue::default();
+        let group_off = |group_id: &str| Check::GroupOff {
+            group_id: group_id.to_string(),
+        };
+
+        queue.check_later("1", group_off("1"), Duration::from_secs(5));
+        queue.check_later("2", group_off("2"), Duration::from_secs(5));
+        assert!(queue.next_due().is_some());
+        assert_eq!(queue.due_checks(), vec![]);
+
+        queue.forget("2");
+        expire(&mut queue);
+        assert_eq!(queue.due_checks(), vec![group_off("1")]);
+        assert_eq!(queue.due_checks(), vec![]);
+        assert_eq!(queue.next_due(), None);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::solar::{MoonPhase, Season};
+use huelib2::resource::Sensor;
+use regex::Regex;
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+static REGEX_VALUES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\((?<values>.*?)\)").unwrap());
+
+static REGEX_TEMPERATURE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(?<operator>[<>])\s*(?<value>-?\d+([.,]\d+)?)\s*°?C$").unwrap());
+
+static REGEX_MODES: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^modes?\s*=\s*(?<modes>[\w-]+(\s*\|\s*[\w-]+)*)$").unwrap());
+
+static REGEX_SEASONS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^seasons?\s*=\s*(?<seasons>[a-z]+(\s*\|\s*[a-z]+)*)$").unwrap()
+});
+
+static REGEX_MOON: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^(moon\s*=\s*(?<phases>[a-z]+(\s*\|\s*[a-z]+)*)|(?<phase>full|new)\s*moon)$")
+        .unwrap()
+});
+
+/// Mode scenes without a `mode=...` condition belong to
+pub const DEFAULT_MODE: &str = "normal";
+
+static REGEX_MAX_ACTIVATIONS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^max\s*=\s*(?<value>\d+)$").unwrap());
+
+static REGEX_PRIORITY: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[p(?<value>\d+)\]").unwrap());
+
+/// Condition a scene is restricted to, written next to its time-ranges, e.g. `Cozy (18h-22h, <18°C)`
+#[derive(Clone, PartialEq, Debug)]
+pub enum Condition {
+    TemperatureBelow(f32),
+    TemperatureAbove(f32),
+    /// Only scheduled in one of these modes, e.g. `Lights on (sunset-23h, mode=vacation)`
+    Mode(Vec<String>),
+    /// Only scheduled in one of these seasons, e.g. `Garden (sunset-23h, season=summer)`
+    Season(Vec<Season>),
+    /// Only scheduled in one of these phases of the moon, e.g. `Garden (night, fullmoon)`
+    Moon(Vec<MoonPhase>),
+}
+
+/// Readings conditions are checked against
+#[derive(Clone, PartialEq, Debug)]
+pub struct Environment {
+    /// Temperature in °C
+    pub temperature: Option<f32>,
+    pub mode: String,
+    pub season: Option<Season>,
+    pub moon: Option<MoonPhase>,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Environment {
+            temperature: None,
+            mode: DEFAULT_MODE.to_string(),
+            season: None,
+            moon: None,
+        }
+    }
+}
+
+/// Extracts all conditions from a scene name
+/// # Examples
+/// ```
+/// assert_eq!(parse_conditions("Cozy (18h-22h, <18°C)"), vec![Condition::TemperatureBelow(18.0)]);
+/// assert_eq!(parse_conditions("Cool (>24.5C)"), vec![Condition::TemperatureAbove(24.5)]);
+/// assert_eq!(parse_conditions("Away (sunset-23h, mode=vacation)"), vec![Condition::Mode(vec!["vacation".to_string()])]);
+/// assert_eq!(parse_conditions("Garden (night, season=spring|summer)"), vec![Condition::Season(vec![Season::Spring, Season::Summer])]);
+/// assert_eq!(parse_conditions("Garden (night, fullmoon)"), vec![Condition::Moon(vec![MoonPhase::Full])]);
+/// ```
+pub fn parse_conditions(name: &str) -> Vec<Condition> {
+    let Some(parsed) = REGEX_VALUES.captures(name) else {
+        return vec![];
+    };
+
+    parsed["values"]
+        .split(",")
+        .filter_map(|value| {
+            if let Some(parsed) = REGEX_MODES.captures(value.trim()) {
+                return Some(Condition::Mode(
+                    parsed["modes"]
+                        .split("|")
+                        .map(|mode| mode.trim().to_lowercase())
+                        .collect(),
+                ));
+            }
+
+            if let Some(parsed) = REGEX_SEASONS.captures(value.trim()) {
+                return Some(Condition::Season(parse_list(&parsed["seasons"])?));
+            }
+
+            if let Some(parsed) = REGEX_MOON.captures(value.trim()) {
+                return Some(Condition::Moon(match parsed.name("phase") {
+                    Some(phase) => vec![phase.as_str().parse().ok()?],
+                    None => parse_list(&parsed["phases"])?,
+                }));
+            }
+
+            let parsed = REGEX_TEMPERATURE.captures(value.trim())?;
+            let temperature = parsed["value"].replace(",", ".").parse::<f32>().ok()?;
+
+            match &parsed["operator"] {
+                "<" => Some(Condition::TemperatureBelow(temperature)),
+                _ => Some(Condition::TemperatureAbove(temperature)),
+            }
+        })
+        .collect()
+}
+
+/// Parses values separated by `|`, e.g. `spring|summer`, `None` if any of them is unknown
+fn parse_list<T: FromStr>(values: &str) -> Option<Vec<T>> {
+    values
+        .split("|")
+        .map(|value| value.trim().parse::<T>().ok())
+        .collect()
+}
+
+/// Extracts how often a scene may be activated per day
+/// # Examples
+/// ```
+/// assert_eq!(parse_max_activations("Wake up (sunrise-8:30h, max=1)"), Some(1));
+/// assert_eq!(parse_max_activations("Evening (18h-22h)"), None);
+/// ```
+pub fn parse_max_activations(name: &str) -> Option<u32> {
+    let parsed = REGEX_VALUES.captures(name)?;
+
+    parsed["values"].split(",").find_map(|value| {
+        REGEX_MAX_ACTIVATIONS.captures(value.trim())?["value"]
+            .parse::<u32>()
+            .ok()
+    })
+}
+
+/// Whether a scene is marked as the fallback of its lights while nothing is scheduled for them
+/// # Examples
+/// ```
+/// assert!(is_default_scene("Cozy (default)"));
+/// assert!(!is_default_scene("Evening (18h-22h)"));
+/// ```
+pub fn is_default_scene(name: &str) -> bool {
+    REGEX_VALUES.captures(name).is_some_and(|parsed| {
+        parsed["values"]
+            .split(",")
+            .any(|value| value.trim().eq_ignore_ascii_case("default"))
+    })
+}
+
+/// Extracts the priority of a scene, e.g. `[p1]`, which wins over overlapping scenes for the same lights.
+/// Lower numbers win, scenes without a priority lose against all scenes with one.
+/// # Examples
+/// ```
+/// assert_eq!(parse_priority("Movie [p1] (20h-22h)"), Some(1));
+/// assert_eq!(parse_priority("Evening (18h-23h)"), None);
+/// ```
+pub fn parse_priority(name: &str) -> Option<u32> {
+    REGEX_PRIORITY.captures(name)?["value"].parse::<u32>().ok()
+}
+
+impl Condition {
+    /// Conditions depending on unknown readings are never met
+    pub fn is_met(&self, environment: &Environment) -> bool {
+        match self {
+            Condition::TemperatureBelow(value) => environment
+                .temperature
+                .map(|temperature| temperature < *value)
+                .unwrap_or(false),
+            Condition::TemperatureAbove(value) => environment
+                .temperature
+                .map(|temperature| temperature > *value)
+                .unwrap_or(false),
+            Condition::Mode(modes) => modes.contains(&environment.mode),
+            Condition::Season(seasons) => environment
+                .season
+                .map(|season| seasons.contains(&season))
+                .unwrap_or(false),
+            Condition::Moon(phases) => environment
+                .moon
+                .map(|phase| phases.contains(&phase))
+                .unwrap_or(false),
+        }
+    }
+
+    /// Whether checking the condition requires sensor readings
+    pub fn needs_sensors(&self) -> bool {
+        matches!(
+            self,
+            Condition::TemperatureBelow(_) | Condition::TemperatureAbove(_)
+        )
+    }
+}
+
+/// Returns the temperature of the given sensor, or the average of all reachable ones.
+/// Hue motion sensors expose their thermometer as separate `ZLLTemperature` sensor.
+pub fn get_temperature(sensors: &[Sensor], sensor_name: Option<&str>) -> Option<f32> {
+    let temperatures = sensors
+        .iter()
+        .filter(|sensor| {
+            sensor.type_name == "ZLLTemperature"
+                && sensor.config.reachable.unwrap_or(true)
+                && sensor_name.map(|name| sensor.name == name).unwrap_or(true)
+        })
+        .filter_map(|sensor| sensor.state.temperature)
+        .collect::<Vec<i32>>();
+
+    if temperatures.is_empty() {
+        return None;
+    }
+
+    // The bridge reports hundredths of a degree
+    Some(temperatures.iter().sum::<i32>() as f32 / temperatures.len() as f32 / 100.0)
+}
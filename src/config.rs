@@ -1,31 +1,441 @@
+use crate::calibration::{self, Calibration};
+use crate::conditions::DEFAULT_MODE;
+use crate::holidays::HolidayCalendar;
+#[cfg(feature = "hooks")]
+use crate::hooks::Hooks;
+use crate::logging::LogLevel;
+#[cfg(feature = "zigbee2mqtt")]
+use crate::mqtt;
+use crate::utils::{Backend, OverlapStrategy, TimeFormat, UnknownOnState, UnscheduledPolicy};
 use chrono_tz::Tz;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
 use std::env;
-use std::fs::File;
 use std::net::IpAddr;
+#[cfg(feature = "control")]
+use std::net::SocketAddr;
+use std::panic;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{Mutex, OnceLock, PoisonError};
 use std::time::Duration;
 
 #[derive(Debug)]
 pub struct Config {
     pub bridge_ip: IpAddr,
     pub bridge_username: String,
+    /// Further bridges scheduled alongside the one above, see `BRIDGES`
+    pub bridges: Vec<(IpAddr, String)>,
+    /// How long scenes and groups are cached
+    pub scene_cache_ttl: Duration,
+    /// What is scheduled instead of a hue bridge, see `BRIDGE_BACKEND`
+    pub backend: Backend,
+    /// Broker zigbee2mqtt is connected to, see `MQTT_ADDRESS`
+    #[cfg(feature = "zigbee2mqtt")]
+    pub mqtt: Option<mqtt::Options>,
+    /// Base topic of zigbee2mqtt
+    #[cfg(feature = "zigbee2mqtt")]
+    pub zigbee2mqtt_topic: String,
+    /// Address of Home Assistant, e.g. `homeassistant.local:8123`
+    #[cfg(feature = "home-assistant")]
+    pub home_assistant_address: Option<String>,
+    /// Long-lived access token for Home Assistant
+    #[cfg(feature = "home-assistant")]
+    pub home_assistant_token: Option<String>,
+    /// Commands per second sent to each bridge, `0` for no limit
+    pub bridge_rate_limit: u32,
+    /// Requests sent to each bridge at the same time when retrieving scenes and groups, see `BRIDGE_CONCURRENCY`
+    pub bridge_concurrency: usize,
+    /// After which a request to the bridge is given up on, see `BRIDGE_TIMEOUT`
+    pub bridge_timeout: Duration,
     pub ping_interval: Duration,
     pub reachability_window: Duration,
+    pub startup_grace_period: Duration,
+    /// Whether scheduled scenes are applied to lights that are on at startup
+    pub startup_sync: bool,
     pub home_timezone: Tz,
     pub home_latitude: f64,
     pub home_longitude: f64,
-    pub debug_file: Option<File>,
+    /// Opened once at startup, reloading the configuration doesn't truncate it
+    pub debug_file: Option<PathBuf>,
+    pub log_level: LogLevel,
+    pub light_calibrations: HashMap<String, Calibration>,
+    pub light_debounce: Option<Duration>,
+    pub light_debounces: HashMap<String, Duration>,
+    pub scene_verification_delay: Option<Duration>,
+    pub command_retries: u32,
+    /// Apply scenes to their reachable lights instead of waiting for all of them, see `PARTIAL_SCENES`
+    pub partial_scenes: bool,
+    pub schedule_jitter: u32,
+    pub temperature_sensor: Option<String>,
+    pub unknown_on_state: UnknownOnState,
+    pub overlap_strategy: OverlapStrategy,
+    pub arrival_rooms: Vec<String>,
+    pub arrival_time: String,
+    pub time_variables: Vec<(String, String)>,
+    pub holidays: Option<HolidayCalendar>,
+    pub presence_sensors: Option<Regex>,
+    pub overnight_auto_off: bool,
+    /// Whether lights that are on switch to the next scene once the range of theirs ends
+    pub scene_transitions: bool,
+    pub auto_off_transition: Option<Duration>,
+    pub min_scene_duration: Option<Duration>,
+    pub room_min_scene_durations: HashMap<String, Duration>,
+    pub min_on_duration: Option<Duration>,
+    pub group_min_on_durations: HashMap<String, Duration>,
+    pub unscheduled_policy: UnscheduledPolicy,
+    pub group_unscheduled_policies: HashMap<String, UnscheduledPolicy>,
+    #[cfg(feature = "hooks")]
+    pub webhook_url: Option<String>,
+    #[cfg(feature = "hooks")]
+    pub hooks: Hooks,
+    /// Names of plugins to start, see `PLUGINS`
+    pub plugins: Vec<String>,
+    pub state_file: Option<PathBuf>,
+    pub schedules_file: Option<PathBuf>,
+    pub named_schedules: HashMap<String, String>,
+    pub safe_mode_restarts: usize,
+    /// Whether commands are only logged instead of sent, see `DRY_RUN`
+    pub dry_run: bool,
+    pub safe_mode_window: Duration,
+    pub group_lock_ttl: Option<Duration>,
+    pub lock_holder: String,
+    #[cfg(feature = "clip-v2")]
+    pub smart_scenes: bool,
+    #[cfg(feature = "clip-v2")]
+    pub bridge_api_v2: bool,
+    /// Whether the certificate of the bridge is checked, see `BRIDGE_TLS_VERIFY`
+    #[cfg(feature = "clip-v2")]
+    pub bridge_tls_verify: bool,
+    #[cfg(feature = "clip-v2")]
+    pub bridge_ca_file: Option<PathBuf>,
+    /// Id the certificate of `bridge_ip` must be issued for, see `BRIDGE_ID`
+    #[cfg(feature = "clip-v2")]
+    pub bridge_id: Option<String>,
+    #[cfg(feature = "clip-v2")]
+    pub scene_preferences_file: Option<PathBuf>,
+    pub managed_group_types: Option<Vec<String>>,
+    pub managed_groups: Option<Regex>,
+    pub ignored_groups: Option<Regex>,
+    pub manual_scenes: Option<Regex>,
+    pub mode: String,
+    /// Name of the home this configuration belongs to, see `PROFILES`
+    pub profile: Option<String>,
+    /// File the variables of the home are read from, it's watched for changes like the .env file
+    pub profile_file: Option<PathBuf>,
+    #[cfg(feature = "control")]
+    pub control_address: Option<SocketAddr>,
+    #[cfg(feature = "control")]
+    pub control_socket: Option<PathBuf>,
 }
 
-pub fn load_config() -> Config {
-    if dotenv::dotenv().is_err() {
-        println!("No .env file found");
+/// The .env file that was loaded, it's watched for changes
+static ENV_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Variables taken from the .env file, the ones set by the environment itself win
+static ENV_FILE_KEYS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+pub fn load_env() {
+    let inherited = env::vars_os()
+        .map(|(key, _)| key.to_string_lossy().to_string())
+        .collect::<HashSet<String>>();
+
+    match dotenv::dotenv() {
+        Ok(path) => {
+            *ENV_FILE_KEYS.lock().unwrap_or_else(PoisonError::into_inner) = read_env_file(&path)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(key, _)| key)
+                .filter(|key| !inherited.contains(key))
+                .collect();
+
+            let _ = ENV_FILE.set(path);
+        }
+        Err(_) => println!("No .env file found"),
+    }
+}
+
+/// Variables of a .env file without applying them, what dotenv suggests instead can't do that
+#[allow(deprecated)]
+fn read_env_file(path: &Path) -> Result<Vec<(String, String)>, dotenv::Error> {
+    dotenv::from_path_iter(path)?.collect()
+}
+
+pub fn env_file() -> Option<&'static Path> {
+    ENV_FILE.get().map(PathBuf::as_path)
+}
+
+/// Reads the .env file again, variables removed from it are removed from the environment as well
+fn reload_env() -> Result<(), String> {
+    let Some(path) = ENV_FILE.get() else {
+        return Ok(());
+    };
+
+    let variables = read_env_file(path).map_err(|err| err.to_string())?;
+
+    let mut keys = ENV_FILE_KEYS.lock().unwrap_or_else(PoisonError::into_inner);
+
+    for key in keys.iter() {
+        if !variables.iter().any(|(name, _)| name == key) {
+            env::remove_var(key);
+        }
+    }
+
+    *keys = variables
+        .into_iter()
+        .filter(|(key, _)| keys.contains(key) || env::var_os(key).is_none())
+        .map(|(key, value)| {
+            env::set_var(&key, value);
+            key
+        })
+        .collect();
+
+    Ok(())
+}
+
+/// Variables that apply to the whole process, they can only be set in the .env file and not per profile
+const PROCESS_VARIABLES: [&str; 5] = [
+    "CONTROL_ADDRESS",
+    "CONTROL_SOCKET",
+    "LOG_LEVEL",
+    "BRIDGE_TIMEOUT",
+    "PROFILES",
+];
+
+/// Serializes loading profiles, their variables are put into the environment while they're read
+static PROFILE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Loads the configuration again after the .env file or the file of `profile` changed, a broken one is reported and ignored
+pub fn reload(profile: Option<&str>) -> Option<Config> {
+    let _lock = PROFILE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+
+    if let Err(err) = reload_env() {
+        eprintln!("Failed to read the .env file again: {}", err);
+        return None;
+    }
+
+    // Invalid values panic with the reason, it's printed by the panic hook
+    panic::catch_unwind(|| match profile {
+        None => Some(load_config()),
+        Some(name) => profile_files()
+            .into_iter()
+            .find(|path| profile_name(path) == name)
+            .map(|path| load_profile(&path)),
+    })
+    .inspect_err(|_| eprintln!("Failed to reload the configuration, keeping the current one"))
+    .ok()
+    .flatten()
+}
+
+/// Files listed in `PROFILES`
+fn profile_files() -> Vec<PathBuf> {
+    optional_env("PROFILES")
+        .unwrap_or_default()
+        .split(";")
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Homes are named after their file, e.g. `parents` for `/etc/hue-scheduler/parents.env`
+fn profile_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Configuration of a single home, its variables win over the ones of the .env file and the environment
+fn load_profile(path: &Path) -> Config {
+    let variables = read_env_file(path)
+        .unwrap_or_else(|err| panic!("failed to read profile {}: {}", path.display(), err));
+
+    if let Some((key, _)) = variables
+        .iter()
+        .find(|(key, _)| PROCESS_VARIABLES.contains(&key.as_str()))
+    {
+        panic!(
+            "{} can't be set in profile {}, it applies to all homes and belongs in the .env file",
+            key,
+            path.display()
+        );
+    }
+
+    let mut conf = load_with(&variables);
+    conf.profile = Some(profile_name(path));
+    conf.profile_file = Some(path.to_path_buf());
+    conf
+}
+
+/// Loads the configuration with `variables` put into the environment, it's restored afterwards
+fn load_with(variables: &[(String, String)]) -> Config {
+    let previous = variables
+        .iter()
+        .map(|(key, _)| (key.clone(), env::var_os(key)))
+        .collect::<Vec<_>>();
+
+    for (key, value) in variables.iter() {
+        env::set_var(key, value);
     }
 
-    let bridge_username = env::var("BRIDGE_USERNAME").expect("BRIDGE_USERNAME missing");
-    let bridge_raw_addr = env::var("BRIDGE_IP").expect("BRIDGE_IP missing");
+    let conf = panic::catch_unwind(load_config);
+
+    for (key, value) in previous {
+        match value {
+            Some(value) => env::set_var(key, value),
+            None => env::remove_var(key),
+        }
+    }
+
+    conf.unwrap_or_else(|payload| panic::resume_unwind(payload))
+}
+
+/// Utility function for tests to load a configuration, variables every configuration needs are filled in
+#[cfg(test)]
+pub(crate) fn load_test_config(variables: &[(&str, &str)]) -> Config {
+    let _lock = PROFILE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+
+    load_with(
+        &[
+            ("BRIDGE_IP", "127.0.0.1"),
+            ("BRIDGE_USERNAME", "test"),
+            ("PING_INTERVAL", "1000"),
+            ("REACHABILITY_WINDOW", "5000"),
+            ("HOME_LATITUDE", "52.52"),
+            ("HOME_LONGITUDE", "13.40"),
+            ("HOME_TIMEZONE", "Europe/Berlin"),
+        ]
+        .iter()
+        .chain(variables)
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect::<Vec<_>>(),
+    )
+}
+
+/// Configurations of the homes listed in `PROFILES`, each named after its file.
+/// Variables of a profile win over the ones of the .env file and the environment.
+pub fn load_profiles() -> Vec<Config> {
+    let _lock = PROFILE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+
+    profile_files()
+        .iter()
+        .map(|path| load_profile(path))
+        .collect()
+}
+
+/// Returns the value of an optional variable, empty values are treated as missing
+fn optional_env(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|value| !value.is_empty())
+}
+
+/// Warns about options of subsystems that weren't compiled in
+#[cfg(not(all(
+    feature = "clip-v2",
+    feature = "control",
+    feature = "zigbee2mqtt",
+    feature = "home-assistant",
+    feature = "hooks"
+)))]
+fn warn_unsupported(key: &str, feature: &str) {
+    if optional_env(key).is_some() {
+        eprintln!(
+            "{} is ignored, hue-scheduler was built without the {} feature",
+            key, feature
+        );
+    }
+}
+
+/// Address of the control server, shared by the daemon and the cli commands
+#[cfg(feature = "control")]
+pub fn load_control_address() -> Option<SocketAddr> {
+    optional_env("CONTROL_ADDRESS").map(|address| {
+        SocketAddr::from_str(address.as_str()).expect("failed to parse CONTROL_ADDRESS")
+    })
+}
+
+/// Path of the control unix socket, shared by the daemon and the cli commands
+#[cfg(feature = "control")]
+pub fn load_control_socket() -> Option<PathBuf> {
+    optional_env("CONTROL_SOCKET").map(PathBuf::from)
+}
+
+/// Whether the certificate of the bridge is checked, shared by the daemon and `pair`
+#[cfg(feature = "clip-v2")]
+pub fn load_bridge_tls_verify() -> bool {
+    optional_env("BRIDGE_TLS_VERIFY")
+        .map(|value| {
+            value
+                .parse::<bool>()
+                .expect("failed to parse BRIDGE_TLS_VERIFY")
+        })
+        .unwrap_or(true)
+}
+
+/// Root certificate the certificate of the bridge must be signed with, shared by the daemon and `pair`
+#[cfg(feature = "clip-v2")]
+pub fn load_bridge_ca_file() -> Option<PathBuf> {
+    optional_env("BRIDGE_CA_FILE").map(PathBuf::from)
+}
+
+/// Id the certificate of the bridge must be issued for, shared by the daemon and `pair`
+#[cfg(feature = "clip-v2")]
+pub fn load_bridge_id() -> Option<String> {
+    optional_env("BRIDGE_ID").map(|id| id.trim().to_lowercase())
+}
+
+/// Timezone of the home, the cli commands show times in it if it's set
+pub fn load_home_timezone() -> Option<Tz> {
+    optional_env("HOME_TIMEZONE")
+        .map(|value| value.parse::<Tz>().expect("failed to parse HOME_TIMEZONE"))
+}
+
+/// Whether times are shown with a 24-hour or 12-hour clock, shared by the daemon and the cli commands
+pub fn load_time_format() -> TimeFormat {
+    optional_env("TIME_FORMAT")
+        .map(|value| {
+            value
+                .parse::<TimeFormat>()
+                .expect("failed to parse TIME_FORMAT")
+        })
+        .unwrap_or(TimeFormat::H24)
+}
+
+pub fn load_config() -> Config {
+    let backend = optional_env("BRIDGE_BACKEND")
+        .map(|value| {
+            value
+                .parse::<Backend>()
+                .expect("failed to parse BRIDGE_BACKEND, expected hue, deconz, zigbee2mqtt or homeassistant")
+        })
+        .unwrap_or(Backend::Hue);
+
+    // zigbee2mqtt and Home Assistant are reached without a bridge
+    let bridge = match backend.is_bridge() {
+        false => (
+            optional_env("BRIDGE_USERNAME").unwrap_or_default(),
+            optional_env("BRIDGE_IP").unwrap_or("127.0.0.1".to_string()),
+        ),
+        true => (
+            env::var("BRIDGE_USERNAME").expect("BRIDGE_USERNAME missing"),
+            env::var("BRIDGE_IP").expect("BRIDGE_IP missing"),
+        ),
+    };
+
+    let (bridge_username, bridge_raw_addr) = bridge;
     let bridge_ip = IpAddr::from_str(bridge_raw_addr.as_str()).expect("failed to parse BRIDGE_IP");
 
+    let bridges = optional_env("BRIDGES")
+        .unwrap_or_default()
+        .split(";")
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let (ip, username) = entry.split_once("=").expect("failed to parse BRIDGES");
+            let ip = IpAddr::from_str(ip.trim()).expect("failed to parse BRIDGES");
+
+            (ip, username.trim().to_string())
+        })
+        .collect::<Vec<(IpAddr, String)>>();
+
     let ping_interval = Duration::from_millis(
         env::var("PING_INTERVAL")
             .expect("PING_INTERVAL missing")
@@ -33,6 +443,16 @@ pub fn load_config() -> Config {
             .expect("failed to parse INTERVAL"),
     );
 
+    let bridge_timeout = Duration::from_millis(
+        optional_env("BRIDGE_TIMEOUT")
+            .map(|value| {
+                value
+                    .parse::<u64>()
+                    .expect("failed to parse BRIDGE_TIMEOUT")
+            })
+            .unwrap_or(10000),
+    );
+
     let reachability_window = Duration::from_millis(
         env::var("REACHABILITY_WINDOW")
             .expect("REACHABILITY_WINDOW missing")
@@ -40,6 +460,20 @@ pub fn load_config() -> Config {
             .expect("failed to parse REACHABILITY_WINDOW"),
     );
 
+    let startup_grace_period = Duration::from_millis(
+        optional_env("STARTUP_GRACE_PERIOD")
+            .map(|value| {
+                value
+                    .parse::<u64>()
+                    .expect("failed to parse STARTUP_GRACE_PERIOD")
+            })
+            .unwrap_or(0),
+    );
+
+    let startup_sync = optional_env("STARTUP_SYNC")
+        .map(|value| value.parse::<bool>().expect("failed to parse STARTUP_SYNC"))
+        .unwrap_or(false);
+
     let home_latitude = env::var("HOME_LATITUDE")
         .expect("HOME_LATITUDE missing")
         .parse::<f64>()
@@ -50,29 +484,650 @@ pub fn load_config() -> Config {
         .parse::<f64>()
         .expect("failed to parse HOME_LONGITUDE");
 
-    let home_timezone = env::var("HOME_TIMEZONE")
-        .expect("HOME_TIMEZONE missing")
-        .parse::<Tz>()
-        .expect("failed to parse HOME_TIMEZONE");
-
-    let debug_file = env::var("DEBUG_FILE")
-        .map(|path| {
-            if path.is_empty() {
-                None
-            } else {
-                Some(File::create(path).expect("failed to create debug file"))
+    let home_timezone = load_home_timezone().expect("HOME_TIMEZONE missing");
+
+    let debug_file = optional_env("DEBUG_FILE").map(PathBuf::from);
+
+    let log_level = optional_env("LOG_LEVEL")
+        .map(|value| {
+            value
+                .parse::<LogLevel>()
+                .expect("failed to parse LOG_LEVEL")
+        })
+        .unwrap_or(LogLevel::Info);
+
+    let light_calibrations = optional_env("LIGHT_CALIBRATION")
+        .map(|value| {
+            calibration::parse_calibrations(&value).expect("failed to parse LIGHT_CALIBRATION")
+        })
+        .unwrap_or_default();
+
+    // Entries without a light apply to all lights not listed
+    let mut light_debounce = None;
+    let mut light_debounces = HashMap::new();
+
+    for entry in optional_env("LIGHT_DEBOUNCE")
+        .unwrap_or_default()
+        .split(";")
+        .filter(|entry| !entry.trim().is_empty())
+    {
+        let (light, millis) = match entry.rsplit_once(":") {
+            Some((light, millis)) => (Some(light.trim()), millis),
+            None => (None, entry),
+        };
+
+        let duration = Duration::from_millis(
+            millis
+                .trim()
+                .parse::<u64>()
+                .expect("failed to parse LIGHT_DEBOUNCE"),
+        );
+
+        match light {
+            Some(light) => {
+                light_debounces.insert(light.to_string(), duration);
+            }
+            None => light_debounce = Some(duration),
+        }
+    }
+
+    let scene_verification_delay = optional_env("SCENE_VERIFICATION_DELAY")
+        .map(|value| {
+            value
+                .parse::<u64>()
+                .expect("failed to parse SCENE_VERIFICATION_DELAY")
+        })
+        .map(Duration::from_millis)
+        .or(Some(Duration::from_secs(2)))
+        .filter(|delay| !delay.is_zero());
+
+    let command_retries = optional_env("COMMAND_RETRIES")
+        .map(|value| {
+            value
+                .parse::<u32>()
+                .expect("failed to parse COMMAND_RETRIES")
+        })
+        .unwrap_or(3);
+
+    let partial_scenes = optional_env("PARTIAL_SCENES")
+        .map(|value| {
+            value
+                .parse::<bool>()
+                .expect("failed to parse PARTIAL_SCENES")
+        })
+        .unwrap_or(false);
+
+    let dry_run = optional_env("DRY_RUN")
+        .map(|value| value.parse::<bool>().expect("failed to parse DRY_RUN"))
+        .unwrap_or(false);
+
+    let safe_mode_restarts = optional_env("SAFE_MODE_RESTARTS")
+        .map(|value| {
+            value
+                .parse::<usize>()
+                .expect("failed to parse SAFE_MODE_RESTARTS")
+        })
+        .unwrap_or(5);
+
+    let safe_mode_window = Duration::from_millis(
+        optional_env("SAFE_MODE_WINDOW")
+            .map(|value| {
+                value
+                    .parse::<u64>()
+                    .expect("failed to parse SAFE_MODE_WINDOW")
+            })
+            .unwrap_or(10 * 60 * 1000),
+    );
+
+    let unknown_on_state = optional_env("UNKNOWN_ON_STATE")
+        .map(|value| {
+            value
+                .parse::<UnknownOnState>()
+                .expect("failed to parse UNKNOWN_ON_STATE")
+        })
+        .unwrap_or(UnknownOnState::On);
+
+    let overlap_strategy = optional_env("OVERLAP_STRATEGY")
+        .map(|value| {
+            value
+                .parse::<OverlapStrategy>()
+                .expect("failed to parse OVERLAP_STRATEGY")
+        })
+        .unwrap_or(OverlapStrategy::Latest);
+
+    let arrival_rooms = optional_env("ARRIVAL_ROOMS")
+        .map(|value| {
+            value
+                .split(",")
+                .map(|room| room.trim().to_string())
+                .collect::<Vec<String>>()
+        })
+        .unwrap_or_default();
+
+    let presence_sensors = optional_env("PRESENCE_SENSORS")
+        .map(|value| Regex::new(&value).expect("failed to parse PRESENCE_SENSORS"));
+
+    let schedule_jitter = optional_env("SCHEDULE_JITTER")
+        .map(|value| {
+            value
+                .parse::<u32>()
+                .expect("failed to parse SCHEDULE_JITTER")
+        })
+        .unwrap_or(0);
+
+    #[cfg(not(feature = "clip-v2"))]
+    {
+        warn_unsupported("SMART_SCENES", "clip-v2");
+        warn_unsupported("BRIDGE_API", "clip-v2");
+        warn_unsupported("BRIDGE_TLS_VERIFY", "clip-v2");
+        warn_unsupported("BRIDGE_CA_FILE", "clip-v2");
+        warn_unsupported("BRIDGE_ID", "clip-v2");
+        warn_unsupported("SCENE_PREFERENCES_FILE", "clip-v2");
+    }
+
+    #[cfg(not(feature = "control"))]
+    {
+        warn_unsupported("CONTROL_ADDRESS", "control");
+        warn_unsupported("CONTROL_SOCKET", "control");
+    }
+
+    #[cfg(not(feature = "zigbee2mqtt"))]
+    {
+        warn_unsupported("MQTT_ADDRESS", "zigbee2mqtt");
+        warn_unsupported("MQTT_CLIENT_ID", "zigbee2mqtt");
+        warn_unsupported("MQTT_USERNAME", "zigbee2mqtt");
+        warn_unsupported("MQTT_PASSWORD", "zigbee2mqtt");
+        warn_unsupported("ZIGBEE2MQTT_TOPIC", "zigbee2mqtt");
+    }
+
+    #[cfg(not(feature = "home-assistant"))]
+    {
+        warn_unsupported("HOME_ASSISTANT_ADDRESS", "home-assistant");
+        warn_unsupported("HOME_ASSISTANT_TOKEN", "home-assistant");
+    }
+
+    #[cfg(not(feature = "hooks"))]
+    {
+        warn_unsupported("WEBHOOK_URL", "hooks");
+        warn_unsupported("ON_SCENE_APPLIED", "hooks");
+        warn_unsupported("ON_GROUP_OFF", "hooks");
+    }
+
+    let scene_cache_ttl = Duration::from_secs(
+        optional_env("SCENE_CACHE_TTL")
+            .map(|value| {
+                value
+                    .parse::<u64>()
+                    .expect("failed to parse SCENE_CACHE_TTL")
+            })
+            .unwrap_or(60),
+    );
+
+    #[cfg(feature = "zigbee2mqtt")]
+    let mqtt = optional_env("MQTT_ADDRESS").map(|address| mqtt::Options {
+        address,
+        client_id: optional_env("MQTT_CLIENT_ID").unwrap_or("hue-scheduler".to_string()),
+        username: optional_env("MQTT_USERNAME"),
+        password: optional_env("MQTT_PASSWORD"),
+    });
+
+    #[cfg(feature = "zigbee2mqtt")]
+    if backend == Backend::Zigbee2Mqtt && mqtt.is_none() {
+        panic!("MQTT_ADDRESS missing");
+    }
+
+    // MQTT 3.1.1 only allows a password together with a username
+    #[cfg(feature = "zigbee2mqtt")]
+    if mqtt
+        .as_ref()
+        .is_some_and(|mqtt| mqtt.password.is_some() && mqtt.username.is_none())
+    {
+        panic!("MQTT_PASSWORD requires MQTT_USERNAME");
+    }
+
+    #[cfg(feature = "home-assistant")]
+    let home_assistant_address = optional_env("HOME_ASSISTANT_ADDRESS");
+    #[cfg(feature = "home-assistant")]
+    let home_assistant_token = optional_env("HOME_ASSISTANT_TOKEN");
+
+    #[cfg(feature = "home-assistant")]
+    if backend == Backend::HomeAssistant {
+        home_assistant_address
+            .as_ref()
+            .expect("HOME_ASSISTANT_ADDRESS missing");
+        home_assistant_token
+            .as_ref()
+            .expect("HOME_ASSISTANT_TOKEN missing");
+    }
+
+    let bridge_rate_limit = optional_env("BRIDGE_RATE_LIMIT")
+        .map(|value| {
+            value
+                .parse::<u32>()
+                .expect("failed to parse BRIDGE_RATE_LIMIT")
+        })
+        .unwrap_or(10);
+
+    let bridge_concurrency = optional_env("BRIDGE_CONCURRENCY")
+        .map(|value| {
+            value
+                .parse::<usize>()
+                .expect("failed to parse BRIDGE_CONCURRENCY")
+        })
+        .unwrap_or(3)
+        .max(1);
+
+    let group_lock_ttl = optional_env("GROUP_LOCK_TTL")
+        .map(|value| {
+            value
+                .parse::<u64>()
+                .expect("failed to parse GROUP_LOCK_TTL")
+        })
+        .map(Duration::from_secs)
+        .filter(|ttl| !ttl.is_zero());
+
+    let overnight_auto_off = optional_env("OVERNIGHT_AUTO_OFF")
+        .map(|value| {
+            value
+                .parse::<bool>()
+                .expect("failed to parse OVERNIGHT_AUTO_OFF")
+        })
+        .unwrap_or(false);
+
+    let scene_transitions = optional_env("SCENE_TRANSITIONS")
+        .map(|value| {
+            value
+                .parse::<bool>()
+                .expect("failed to parse SCENE_TRANSITIONS")
+        })
+        .unwrap_or(false);
+
+    let auto_off_transition = optional_env("AUTO_OFF_TRANSITION")
+        .map(|value| {
+            value
+                .parse::<u64>()
+                .expect("failed to parse AUTO_OFF_TRANSITION")
+        })
+        .map(Duration::from_secs)
+        .filter(|transition| !transition.is_zero());
+
+    // Entries without a room apply to all rooms not listed
+    let mut min_scene_duration = None;
+    let mut room_min_scene_durations = HashMap::new();
+
+    for entry in optional_env("MIN_SCENE_DURATION")
+        .unwrap_or_default()
+        .split(";")
+        .filter(|entry| !entry.trim().is_empty())
+    {
+        let (room, minutes) = match entry.rsplit_once(":") {
+            Some((room, minutes)) => (Some(room.trim()), minutes),
+            None => (None, entry),
+        };
+
+        let duration = Duration::from_secs(
+            minutes
+                .trim()
+                .parse::<u64>()
+                .expect("failed to parse MIN_SCENE_DURATION")
+                * 60,
+        );
+
+        match room {
+            Some(room) => {
+                room_min_scene_durations.insert(room.to_string(), duration);
             }
+            None => min_scene_duration = Some(duration),
+        }
+    }
+
+    // Entries without a group apply to all groups not listed
+    let mut min_on_duration = None;
+    let mut group_min_on_durations = HashMap::new();
+
+    for entry in optional_env("MIN_ON_DURATION")
+        .unwrap_or_default()
+        .split(";")
+        .filter(|entry| !entry.trim().is_empty())
+    {
+        let (group, seconds) = match entry.rsplit_once(":") {
+            Some((group, seconds)) => (Some(group.trim()), seconds),
+            None => (None, entry),
+        };
+
+        let duration = Duration::from_secs(
+            seconds
+                .trim()
+                .parse::<u64>()
+                .expect("failed to parse MIN_ON_DURATION"),
+        );
+
+        match group {
+            Some(group) => {
+                group_min_on_durations.insert(group.to_string(), duration);
+            }
+            None => min_on_duration = Some(duration),
+        }
+    }
+
+    // Values are resolved by the parser, they may refer to sunrise, sunset or variables defined before them
+    let time_variables = optional_env("TIME_VARIABLES")
+        .unwrap_or_default()
+        .split(";")
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let (name, value) = entry
+                .split_once("=")
+                .expect("failed to parse TIME_VARIABLES");
+            let name = name.trim();
+
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_lowercase() || c == '_') {
+                panic!(
+                    "failed to parse TIME_VARIABLES, names may only contain a-z and _: {}",
+                    name
+                );
+            }
+
+            (name.to_string(), value.trim().to_string())
         })
-        .unwrap_or(None);
+        .collect::<Vec<(String, String)>>();
+
+    // Values are inserted into scene names as they are, they're parsed along with the rest of the name
+    let named_schedules = optional_env("NAMED_SCHEDULES")
+        .unwrap_or_default()
+        .split(";")
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let (name, value) = entry
+                .split_once("=")
+                .expect("failed to parse NAMED_SCHEDULES");
+            let name = name.trim();
+
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_lowercase() || c == '_') {
+                panic!(
+                    "failed to parse NAMED_SCHEDULES, names may only contain a-z and _: {}",
+                    name
+                );
+            }
+
+            (name.to_string(), value.trim().to_string())
+        })
+        .collect::<HashMap<String, String>>();
+
+    // Entries without a group apply to all groups not listed
+    let mut unscheduled_policy = UnscheduledPolicy::Ignore;
+    let mut group_unscheduled_policies = HashMap::new();
+
+    for entry in optional_env("UNSCHEDULED_POLICY")
+        .unwrap_or_default()
+        .split(";")
+        .filter(|entry| !entry.trim().is_empty())
+    {
+        let (group, policy) = match entry.split_once(":") {
+            Some((group, policy)) => (Some(group.trim()), policy),
+            None => (None, entry),
+        };
+
+        let policy = policy
+            .parse::<UnscheduledPolicy>()
+            .expect("failed to parse UNSCHEDULED_POLICY");
+
+        match group {
+            Some(group) => {
+                group_unscheduled_policies.insert(group.to_string(), policy);
+            }
+            None => unscheduled_policy = policy,
+        }
+    }
+
+    #[cfg(feature = "clip-v2")]
+    let smart_scenes = optional_env("SMART_SCENES")
+        .map(|value| value.parse::<bool>().expect("failed to parse SMART_SCENES"))
+        .unwrap_or(false);
+
+    #[cfg(feature = "clip-v2")]
+    let bridge_api_v2 = match optional_env("BRIDGE_API").as_deref() {
+        None | Some("v1") => false,
+        Some("v2") => true,
+        Some(value) => panic!("failed to parse BRIDGE_API, expected v1 or v2: {}", value),
+    };
+
+    // deCONZ only offers an api similar to v1 of the hue bridge, zigbee2mqtt none at all
+    #[cfg(feature = "clip-v2")]
+    if backend != Backend::Hue
+        && (bridge_api_v2 || smart_scenes || optional_env("SCENE_PREFERENCES_FILE").is_some())
+    {
+        panic!(
+            "BRIDGE_API=v2, SMART_SCENES and SCENE_PREFERENCES_FILE aren't supported by {}",
+            backend
+        );
+    }
+
+    // Presence is read from the sensors of a bridge, both only know a single broker or instance
+    if !backend.is_bridge() && (!arrival_rooms.is_empty() || !bridges.is_empty()) {
+        panic!("ARRIVAL_ROOMS and BRIDGES aren't supported by {}", backend);
+    }
+
+    let managed_group_types = optional_env("MANAGED_GROUP_TYPES").map(|value| {
+        value
+            .split(",")
+            .map(|kind| kind.trim().to_string())
+            .collect::<Vec<String>>()
+    });
+
+    let managed_groups = optional_env("MANAGED_GROUPS")
+        .map(|value| Regex::new(&value).expect("failed to parse MANAGED_GROUPS"));
+
+    let ignored_groups = optional_env("IGNORED_GROUPS")
+        .map(|value| Regex::new(&value).expect("failed to parse IGNORED_GROUPS"));
+
+    let manual_scenes = optional_env("MANUAL_SCENES")
+        .map(|value| Regex::new(&value).expect("failed to parse MANUAL_SCENES"));
 
     Config {
         bridge_ip,
         bridge_username,
+        bridges,
+        scene_cache_ttl,
+        backend,
+        #[cfg(feature = "zigbee2mqtt")]
+        mqtt,
+        #[cfg(feature = "zigbee2mqtt")]
+        zigbee2mqtt_topic: optional_env("ZIGBEE2MQTT_TOPIC").unwrap_or("zigbee2mqtt".to_string()),
+        #[cfg(feature = "home-assistant")]
+        home_assistant_address,
+        #[cfg(feature = "home-assistant")]
+        home_assistant_token,
+        bridge_rate_limit,
+        bridge_concurrency,
+        bridge_timeout,
         ping_interval,
         reachability_window,
+        startup_grace_period,
+        startup_sync,
         home_timezone,
         home_latitude,
         home_longitude,
         debug_file,
+        log_level,
+        light_calibrations,
+        light_debounce,
+        light_debounces,
+        scene_verification_delay,
+        command_retries,
+        partial_scenes,
+        schedule_jitter,
+        temperature_sensor: optional_env("TEMPERATURE_SENSOR"),
+        unknown_on_state,
+        overlap_strategy,
+        arrival_rooms,
+        arrival_time: optional_env("ARRIVAL_TIME").unwrap_or("night".to_string()),
+        time_variables,
+        holidays: optional_env("HOLIDAYS").map(|value| {
+            value
+                .parse::<HolidayCalendar>()
+                .expect("failed to parse HOLIDAYS")
+        }),
+        presence_sensors,
+        overnight_auto_off,
+        scene_transitions,
+        auto_off_transition,
+        min_scene_duration,
+        room_min_scene_durations,
+        min_on_duration,
+        group_min_on_durations,
+        unscheduled_policy,
+        group_unscheduled_policies,
+        #[cfg(feature = "hooks")]
+        webhook_url: optional_env("WEBHOOK_URL"),
+        #[cfg(feature = "hooks")]
+        hooks: Hooks {
+            on_scene_applied: optional_env("ON_SCENE_APPLIED"),
+            on_group_off: optional_env("ON_GROUP_OFF"),
+        },
+        plugins: optional_env("PLUGINS")
+            .map(|value| {
+                value
+                    .split(",")
+                    .map(|name| name.trim().to_string())
+                    .filter(|name| !name.is_empty())
+                    .collect::<Vec<String>>()
+            })
+            .unwrap_or_default(),
+        state_file: optional_env("STATE_FILE").map(PathBuf::from),
+        schedules_file: optional_env("SCHEDULES_FILE").map(PathBuf::from),
+        named_schedules,
+        safe_mode_restarts,
+        dry_run,
+        safe_mode_window,
+        group_lock_ttl,
+        lock_holder: optional_env("LOCK_HOLDER").unwrap_or("hue-scheduler".to_string()),
+        #[cfg(feature = "clip-v2")]
+        smart_scenes,
+        #[cfg(feature = "clip-v2")]
+        bridge_api_v2,
+        #[cfg(feature = "clip-v2")]
+        bridge_tls_verify: load_bridge_tls_verify(),
+        #[cfg(feature = "clip-v2")]
+        bridge_ca_file: load_bridge_ca_file(),
+        #[cfg(feature = "clip-v2")]
+        bridge_id: load_bridge_id(),
+        #[cfg(feature = "clip-v2")]
+        scene_preferences_file: optional_env("SCENE_PREFERENCES_FILE").map(PathBuf::from),
+        managed_group_types,
+        managed_groups,
+        ignored_groups,
+        manual_scenes,
+        mode: optional_env("MODE")
+            .map(|mode| mode.trim().to_lowercase())
+            .unwrap_or(DEFAULT_MODE.to_string()),
+        profile: None,
+        profile_file: None,
+        #[cfg(feature = "control")]
+        control_address: load_control_address(),
+        #[cfg(feature = "control")]
+        control_socket: load_control_socket(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::panic_message;
+    use std::fs;
+
+    /// Returns why a configuration with the given variables is rejected
+    fn error(variables: &[(&str, &str)]) -> String {
+        panic::catch_unwind(|| load_test_config(variables))
+            .map(|_| "accepted".to_string())
+            .unwrap_or_else(|payload| panic_message(payload.as_ref()))
+    }
+
+    #[test]
+    fn test_load_config() {
+        let conf = load_test_config(&[
+            ("MIN_ON_DURATION", "30; Hallway: 120"),
+            ("MIN_SCENE_DURATION", "Living room:10"),
+            ("TIME_VARIABLES", "dinner=18:30h; bedtime=dinner+4h"),
+            ("UNKNOWN_ON_STATE", "ignore"),
+        ]);
+
+        assert_eq!(conf.bridge_ip, IpAddr::from_str("127.0.0.1").unwrap());
+        assert_eq!(conf.min_on_duration, Some(Duration::from_secs(30)));
+        assert_eq!(
+            conf.group_min_on_durations,
+            HashMap::from([("Hallway".to_string(), Duration::from_secs(120))])
+        );
+        assert_eq!(conf.min_scene_duration, None);
+        assert_eq!(
+            conf.room_min_scene_durations,
+            HashMap::from([("Living room".to_string(), Duration::from_secs(600))])
+        );
+        assert_eq!(
+            conf.time_variables,
+            vec![
+                ("dinner".to_string(), "18:30h".to_string()),
+                ("bedtime".to_string(), "dinner+4h".to_string())
+            ]
+        );
+        assert_eq!(conf.unknown_on_state, UnknownOnState::Ignore);
+        assert!(!conf.scene_transitions);
+
+        // Variables are only set while the configuration is read
+        assert!(optional_env("MIN_ON_DURATION").is_none());
+    }
+
+    #[test]
+    fn test_validation() {
+        assert_eq!(error(&[("HOME_TIMEZONE", "")]), "HOME_TIMEZONE missing");
+        assert!(error(&[("BRIDGE_IP", "bridge")]).starts_with("failed to parse BRIDGE_IP"));
+        assert!(error(&[("MIN_ON_DURATION", "Hallway:soon")])
+            .starts_with("failed to parse MIN_ON_DURATION"));
+        assert!(
+            error(&[("UNKNOWN_ON_STATE", "maybe")]).starts_with("failed to parse UNKNOWN_ON_STATE")
+        );
+        assert_eq!(
+            error(&[("TIME_VARIABLES", "Bedtime=22h")]),
+            "failed to parse TIME_VARIABLES, names may only contain a-z and _: Bedtime"
+        );
+        assert!(error(&[("NAMED_SCHEDULES", "weekdays")])
+            .starts_with("failed to parse NAMED_SCHEDULES"));
+
+        #[cfg(feature = "clip-v2")]
+        assert_eq!(
+            error(&[("BRIDGE_API", "v3")]),
+            "failed to parse BRIDGE_API, expected v1 or v2: v3"
+        );
+
+        #[cfg(feature = "zigbee2mqtt")]
+        assert_eq!(
+            error(&[
+                ("BRIDGE_BACKEND", "zigbee2mqtt"),
+                ("MQTT_ADDRESS", "localhost:1883"),
+                ("ARRIVAL_ROOMS", "Hallway")
+            ]),
+            "ARRIVAL_ROOMS and BRIDGES aren't supported by zigbee2mqtt"
+        );
+    }
+
+    #[test]
+    fn test_profile_validation() {
+        let path = env::temp_dir().join(format!("hue-scheduler-{}.env", std::process::id()));
+        fs::write(&path, "HOME_TIMEZONE=Europe/Paris\nLOG_LEVEL=debug\n").unwrap();
+
+        // Variables of the whole process can't differ between homes
+        let message = panic::catch_unwind(|| load_profile(&path))
+            .map(|_| "accepted".to_string())
+            .unwrap_or_else(|payload| panic_message(payload.as_ref()));
+
+        assert_eq!(
+            message,
+            format!(
+                "LOG_LEVEL can't be set in profile {}, it applies to all homes and belongs in the .env file",
+                path.display()
+            )
+        );
+
+        let _ = fs::remove_file(&path);
     }
 }
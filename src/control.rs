@@ -0,0 +1,548 @@
+use crate::config;
+use crate::info;
+use crate::logging::{self, LogLevel};
+use crate::runtime::Runtime;
+use crate::status::Status;
+use crate::utils;
+use chrono::{Local, Utc};
+use chrono_tz::Tz;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Largest request body accepted, bodies are a log level or a mode name
+const MAX_BODY_LENGTH: usize = 1024;
+
+/// Watchers get an empty line this often without events, so connections of watchers that are gone are noticed
+const WATCH_KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+/// Time after which a client that doesn't read its response is given up on
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where the control api of a running daemon can be reached
+#[derive(Clone, PartialEq, Debug)]
+pub enum Endpoint {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+/// A home the control api reports on, named after its profile if `PROFILES` is set
+#[derive(Clone)]
+pub struct Home {
+    pub profile: Option<String>,
+    pub runtime: Arc<Runtime>,
+}
+
+/// A connection to the control api, either via tcp or a unix socket
+trait Connection: Read + Write {}
+
+impl<T: Read + Write> Connection for T {}
+
+/// Returns the endpoint cli commands should talk to, the unix socket is preferred if present
+pub fn endpoint() -> Option<Endpoint> {
+    config::load_control_socket()
+        .filter(|path| cfg!(unix) && path.exists())
+        .map(Endpoint::Unix)
+        .or(config::load_control_address().map(Endpoint::Tcp))
+}
+
+/// Starts the control server of all homes in the background, connections are handled in their own thread
+pub fn serve(endpoint: Endpoint, homes: Vec<Home>) {
+    let homes = Arc::new(homes);
+
+    match endpoint {
+        Endpoint::Tcp(address) => {
+            let listener = match TcpListener::bind(address) {
+                Ok(listener) => listener,
+                Err(err) => {
+                    eprintln!("Failed to start control server on {}: {}", address, err);
+                    return;
+                }
+            };
+
+            info!("Control server listening on {}", address);
+
+            thread::spawn(move || {
+                for stream in listener.incoming().map_while(Result::ok) {
+                    let homes = homes.clone();
+                    let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+                    thread::spawn(move || handle_connection(&stream, &stream, &homes));
+                }
+            });
+        }
+        #[cfg(unix)]
+        Endpoint::Unix(path) => {
+            // Remove the socket of a previous run, binding fails otherwise.
+            // Anything else at that path is left alone, it's most likely a misconfigured CONTROL_SOCKET.
+            if utils::is_socket(&path) {
+                let _ = std::fs::remove_file(&path);
+            } else if path.symlink_metadata().is_ok() {
+                eprintln!(
+                    "Failed to start control server on {}: a file that isn't a socket exists there, not replacing it",
+                    path.display()
+                );
+                return;
+            }
+
+            let listener = match UnixListener::bind(&path) {
+                Ok(listener) => listener,
+                Err(err) => {
+                    eprintln!(
+                        "Failed to start control server on {}: {}",
+                        path.display(),
+                        err
+                    );
+                    return;
+                }
+            };
+
+            info!("Control server listening on {}", path.display());
+
+            thread::spawn(move || {
+                for stream in listener.incoming().map_while(Result::ok) {
+                    let homes = homes.clone();
+                    let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+                    thread::spawn(move || handle_connection(&stream, &stream, &homes));
+                }
+            });
+        }
+        #[cfg(not(unix))]
+        Endpoint::Unix(path) => {
+            eprintln!(
+                "Unix sockets are not supported on this platform, ignoring {}",
+                path.display()
+            );
+        }
+    }
+}
+
+/// Writes a plain text response
+fn respond(mut writer: impl Write, status: &str, body: &str) {
+    let _ = write!(
+        writer,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+}
+
+/// Events of the given homes as they happen, prefixed with the name of their home if there are several
+fn subscribe(homes: &[&Home]) -> Receiver<String> {
+    let (sender, receiver) = channel();
+
+    for home in homes {
+        let events = home.runtime.events.subscribe();
+        let sender = sender.clone();
+        let prefix = match (homes.len(), &home.profile) {
+            (2.., Some(profile)) => format!("[{}] ", profile),
+            _ => String::new(),
+        };
+
+        // Stops with the first event after the watcher hung up, the home drops its receiver then
+        thread::spawn(move || {
+            for event in events {
+                if sender.send(format!("{}{}", prefix, event)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    receiver
+}
+
+/// Handles a single HTTP request, only the request line and body are of interest.
+/// Requests to `/profiles/<name>/…` go to a single home, the others to all of them.
+fn handle_connection(reader: impl Read, mut writer: impl Write, homes: &[Home]) {
+    let mut reader = BufReader::new(reader);
+    let mut request_line = String::new();
+
+    match reader.read_line(&mut request_line) {
+        Ok(_) => {}
+        Err(err) if err.kind() == ErrorKind::InvalidData => {
+            return respond(writer, "400 Bad Request", "request isn't valid UTF-8");
+        }
+        Err(_) => return,
+    }
+
+    // Skip headers, only the length of the body is needed
+    let mut content_length = 0;
+    let mut header = String::new();
+    loop {
+        header.clear();
+
+        match reader.read_line(&mut header) {
+            Ok(0) => break,
+            Ok(_) if header.trim().is_empty() => break,
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::InvalidData => {
+                return respond(writer, "400 Bad Request", "request isn't valid UTF-8");
+            }
+            Err(_) => return,
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                match value.trim().parse::<usize>() {
+                    Ok(length) => content_length = length,
+                    Err(_) => {
+                        return respond(writer, "400 Bad Request", "invalid Content-Length");
+                    }
+                }
+            }
+        }
+    }
+
+    if content_length > MAX_BODY_LENGTH {
+        return respond(
+            writer,
+            "413 Payload Too Large",
+            &format!("bodies are limited to {} bytes", MAX_BODY_LENGTH),
+        );
+    }
+
+    let mut body = vec![0; content_length];
+    if reader.read_exact(&mut body).is_err() {
+        return;
+    }
+
+    let mut request = request_line.split_whitespace();
+    let method = request.next().unwrap_or_default();
+    let mut path = request.next().unwrap_or_default();
+
+    // Without a profile in the path every home is addressed
+    let mut selected = homes.iter().collect::<Vec<&Home>>();
+
+    if let Some((profile, rest)) = path
+        .strip_prefix("/profiles/")
+        .and_then(|path| path.split_once('/'))
+    {
+        selected.retain(|home| home.profile.as_deref() == Some(profile));
+
+        if selected.is_empty() {
+            return respond(
+                writer,
+                "404 Not Found",
+                &format!("unknown profile: {}", profile),
+            );
+        }
+
+        path = &path[path.len() - rest.len() - 1..];
+    }
+
+    match (method, path) {
+        ("GET", "/status") => {
+            let body = match selected[..] {
+                [home] => serde_json::to_string(&*home.runtime.status()),
+                _ => serde_json::to_string(
+                    &selected
+                        .iter()
+                        .map(|home| {
+                            (
+                                home.profile.clone().unwrap_or_default(),
+                                home.runtime.status().clone(),
+                            )
+                        })
+                        .collect::<BTreeMap<String, Status>>(),
+                ),
+            }
+            .unwrap_or_default();
+
+            let _ = write!(
+                writer,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+        }
+        ("GET", "/watch") => {
+            let receiver = subscribe(&selected);
+
+            if writer
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n")
+                .is_err()
+            {
+                return;
+            }
+
+            // Stream events until the watcher hangs up, which is only noticed once something is written
+            loop {
+                let written = match receiver.recv_timeout(WATCH_KEEP_ALIVE) {
+                    Ok(event) => writeln!(writer, "{}", event),
+                    Err(RecvTimeoutError::Timeout) => writeln!(writer),
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+
+                if written.and_then(|_| writer.flush()).is_err() {
+                    break;
+                }
+            }
+        }
+        ("GET", "/log-level") => respond(writer, "200 OK", &logging::level().to_string()),
+        ("POST", "/log-level") => match String::from_utf8_lossy(&body).parse::<LogLevel>() {
+            Ok(level) => {
+                logging::set_level(level);
+                respond(writer, "200 OK", &level.to_string());
+            }
+            Err(err) => respond(writer, "400 Bad Request", &err),
+        },
+        // A mode belongs to a single home
+        ("GET" | "POST", "/mode") if selected.len() > 1 => respond(
+            writer,
+            "400 Bad Request",
+            "several homes are scheduled, select one with /profiles/<name>/mode",
+        ),
+        ("GET", "/mode") => respond(writer, "200 OK", &selected[0].runtime.mode()),
+        ("POST", "/mode") => {
+            let mode = String::from_utf8_lossy(&body).trim().to_lowercase();
+
+            if mode.is_empty()
+                || !mode
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+            {
+                respond(
+                    writer,
+                    "400 Bad Request",
+                    &format!("invalid mode: {}", mode),
+                );
+            } else {
+                selected[0].runtime.set_mode(mode.clone());
+                respond(writer, "200 OK", &mode);
+            }
+        }
+        _ => respond(writer, "404 Not Found", ""),
+    }
+}
+
+/// Opens a connection to the control api of a running daemon
+fn connect(endpoint: &Endpoint) -> Box<dyn Connection> {
+    match endpoint {
+        Endpoint::Tcp(address) => {
+            Box::new(TcpStream::connect(address).expect("failed to connect to hue-scheduler"))
+        }
+        #[cfg(unix)]
+        Endpoint::Unix(path) => {
+            Box::new(UnixStream::connect(path).expect("failed to connect to hue-scheduler"))
+        }
+        #[cfg(not(unix))]
+        Endpoint::Unix(_) => panic!("unix sockets are not supported on this platform"),
+    }
+}
+
+/// Sends a request to a running daemon and returns the response body
+fn request(endpoint: &Endpoint, method: &str, path: &str, body: &str) -> String {
+    let mut stream = connect(endpoint);
+
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    )
+    .expect("failed to send request");
+
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .expect("failed to read response");
+
+    match response.split_once("\r\n\r\n") {
+        Some((head, body)) if head.starts_with("HTTP/1.1 200") => body.to_string(),
+        Some((_, body)) if !body.is_empty() => {
+            panic!("hue-scheduler refused the request: {}", body)
+        }
+        _ => panic!("hue-scheduler refused the request to {}", path),
+    }
+}
+
+/// Path of a request to the home of `profile`, or to all homes if none is given
+fn profile_path(profile: Option<&str>, path: &str) -> String {
+    match profile {
+        Some(profile) => format!("/profiles/{}{}", profile, path),
+        None => path.to_string(),
+    }
+}
+
+/// Prints the status of a running daemon, each home on its own if it schedules several
+pub fn status(endpoint: Endpoint, profile: Option<String>) {
+    let response = request(
+        &endpoint,
+        "GET",
+        &profile_path(profile.as_deref(), "/status"),
+        "",
+    );
+
+    if let Ok(status) = serde_json::from_str::<Status>(&response) {
+        return print!("{}", status);
+    }
+
+    let homes = serde_json::from_str::<BTreeMap<String, Status>>(&response)
+        .expect("failed to parse status");
+
+    for (profile, status) in homes {
+        println!("Home {}:", profile);
+        print!("{}", status);
+        println!();
+    }
+}
+
+/// Prints the log level of a running daemon, or changes it if one is given
+pub fn log_level(endpoint: Endpoint, level: Option<String>) {
+    let level = match level {
+        Some(level) => request(&endpoint, "POST", "/log-level", &level),
+        None => request(&endpoint, "GET", "/log-level", ""),
+    };
+
+    println!("Log level: {}", level);
+}
+
+/// Prints the mode of a running daemon, or switches to the given one
+pub fn mode(endpoint: Endpoint, profile: Option<String>, mode: Option<String>) {
+    let path = profile_path(profile.as_deref(), "/mode");
+    let mode = match mode {
+        Some(mode) => request(&endpoint, "POST", &path, &mode),
+        None => request(&endpoint, "GET", &path, ""),
+    };
+
+    println!("Mode: {}", mode);
+}
+
+/// Connects to a running daemon and prints its decisions as they happen, in the home timezone if it's known
+pub fn watch(endpoint: Endpoint, profile: Option<String>, timezone: Option<Tz>) {
+    let mut stream = connect(&endpoint);
+
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nConnection: keep-alive\r\n\r\n",
+        profile_path(profile.as_deref(), "/watch")
+    )
+    .expect("failed to send watch request");
+
+    let mut lines = BufReader::new(stream).lines();
+
+    match lines.next() {
+        Some(Ok(status)) if status.contains(" 200 ") => {}
+        _ => panic!("hue-scheduler refused the watch request"),
+    }
+
+    match endpoint {
+        Endpoint::Tcp(address) => println!("Watching hue-scheduler on {}", address),
+        Endpoint::Unix(path) => println!("Watching hue-scheduler on {}", path.display()),
+    }
+
+    // Skip headers, anything after them is an event or an empty line to keep the connection alive
+    for line in lines
+        .map_while(Result::ok)
+        .skip_while(|line| !line.is_empty())
+        .skip(1)
+        .filter(|line| !line.is_empty())
+    {
+        let now = match timezone {
+            Some(timezone) => utils::format_time(&Utc::now().with_timezone(&timezone), true),
+            None => utils::format_time(&Local::now(), true),
+        };
+
+        println!("[{}] {}", now, line);
+    }
+
+    println!("Connection to hue-scheduler closed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handle(request: &[u8]) -> String {
+        let homes = [Home {
+            profile: None,
+            runtime: Arc::new(Runtime::new("default".to_string())),
+        }];
+        let mut response = vec![];
+        handle_connection(request, &mut response, &homes);
+        String::from_utf8(response).unwrap()
+    }
+
+    fn handle_profiles(homes: &[Home], request: &[u8]) -> String {
+        let mut response = vec![];
+        handle_connection(request, &mut response, homes);
+        String::from_utf8(response).unwrap()
+    }
+
+    #[test]
+    fn test_requests() {
+        assert!(handle(b"GET /mode HTTP/1.1\r\n\r\n").ends_with("\r\n\r\ndefault"));
+        assert!(
+            handle(b"POST /mode HTTP/1.1\r\nContent-Length: 5\r\n\r\nnight")
+                .starts_with("HTTP/1.1 200 OK")
+        );
+        assert!(handle(b"GET /nothing HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+        assert_eq!(handle(b""), "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_profiles() {
+        let homes = ["flat", "parents"].map(|profile| Home {
+            profile: Some(profile.to_string()),
+            runtime: Arc::new(Runtime::new("default".to_string())),
+        });
+
+        let status = handle_profiles(&homes, b"GET /status HTTP/1.1\r\n\r\n");
+        let (_, body) = status.split_once("\r\n\r\n").unwrap();
+        let statuses = serde_json::from_str::<BTreeMap<String, Status>>(body).unwrap();
+        assert_eq!(
+            statuses.keys().collect::<Vec<&String>>(),
+            vec!["flat", "parents"]
+        );
+
+        assert!(handle_profiles(&homes, b"GET /mode HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 400"));
+        assert!(handle_profiles(
+            &homes,
+            b"POST /profiles/parents/mode HTTP/1.1\r\nContent-Length: 4\r\n\r\naway"
+        )
+        .starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(homes[0].runtime.mode(), "default");
+        assert_eq!(homes[1].runtime.mode(), "away");
+
+        assert!(
+            handle_profiles(&homes, b"GET /profiles/parents/status HTTP/1.1\r\n\r\n")
+                .contains("\"mode\":\"away\"")
+        );
+        assert!(
+            handle_profiles(&homes, b"GET /profiles/garage/status HTTP/1.1\r\n\r\n")
+                .starts_with("HTTP/1.1 404")
+        );
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        assert!(
+            handle(b"POST /mode HTTP/1.1\r\nContent-Length: 1025\r\n\r\n")
+                .starts_with("HTTP/1.1 413")
+        );
+        assert!(
+            handle(b"POST /mode HTTP/1.1\r\nContent-Length: 99999999999999\r\n\r\n")
+                .starts_with("HTTP/1.1 413")
+        );
+        assert!(handle(b"POST /mode HTTP/1.1\r\nContent-Length: -1\r\n\r\n")
+            .starts_with("HTTP/1.1 400"));
+        assert!(handle(b"GET /\xff\xfe HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 400"));
+        assert!(handle(b"GET /mode HTTP/1.1\r\nX-\xff: 1\r\n\r\n").starts_with("HTTP/1.1 400"));
+
+        // Bodies shorter than announced are dropped
+        assert_eq!(
+            handle(b"POST /mode HTTP/1.1\r\nContent-Length: 10\r\n\r\nnight"),
+            ""
+        );
+    }
+}
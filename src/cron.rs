@@ -0,0 +1,212 @@
+use crate::time_range_parser::TimeRange;
+use chrono::{Datelike, NaiveDate};
+
+/// A cron expression like `* 7-9 * * 1-5`, a scene is scheduled for every minute it matches.
+/// The fields are minute, hour, day of the month, month and day of the week (0 or 7 is Sunday).
+#[derive(Clone, PartialEq, Debug)]
+pub struct Cron {
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days: Option<Vec<u32>>,
+    months: Vec<u32>,
+    weekdays: Option<Vec<u32>>,
+}
+
+/// Parses a single field, e.g. `*`, `*/2`, `1-5`, `0,30` or `8-18/2`, within `min..=max`
+/// # Examples
+/// ```
+/// assert_eq!(parse_field("*/20", 0, 59), Some(vec![0, 20, 40]));
+/// assert_eq!(parse_field("1-3,5", 0, 6), Some(vec![1, 2, 3, 5]));
+/// ```
+fn parse_field(str: &str, min: u32, max: u32) -> Option<Vec<u32>> {
+    let mut values = vec![];
+
+    for part in str.split(",") {
+        let (range, step) = match part.split_once("/") {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|step| *step > 0)?),
+            None => (part, 1),
+        };
+
+        let (from, to) = match range.split_once("-") {
+            _ if range == "*" => (min, max),
+            Some((from, to)) => (from.parse().ok()?, to.parse().ok()?),
+            None => {
+                let value = range.parse().ok()?;
+                (value, if part.contains("/") { max } else { value })
+            }
+        };
+
+        if from < min || to > max || from > to {
+            return None;
+        }
+
+        values.extend((from..=to).step_by(step as usize));
+    }
+
+    values.sort();
+    values.dedup();
+    Some(values)
+}
+
+impl Cron {
+    pub fn parse(str: &str) -> Option<Cron> {
+        let fields = str.split_whitespace().collect::<Vec<&str>>();
+
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return None;
+        };
+
+        // Unrestricted days are kept apart, they change how both day fields are combined
+        let days = match days {
+            "*" => None,
+            days => Some(parse_field(days, 1, 31)?),
+        };
+
+        let weekdays = match weekdays {
+            "*" => None,
+            weekdays => Some(
+                parse_field(weekdays, 0, 7)?
+                    .iter()
+                    .map(|day| day % 7)
+                    .collect(),
+            ),
+        };
+
+        Some(Cron {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days,
+            months: parse_field(months, 1, 12)?,
+            weekdays,
+        })
+    }
+
+    /// Checks if the expression matches a day, if both days of the month and of the week are restricted
+    /// either one matching is enough, like in cron
+    pub fn matches_date(&self, date: NaiveDate) -> bool {
+        let day = self.days.as_ref().map(|days| days.contains(&date.day()));
+        let weekday = self
+            .weekdays
+            .as_ref()
+            .map(|weekdays| weekdays.contains(&date.weekday().num_days_from_sunday()));
+
+        self.months.contains(&date.month())
+            && match (day, weekday) {
+                (Some(day), Some(weekday)) => day || weekday,
+                (day, weekday) => day.unwrap_or(true) && weekday.unwrap_or(true),
+            }
+    }
+
+    /// Returns the matching minutes of a day merged into time-ranges
+    /// # Examples
+    /// ```
+    /// let cron = Cron::parse("* 7-8 * * *").unwrap();
+    /// let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+    ///
+    /// assert_eq!(cron.ranges_on(date), vec![(h(7), h(9))]);
+    /// ```
+    pub fn ranges_on(&self, date: NaiveDate) -> Vec<TimeRange> {
+        if !self.matches_date(date) {
+            return vec![];
+        }
+
+        let mut ranges = Vec::<TimeRange>::new();
+
+        for start in self.hours.iter().flat_map(|hour| {
+            self.minutes
+                .iter()
+                .map(move |minute| hour * 3600 + minute * 60)
+        }) {
+            match ranges.last_mut() {
+                Some(range) if range.1 == start => range.1 = start + 60,
+                _ => ranges.push((start, start + 60)),
+            }
+        }
+
+        ranges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time_range_parser::{d, h, m};
+
+    #[test]
+    fn test_parse_field() {
+        let pf = |v: &str| parse_field(v, 0, 59);
+
+        assert_eq!(pf("5"), Some(vec![5]));
+        assert_eq!(pf("10-13"), Some(vec![10, 11, 12, 13]));
+        assert_eq!(pf("*/15"), Some(vec![0, 15, 30, 45]));
+        assert_eq!(pf("10-20/5"), Some(vec![10, 15, 20]));
+        assert_eq!(pf("50/4"), Some(vec![50, 54, 58]));
+        assert_eq!(pf("30,0,15"), Some(vec![0, 15, 30]));
+        assert_eq!(pf("1-3,2-4"), Some(vec![1, 2, 3, 4]));
+        assert_eq!(pf("*").map(|values| values.len()), Some(60));
+
+        assert_eq!(pf("60"), None);
+        assert_eq!(pf("20-10"), None);
+        assert_eq!(pf("*/0"), None);
+        assert_eq!(pf("a"), None);
+        assert_eq!(pf("1,"), None);
+        assert_eq!(pf("-5"), None);
+        assert_eq!(parse_field("0", 1, 31), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(Cron::parse("* * * * *").is_some());
+        assert!(Cron::parse("0,30 7 * * 1-5").is_some());
+        assert!(Cron::parse(" 0  7 1 * 0 ").is_some());
+        assert!(Cron::parse("0 7 * *").is_none());
+        assert!(Cron::parse("0 7 * * * *").is_none());
+        assert!(Cron::parse("0 24 * * *").is_none());
+        assert!(Cron::parse("0 7 32 * *").is_none());
+        assert!(Cron::parse("0 7 * 13 *").is_none());
+        assert!(Cron::parse("0 7 * * 8").is_none());
+        assert!(Cron::parse("").is_none());
+    }
+
+    #[test]
+    fn test_matches_date() {
+        let md = |v: &str, date: NaiveDate| Cron::parse(v).unwrap().matches_date(date);
+
+        // 2024-01-01 is a Monday, 2024-01-07 a Sunday
+        assert!(md("* * * * *", d(2024, 1, 1)));
+        assert!(md("* * * * 1-5", d(2024, 1, 1)));
+        assert!(!md("* * * * 1-5", d(2024, 1, 7)));
+        assert!(md("* * * * 0", d(2024, 1, 7)));
+        assert!(md("* * * * 7", d(2024, 1, 7)));
+        assert!(md("* * 1 * *", d(2024, 1, 1)));
+        assert!(!md("* * 2 * *", d(2024, 1, 1)));
+        assert!(!md("* * * 2 *", d(2024, 1, 1)));
+        assert!(md("* * * 12,1 *", d(2024, 1, 1)));
+
+        // Both day fields restricted, either one is enough
+        assert!(md("* * 15 * 1", d(2024, 1, 1)));
+        assert!(md("* * 15 * 1", d(2024, 1, 15)));
+        assert!(!md("* * 15 * 1", d(2024, 1, 16)));
+
+        // Only one of them restricted, it alone decides
+        assert!(!md("* * 15 * *", d(2024, 1, 1)));
+        assert!(!md("* * * * 2", d(2024, 1, 15)));
+    }
+
+    #[test]
+    fn test_ranges_on() {
+        let ro = |v: &str, date: NaiveDate| Cron::parse(v).unwrap().ranges_on(date);
+
+        assert_eq!(ro("* 7-8 * * *", d(2024, 1, 1)), vec![(h(7), h(9))]);
+        assert_eq!(
+            ro("0,30 7 * * *", d(2024, 1, 1)),
+            vec![(h(7), h(7) + m(1)), (h(7) + m(30), h(7) + m(31))]
+        );
+        assert_eq!(
+            ro("0-14 7,9 * * *", d(2024, 1, 1)),
+            vec![(h(7), h(7) + m(15)), (h(9), h(9) + m(15))]
+        );
+        assert_eq!(ro("* 23 * * *", d(2024, 1, 1)), vec![(h(23), h(24))]);
+        assert_eq!(ro("* 7 * * 0", d(2024, 1, 1)), vec![]);
+    }
+}
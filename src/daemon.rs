@@ -0,0 +1,1019 @@
+use crate::api_usage;
+use crate::backend;
+use crate::backoff;
+#[cfg(feature = "clip-v2")]
+use crate::clip_v2::ClipV2;
+use crate::clock;
+use crate::conditions::{self, Condition, Environment};
+use crate::config::{self, Config};
+#[cfg(feature = "control")]
+use crate::control;
+use crate::debounce::Debouncer;
+use crate::debug;
+use crate::discovery;
+use crate::engine::{self, Context, Engine, Snapshot};
+use crate::entertainment;
+use crate::events::Event;
+use crate::holidays;
+#[cfg(feature = "home-assistant")]
+use crate::home_assistant::HomeAssistant;
+use crate::info;
+use crate::logging;
+use crate::network_health;
+use crate::plugins;
+#[cfg(feature = "clip-v2")]
+use crate::preferences;
+use crate::presence;
+use crate::rate_limit::RateLimiter;
+use crate::reload;
+use crate::resource_cache;
+use crate::runtime::Runtime;
+use crate::safe_mode;
+use crate::schedule_report;
+use crate::schedules;
+use crate::shutdown;
+#[cfg(feature = "clip-v2")]
+use crate::smart_scenes;
+use crate::state;
+use crate::status::DegradedBridge;
+use crate::systemd;
+use crate::utils::{self, Backend};
+use crate::watchdog;
+#[cfg(feature = "zigbee2mqtt")]
+use crate::zigbee2mqtt::Zigbee2Mqtt;
+use chrono::NaiveDate;
+#[cfg(feature = "clip-v2")]
+use chrono::Timelike;
+use huelib2::resource::Light;
+use huelib2::Bridge;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{Seek, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often zigbee channel and connectivity are checked
+const NETWORK_HEALTH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Engines wake up on their own between changes, the snapshot they keep using is rebuilt this often so
+/// sunrise, sunset, `night` and everything else fixed to the moment its parser was created don't go stale
+const SNAPSHOT_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// How often active scenes are checked for ones selected manually
+#[cfg(feature = "clip-v2")]
+const PREFERENCES_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Runs the scheduler until it's stopped, this is what `hue-scheduler run` does.
+/// Homes listed in `PROFILES` are scheduled side by side, each with its own bridges, location and schedules.
+pub fn run() {
+    let conf = Arc::new(config::load_config());
+
+    logging::init(conf.log_level);
+    logging::listen_for_signals();
+    watchdog::init(conf.bridge_timeout);
+    reload::watch();
+
+    let profiles = config::load_profiles();
+
+    let homes = match profiles.is_empty() {
+        true => vec![start_home(conf.clone())],
+        false => profiles
+            .into_iter()
+            .map(|profile| start_home(Arc::new(profile)))
+            .collect::<Vec<Home>>(),
+    };
+
+    // A single control server reports on all homes, see `PROFILES`
+    #[cfg(feature = "control")]
+    {
+        let homes = homes
+            .iter()
+            .map(|home| control::Home {
+                profile: home.contexts[0].conf.profile.clone(),
+                runtime: home.contexts[0].runtime.clone(),
+            })
+            .collect::<Vec<control::Home>>();
+
+        if let Some(address) = conf.control_address {
+            control::serve(control::Endpoint::Tcp(address), homes.clone());
+        }
+
+        if let Some(path) = conf.control_socket.clone() {
+            control::serve(control::Endpoint::Unix(path), homes);
+        }
+    }
+
+    let mut cleanups = vec![];
+    let mut primaries = vec![];
+
+    // Every bridge is polled on its own, the first one of the first home on this thread
+    for home in homes {
+        for context in home.contexts.iter().skip(1).cloned() {
+            info!("Scheduling bridge {} as well", context.bridge_ip);
+            std::thread::spawn(move || poll(context, home.safe_mode, None, false));
+        }
+
+        primaries.push((home.contexts[0].clone(), home.safe_mode, home.debug_file));
+        cleanups.push(home.cleanup);
+    }
+
+    shutdown::listen(cleanups);
+    systemd::notify("READY=1");
+    systemd::watch();
+
+    let mut primaries = primaries.into_iter();
+    let (context, safe_mode, debug_file) = primaries.next().expect("no home to schedule");
+
+    for (context, safe_mode, debug_file) in primaries {
+        std::thread::spawn(move || poll(context, safe_mode, debug_file, true));
+    }
+
+    poll(context, safe_mode, debug_file, true);
+}
+
+/// Everything a home needs to be polled, see `PROFILES`
+struct Home {
+    contexts: Vec<Context>,
+    safe_mode: bool,
+    debug_file: Option<File>,
+    cleanup: shutdown::Cleanup,
+}
+
+/// Sets up the runtime and plugins of a home and connects to its bridges
+fn start_home(conf: Arc<Config>) -> Home {
+    let debug_file = conf
+        .debug_file
+        .as_ref()
+        .map(|path| File::create(path).expect("failed to create debug file"));
+
+    if let Some(parser) = utils::create_parser(&conf) {
+        for (name, value) in conf.time_variables.iter() {
+            if parser.variable(name).is_none() {
+                eprintln!(
+                    "Time variable \"{}\" can't be resolved: {}, scenes using it aren't scheduled",
+                    name, value
+                );
+            }
+        }
+    }
+
+    let runtime = Arc::new(Runtime::new(conf.mode.clone()));
+
+    plugins::start(&conf, runtime.clone());
+
+    // A schedule crashing the scheduler over and over must not keep flashing the lights
+    let restarts = conf
+        .state_file
+        .as_ref()
+        .map(|path| safe_mode::record_start(path, conf.safe_mode_window))
+        .unwrap_or(0);
+    let safe_mode = restarts >= conf.safe_mode_restarts;
+
+    let cleanup = shutdown::Cleanup {
+        profile: conf.profile.clone(),
+        runtime: runtime.clone(),
+        started_at: Instant::now(),
+        state_file: conf.state_file.clone(),
+        #[cfg(feature = "control")]
+        control_socket: conf.control_socket.clone(),
+        #[cfg(not(feature = "control"))]
+        control_socket: None,
+    };
+
+    if let Some(ref path) = conf.state_file {
+        let today = utils::now_at_home(&conf).date_naive();
+        runtime
+            .status()
+            .restore_activations(today, state::activations_on(path, today));
+        runtime.restore_engines(state::load(path).engines);
+    }
+
+    if safe_mode {
+        runtime.status().safe_mode = true;
+        runtime.events.publish(Event::SafeModeEntered { restarts });
+    }
+
+    let started_at = utils::now_at_home(&conf);
+    info!(
+        "Starting hue-scheduler{} at {} {} {}",
+        conf.profile
+            .as_ref()
+            .map(|profile| format!(" for {}", profile))
+            .unwrap_or_default(),
+        started_at.format("%Y-%m-%d"),
+        utils::format_time(&started_at, true),
+        started_at.format("%Z")
+    );
+
+    if conf.dry_run {
+        info!("Dry run, commands are logged instead of sent to the bridge");
+    }
+
+    #[cfg(feature = "zigbee2mqtt")]
+    let zigbee2mqtt = conf
+        .mqtt
+        .clone()
+        .filter(|_| conf.backend == Backend::Zigbee2Mqtt)
+        .map(|options| {
+            Arc::new(Zigbee2Mqtt::connect(
+                options,
+                conf.zigbee2mqtt_topic.clone(),
+            ))
+        });
+
+    #[cfg(feature = "home-assistant")]
+    let home_assistant = conf
+        .home_assistant_address
+        .clone()
+        .zip(conf.home_assistant_token.clone())
+        .filter(|_| conf.backend == Backend::HomeAssistant)
+        .map(|(address, token)| HomeAssistant::connect(address, token));
+
+    let started_at = Instant::now();
+    let contexts = [(conf.bridge_ip, conf.bridge_username.clone())]
+        .into_iter()
+        .chain(conf.bridges.clone())
+        .map(|(bridge_ip, username)| {
+            let bridge = Bridge::new(bridge_ip, &username);
+
+            // BRIDGE_ID belongs to BRIDGE_IP, further bridges are looked up through discovery
+            #[cfg(feature = "clip-v2")]
+            let clip = ClipV2::new(
+                &bridge,
+                conf.bridge_id
+                    .clone()
+                    .filter(|_| bridge_ip == conf.bridge_ip),
+                &conf,
+            )
+            .unwrap_or_else(|err| {
+                eprintln!("Failed to set up HTTPS for bridge {}: {}", bridge_ip, err);
+                std::process::exit(1);
+            });
+
+            Context {
+                conf: conf.clone(),
+                bridge: bridge.clone(),
+                bridge_ip,
+                limiter: Arc::new(RateLimiter::new(conf.bridge_rate_limit)),
+                #[cfg(feature = "clip-v2")]
+                clip: Arc::new(clip),
+                #[cfg(feature = "zigbee2mqtt")]
+                zigbee2mqtt: zigbee2mqtt.clone(),
+                #[cfg(feature = "home-assistant")]
+                home_assistant: home_assistant.clone(),
+                simulation: None,
+                runtime: runtime.clone(),
+                started_at,
+            }
+        })
+        .collect::<Vec<Context>>();
+
+    Home {
+        contexts,
+        safe_mode,
+        debug_file,
+        cleanup,
+    }
+}
+
+/// Polls a bridge and hands its lights and scenes to the engines of its rooms.
+/// Only the first bridge writes the debug file, learns preferences and reports the network health and api usage.
+fn poll(mut context: Context, safe_mode: bool, mut debug_file: Option<File>, primary: bool) {
+    let mut conf = context.conf.clone();
+    let runtime = context.runtime.clone();
+    let mut last_reachable = HashMap::<String, bool>::new();
+    let mut partial_state_lights = HashSet::<String>::new();
+    let mut debouncer = Debouncer::default();
+    let mut jump_detector = clock::JumpDetector::default();
+    // Stays set until the engines were handed a snapshot, the bridge may not be reachable right after waking up
+    let mut clock_jumped = false;
+    let mut last_presence: Option<bool> = None;
+    let mut last_arrivals = runtime.arrivals();
+    let mut dangling_scenes = HashSet::<String>::new();
+    let mut engines = HashMap::<Option<String>, Sender<Arc<Snapshot>>>::new();
+    let mut last_network_check: Option<Instant> = None;
+    let mut last_snapshot: Option<(Instant, NaiveDate)> = None;
+    let mut last_summary_date = utils::now_at_home(&conf).date_naive();
+    let mut last_clock_check: Option<NaiveDate> = None;
+    let mut holidays = conf.holidays.clone().map(holidays::Holidays::new);
+    let mut schedules = conf.schedules_file.clone().map(schedules::Schedules::new);
+    #[cfg(feature = "clip-v2")]
+    let mut learner = conf
+        .scene_preferences_file
+        .clone()
+        .filter(|_| primary)
+        .map(preferences::Learner::new);
+    #[cfg(feature = "clip-v2")]
+    let mut last_preferences_check: Option<Instant> = None;
+
+    let mut resource_cache = resource_cache::ResourceCache::new(conf.scene_cache_ttl);
+    let mut backoff = backoff::Backoff::new(conf.ping_interval);
+    let mut last_error: Option<String> = None;
+    // Identifies the bridge if it has to be looked for under a new address
+    let mut bridge_id: Option<String> = None;
+    let mut generation = 0;
+    // Only the first bridge of a home reloads its configuration and is waited for by the watchdog of systemd
+    let mut reload_watcher = primary.then(|| reload::Watcher::new(&conf));
+    let heartbeat = primary.then(systemd::Heartbeat::register);
+
+    loop {
+        if let Some(ref heartbeat) = heartbeat {
+            heartbeat.finish();
+        }
+
+        std::thread::sleep(backoff.delay());
+
+        if let Some(ref heartbeat) = heartbeat {
+            heartbeat.start();
+        }
+
+        // All other bridges of the home pick the configuration up from there
+        if reload_watcher
+            .as_mut()
+            .is_some_and(|watcher| watcher.requested())
+        {
+            systemd::notify("RELOADING=1");
+
+            if let Some(reloaded) = config::reload(conf.profile.as_deref()) {
+                reload_config(&runtime, &conf, &reloaded);
+                reload::publish(Arc::new(reloaded));
+            }
+
+            systemd::notify("READY=1");
+        }
+
+        // Reachability and applied scenes are kept, everything derived from the configuration starts over
+        if let Some(reloaded) = reload::newer(&mut generation, conf.profile.as_ref()) {
+            conf = reloaded;
+            context.conf = conf.clone();
+            backoff.set_interval(conf.ping_interval);
+            resource_cache = resource_cache::ResourceCache::new(conf.scene_cache_ttl);
+            schedules = conf.schedules_file.clone().map(schedules::Schedules::new);
+            holidays = conf.holidays.clone().map(holidays::Holidays::new);
+
+            if let Some(ref mut holidays) = holidays {
+                holidays.update(utils::now_at_home(&conf).date_naive());
+            }
+        }
+
+        if let Some(jump) = jump_detector.check(&conf) {
+            info!(
+                "Clock jumped {}s {}, e.g. the host was suspended, taking lights as they are now",
+                jump.abs(),
+                if jump > 0 { "ahead" } else { "back" }
+            );
+
+            debouncer = Debouncer::default();
+            clock_jumped = true;
+        }
+
+        // A panic while polling must not take down the whole scheduler, the next iteration starts over.
+        // Failing requests to the bridge are returned as error, the bridge is polled less often then.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| -> Result<(), String> {
+            let mut all_lights = backend::get_all_lights(&context).map_err(|error| {
+                format!(
+                    "Failed to retrieve lights from {}: {}",
+                    context.bridge_ip, error
+                )
+            })?;
+
+            debug!(
+                "Bridge: GET /lights → {}",
+                light_stats(&all_lights).join(", ")
+            );
+
+            // Lights flapping between reachable and unreachable are seen as they were until they settle
+            debouncer.apply(&conf, &mut all_lights);
+
+            // Let the user know about lights with partial state, they are handled as configured
+            for light in all_lights.iter().filter(|light| light.state.on.is_none()) {
+                if partial_state_lights.insert(light.id.clone()) {
+                    info!(
+                        "Light \"{}\" doesn't report whether it's on, treating it as {}",
+                        light.name, conf.unknown_on_state
+                    );
+                }
+            }
+
+            // Write debug file if needed
+            if let Some(ref mut file) = debug_file {
+                write_debug_file(&all_lights, file);
+            }
+
+            // Network health changes slowly, refresh it once in a while. Only the hue bridge reports it.
+            if conf.backend == Backend::Hue
+                && last_network_check
+                    .map(|checked| checked.elapsed() >= NETWORK_HEALTH_INTERVAL)
+                    .unwrap_or(true)
+            {
+                last_network_check = Some(Instant::now());
+                let health = network_health::get_network_health(&context);
+
+                for hint in health.hints.iter() {
+                    info!("Network: {}", hint);
+                }
+
+                if primary {
+                    runtime.status().network = health;
+                }
+            }
+
+            // Requests to all bridges are counted together
+            if primary {
+                let bridges = [conf.bridge_ip]
+                    .into_iter()
+                    .chain(conf.bridges.iter().map(|(bridge_ip, _)| *bridge_ip))
+                    .map(|bridge_ip| bridge_ip.to_string())
+                    .collect::<Vec<String>>();
+
+                runtime.status().api = api_usage::get_api_usage(&bridges.join(", "));
+            }
+
+            // Manually selected scenes are picked up once in a while
+            #[cfg(feature = "clip-v2")]
+            if let Some(ref mut learner) = learner {
+                if last_preferences_check
+                    .map(|checked| checked.elapsed() >= PREFERENCES_INTERVAL)
+                    .unwrap_or(true)
+                {
+                    last_preferences_check = Some(Instant::now());
+                    learner.update(&context.clip, &runtime, utils::now_at_home(&conf).hour());
+                }
+            }
+
+            let today = utils::now_at_home(&conf).date_naive();
+
+            // Clocks drift slowly, checking them once a day is enough
+            if last_clock_check != Some(today) {
+                last_clock_check = Some(today);
+
+                if conf.backend == Backend::Hue {
+                    clock::check_clock(&context.hue());
+                }
+
+                if let Some(ref mut holidays) = holidays {
+                    holidays.update(today);
+                }
+            }
+
+            // Print a summary of the past day once it's over
+            if primary && today != last_summary_date {
+                last_summary_date = today;
+                info!(
+                    "Daily summary{}:\n{}",
+                    conf.profile
+                        .as_ref()
+                        .map(|profile| format!(" of {}", profile))
+                        .unwrap_or_default(),
+                    runtime.status()
+                );
+            }
+
+            // Scenes and groups are only needed if any light changed or the last snapshot is outdated
+            let initializing = last_reachable.is_empty();
+            let any_changed = all_lights.iter().any(|light| {
+                last_reachable
+                    .get(&light.id)
+                    .map(|reachable| *reachable != light.state.reachable)
+                    .unwrap_or(true)
+            });
+
+            // Presence is only needed for arrival scenes
+            let mut arrived = false;
+
+            if !conf.arrival_rooms.is_empty() {
+                match backend::get_all_sensors(&context) {
+                    Ok(sensors) => {
+                        let home = presence::is_home(&sensors, conf.presence_sensors.as_ref());
+                        debug!("Bridge: GET /sensors → home {:?}", home);
+
+                        arrived = last_presence == Some(false) && home == Some(true);
+                        last_presence = home.or(last_presence);
+                    }
+                    Err(err) => eprintln!("Failed to retrieve sensors: {}", err),
+                }
+            }
+
+            // Triggers report arrivals as well, e.g. a doorbell
+            let arrivals = runtime.arrivals();
+
+            if arrivals != last_arrivals {
+                last_arrivals = arrivals;
+                arrived = !conf.arrival_rooms.is_empty();
+            }
+
+            let outdated = last_snapshot
+                .map(|(built, date)| {
+                    built.elapsed() >= SNAPSHOT_MAX_AGE
+                        || date != utils::now_at_home(&conf).date_naive()
+                })
+                .unwrap_or(true);
+
+            if !any_changed && !arrived && !outdated {
+                return Ok(());
+            }
+
+            // Scenes and groups are retrieved at the same time, together with streaming entertainment areas
+            let refresh = !resource_cache.is_valid(&all_lights);
+            let (mut scenes, mut groups, mut streaming_lights) = (None, None, None);
+            let mut requests = Vec::<backend::Request>::new();
+
+            if refresh {
+                requests.push(Box::new(|| {
+                    scenes = Some(backend::get_all_scenes(&context))
+                }));
+                requests.push(Box::new(|| {
+                    groups = Some(backend::get_all_groups(&context))
+                }));
+            }
+
+            // Hue Sync and other entertainment apps would be interrupted by scenes, only bridges stream
+            if conf.backend == Backend::Hue {
+                requests.push(Box::new(|| {
+                    streaming_lights = Some(entertainment::get_streaming_lights(&context.hue()))
+                }));
+            }
+
+            backend::concurrently(conf.bridge_concurrency, requests);
+
+            let (mut all_scenes, all_groups) = resource_cache.get(&all_lights, || {
+                let scenes = scenes.expect("scenes were retrieved").map_err(|error| {
+                    format!(
+                        "Failed to retrieve scenes from {}: {}",
+                        context.bridge_ip, error
+                    )
+                })?;
+
+                let groups = groups.expect("groups were retrieved").map_err(|error| {
+                    format!(
+                        "Failed to retrieve groups from {}: {}",
+                        context.bridge_ip, error
+                    )
+                })?;
+
+                debug!(
+                    "Bridge: GET /scenes → {} scenes, GET /groups → {} groups",
+                    scenes.len(),
+                    groups.len()
+                );
+
+                Ok((scenes, groups))
+            })?;
+
+            if let Some(ref mut schedules) = schedules {
+                schedules.update();
+                schedules.apply(&mut all_scenes, &all_groups);
+            }
+
+            if !conf.named_schedules.is_empty() {
+                schedules::expand_named(&mut all_scenes, &conf.named_schedules);
+            }
+
+            let Some(mut parser) = utils::create_parser(&conf) else {
+                eprintln!("Failed to retrieve sunrise/sunset");
+                return Ok(());
+            };
+
+            if let Some(ref holidays) = holidays {
+                parser.define_holidays(holidays.dates().clone());
+            }
+
+            last_reachable = all_lights
+                .iter()
+                .map(|light| (light.id.clone(), light.state.reachable))
+                .collect();
+
+            if initializing {
+                info!("Initialized reachable lights.");
+            }
+
+            // Arrivals only apply scenes within the configured time-range
+            let arrival = arrived
+                && match parser.extract_time_range(&conf.arrival_time) {
+                    Some(range) => parser.matches_time_range(
+                        &range,
+                        utils::seconds_of_day(&utils::now_at_home(&conf)),
+                    ),
+                    None => {
+                        eprintln!("Failed to parse ARRIVAL_TIME: {}", conf.arrival_time);
+                        false
+                    }
+                };
+
+            if arrival {
+                runtime.events.publish(Event::Arrived);
+            }
+
+            // Timeslots of smart scenes act as schedules for the scenes they target
+            #[cfg(feature = "clip-v2")]
+            let (smart_ranges, smart_scene_ids) = if conf.smart_scenes {
+                let weekday = utils::now_at_home(&conf)
+                    .format("%A")
+                    .to_string()
+                    .to_lowercase();
+
+                match smart_scenes::get_smart_schedules(&context.clip, &parser, &weekday) {
+                    Ok(schedules) => smart_scenes::into_ranges(schedules),
+                    Err(err) => {
+                        eprintln!("Failed to retrieve smart scenes: {}", err);
+                        Default::default()
+                    }
+                }
+            } else {
+                Default::default()
+            };
+
+            #[cfg(not(feature = "clip-v2"))]
+            let (smart_ranges, smart_scene_ids) = (HashMap::new(), HashMap::new());
+
+            // Sensors are only needed if any scene depends on their readings, only bridges have them
+            let mut environment = Environment::default();
+
+            if conf.backend.has_sensors()
+                && all_scenes.iter().any(|scene| {
+                    conditions::parse_conditions(&scene.name)
+                        .iter()
+                        .any(Condition::needs_sensors)
+                })
+            {
+                match backend::get_all_sensors(&context) {
+                    Ok(sensors) => {
+                        environment.temperature = conditions::get_temperature(
+                            &sensors,
+                            conf.temperature_sensor.as_deref(),
+                        );
+
+                        debug!(
+                            "Bridge: GET /sensors → temperature {:?}",
+                            environment.temperature
+                        );
+                    }
+                    Err(err) => eprintln!("Failed to retrieve sensors: {}", err),
+                }
+            }
+
+            // Misconfigured scene names would otherwise go unnoticed
+            if initializing {
+                let findings = schedule_report::analyze(
+                    &conf,
+                    &parser,
+                    &all_scenes,
+                    &all_groups,
+                    &all_lights,
+                    &smart_ranges,
+                );
+
+                match findings.is_empty() {
+                    true => info!("Schedule report: no overlaps, gaps or unused scenes"),
+                    false => info!("Schedule report:\n  {}", findings.join("\n  ")),
+                }
+            }
+
+            // Keep track of all scenes with a schedule, including those that never fire
+            {
+                let mut status = runtime.status();
+                for scene in all_scenes.iter() {
+                    if !utils::is_manual_scene(&conf, scene)
+                        && (!parser.extract_time_ranges(&scene.name).is_empty()
+                            || parser.extract_cron(&scene.name).is_some()
+                            || smart_ranges.contains_key(&scene.id))
+                    {
+                        status.register_scene(&scene.id, &scene.name);
+
+                        // Lights deleted from the bridge don't hold up the scene, but it's likely outdated
+                        let missing_lights = scene
+                            .lights
+                            .iter()
+                            .flatten()
+                            .filter(|light_id| {
+                                !all_lights.iter().any(|light| &light.id == *light_id)
+                            })
+                            .cloned()
+                            .collect::<Vec<String>>();
+
+                        if !missing_lights.is_empty() && dangling_scenes.insert(scene.id.clone()) {
+                            eprintln!(
+                                "Scene \"{}\" references lights that don't exist anymore: {}, they're ignored",
+                                scene.name,
+                                missing_lights.join(", ")
+                            );
+                        }
+                    }
+                }
+            }
+
+            let mut snapshot = Snapshot::new(
+                all_lights,
+                all_scenes,
+                all_groups,
+                parser,
+                smart_ranges,
+                smart_scene_ids,
+                environment,
+            );
+
+            last_snapshot = Some((Instant::now(), utils::now_at_home(&conf).date_naive()));
+            snapshot.arrival = arrival;
+            snapshot.clock_jumped = clock_jumped;
+
+            match streaming_lights {
+                Some(Ok(lights)) => snapshot.streaming_lights = lights,
+                Some(Err(err)) => eprintln!("Failed to retrieve entertainment areas: {}", err),
+                None => {}
+            }
+
+            #[cfg(feature = "clip-v2")]
+            if let Some(ref learner) = learner {
+                snapshot.scene_weights = learner
+                    .preferences
+                    .weights(utils::now_at_home(&conf).hour());
+            }
+            let snapshot = Arc::new(snapshot);
+
+            // Lights are still monitored in safe mode, but engines are what write to the bridge
+            if safe_mode {
+                return Ok(());
+            }
+
+            // Every room gets its own engine, everything else is handled by the one without a room
+            let rooms = snapshot
+                .rooms()
+                .into_iter()
+                .map(|(id, name)| (Some(id), name))
+                .chain([(None, "Other".to_string())])
+                .collect::<Vec<(Option<String>, String)>>();
+
+            // Engines of removed rooms stop once their sender is dropped
+            engines.retain(|room, _| rooms.iter().any(|(id, _)| id == room));
+
+            for (room, name) in rooms {
+                let engine = engines
+                    .entry(room.clone())
+                    .or_insert_with(|| Engine::spawn(room.clone(), name.clone(), context.clone()));
+
+                // Restart engines whose thread died, they pick up again with the current snapshot
+                if let Err(err) = engine.send(snapshot.clone()) {
+                    eprintln!(
+                        "Engine of room \"{}\" stopped unexpectedly, restarting",
+                        name
+                    );
+                    runtime
+                        .status()
+                        .record_room_restart(&engine::engine_key(&context, &room));
+
+                    *engine = Engine::spawn(room, name, context.clone());
+
+                    let _ = engine.send(err.0);
+                }
+            }
+
+            clock_jumped = false;
+
+            Ok(())
+        }));
+
+        let bridge = context.bridge_ip.to_string();
+
+        match result {
+            Ok(Ok(())) => {
+                let failures = backoff.succeed();
+                last_error = None;
+
+                if bridge_id.is_none() && conf.backend == Backend::Hue {
+                    bridge_id = discovery::bridge_id(&context).ok();
+                }
+
+                if failures >= backoff::DEGRADED_AFTER {
+                    runtime.status().degraded_bridges.remove(&bridge);
+                    runtime
+                        .events
+                        .publish(Event::BridgeRecovered { bridge, failures });
+                }
+            }
+            Ok(Err(error)) => {
+                backoff.fail();
+
+                // The same error over and over only floods the log
+                match last_error.as_ref() == Some(&error) {
+                    true => debug!("{} (attempt {})", error, backoff.failures()),
+                    false => eprintln!("{}", error),
+                }
+
+                if backoff.failures() >= backoff::DEGRADED_AFTER {
+                    runtime.status().degraded_bridges.insert(
+                        bridge.clone(),
+                        DegradedBridge {
+                            failures: backoff.failures(),
+                            last_error: error.clone(),
+                        },
+                    );
+                }
+
+                if backoff.failures() == backoff::DEGRADED_AFTER {
+                    runtime.events.publish(Event::BridgeDegraded {
+                        bridge: bridge.clone(),
+                        error: error.clone(),
+                    });
+                }
+
+                last_error = Some(error);
+
+                // Bridges usually get their address from DHCP, it may have changed while it was offline
+                if conf.backend == Backend::Hue
+                    && backoff.failures() % discovery::REDISCOVER_AFTER == 0
+                {
+                    if let Some(bridge_ip) = discovery::rediscover(&context, bridge_id.as_deref()) {
+                        runtime.status().degraded_bridges.remove(&bridge);
+                        runtime.events.publish(Event::BridgeMoved {
+                            bridge,
+                            address: bridge_ip.to_string(),
+                        });
+
+                        // Engines stop once their sender is dropped, they're started again with the new address
+                        context = context.moved_to(bridge_ip);
+                        engines.clear();
+                        resource_cache.invalidate();
+                        backoff.succeed();
+                        last_error = None;
+                    }
+                }
+            }
+            Err(payload) => {
+                eprintln!(
+                    "Scheduler panicked while polling the bridge: {}, retrying with a fresh snapshot",
+                    engine::panic_message(payload.as_ref())
+                );
+
+                runtime.status().record_panic();
+                last_reachable.clear();
+                resource_cache.invalidate();
+            }
+        }
+    }
+}
+
+/// Reports a reloaded configuration, settings the running connections were made with only change on a restart.
+/// A new `MODE` is switched to right away, a mode set through the control api is kept otherwise.
+fn reload_config(runtime: &Runtime, current: &config::Config, reloaded: &config::Config) {
+    logging::init(reloaded.log_level);
+    info!(
+        "Configuration{} reloaded",
+        reloaded
+            .profile
+            .as_ref()
+            .map(|profile| format!(" of {}", profile))
+            .unwrap_or_default()
+    );
+
+    if current.mode != reloaded.mode {
+        runtime.set_mode(reloaded.mode.clone());
+    }
+
+    #[cfg_attr(
+        not(any(
+            feature = "clip-v2",
+            feature = "control",
+            feature = "hooks",
+            feature = "zigbee2mqtt",
+            feature = "home-assistant"
+        )),
+        allow(unused_mut)
+    )]
+    let mut restart_required = vec![
+        ("BRIDGE_IP", current.bridge_ip != reloaded.bridge_ip),
+        (
+            "BRIDGE_USERNAME",
+            current.bridge_username != reloaded.bridge_username,
+        ),
+        ("BRIDGES", current.bridges != reloaded.bridges),
+        ("BRIDGE_BACKEND", current.backend != reloaded.backend),
+        (
+            "BRIDGE_RATE_LIMIT",
+            current.bridge_rate_limit != reloaded.bridge_rate_limit,
+        ),
+        (
+            "BRIDGE_TIMEOUT",
+            current.bridge_timeout != reloaded.bridge_timeout,
+        ),
+        ("PLUGINS", current.plugins != reloaded.plugins),
+        ("STATE_FILE", current.state_file != reloaded.state_file),
+        ("DEBUG_FILE", current.debug_file != reloaded.debug_file),
+    ];
+
+    #[cfg(feature = "hooks")]
+    restart_required.extend([
+        ("WEBHOOK_URL", current.webhook_url != reloaded.webhook_url),
+        (
+            "ON_SCENE_APPLIED or ON_GROUP_OFF",
+            current.hooks != reloaded.hooks,
+        ),
+    ]);
+
+    #[cfg(feature = "zigbee2mqtt")]
+    restart_required.extend([
+        (
+            "MQTT_ADDRESS, MQTT_CLIENT_ID, MQTT_USERNAME or MQTT_PASSWORD",
+            current.mqtt != reloaded.mqtt,
+        ),
+        (
+            "ZIGBEE2MQTT_TOPIC",
+            current.zigbee2mqtt_topic != reloaded.zigbee2mqtt_topic,
+        ),
+    ]);
+
+    #[cfg(feature = "home-assistant")]
+    restart_required.extend([
+        (
+            "HOME_ASSISTANT_ADDRESS",
+            current.home_assistant_address != reloaded.home_assistant_address,
+        ),
+        (
+            "HOME_ASSISTANT_TOKEN",
+            current.home_assistant_token != reloaded.home_assistant_token,
+        ),
+    ]);
+
+    #[cfg(feature = "clip-v2")]
+    restart_required.extend([
+        (
+            "BRIDGE_TLS_VERIFY",
+            current.bridge_tls_verify != reloaded.bridge_tls_verify,
+        ),
+        (
+            "BRIDGE_CA_FILE",
+            current.bridge_ca_file != reloaded.bridge_ca_file,
+        ),
+        ("BRIDGE_ID", current.bridge_id != reloaded.bridge_id),
+    ]);
+
+    #[cfg(feature = "control")]
+    restart_required.extend([
+        (
+            "CONTROL_ADDRESS",
+            current.control_address != reloaded.control_address,
+        ),
+        (
+            "CONTROL_SOCKET",
+            current.control_socket != reloaded.control_socket,
+        ),
+    ]);
+
+    for (key, _) in restart_required.iter().filter(|(_, changed)| *changed) {
+        eprintln!("{} changed, it only takes effect after a restart", key);
+    }
+}
+
+/// Short summary of each light, e.g. `3 "Hall" reachable on`
+fn light_stats(lights: &[Light]) -> Vec<String> {
+    let mut light_stats = lights
+        .iter()
+        .map(|light| {
+            format!(
+                "{} \"{}\" {} {}",
+                light.id,
+                light.name,
+                if light.state.reachable {
+                    "reachable"
+                } else {
+                    "unreachable"
+                },
+                match light.state.on {
+                    Some(true) => "on",
+                    Some(false) => "off",
+                    None => "unknown",
+                }
+            )
+        })
+        .collect::<Vec<String>>();
+
+    light_stats.sort();
+    light_stats
+}
+
+fn write_debug_file(lights: &[Light], file: &mut File) {
+    let mut light_stats = lights
+        .iter()
+        .map(|light| {
+            format!(
+                "light_{:0>3} = {{ name = \"{}\", reachable = {}, on = {} }}",
+                light.id,
+                light.name,
+                light.state.reachable,
+                light.state.on.unwrap_or(false)
+            )
+        })
+        .collect::<Vec<String>>();
+
+    light_stats.sort();
+
+    // Seek to the beginning of the file
+    if let Err(err) = file.seek(std::io::SeekFrom::Start(0)) {
+        eprintln!("Failed to seek to beginning of debug file: {}", err);
+    }
+
+    if let Err(err) = file.write_all(light_stats.join("\n").as_bytes()) {
+        eprintln!("Failed to write to debug file: {}", err);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::config::Config;
+use crate::debug;
+use huelib2::resource::Light;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Holds back changes of reachability until they lasted for the debounce duration of a light, see `LIGHT_DEBOUNCE`.
+/// Lights bouncing between reachable and unreachable in a weak mesh would otherwise trigger scenes over and over.
+#[derive(Default)]
+pub struct Debouncer {
+    /// Reachability of each light the engines were told about
+    stable: HashMap<String, bool>,
+    /// Lights whose reachability differs from the stable one, and since when
+    pending: HashMap<String, Instant>,
+}
+
+impl Debouncer {
+    /// Replaces the reachability of lights which changed too recently with the one they had before
+    pub fn apply(&mut self, conf: &Config, lights: &mut [Light]) {
+        let now = Instant::now();
+
+        for light in lights.iter_mut() {
+            let debounce = conf
+                .light_debounces
+                .get(&light.name)
+                .copied()
+                .or(conf.light_debounce)
+                .filter(|debounce| !debounce.is_zero());
+
+            self.observe(light, debounce, now);
+        }
+    }
+
+    /// Debounces the reachability of a light seen at `now`
+    fn observe(&mut self, light: &mut Light, debounce: Option<Duration>, now: Instant) {
+        let reachable = light.state.reachable;
+
+        // Lights seen for the first time are taken as they are
+        let stable = *self.stable.entry(light.id.clone()).or_insert(reachable);
+
+        if reachable == stable {
+            if self.pending.remove(&light.id).is_some() {
+                debug!(
+                    "Light \"{}\" went back to being {} within its debounce, ignoring it",
+                    light.name,
+                    reachability(stable)
+                );
+            }
+
+            return;
+        }
+
+        let since = *self.pending.entry(light.id.clone()).or_insert(now);
+
+        match debounce {
+            Some(debounce) if now.duration_since(since) < debounce => {
+                light.state.reachable = stable
+            }
+            _ => {
+                self.pending.remove(&light.id);
+                self.stable.insert(light.id.clone(), reachable);
+            }
+        }
+    }
+}
+
+fn reachability(reachable: bool) -> &'static str {
+    match reachable {
+        true => "reachable",
+        false => "unreachable",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend;
+    use huelib2::resource::light::State as LightState;
+
+    fn light(reachable: bool) -> Light {
+        let state = LightState {
+            on: Some(true),
+            brightness: None,
+            hue: None,
+            saturation: None,
+            color_space_coordinates: None,
+            color_temperature: None,
+            alert: None,
+            effect: None,
+            color_mode: None,
+            reachable,
+        };
+
+        backend::new_light(
+            "1".to_string(),
+            "Lamp".to_string(),
+            "Extended color light",
+            state,
+        )
+    }
+
+    #[test]
+    fn test_debounce() {
+        let mut debouncer = Debouncer::default();
+        let debounce = Some(Duration::from_secs(10));
+        let start = Instant::now();
+
+        // Reachability as the engines see it after a poll at `secs`
+        let mut poll = |reachable: bool, secs: u64| {
+            let mut light = light(reachable);
+            debouncer.observe(&mut light, debounce, start + Duration::from_secs(secs));
+            light.state.reachable
+        };
+
+        assert!(poll(true, 0));
+
+        // A burst of drop-outs within the debounce is held back
+        assert!(poll(false, 1));
+        assert!(poll(true, 2));
+        assert!(poll(false, 3));
+        assert!(poll(true, 4));
+        assert!(poll(false, 5));
+
+        // Once the light stayed unreachable for the debounce, it's passed on exactly once
+        assert!(poll(false, 14));
+        assert!(!poll(false, 15));
+        assert!(!poll(false, 16));
+
+        // Coming back is debounced as well
+        assert!(!poll(true, 17));
+        assert!(poll(true, 27));
+        assert!(poll(true, 28));
+    }
+
+    #[test]
+    fn test_without_debounce() {
+        let mut debouncer = Debouncer::default();
+        let now = Instant::now();
+
+        for reachable in [true, false, true, false] {
+            let mut light = light(reachable);
+            debouncer.observe(&mut light, None, now);
+            assert_eq!(light.state.reachable, reachable);
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use crate::api_usage;
+use crate::backend;
+use crate::debug;
+use crate::scene_states::SceneLightState;
+use crate::watchdog;
+use huelib2::resource::group::{CreatableKind, Kind as GroupKind, State as GroupState};
+use huelib2::resource::light::State as LightState;
+use huelib2::resource::scene::{AppData, Kind as SceneKind, Version};
+use huelib2::resource::{ColorMode, Group, Light, Scene};
+use huelib2::Bridge;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Devices deCONZ lists as lights although they aren't any, e.g. the ConBee itself
+const IGNORED_LIGHT_TYPES: [&str; 1] = ["Configuration tool"];
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct DeconzLightState {
+    on: Option<bool>,
+    bri: Option<u8>,
+    hue: Option<u16>,
+    sat: Option<u8>,
+    xy: Option<(f32, f32)>,
+    ct: Option<u16>,
+    colormode: Option<String>,
+    reachable: bool,
+}
+
+#[derive(Deserialize)]
+struct DeconzLight {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    state: DeconzLightState,
+    #[serde(default)]
+    modelid: String,
+    manufacturername: Option<String>,
+    #[serde(default)]
+    uniqueid: String,
+    #[serde(default)]
+    swversion: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DeconzGroupScene {
+    id: String,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct DeconzGroupState {
+    any_on: bool,
+    all_on: bool,
+}
+
+#[derive(Deserialize)]
+struct DeconzGroup {
+    name: String,
+    #[serde(default)]
+    lights: Vec<String>,
+    #[serde(default)]
+    hidden: bool,
+    #[serde(default)]
+    state: DeconzGroupState,
+    #[serde(default)]
+    scenes: Vec<DeconzGroupScene>,
+}
+
+#[derive(Deserialize)]
+struct DeconzSceneLight {
+    id: String,
+    on: Option<bool>,
+    bri: Option<u8>,
+    x: Option<u16>,
+    y: Option<u16>,
+    ct: Option<u16>,
+}
+
+#[derive(Deserialize)]
+struct DeconzScene {
+    name: String,
+    #[serde(default)]
+    lights: Vec<DeconzSceneLight>,
+}
+
+/// Fetches a resource of the REST api of deCONZ, which mostly follows the v1 api of the hue bridge
+fn get<T: DeserializeOwned>(bridge: &Bridge, path: &str) -> Result<T, Box<ureq::Error>> {
+    let url = format!(
+        "http://{}/api/{}/{}",
+        bridge.ip_address(),
+        bridge.username(),
+        path
+    );
+
+    debug!("deCONZ: GET /{}", path);
+
+    api_usage::track(|| watchdog::agent().get(&url).call().map_err(Box::new))?
+        .into_json::<T>()
+        .map_err(|err| Box::new(ureq::Error::from(err)))
+}
+
+/// Scenes belong to a group, their id consists of both, e.g. `3/1`
+fn split_scene_id(scene_id: &str) -> (&str, &str) {
+    scene_id.split_once("/").unwrap_or(("0", scene_id))
+}
+
+/// Converts a light into the shape of the hue api
+fn into_light(id: String, light: DeconzLight) -> Light {
+    let state = LightState {
+        on: light.state.on,
+        brightness: light.state.bri,
+        hue: light.state.hue,
+        saturation: light.state.sat,
+        color_space_coordinates: light.state.xy,
+        color_temperature: light.state.ct,
+        alert: None,
+        effect: None,
+        color_mode: match light.state.colormode.as_deref() {
+            Some("ct") => Some(ColorMode::ColorTemperature),
+            Some("xy") => Some(ColorMode::ColorSpaceCoordinates),
+            Some("hs") => Some(ColorMode::HueAndSaturation),
+            _ => None,
+        },
+        reachable: light.state.reachable,
+    };
+
+    Light {
+        model_id: light.modelid,
+        unique_id: light.uniqueid,
+        manufacturer_name: light.manufacturername,
+        software_version: light.swversion.unwrap_or_default(),
+        ..backend::new_light(id, light.name, &light.kind, state)
+    }
+}
+
+/// Returns all lights in the shape of the hue api
+pub fn get_all_lights(bridge: &Bridge) -> Result<Vec<Light>, Box<ureq::Error>> {
+    let lights = get::<HashMap<String, DeconzLight>>(bridge, "lights")?;
+
+    Ok(lights
+        .into_iter()
+        .filter(|(_, light)| !IGNORED_LIGHT_TYPES.contains(&light.kind.as_str()))
+        .map(|(id, light)| into_light(id, light))
+        .collect())
+}
+
+/// Returns a single light in the shape of the hue api
+pub fn get_light(bridge: &Bridge, id: &str) -> Result<Light, Box<ureq::Error>> {
+    let light = get::<DeconzLight>(bridge, &format!("lights/{}", id))?;
+    Ok(into_light(id.to_string(), light))
+}
+
+/// Returns all groups in the shape of the hue api, deCONZ doesn't tell rooms apart so all of them count as rooms.
+/// Hidden groups, e.g. the one with all lights, are left out.
+pub fn get_all_groups(bridge: &Bridge) -> Result<Vec<Group>, Box<ureq::Error>> {
+    let groups = get::<HashMap<String, DeconzGroup>>(bridge, "groups")?;
+
+    Ok(groups
+        .into_iter()
+        .filter(|(_, group)| !group.hidden)
+        .map(|(id, group)| Group {
+            id,
+            name: group.name,
+            lights: group.lights,
+            sensors: vec![],
+            kind: GroupKind::Creatable(CreatableKind::Room),
+            class: None,
+            state: Some(GroupState {
+                any_on: group.state.any_on,
+                all_on: group.state.all_on,
+            }),
+            model_id: None,
+            unique_id: None,
+            recycle: None,
+        })
+        .collect())
+}
+
+/// Returns the scenes of all groups in the shape of the hue api, their ids are prefixed with the id of their group
+pub fn get_all_scenes(bridge: &Bridge) -> Result<Vec<Scene>, Box<ureq::Error>> {
+    let groups = get::<HashMap<String, DeconzGroup>>(bridge, "groups")?;
+    let mut scenes = vec![];
+
+    // Groups only list their scenes, the lights of a scene have to be retrieved one by one
+    for (group_id, group) in groups.iter().filter(|(_, group)| !group.hidden) {
+        for scene in group.scenes.iter() {
+            let details =
+                get::<DeconzScene>(bridge, &format!("groups/{}/scenes/{}", group_id, scene.id))?;
+
+            scenes.push(Scene {
+                id: format!("{}/{}", group_id, scene.id),
+                name: details.name,
+                kind: SceneKind::GroupScene,
+                group: Some(group_id.clone()),
+                lights: Some(details.lights.into_iter().map(|light| light.id).collect()),
+                owner: None,
+                recycle: false,
+                locked: false,
+                app_data: AppData::default(),
+                picture: None,
+                last_update: None,
+                version: Version::Post,
+            });
+        }
+    }
+
+    Ok(scenes)
+}
+
+/// Returns the stored light states of a scene, coordinates are given in 1/65535 instead of 0 to 1
+pub fn get_scene_light_states(
+    bridge: &Bridge,
+    scene_id: &str,
+) -> Result<HashMap<String, SceneLightState>, Box<ureq::Error>> {
+    let (group_id, scene_id) = split_scene_id(scene_id);
+    let scene = get::<DeconzScene>(bridge, &format!("groups/{}/scenes/{}", group_id, scene_id))?;
+
+    Ok(scene
+        .lights
+        .into_iter()
+        .map(|light| {
+            let state = SceneLightState {
+                on: light.on,
+                bri: light.bri,
+                hue: None,
+                sat: None,
+                xy: light
+                    .x
+                    .zip(light.y)
+                    .map(|(x, y)| (x as f32 / 65535.0, y as f32 / 65535.0)),
+                ct: light.ct,
+            };
+
+            (light.id, state)
+        })
+        .collect())
+}
+
+/// Recalls a scene, deCONZ doesn't accept scenes in the action of a group
+pub fn recall_scene(bridge: &Bridge, scene_id: &str) -> Result<(), Box<ureq::Error>> {
+    let (group_id, scene_id) = split_scene_id(scene_id);
+    let url = format!(
+        "http://{}/api/{}/groups/{}/scenes/{}/recall",
+        bridge.ip_address(),
+        bridge.username(),
+        group_id,
+        scene_id
+    );
+
+    debug!(
+        "deCONZ: PUT /groups/{}/scenes/{}/recall",
+        group_id, scene_id
+    );
+
+    api_usage::track(|| {
+        watchdog::agent()
+            .put(&url)
+            .send_string("{}")
+            .map_err(Box::new)
+    })?;
+    Ok(())
+}
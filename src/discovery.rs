@@ -0,0 +1,72 @@
+use crate::api_usage;
+use crate::debug;
+use crate::engine::Context;
+use crate::watchdog;
+use serde::Deserialize;
+use std::net::IpAddr;
+
+/// Discovery service of Philips, bridges report their id and local address to it
+const DISCOVERY_URL: &str = "https://discovery.meethue.com/";
+
+/// Failed attempts in a row after which the bridge is looked for under a different address, and again after as many
+pub const REDISCOVER_AFTER: u32 = 5;
+
+#[derive(Deserialize)]
+struct DiscoveredBridge {
+    id: String,
+    #[serde(rename = "internalipaddress")]
+    ip: IpAddr,
+}
+
+/// Returns the ids and addresses of the bridges in the network, as reported by the discovery service of Philips.
+/// It's reached through HTTPS with a publicly trusted certificate, unlike the bridges themselves.
+pub fn discover() -> Result<Vec<(String, IpAddr)>, Box<ureq::Error>> {
+    let bridges = api_usage::track(|| {
+        watchdog::agent()
+            .get(DISCOVERY_URL)
+            .call()
+            .map_err(Box::new)
+    })?
+    .into_json::<Vec<DiscoveredBridge>>()
+    .map_err(|err| Box::new(ureq::Error::from(err)))?;
+
+    Ok(bridges
+        .into_iter()
+        .map(|bridge| (bridge.id.to_lowercase(), bridge.ip))
+        .collect())
+}
+
+/// Returns the id of the bridge, e.g. `001788FFFE23BFC2`
+pub fn bridge_id(context: &Context) -> Result<String, huelib2::Error> {
+    Ok(context.hue().get_config()?.bridge_id)
+}
+
+/// Looks for the bridge if it got a new address, e.g. from DHCP.
+/// A bridge only counts if it has the same id, or accepts the username if the id was never seen.
+pub fn rediscover(context: &Context, known_id: Option<&str>) -> Option<IpAddr> {
+    let candidates = match discover() {
+        Ok(candidates) => candidates,
+        Err(err) => {
+            eprintln!("Failed to search for bridges: {}", err);
+            return None;
+        }
+    };
+
+    debug!(
+        "Discovery: found {}",
+        candidates
+            .iter()
+            .map(|(id, ip)| format!("{} at {}", id, ip))
+            .collect::<Vec<String>>()
+            .join(", ")
+    );
+
+    candidates
+        .into_iter()
+        .filter(|(_, candidate)| *candidate != context.bridge_ip)
+        .find(|(id, candidate)| match known_id {
+            Some(known_id) => id.eq_ignore_ascii_case(known_id),
+            None => context.moved_to(*candidate).hue().get_all_groups().is_ok(),
+        })
+        .map(|(_, candidate)| candidate)
+}
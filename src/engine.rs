@@ -0,0 +1,2498 @@
+use crate::backend;
+use crate::calibration;
+#[cfg(feature = "clip-v2")]
+use crate::clip_v2::ClipV2;
+use crate::commands::{Check, Command, CommandQueue};
+use crate::conditions;
+use crate::conditions::Environment;
+use crate::config::Config;
+use crate::events::Event;
+#[cfg(feature = "home-assistant")]
+use crate::home_assistant::HomeAssistant;
+use crate::hue_api::HueApi;
+use crate::rate_limit::RateLimiter;
+use crate::reload;
+use crate::runtime::Runtime;
+use crate::scene_states::SceneLightState;
+use crate::simulation::Simulation;
+use crate::solar;
+use crate::state::{self, EngineState, SavedLight};
+use crate::status::RoomSchedule;
+use crate::time_range_parser::{TimeRange, TimeRangeParser};
+use crate::utils::{self, ScheduledScene, UnscheduledPolicy};
+#[cfg(feature = "zigbee2mqtt")]
+use crate::zigbee2mqtt::Zigbee2Mqtt;
+use crate::{debug, info};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use huelib2::resource::group::StateModifier;
+use huelib2::resource::{light, Adjust, ColorMode, Group, Light, Scene};
+use huelib2::Bridge;
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Engines wake up at least this often without changes, see [Engine::next_wakeup]
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Brightness and color temperature (mired) of the "Bright" scene of the Hue app, used by `UNSCHEDULED_POLICY`.
+/// It's also the white lights come back with after a power cut, unless the bridge was told otherwise.
+const BRIGHT_BRIGHTNESS: u8 = 254;
+const BRIGHT_COLOR_TEMPERATURE: u16 = 366;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct StateChange {
+    pub timestamp: Option<Instant>,
+    pub reachable: bool,
+    /// On/off, brightness and color the light was last seen with, or was given by the scene applied to it
+    pub state: Option<SceneLightState>,
+}
+
+impl StateChange {
+    /// Lights keep the state they were last seen with while they're unreachable, the bridge only reports a stale one
+    fn of(light: &Light, timestamp: Option<Instant>, last: Option<&StateChange>) -> Self {
+        StateChange {
+            timestamp,
+            reachable: light.state.reachable,
+            state: match light.state.reachable {
+                true => Some(SceneLightState::of(&light.state)),
+                false => last.and_then(|last| last.state.clone()),
+            },
+        }
+    }
+}
+
+/// Everything retrieved from the bridge in one iteration, shared by all engines
+pub struct Snapshot {
+    pub lights: Vec<Light>,
+    pub scenes: Vec<Scene>,
+    pub groups: Vec<Group>,
+    pub parser: TimeRangeParser,
+    pub smart_ranges: HashMap<String, Vec<TimeRange>>,
+    pub smart_scene_ids: HashMap<String, String>,
+    pub environment: Environment,
+    /// Someone just arrived home, see `ARRIVAL_ROOMS`
+    pub arrival: bool,
+    /// The clock jumped since the last snapshot, e.g. because the host woke up from suspend
+    pub clock_jumped: bool,
+    /// Lights of entertainment areas that are streaming, they're left alone until it ends
+    pub streaming_lights: HashSet<String>,
+    /// How often scenes were selected manually at this hour, see `SCENE_PREFERENCES_FILE`
+    pub scene_weights: HashMap<String, u32>,
+    light_rooms: HashMap<String, String>,
+    light_index: HashMap<String, usize>,
+    attached_lights: HashSet<String>,
+}
+
+impl Snapshot {
+    pub fn new(
+        lights: Vec<Light>,
+        scenes: Vec<Scene>,
+        groups: Vec<Group>,
+        parser: TimeRangeParser,
+        smart_ranges: HashMap<String, Vec<TimeRange>>,
+        smart_scene_ids: HashMap<String, String>,
+        environment: Environment,
+    ) -> Snapshot {
+        let light_rooms = groups
+            .iter()
+            .filter(|group| utils::group_type(group) == "Room")
+            .flat_map(|room| {
+                room.lights
+                    .iter()
+                    .map(|light_id| (light_id.clone(), room.id.clone()))
+            })
+            .collect::<HashMap<String, String>>();
+
+        let light_index = lights
+            .iter()
+            .enumerate()
+            .map(|(index, light)| (light.id.clone(), index))
+            .collect::<HashMap<String, usize>>();
+
+        let attached_lights = lights
+            .iter()
+            .filter(|light| utils::is_attached_light(light))
+            .map(|light| light.id.clone())
+            .chain(
+                groups
+                    .iter()
+                    .filter(|group| utils::is_attached_group(group))
+                    .flat_map(|group| group.lights.iter().cloned()),
+            )
+            .collect::<HashSet<String>>();
+
+        Snapshot {
+            lights,
+            scenes,
+            groups,
+            parser,
+            smart_ranges,
+            smart_scene_ids,
+            environment,
+            arrival: false,
+            clock_jumped: false,
+            streaming_lights: HashSet::new(),
+            scene_weights: HashMap::new(),
+            light_rooms,
+            light_index,
+            attached_lights,
+        }
+    }
+
+    pub fn light(&self, light_id: &str) -> Option<&Light> {
+        self.light_index
+            .get(light_id)
+            .map(|index| &self.lights[*index])
+    }
+
+    /// Attached lights are marked with `(att)`, either themselves or the room or zone they're in
+    pub fn is_attached(&self, light: &Light) -> bool {
+        self.attached_lights.contains(&light.id)
+    }
+
+    /// Whether any of the lights is part of an entertainment area that's streaming, e.g. for Hue Sync
+    pub fn is_streaming(&self, light_ids: &[String]) -> bool {
+        light_ids
+            .iter()
+            .any(|light_id| self.streaming_lights.contains(light_id))
+    }
+
+    /// Returns all rooms as id and name
+    pub fn rooms(&self) -> Vec<(String, String)> {
+        self.groups
+            .iter()
+            .filter(|group| utils::group_type(group) == "Room")
+            .map(|room| (room.id.clone(), room.name.clone()))
+            .collect()
+    }
+
+    /// Returns the room all given lights are in, if they share one
+    fn common_room(&self, light_ids: &[String]) -> Option<String> {
+        let mut rooms = light_ids.iter().map(|id| self.light_rooms.get(id));
+        let first = rooms.next()??;
+
+        if rooms.all(|room| room == Some(first)) {
+            Some(first.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Scenes belong to the room they're stored for, or the one all their lights are in
+    fn room_of_scene(&self, scene: &Scene) -> Option<String> {
+        match &scene.group {
+            Some(group_id) if self.light_rooms.values().any(|room| room == group_id) => {
+                Some(group_id.clone())
+            }
+            _ => self.common_room(scene.lights.as_deref().unwrap_or_default()),
+        }
+    }
+
+    /// Groups belong to the room all their lights are in, zones usually span multiple rooms
+    fn room_of_group(&self, group: &Group) -> Option<String> {
+        if utils::group_type(group) == "Room" {
+            Some(group.id.clone())
+        } else {
+            self.common_room(&group.lights)
+        }
+    }
+
+    fn room_of_light(&self, light: &Light) -> Option<String> {
+        self.light_rooms.get(&light.id).cloned()
+    }
+}
+
+/// Everything an engine needs besides its room, shared by all engines
+#[derive(Clone)]
+pub struct Context {
+    pub conf: Arc<Config>,
+    pub bridge: Bridge,
+    /// Address of `bridge`, see `BRIDGES`
+    pub bridge_ip: IpAddr,
+    /// Shared by all engines of the bridge
+    pub limiter: Arc<RateLimiter>,
+    #[cfg(feature = "clip-v2")]
+    pub clip: Arc<ClipV2>,
+    /// Connection to zigbee2mqtt if it's the backend
+    #[cfg(feature = "zigbee2mqtt")]
+    pub zigbee2mqtt: Option<Arc<Zigbee2Mqtt>>,
+    /// Connection to Home Assistant if it's the backend
+    #[cfg(feature = "home-assistant")]
+    pub home_assistant: Option<Arc<HomeAssistant>>,
+    /// Simulated bridge of `hue-scheduler simulate`
+    pub simulation: Option<Arc<Simulation>>,
+    pub runtime: Arc<Runtime>,
+    pub started_at: Instant,
+}
+
+impl Context {
+    /// Same bridge under a new address, engines of the old one have to be restarted with it
+    pub fn moved_to(&self, bridge_ip: IpAddr) -> Context {
+        let bridge = Bridge::new(bridge_ip, self.bridge.username());
+
+        Context {
+            #[cfg(feature = "clip-v2")]
+            clip: Arc::new(self.clip.moved_to(&bridge)),
+            bridge,
+            bridge_ip,
+            ..self.clone()
+        }
+    }
+
+    /// Client for the v1 api of the bridge, through HTTPS with the certificate checks of `clip` if it's built in
+    pub fn hue(&self) -> HueApi {
+        #[cfg(feature = "clip-v2")]
+        let agent = self.clip.agent().clone();
+        #[cfg(not(feature = "clip-v2"))]
+        let agent = crate::watchdog::agent().clone();
+
+        HueApi::new(agent, &self.bridge)
+    }
+}
+
+/// Scene applied with an overnight range, turned off once the range ends if `OVERNIGHT_AUTO_OFF` is set
+#[derive(Clone, Debug)]
+struct OvernightScene {
+    name: String,
+    group: Option<String>,
+    lights: Vec<String>,
+    range: TimeRange,
+}
+
+/// Scheduled scene applied by the scheduler, see `SCENE_TRANSITIONS`
+struct ActiveScene {
+    name: String,
+    lights: Vec<String>,
+    range: TimeRange,
+}
+
+/// Schedules a single room, or everything not belonging to one room if `room` is `None`.
+/// Each engine runs in its own thread so a failing room never stalls the others.
+pub struct Engine {
+    room: Option<String>,
+    name: String,
+    context: Context,
+    light_states: HashMap<String, StateChange>,
+    overnight_scenes: HashMap<String, OvernightScene>,
+    active_scenes: HashMap<String, ActiveScene>,
+    /// When scenes were applied last, to keep them for `MIN_SCENE_DURATION`
+    applied_at: HashMap<String, Instant>,
+    /// Groups waiting for `MIN_ON_DURATION` before they're turned off
+    delayed_group_offs: HashSet<String>,
+    /// Scenes and group-offs that failed and are sent again, see `COMMAND_RETRIES`
+    commands: CommandQueue,
+    schedule: Option<RoomSchedule>,
+    /// Mode the schedule was computed for
+    schedule_mode: String,
+    /// Someone arrived in a snapshot that was skipped, see [Engine::spawn]
+    missed_arrival: bool,
+    /// The clock jumped in a snapshot that was skipped, see [Engine::rebaseline]
+    missed_clock_jump: bool,
+    /// What the engine knew before a restart, it's taken over once the lights are seen for the first time
+    restored: Option<EngineState>,
+}
+
+/// Key of an engine in the status, `other` for the one without a room.
+/// Rooms of different bridges share ids, they're prefixed by the address of their bridge if there are several.
+pub fn engine_key(context: &Context, room: &Option<String>) -> String {
+    let key = room.clone().unwrap_or("other".to_string());
+
+    match context.conf.bridges.is_empty() {
+        true => key,
+        false => format!("{}/{}", context.bridge_ip, key),
+    }
+}
+
+/// Logs whether a light that became reachable again shows the state it had, or the default white of the bridge
+fn log_comeback(light: &Light, last: &StateChange) {
+    let Some(ref state) = last.state else {
+        return;
+    };
+
+    let diff = state.diff(&light.state);
+
+    let bridge_default = SceneLightState {
+        on: Some(true),
+        bri: Some(BRIGHT_BRIGHTNESS),
+        hue: None,
+        sat: None,
+        xy: None,
+        ct: Some(BRIGHT_COLOR_TEMPERATURE),
+    };
+
+    if diff.is_empty() {
+        debug!("Light \"{}\" came back in its saved state", light.name);
+    } else if bridge_default.matches(&light.state)
+        && !matches!(
+            light.state.color_mode,
+            Some(ColorMode::HueAndSaturation | ColorMode::ColorSpaceCoordinates)
+        )
+    {
+        debug!(
+            "Light \"{}\" came back at bridge-default white ({})",
+            light.name,
+            diff.join(", ")
+        );
+    } else {
+        debug!(
+            "Light \"{}\" came back changed ({})",
+            light.name,
+            diff.join(", ")
+        );
+    }
+}
+
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or(payload.downcast_ref::<String>().cloned())
+        .unwrap_or("unknown panic".to_string())
+}
+
+impl Engine {
+    pub fn new(room: Option<String>, name: String, context: Context) -> Engine {
+        let key = engine_key(&context, &room);
+        context.runtime.status().register_room(&key, &name);
+        let restored = context.runtime.saved_engine(&key);
+
+        Engine {
+            room,
+            name,
+            context,
+            light_states: HashMap::new(),
+            overnight_scenes: HashMap::new(),
+            active_scenes: HashMap::new(),
+            applied_at: HashMap::new(),
+            delayed_group_offs: HashSet::new(),
+            commands: CommandQueue::default(),
+            schedule: None,
+            schedule_mode: String::new(),
+            missed_arrival: false,
+            missed_clock_jump: false,
+            restored,
+        }
+    }
+
+    /// Starts an engine in a separate thread, it stops once the returned sender is dropped
+    pub fn spawn(room: Option<String>, name: String, context: Context) -> Sender<Arc<Snapshot>> {
+        let (sender, receiver) = channel::<Arc<Snapshot>>();
+        let mut engine = Engine::new(room, name, context);
+
+        thread::spawn(move || {
+            let mut last_snapshot: Option<Arc<Snapshot>> = None;
+            let mut generation = 0;
+
+            loop {
+                // Scenes applied and overnight scenes are kept, only the configuration is swapped
+                if let Some(conf) =
+                    reload::newer(&mut generation, engine.context.conf.profile.as_ref())
+                {
+                    engine.context.conf = conf;
+                }
+
+                // Without changes there's nothing to do until the schedule moves on or something else is due
+                let timeout = match last_snapshot {
+                    Some(_) => engine.next_wakeup(),
+                    None => engine.context.conf.ping_interval,
+                };
+
+                let (snapshot, changed) = match receiver.recv_timeout(timeout) {
+                    Ok(mut snapshot) => {
+                        // Skip snapshots this engine fell behind on, only the latest one matters
+                        while let Ok(newer) = receiver.try_recv() {
+                            engine.missed_arrival |= snapshot.arrival;
+                            engine.missed_clock_jump |= snapshot.clock_jumped;
+                            snapshot = newer;
+                        }
+
+                        (snapshot, true)
+                    }
+                    Err(RecvTimeoutError::Timeout) => match last_snapshot {
+                        Some(ref snapshot) => (snapshot.clone(), false),
+                        None => continue,
+                    },
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+
+                last_snapshot = Some(snapshot.clone());
+                engine.step(&snapshot, changed);
+            }
+        });
+
+        sender
+    }
+
+    /// Returns how long to sleep until the next boundary of the schedule, a retry or anything else is due.
+    /// It's at most `MAX_SLEEP`, e.g. to pick up a new mode or the next day.
+    fn next_wakeup(&self) -> Duration {
+        let conf = &self.context.conf;
+        let now = utils::now_at_home(conf);
+
+        let next_change = self
+            .schedule
+            .as_ref()
+            .and_then(|schedule| schedule.next_change)
+            .map(|next_change| {
+                (next_change - now.fixed_offset())
+                    .to_std()
+                    .unwrap_or_default()
+            });
+
+        // Scenes kept for `MIN_SCENE_DURATION` may end or switch once it's over
+        let min_duration_over = self.min_scene_duration().and_then(|min_duration| {
+            self.overnight_scenes
+                .keys()
+                .chain(self.active_scenes.keys())
+                .filter_map(|scene_id| self.applied_at.get(scene_id))
+                .map(|applied_at| min_duration.saturating_sub(utils::elapsed(*applied_at)))
+                .filter(|remaining| !remaining.is_zero())
+                .min()
+        });
+
+        let retry = self
+            .commands
+            .next_due()
+            .map(|due| due.saturating_duration_since(utils::instant_now()));
+
+        // Groups waiting for `MIN_ON_DURATION` are checked as often as the bridge is polled
+        let group_off = (!self.delayed_group_offs.is_empty()).then_some(conf.ping_interval);
+
+        [next_change, min_duration_over, retry, group_off]
+            .into_iter()
+            .flatten()
+            .chain([MAX_SLEEP])
+            .min()
+            .unwrap_or(MAX_SLEEP)
+    }
+
+    /// Starts over after the clock jumped, timestamps taken before can't be compared with the current time anymore.
+    /// Lights switched in the meantime are taken as they are instead of triggering scenes or group-offs,
+    /// and scenes kept for `MIN_SCENE_DURATION` as well as commands waiting to be sent again are dropped.
+    fn rebaseline(&mut self, snapshot: &Snapshot) {
+        for light in snapshot.lights.iter() {
+            if let Some(change) = self.light_states.get_mut(&light.id) {
+                change.timestamp = None;
+                change.reachable = light.state.reachable;
+            }
+        }
+
+        self.applied_at.clear();
+        self.delayed_group_offs.clear();
+        self.commands = CommandQueue::default();
+    }
+
+    /// Handles a snapshot, `changed` is false if it's the last one again after waking up without changes
+    pub fn step(&mut self, snapshot: &Snapshot, changed: bool) {
+        // Keep the state of the last successful tick, a panic may leave it half updated
+        let light_states = self.light_states.clone();
+        let mut errors = Vec::<String>::new();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let missed_clock_jump = std::mem::take(&mut self.missed_clock_jump);
+
+            if changed && (snapshot.clock_jumped || missed_clock_jump) {
+                self.rebaseline(snapshot);
+            }
+
+            if changed {
+                self.tick(snapshot, &mut errors);
+            }
+
+            // Groups waiting to be turned off are due eventually, even without changes
+            if !changed && !self.delayed_group_offs.is_empty() {
+                let ignored_light_ids = snapshot
+                    .lights
+                    .iter()
+                    .filter(|light| snapshot.is_attached(light))
+                    .map(|light| &light.id)
+                    .collect::<HashSet<&String>>();
+
+                self.turn_off_groups(snapshot, &ignored_light_ids, true, &mut errors);
+            }
+
+            self.retry_commands(snapshot, &mut errors);
+            self.run_checks(snapshot, &mut errors);
+            self.transition_scenes(snapshot, &mut errors);
+            self.end_overnight_scenes(snapshot, &mut errors);
+            self.update_schedule(snapshot, changed);
+        }));
+
+        for error in errors.iter() {
+            eprintln!("[{}] {}", self.name, error);
+        }
+
+        let panic = result.err().map(|payload| panic_message(payload.as_ref()));
+
+        if let Some(ref message) = panic {
+            eprintln!(
+                "[{}] Engine panicked with {} lights and {} scenes: {}, restoring last known state",
+                self.name,
+                snapshot.lights.len(),
+                snapshot.scenes.len(),
+                message
+            );
+
+            self.light_states = light_states;
+        }
+
+        // Idle wake-ups only count if something happened
+        if !changed && errors.is_empty() && panic.is_none() {
+            return;
+        }
+
+        self.context.runtime.status().record_room_tick(
+            &engine_key(&self.context, &self.room),
+            &errors,
+            panic,
+        );
+
+        self.save(snapshot);
+    }
+
+    /// Hands the state of the lights of the room and pending group-offs to the runtime, see `STATE_FILE`
+    fn save(&self, snapshot: &Snapshot) {
+        // Nothing is known before the lights were seen, what was restored is kept until then
+        if self.context.conf.state_file.is_none() || self.light_states.is_empty() {
+            return;
+        }
+
+        let now = utils::instant_now();
+        let wall_clock = utils::now_at_home(&self.context.conf).with_timezone(&Utc);
+        let group_lights = snapshot
+            .groups
+            .iter()
+            .filter(|group| snapshot.room_of_group(group) == self.room)
+            .flat_map(|group| group.lights.iter())
+            .collect::<HashSet<&String>>();
+
+        let lights = snapshot
+            .lights
+            .iter()
+            .filter(|light| self.owns_light(snapshot, light) || group_lights.contains(&light.id))
+            .filter_map(|light| Some((light.id.clone(), self.light_states.get(&light.id)?)))
+            .map(|(light_id, change)| {
+                let changed_at = change.timestamp.map(|timestamp| {
+                    wall_clock
+                        - chrono::Duration::from_std(now.saturating_duration_since(timestamp))
+                            .unwrap_or_default()
+                });
+
+                let saved = SavedLight {
+                    changed_at,
+                    reachable: change.reachable,
+                    state: change.state.clone(),
+                };
+
+                (light_id, saved)
+            })
+            .collect();
+
+        self.context.runtime.save_engine(
+            &engine_key(&self.context, &self.room),
+            EngineState {
+                lights,
+                delayed_group_offs: self.delayed_group_offs.iter().cloned().collect(),
+            },
+        );
+    }
+
+    /// The state a light had before a restart. Only lights unreachable since then keep when they went off,
+    /// so groups waiting for `MIN_ON_DURATION` are turned off when they would have been without the restart.
+    fn restored_light(&self, light: &Light) -> Option<StateChange> {
+        let saved = self.restored.as_ref()?.lights.get(&light.id)?;
+        let wall_clock = utils::now_at_home(&self.context.conf).with_timezone(&Utc);
+
+        Some(StateChange {
+            timestamp: saved
+                .changed_at
+                .filter(|_| !saved.reachable && !light.state.reachable)
+                .and_then(|changed_at| {
+                    utils::instant_now().checked_sub((wall_clock - changed_at).to_std().ok()?)
+                }),
+            reachable: saved.reachable,
+            state: saved.state.clone(),
+        })
+    }
+
+    fn owns_light(&self, snapshot: &Snapshot, light: &Light) -> bool {
+        snapshot.room_of_light(light) == self.room
+    }
+
+    /// Returns the policy for a light no scene is scheduled for, zones it's in take precedence over its room.
+    /// Without one for its groups a `(default)` scene of the room with the light is used.
+    fn unscheduled_policy(&self, snapshot: &Snapshot, light: &Light) -> UnscheduledPolicy {
+        let conf = &self.context.conf;
+
+        let default_scene = || {
+            snapshot.scenes.iter().find(|scene| {
+                conditions::is_default_scene(&scene.name)
+                    && snapshot.room_of_scene(scene) == self.room
+                    && scene
+                        .lights
+                        .as_ref()
+                        .is_some_and(|lights| lights.contains(&light.id))
+            })
+        };
+
+        snapshot
+            .groups
+            .iter()
+            .filter(|group| group.lights.contains(&light.id))
+            .filter_map(|group| {
+                conf.group_unscheduled_policies
+                    .get(&group.name)
+                    .map(|policy| (utils::group_type(group) == "Room", policy))
+            })
+            .min_by_key(|(room, _)| *room)
+            .map(|(_, policy)| policy.clone())
+            .or_else(|| default_scene().map(|scene| UnscheduledPolicy::Scene(scene.name.clone())))
+            .unwrap_or(conf.unscheduled_policy.clone())
+    }
+
+    /// Applies the policy of lights that became reachable while no scene is scheduled for them, see `UNSCHEDULED_POLICY`
+    fn handle_unscheduled_lights(
+        &mut self,
+        snapshot: &Snapshot,
+        environment: &Environment,
+        trigger_light_ids: &[String],
+        errors: &mut Vec<String>,
+    ) {
+        // Lights of a scheduled scene may still be waiting for the rest of its lights
+        let room_scenes = snapshot
+            .scenes
+            .iter()
+            .filter(|scene| snapshot.room_of_scene(scene) == self.room)
+            .cloned()
+            .collect::<Vec<Scene>>();
+
+        let scheduled_light_ids = utils::get_scheduled_scenes(
+            &self.context.conf,
+            &snapshot.parser,
+            &room_scenes,
+            &snapshot.smart_ranges,
+            environment,
+            &snapshot.scene_weights,
+        )
+        .iter()
+        .filter_map(|scheduled| {
+            room_scenes
+                .iter()
+                .find(|scene| scene.id == scheduled.scene_id)
+        })
+        .flat_map(|scene| scene.lights.clone().unwrap_or_default())
+        .collect::<HashSet<String>>();
+
+        let unscheduled_lights = snapshot
+            .lights
+            .iter()
+            .filter(|light| {
+                trigger_light_ids.contains(&light.id)
+                    && !scheduled_light_ids.contains(&light.id)
+                    && self.owns_light(snapshot, light)
+                    && !snapshot.streaming_lights.contains(&light.id)
+            })
+            .collect::<Vec<&Light>>();
+
+        let mut bright_lights = vec![];
+        let mut fallback_scenes = Vec::<&Scene>::new();
+
+        for light in unscheduled_lights {
+            let policy = self.unscheduled_policy(snapshot, light);
+
+            if policy == UnscheduledPolicy::Ignore {
+                continue;
+            }
+
+            // They're handled once, like lights of an applied scene
+            self.light_states.insert(
+                light.id.clone(),
+                StateChange {
+                    timestamp: None,
+                    reachable: true,
+                    state: None,
+                },
+            );
+
+            match policy {
+                UnscheduledPolicy::Ignore => {}
+                UnscheduledPolicy::Bright => bright_lights.push(light),
+                UnscheduledPolicy::Scene(name) => {
+                    let scene = snapshot.scenes.iter().find(|scene| {
+                        snapshot.room_of_scene(scene) == self.room
+                            && (scene.name == name || utils::scene_base_name(scene) == name)
+                    });
+
+                    match scene {
+                        Some(scene) if !fallback_scenes.contains(&scene) => {
+                            fallback_scenes.push(scene)
+                        }
+                        Some(_) => {}
+                        None => errors.push(format!("Fallback scene \"{}\" not found", name)),
+                    }
+                }
+            }
+        }
+
+        for scene in fallback_scenes {
+            self.apply_scene(snapshot, scene, trigger_light_ids, errors);
+        }
+
+        if bright_lights.is_empty() {
+            return;
+        }
+
+        for light in bright_lights.iter() {
+            let mut modifier = light::StateModifier::new()
+                .with_on(true)
+                .with_brightness(Adjust::Override(BRIGHT_BRIGHTNESS));
+
+            // Dimmable-only lights reject color temperatures
+            if light.state.color_temperature.is_some() {
+                modifier =
+                    modifier.with_color_temperature(Adjust::Override(BRIGHT_COLOR_TEMPERATURE));
+            }
+
+            debug!("Bridge: PUT /lights/{}/state {:?}", light.id, modifier);
+            self.context.limiter.wait();
+
+            if let Err(err) = backend::set_light_state(&self.context, &light.id, &modifier) {
+                errors.push(format!(
+                    "Failed to turn on light \"{}\": {}",
+                    light.name, err
+                ));
+            }
+        }
+
+        self.context.runtime.events.publish(Event::BrightApplied {
+            lights: bright_lights
+                .iter()
+                .map(|light| light.name.clone())
+                .collect(),
+        });
+    }
+
+    /// Takes the advisory lock of a group before writing to it, see `GROUP_LOCK_TTL`
+    fn lock_group(&self, snapshot: &Snapshot, group_id: &str, errors: &mut Vec<String>) -> bool {
+        let conf = &self.context.conf;
+
+        let Some(ttl) = conf.group_lock_ttl else {
+            return true;
+        };
+
+        match backend::acquire_lock(&self.context, group_id, ttl) {
+            Ok(None) => true,
+            Ok(Some(holder)) => {
+                let group = snapshot
+                    .groups
+                    .iter()
+                    .find(|group| group.id == group_id)
+                    .map(|group| group.name.clone())
+                    .unwrap_or(group_id.to_string());
+
+                self.context
+                    .runtime
+                    .events
+                    .publish(Event::GroupLocked { group, holder });
+                false
+            }
+            Err(err) => {
+                errors.push(format!("Failed to lock group: {}", err));
+                false
+            }
+        }
+    }
+
+    /// Recalls a scene and makes sure its lights took it, `trigger_light_ids` are the lights that caused it
+    fn apply_scene(
+        &mut self,
+        snapshot: &Snapshot,
+        scene: &Scene,
+        trigger_light_ids: &[String],
+        errors: &mut Vec<String>,
+    ) {
+        let conf = self.context.conf.clone();
+        let group_id = scene.group.as_deref().unwrap_or("0");
+
+        let scene_lights = snapshot
+            .lights
+            .iter()
+            .filter(|light| {
+                scene
+                    .lights
+                    .as_ref()
+                    .map(|ids| ids.contains(&light.id))
+                    .unwrap_or(false)
+            })
+            .cloned()
+            .collect::<Vec<Light>>();
+
+        // Recalling a scene would turn on unreachable lights as soon as they're back, e.g. through the light switch
+        let partial = conf.partial_scenes
+            && scene_lights
+                .iter()
+                .any(|light| !light.state.reachable && !snapshot.is_attached(light));
+
+        if snapshot.is_streaming(scene.lights.as_deref().unwrap_or_default()) {
+            info!(
+                "Skipping scene \"{}\", its lights are streaming to an entertainment area",
+                scene.name
+            );
+            return;
+        }
+
+        if !self.lock_group(snapshot, group_id, errors) {
+            return;
+        }
+
+        self.context.limiter.wait();
+
+        // Smart scenes are activated as a whole, the bridge keeps following their timeslots
+        let result = match snapshot.smart_scene_ids.get(&scene.id) {
+            #[cfg(feature = "clip-v2")]
+            Some(smart_scene_id) => backend::activate_smart_scene(&self.context, smart_scene_id),
+            _ if partial => self.apply_partially(scene, &scene_lights),
+            _ => {
+                debug!("Bridge: PUT /groups/{}/action scene={}", group_id, scene.id);
+                backend::recall_scene(&self.context, group_id, &scene.id)
+            }
+        };
+
+        if let Err(err) = result {
+            errors.push(format!("Failed to set scene: {}", err));
+            self.retry(
+                snapshot,
+                Command::Scene {
+                    group_id: group_id.to_string(),
+                    scene_id: scene.id.clone(),
+                    trigger_light_ids: trigger_light_ids.to_vec(),
+                },
+                errors,
+            );
+            return;
+        }
+
+        self.commands.forget(group_id);
+
+        // What the lights show now is only known if the stored states of the scene are retrieved below
+        for light in scene_lights.iter() {
+            if let Some(change) = self.light_states.get_mut(&light.id) {
+                change.state = None;
+            }
+        }
+
+        self.applied_at
+            .insert(scene.id.clone(), utils::instant_now());
+        let now = utils::now_at_home(&conf).fixed_offset();
+        let activations = {
+            let mut status = self.context.runtime.status();
+            status.record_scene_activation(&scene.id, &scene.name, now);
+            status.activations_on(&scene.id, now.date_naive())
+        };
+
+        // Limits like `max=1` hold across restarts
+        if let Some(ref path) = conf.state_file {
+            state::record_activation(path, &scene.id, now.date_naive(), activations);
+        }
+
+        self.context.runtime.events.publish(Event::SceneApplied {
+            scene: scene.name.clone(),
+            lights: snapshot
+                .lights
+                .iter()
+                .filter(|light| {
+                    trigger_light_ids.contains(&light.id)
+                        && scene
+                            .lights
+                            .as_ref()
+                            .map(|ids| ids.contains(&light.id))
+                            .unwrap_or(false)
+                })
+                .map(|light| light.name.clone())
+                .collect(),
+        });
+
+        // Partially applied scenes are already calibrated
+        let calibrated = !partial
+            && scene_lights
+                .iter()
+                .any(|light| conf.light_calibrations.contains_key(&light.name));
+
+        // Lights never take scenes in a dry run, verifying them would only re-apply them
+        let verification_delay = conf.scene_verification_delay.filter(|_| !conf.dry_run);
+
+        if !calibrated && verification_delay.is_none() {
+            return;
+        }
+
+        let states = match backend::get_scene_light_states(&self.context, &scene.id) {
+            Ok(states) => states,
+            Err(err) => {
+                errors.push(format!("Failed to retrieve light states of scene: {}", err));
+                return;
+            }
+        };
+
+        if calibrated {
+            calibration::apply_calibrations(
+                &self.context,
+                &conf.light_calibrations,
+                &states,
+                &scene_lights,
+            );
+        }
+
+        let expected =
+            calibration::expected_states(&conf.light_calibrations, &states, &scene_lights);
+
+        if let Some(delay) = verification_delay {
+            self.commands.check_later(
+                group_id,
+                Check::Scene {
+                    scene_id: scene.id.clone(),
+                    lights: scene_lights.clone(),
+                    expected: expected.clone(),
+                },
+                delay,
+            );
+        }
+
+        // Lights showing something else later on were changed through an app, see [Engine::transition_scenes]
+        for (light_id, state) in expected {
+            if let Some(change) = self.light_states.get_mut(&light_id) {
+                change.state = Some(state);
+            }
+        }
+    }
+
+    /// Sends the stored state of a scene to each of its reachable lights, see `PARTIAL_SCENES`
+    fn apply_partially(&self, scene: &Scene, scene_lights: &[Light]) -> Result<(), String> {
+        let conf = &self.context.conf;
+        let states = backend::get_scene_light_states(&self.context, &scene.id)?;
+
+        let reachable_lights = scene_lights
+            .iter()
+            .filter(|light| light.state.reachable)
+            .cloned()
+            .collect::<Vec<Light>>();
+
+        info!(
+            "Applying scene \"{}\" to {} of its {} lights, the others are unreachable",
+            scene.name,
+            reachable_lights.len(),
+            scene_lights.len()
+        );
+
+        let expected =
+            calibration::expected_states(&conf.light_calibrations, &states, &reachable_lights);
+
+        for light in reachable_lights.iter() {
+            let Some(state) = expected.get(&light.id) else {
+                continue;
+            };
+
+            let modifier = state.modifier();
+            debug!("Bridge: PUT /lights/{}/state {:?}", light.id, modifier);
+            self.context.limiter.wait();
+
+            backend::set_light_state(&self.context, &light.id, &modifier)
+                .map_err(|err| format!("light \"{}\": {}", light.name, err))?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads back the lights of a scene once they had time to settle and re-sends the state to those
+    /// which didn't take it, which happens to bulbs that were powered on just now
+    fn verify_scene(
+        &self,
+        scene: &Scene,
+        lights: &[Light],
+        expected: &HashMap<String, SceneLightState>,
+        errors: &mut Vec<String>,
+    ) {
+        // Scenes with several lights are read back at once, it's less work for the bridge than a request per light
+        let all_lights = match lights.len() {
+            0 | 1 => None,
+            _ => {
+                debug!("Bridge: GET /lights");
+
+                match backend::get_all_lights(&self.context) {
+                    Ok(all_lights) => Some(all_lights),
+                    Err(err) => {
+                        errors.push(format!(
+                            "Failed to verify scene \"{}\": {}",
+                            scene.name, err
+                        ));
+                        return;
+                    }
+                }
+            }
+        };
+
+        for light in lights {
+            let Some(state) = expected.get(&light.id) else {
+                continue;
+            };
+
+            let actual = match all_lights {
+                Some(ref all_lights) => all_lights
+                    .iter()
+                    .find(|actual| actual.id == light.id)
+                    .cloned()
+                    .ok_or("it was removed".to_string()),
+                None => {
+                    debug!("Bridge: GET /lights/{}", light.id);
+                    backend::get_light(&self.context, &light.id)
+                }
+            };
+
+            let actual = match actual {
+                Ok(actual) => actual,
+                Err(err) => {
+                    errors.push(format!(
+                        "Failed to verify light \"{}\": {}",
+                        light.name, err
+                    ));
+                    continue;
+                }
+            };
+
+            // Lights which became unreachable again can't be fixed
+            if !actual.state.reachable || state.matches(&actual.state) {
+                continue;
+            }
+
+            let modifier = state.modifier();
+            debug!("Bridge: PUT /lights/{}/state {:?}", light.id, modifier);
+            self.context.limiter.wait();
+
+            if let Err(err) = backend::set_light_state(&self.context, &light.id, &modifier) {
+                errors.push(format!(
+                    "Failed to re-apply light \"{}\": {}",
+                    light.name, err
+                ));
+                continue;
+            }
+
+            self.context.runtime.events.publish(Event::SceneReapplied {
+                scene: scene.name.clone(),
+                light: light.name.clone(),
+            });
+        }
+    }
+
+    /// Minimum time scenes of this room are kept, see `MIN_SCENE_DURATION`
+    fn min_scene_duration(&self) -> Option<Duration> {
+        let conf = &self.context.conf;
+
+        conf.room_min_scene_durations
+            .get(&self.name)
+            .copied()
+            .or(conf.min_scene_duration)
+    }
+
+    /// Whether a scene was applied less than the minimum scene duration ago
+    fn is_recently_applied(&self, scene_id: &str) -> bool {
+        match (self.min_scene_duration(), self.applied_at.get(scene_id)) {
+            (Some(min_duration), Some(applied_at)) => utils::elapsed(*applied_at) < min_duration,
+            _ => false,
+        }
+    }
+
+    /// Returns another scene for the same lights that was applied recently, it's kept instead of switching again
+    /// right after a boundary, e.g. if the lights were power-cycled shortly before the range of the scene ended
+    fn recent_scene<'a>(&self, scene: &Scene, candidates: &'a [Scene]) -> Option<&'a Scene> {
+        let lights = scene.lights.as_ref()?;
+
+        candidates
+            .iter()
+            .filter(|candidate| {
+                candidate.id != scene.id
+                    && self.is_recently_applied(&candidate.id)
+                    && candidate
+                        .lights
+                        .as_ref()
+                        .map(|ids| ids.iter().any(|id| lights.contains(id)))
+                        .unwrap_or(false)
+            })
+            .filter_map(|candidate| Some((candidate, self.applied_at.get(&candidate.id)?)))
+            .filter(|(_, applied_at)| {
+                self.applied_at
+                    .get(&scene.id)
+                    .map(|scene_applied_at| scene_applied_at < applied_at)
+                    .unwrap_or(true)
+            })
+            .max_by_key(|(_, applied_at)| **applied_at)
+            .map(|(candidate, _)| candidate)
+    }
+
+    /// Remembers scenes applied with an overnight range, a newer scene for the same lights replaces them
+    fn track_overnight_scene(&mut self, scene: &Scene, scheduled_scene: &ScheduledScene) {
+        if !self.context.conf.overnight_auto_off {
+            return;
+        }
+
+        let lights = scene.lights.clone().unwrap_or_default();
+
+        self.overnight_scenes.retain(|_, overnight| {
+            !overnight
+                .lights
+                .iter()
+                .any(|light_id| lights.contains(light_id))
+        });
+
+        if scheduled_scene.start > scheduled_scene.end {
+            self.overnight_scenes.insert(
+                scene.id.clone(),
+                OvernightScene {
+                    name: scene.name.clone(),
+                    group: scene.group.clone(),
+                    lights,
+                    range: (scheduled_scene.start, scheduled_scene.end),
+                },
+            );
+        }
+    }
+
+    /// Remembers the scene applied to lights, they're switched to the next one once its range ends
+    fn track_active_scene(&mut self, scene: &Scene, scheduled_scene: &ScheduledScene) {
+        if !self.context.conf.scene_transitions {
+            return;
+        }
+
+        let lights = scene.lights.clone().unwrap_or_default();
+
+        self.active_scenes.retain(|_, active| {
+            !active
+                .lights
+                .iter()
+                .any(|light_id| lights.contains(light_id))
+        });
+
+        self.active_scenes.insert(
+            scene.id.clone(),
+            ActiveScene {
+                name: scene.name.clone(),
+                lights,
+                range: (scheduled_scene.start, scheduled_scene.end),
+            },
+        );
+    }
+
+    /// Switches lights that are still on to the scene scheduled next once the range of theirs ends,
+    /// e.g. from `Evening (18h-22h)` to `Night (22h-6h)` without toggling the light switch
+    fn transition_scenes(&mut self, snapshot: &Snapshot, errors: &mut Vec<String>) {
+        let conf = self.context.conf.clone();
+        let now = utils::seconds_of_day(&utils::now_at_home(&conf));
+
+        let ended = self
+            .active_scenes
+            .iter()
+            .filter(|(scene_id, active)| {
+                !snapshot.parser.matches_time_range(&active.range, now)
+                    && !self.is_recently_applied(scene_id)
+                    && !snapshot.is_streaming(&active.lights)
+            })
+            .map(|(scene_id, _)| scene_id.clone())
+            .collect::<Vec<String>>();
+
+        if ended.is_empty() {
+            return;
+        }
+
+        // Snapshots only follow the reachability of lights, whether they're still on has to be asked for
+        let lights = match backend::get_all_lights(&self.context) {
+            Ok(lights) => lights,
+            Err(err) => {
+                errors.push(format!(
+                    "Failed to retrieve lights to switch scenes: {}",
+                    err
+                ));
+                return;
+            }
+        };
+
+        // Lights turned off in the meantime, through the switch or an app, must not be turned on again
+        let light_on = |light_id: &String| {
+            lights
+                .iter()
+                .find(|light| light.id == *light_id)
+                .map(|light| {
+                    snapshot.is_attached(light)
+                        || (light.state.reachable && utils::is_light_on(&conf, light) == Some(true))
+                })
+        };
+
+        let scenes = self.without_exhausted(
+            &snapshot
+                .scenes
+                .iter()
+                .filter(|scene| snapshot.room_of_scene(scene) == self.room)
+                .cloned()
+                .collect::<Vec<Scene>>(),
+        );
+
+        let scheduled_scenes = utils::get_scheduled_scenes(
+            &conf,
+            &snapshot.parser,
+            &scenes,
+            &snapshot.smart_ranges,
+            &self.environment(snapshot),
+            &snapshot.scene_weights,
+        );
+
+        let mut applied = HashSet::<String>::new();
+
+        for scene_id in ended {
+            let Some(active) = self.active_scenes.remove(&scene_id) else {
+                continue;
+            };
+
+            if !active
+                .lights
+                .iter()
+                .filter(|light_id| {
+                    snapshot
+                        .light(light_id)
+                        .is_some_and(|light| !snapshot.is_attached(light))
+                })
+                .any(|light_id| light_on(light_id) == Some(true))
+            {
+                continue;
+            }
+
+            // Lights set to another brightness or color through an app since are left as they are
+            let overridden = active
+                .lights
+                .iter()
+                .filter_map(|light_id| {
+                    let light = lights.iter().find(|light| {
+                        light.id == *light_id
+                            && light.state.reachable
+                            && !snapshot.is_attached(light)
+                            && utils::is_light_on(&conf, light) == Some(true)
+                    })?;
+
+                    let diff = self
+                        .light_states
+                        .get(light_id)?
+                        .state
+                        .as_ref()?
+                        .diff(&light.state);
+                    (!diff.is_empty()).then(|| format!("\"{}\" {}", light.name, diff.join(", ")))
+                })
+                .collect::<Vec<String>>();
+
+            if !overridden.is_empty() {
+                info!(
+                    "Not switching from scene \"{}\", its lights were changed since: {}",
+                    active.name,
+                    overridden.join("; ")
+                );
+                continue;
+            }
+
+            for scheduled_scene in scheduled_scenes.iter() {
+                let Some(scene) = scenes.iter().find(|scene| {
+                    scene.id == scheduled_scene.scene_id
+                        && scene.lights.as_ref().is_some_and(|ids| {
+                            ids.iter().any(|light_id| active.lights.contains(light_id))
+                        })
+                }) else {
+                    continue;
+                };
+
+                // Another range of the same scene, e.g. `Evening (18h-20h, 20h-22h)`
+                if scene.id == scene_id || applied.contains(&scene.id) {
+                    self.track_active_scene(scene, scheduled_scene);
+                    continue;
+                }
+
+                if scene
+                    .lights
+                    .iter()
+                    .flatten()
+                    .any(|light_id| light_on(light_id) == Some(false))
+                {
+                    debug!(
+                        "Not switching to scene \"{}\", some of its lights are off",
+                        scene.name
+                    );
+                    continue;
+                }
+
+                info!(
+                    "Switching from scene \"{}\" to \"{}\", its range ended",
+                    active.name, scene.name
+                );
+
+                applied.insert(scene.id.clone());
+                self.apply_scene(snapshot, scene, &[], errors);
+                self.track_overnight_scene(scene, scheduled_scene);
+                self.track_active_scene(scene, scheduled_scene);
+            }
+        }
+    }
+
+    /// Turns off overnight scenes whose range is over, e.g. a `sunset-sunrise` porch light in the morning
+    fn end_overnight_scenes(&mut self, snapshot: &Snapshot, errors: &mut Vec<String>) {
+        let now = utils::seconds_of_day(&utils::now_at_home(&self.context.conf));
+
+        let ended = self
+            .overnight_scenes
+            .iter()
+            .filter(|(scene_id, overnight)| {
+                !snapshot.parser.matches_time_range(&overnight.range, now)
+                    && !self.is_recently_applied(scene_id)
+                    && !snapshot.is_streaming(&overnight.lights)
+            })
+            .map(|(scene_id, _)| scene_id.clone())
+            .collect::<Vec<String>>();
+
+        for scene_id in ended {
+            let Some(overnight) = self.overnight_scenes.remove(&scene_id) else {
+                continue;
+            };
+
+            if let Some(ref group_id) = overnight.group {
+                if !self.lock_group(snapshot, group_id, errors) {
+                    continue;
+                }
+            }
+
+            self.context.runtime.events.publish(Event::SceneEnded {
+                scene: overnight.name.clone(),
+            });
+
+            // Scenes without a group only switch off their own lights, group 0 would be all of them
+            let result = match overnight.group {
+                Some(ref group_id) => {
+                    debug!("Bridge: PUT /groups/{}/action on=false", group_id);
+                    self.context.limiter.wait();
+
+                    backend::set_group_state(
+                        &self.context,
+                        group_id,
+                        &StateModifier::new().with_on(false),
+                    )
+                }
+                None => overnight.lights.iter().try_for_each(|light_id| {
+                    debug!("Bridge: PUT /lights/{}/state on=false", light_id);
+                    self.context.limiter.wait();
+
+                    backend::set_light_state(
+                        &self.context,
+                        light_id,
+                        &light::StateModifier::new().with_on(false),
+                    )
+                }),
+            };
+
+            if let Err(err) = result {
+                errors.push(format!(
+                    "Failed to turn off scene \"{}\": {}",
+                    overnight.name, err
+                ));
+            }
+        }
+    }
+
+    /// Readings of the snapshot combined with the current mode, which may change in between snapshots
+    fn environment(&self, snapshot: &Snapshot) -> Environment {
+        let conf = &self.context.conf;
+
+        Environment {
+            mode: self.context.runtime.mode(),
+            season: Some(solar::get_season(
+                conf.home_latitude,
+                utils::now_at_home(conf).date_naive(),
+            )),
+            moon: Some(solar::get_moon_phase(utils::now_at_home(conf).to_utc())),
+            ..snapshot.environment.clone()
+        }
+    }
+
+    /// Keeps track of what's scheduled now and next, it's recomputed on new snapshots, once the next change is due
+    /// and when switching modes
+    fn update_schedule(&mut self, snapshot: &Snapshot, changed: bool) {
+        let conf = &self.context.conf;
+        let now = utils::now_at_home(conf);
+        let environment = self.environment(snapshot);
+
+        let due = self
+            .schedule
+            .as_ref()
+            .map(|schedule| {
+                schedule
+                    .next_change
+                    .map(|next_change| next_change <= now)
+                    .unwrap_or(false)
+            })
+            .unwrap_or(true);
+
+        if !changed && !due && environment.mode == self.schedule_mode {
+            return;
+        }
+
+        self.schedule_mode = environment.mode.clone();
+
+        let scenes = snapshot
+            .scenes
+            .iter()
+            .filter(|scene| snapshot.room_of_scene(scene) == self.room)
+            .cloned()
+            .collect::<Vec<Scene>>();
+
+        let scheduled_at = |at: &DateTime<Tz>| {
+            let mut names = utils::get_scheduled_scenes_at(
+                conf,
+                &snapshot.parser,
+                &scenes,
+                &snapshot.smart_ranges,
+                &environment,
+                &snapshot.scene_weights,
+                at,
+            )
+            .iter()
+            .filter_map(|scheduled_scene| {
+                scenes
+                    .iter()
+                    .find(|scene| scene.id == scheduled_scene.scene_id)
+                    .map(|scene| scene.name.clone())
+            })
+            .collect::<Vec<String>>();
+
+            names.sort();
+            names
+        };
+
+        let next_change = utils::next_boundary(
+            conf,
+            &snapshot.parser,
+            &scenes,
+            &snapshot.smart_ranges,
+            &now,
+        );
+
+        let schedule = RoomSchedule {
+            name: self.name.clone(),
+            current: scheduled_at(&now),
+            next_change: next_change.map(|next_change| next_change.fixed_offset()),
+            next: next_change
+                .map(|next_change| scheduled_at(&next_change))
+                .unwrap_or_default(),
+        };
+
+        if self.schedule.as_ref() == Some(&schedule) {
+            return;
+        }
+
+        if !schedule.is_empty() {
+            info!("{}", schedule);
+        }
+
+        self.context
+            .runtime
+            .status()
+            .record_room_schedule(&engine_key(&self.context, &self.room), schedule.clone());
+
+        self.schedule = Some(schedule);
+    }
+
+    /// Leaves out scenes which reached their maximum activations of the day, e.g. `Wake up (sunrise-9h, max=1)`
+    fn without_exhausted(&self, scenes: &[Scene]) -> Vec<Scene> {
+        let today = utils::now_at_home(&self.context.conf).date_naive();
+        let status = self.context.runtime.status();
+
+        scenes
+            .iter()
+            .filter(|scene| {
+                let Some(max) = conditions::parse_max_activations(&scene.name) else {
+                    return true;
+                };
+
+                let exhausted = status.activations_on(&scene.id, today) >= max;
+
+                if exhausted {
+                    debug!(
+                        "Scene \"{}\" reached its maximum of {} activations today",
+                        scene.name, max
+                    );
+                }
+
+                !exhausted
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Applies the currently scheduled scenes of this room once someone arrives home
+    fn arrive(&mut self, snapshot: &Snapshot, errors: &mut Vec<String>) {
+        let conf = self.context.conf.clone();
+
+        if self.room.is_none() || !conf.arrival_rooms.contains(&self.name) {
+            return;
+        }
+
+        let environment = self.environment(snapshot);
+
+        let scenes = self.without_exhausted(
+            &snapshot
+                .scenes
+                .iter()
+                .filter(|scene| snapshot.room_of_scene(scene) == self.room)
+                .cloned()
+                .collect::<Vec<Scene>>(),
+        );
+
+        for scheduled_scene in utils::get_scheduled_scenes(
+            &conf,
+            &snapshot.parser,
+            &scenes,
+            &snapshot.smart_ranges,
+            &environment,
+            &snapshot.scene_weights,
+        ) {
+            if let Some(scene) = scenes
+                .iter()
+                .find(|scene| scene.id == scheduled_scene.scene_id)
+            {
+                self.apply_scene(snapshot, scene, &[], errors);
+                self.track_overnight_scene(scene, &scheduled_scene);
+                self.track_active_scene(scene, &scheduled_scene);
+            }
+        }
+    }
+
+    /// Applies the currently scheduled scenes whose lights are all on at startup, see `STARTUP_SYNC`
+    fn sync(&mut self, snapshot: &Snapshot, errors: &mut Vec<String>) {
+        let conf = self.context.conf.clone();
+        let environment = self.environment(snapshot);
+
+        let scenes = self.without_exhausted(
+            &snapshot
+                .scenes
+                .iter()
+                .filter(|scene| snapshot.room_of_scene(scene) == self.room)
+                .cloned()
+                .collect::<Vec<Scene>>(),
+        );
+
+        for scheduled_scene in utils::get_scheduled_scenes(
+            &conf,
+            &snapshot.parser,
+            &scenes,
+            &snapshot.smart_ranges,
+            &environment,
+            &snapshot.scene_weights,
+        ) {
+            let Some(scene) = scenes
+                .iter()
+                .find(|scene| scene.id == scheduled_scene.scene_id)
+            else {
+                continue;
+            };
+
+            // Attached lights don't say whether someone turned the group on, lights deleted from the bridge are ignored
+            let lights = scene
+                .lights
+                .iter()
+                .flatten()
+                .filter_map(|light_id| snapshot.light(light_id))
+                .filter(|light| !snapshot.is_attached(light))
+                .collect::<Vec<&Light>>();
+
+            if lights.is_empty()
+                || !lights.iter().all(|light| {
+                    light.state.reachable && utils::is_light_on(&conf, light) == Some(true)
+                })
+            {
+                continue;
+            }
+
+            info!(
+                "Applying scene \"{}\" on startup, its lights are on",
+                scene.name
+            );
+
+            self.apply_scene(snapshot, scene, &[], errors);
+            self.track_overnight_scene(scene, &scheduled_scene);
+            self.track_active_scene(scene, &scheduled_scene);
+        }
+    }
+
+    fn tick(&mut self, snapshot: &Snapshot, errors: &mut Vec<String>) {
+        let conf = self.context.conf.clone();
+        let environment = self.environment(snapshot);
+
+        let missed_arrival = std::mem::take(&mut self.missed_arrival);
+
+        if snapshot.arrival || missed_arrival {
+            self.arrive(snapshot, errors);
+        }
+
+        // Check for light changes
+        let changed_lights = snapshot
+            .lights
+            .iter()
+            .filter(|light| {
+                !snapshot.is_attached(light)
+                    && self
+                        .light_states
+                        .get(&light.id)
+                        .map(|last_reachable| last_reachable.reachable != light.state.reachable)
+                        .unwrap_or(true)
+            })
+            .collect::<Vec<&Light>>();
+
+        if changed_lights.is_empty() {
+            return;
+        }
+
+        if self.light_states.is_empty() {
+            // Lights reachable at startup may only count as triggers within the grace period
+            let as_triggers = utils::elapsed(self.context.started_at) < conf.startup_grace_period;
+
+            for light in changed_lights.iter() {
+                let restored = self.restored_light(light);
+                let mut change = StateChange::of(
+                    light,
+                    as_triggers.then(utils::instant_now),
+                    restored.as_ref(),
+                );
+                change.timestamp = restored
+                    .and_then(|restored| restored.timestamp)
+                    .or(change.timestamp);
+
+                self.light_states.insert(light.id.clone(), change);
+            }
+
+            if let Some(restored) = self.restored.take() {
+                self.delayed_group_offs.extend(restored.delayed_group_offs);
+            }
+
+            if !as_triggers {
+                if conf.startup_sync {
+                    self.sync(snapshot, errors);
+                }
+
+                return;
+            }
+        } else {
+            // Update reachable lights
+            for light in changed_lights.iter() {
+                if let Some(last_reachable) = self.light_states.get(&light.id) {
+                    if !self.owns_light(snapshot, light) {
+                        // Reported by the engine of the room the light is in
+                    } else if last_reachable.reachable && !light.state.reachable {
+                        self.context
+                            .runtime
+                            .events
+                            .publish(Event::LightUnreachable {
+                                light: light.name.clone(),
+                            });
+                    } else {
+                        log_comeback(light, last_reachable);
+                        self.context.runtime.events.publish(Event::LightReachable {
+                            light: light.name.clone(),
+                        });
+                    };
+                };
+
+                let last = self.light_states.get(&light.id);
+                let change = StateChange::of(light, Some(utils::instant_now()), last);
+                self.light_states.insert(light.id.clone(), change);
+            }
+        }
+
+        // Collect ids of all lights that are ignored / always on / not controlled by a physical switch
+        // They have the prefix "(att)" for "attached" in their name, or the name of their room or zone
+        let ignored_light_ids = snapshot
+            .lights
+            .iter()
+            .filter(|light| snapshot.is_attached(light))
+            .map(|light| &light.id)
+            .collect::<HashSet<&String>>();
+
+        // The reachability window is stretched to the grace period right after startup
+        let trigger_window = if utils::elapsed(self.context.started_at) < conf.startup_grace_period
+        {
+            conf.reachability_window.max(conf.startup_grace_period)
+        } else {
+            conf.reachability_window
+        };
+
+        // Check for scene changes, this is done by:
+        // 1. Extract all reachable lights that have been reachable for less than the reachability window
+        // 2. Extract all scenes of this room that contain all the lights from 1.
+        let light_trigger_ids = self
+            .light_states
+            .iter()
+            .filter(|(_, state)| {
+                state.reachable
+                    && state
+                        .timestamp
+                        .map(|timestamp| utils::elapsed(timestamp) < trigger_window)
+                        .unwrap_or(false)
+            })
+            .map(|(light_id, _)| light_id.clone())
+            .collect::<Vec<String>>();
+
+        // Extract scenes from which all lights are reachable or
+        // are attached to a scene that can be triggered
+        let changed_scenes = snapshot
+            .scenes
+            .iter()
+            .filter(|scene| {
+                snapshot.room_of_scene(scene) == self.room
+                    && scene
+                        .lights
+                        .clone()
+                        .map(|light_ids| {
+                            // Lights deleted from the bridge would never become reachable
+                            light_ids
+                                .iter()
+                                .any(|light_id| snapshot.light(light_id).is_some())
+                                && light_ids.iter().all(|light_id| {
+                                    ignored_light_ids.contains(&light_id)
+                                        || light_trigger_ids.contains(light_id)
+                                        || snapshot.light(light_id).is_none()
+                                        || (conf.partial_scenes
+                                            && snapshot
+                                                .light(light_id)
+                                                .is_some_and(|light| !light.state.reachable))
+                                })
+                                // Partially applied scenes still need a light to trigger them
+                                && (!conf.partial_scenes
+                                    || light_ids
+                                        .iter()
+                                        .any(|light_id| light_trigger_ids.contains(light_id)))
+                        })
+                        .unwrap_or(false)
+            })
+            .cloned()
+            .collect::<Vec<Scene>>();
+
+        // Reset timestamp to prevent scenes to be set multiple times
+        for changed_scene in changed_scenes.iter() {
+            if let Some(lights) = &changed_scene.lights {
+                // Unreachable lights of partially applied scenes stay unreachable until they're back
+                for light_id in lights.iter().filter(|light_id| {
+                    snapshot
+                        .light(light_id)
+                        .map(|light| light.state.reachable)
+                        .unwrap_or(true)
+                }) {
+                    self.light_states.insert(
+                        light_id.clone(),
+                        StateChange {
+                            timestamp: None,
+                            reachable: true,
+                            state: None,
+                        },
+                    );
+                }
+            }
+        }
+
+        // Turn on currently scheduled scenes
+        for scheduled_scene in utils::get_scheduled_scenes(
+            &conf,
+            &snapshot.parser,
+            &self.without_exhausted(&changed_scenes),
+            &snapshot.smart_ranges,
+            &environment,
+            &snapshot.scene_weights,
+        )
+        .iter()
+        {
+            let Some(scene) = changed_scenes
+                .iter()
+                .find(|scene| scene.id == scheduled_scene.scene_id)
+            else {
+                continue;
+            };
+
+            // Keep a scene applied moments ago instead of switching right after its range ended
+            if let Some(recent_scene) = self.recent_scene(scene, &changed_scenes) {
+                info!(
+                    "Keeping scene \"{}\" instead of \"{}\", it was applied moments ago",
+                    recent_scene.name, scene.name
+                );
+
+                // The minimum duration counts from when it was applied first
+                let applied_at = self.applied_at.get(&recent_scene.id).copied();
+                self.apply_scene(snapshot, recent_scene, &light_trigger_ids, errors);
+
+                if let Some(applied_at) = applied_at {
+                    self.applied_at.insert(recent_scene.id.clone(), applied_at);
+                }
+
+                continue;
+            }
+
+            self.apply_scene(snapshot, scene, &light_trigger_ids, errors);
+            self.track_overnight_scene(scene, scheduled_scene);
+            self.track_active_scene(scene, scheduled_scene);
+        }
+
+        // Lights that came back while no scene of the room is scheduled for them
+        if conf.unscheduled_policy != UnscheduledPolicy::Ignore
+            || !conf.group_unscheduled_policies.is_empty()
+            || snapshot
+                .scenes
+                .iter()
+                .any(|scene| conditions::is_default_scene(&scene.name))
+        {
+            self.handle_unscheduled_lights(snapshot, &environment, &light_trigger_ids, errors);
+        }
+
+        self.turn_off_groups(snapshot, &ignored_light_ids, false, errors);
+    }
+
+    /// Turns off all groups of this room where all lights that are not marked as attached are no longer reachable.
+    /// With `delayed_only` only groups waiting for `MIN_ON_DURATION` are checked again.
+    fn turn_off_groups(
+        &mut self,
+        snapshot: &Snapshot,
+        ignored_light_ids: &HashSet<&String>,
+        delayed_only: bool,
+        errors: &mut Vec<String>,
+    ) {
+        let conf = self.context.conf.clone();
+        let delayed_group_offs = self.delayed_group_offs.clone();
+        let room = self.room.clone();
+
+        for group in snapshot.groups.iter().filter(|group| {
+            snapshot.room_of_group(group) == room
+                && utils::is_managed_group(&conf, group)
+                && !snapshot.is_streaming(&group.lights)
+                && (!delayed_only || delayed_group_offs.contains(&group.id))
+        }) {
+            // The bridge already knows whether any light of the group is on, older firmware may not report it
+            let some_lights_on = match group.state {
+                Some(ref state) => state.any_on,
+                None => group
+                    .lights
+                    .iter()
+                    .filter_map(|light_id| snapshot.light(light_id))
+                    .any(|light| utils::is_light_on(&conf, light) == Some(true)),
+            };
+
+            if !some_lights_on {
+                self.delayed_group_offs.remove(&group.id);
+                continue;
+            }
+
+            // Lights with an unknown state that should be ignored are treated like attached ones
+            let all_non_attached_turned_off = group.lights.iter().all(|light_id| {
+                ignored_light_ids.contains(light_id)
+                    || snapshot
+                        .light(light_id)
+                        .map(|light| utils::is_light_on(&conf, light).is_none())
+                        .unwrap_or(false)
+                    || self
+                        .light_states
+                        .get(light_id)
+                        .map(|state| !state.reachable)
+                        .unwrap_or(false)
+            });
+
+            if !all_non_attached_turned_off {
+                self.delayed_group_offs.remove(&group.id);
+                continue;
+            }
+
+            // Brief power dips shouldn't turn off attached lights, the others have to stay off for a while
+            let min_on_duration = conf
+                .group_min_on_durations
+                .get(&group.name)
+                .copied()
+                .or(conf.min_on_duration);
+
+            if let Some(min_on_duration) = min_on_duration {
+                let off_for = group
+                    .lights
+                    .iter()
+                    .filter(|light_id| !ignored_light_ids.contains(light_id))
+                    .filter_map(|light_id| self.light_states.get(light_id))
+                    .filter(|state| !state.reachable)
+                    .filter_map(|state| state.timestamp)
+                    .map(utils::elapsed)
+                    .min();
+
+                if off_for.is_some_and(|off_for| off_for < min_on_duration) {
+                    if self.delayed_group_offs.insert(group.id.clone()) {
+                        debug!(
+                            "Lights of group \"{}\" are unreachable, waiting {}s before turning it off",
+                            group.name,
+                            min_on_duration.as_secs()
+                        );
+                    }
+
+                    continue;
+                }
+            }
+
+            self.delayed_group_offs.remove(&group.id);
+            self.turn_off_group(snapshot, group, errors);
+        }
+    }
+
+    /// Turns off a group, fading out attached lights if configured. Lights of it still on afterwards get it sent again.
+    fn turn_off_group(&mut self, snapshot: &Snapshot, group: &Group, errors: &mut Vec<String>) {
+        let conf = self.context.conf.clone();
+
+        if !self.lock_group(snapshot, &group.id, errors) {
+            return;
+        }
+
+        self.context.runtime.events.publish(Event::GroupTurnedOff {
+            group: group.name.clone(),
+        });
+
+        // Turn attached lights off, fading them out if configured
+        let mut modifier = StateModifier::new().with_on(false);
+
+        if let Some(transition) = conf.auto_off_transition {
+            // Given in multiples of 100ms
+            modifier = modifier.with_transition_time(
+                u16::try_from(transition.as_millis() / 100).unwrap_or(u16::MAX),
+            );
+        }
+
+        debug!("Bridge: PUT /groups/{}/action on=false", group.id);
+        self.context.limiter.wait();
+
+        let command = Command::GroupOff {
+            group_id: group.id.clone(),
+        };
+
+        if let Err(err) = backend::set_group_state(&self.context, &group.id, &modifier) {
+            errors.push(format!("Failed to turn off attached lights: {}", err));
+            self.retry(snapshot, command, errors);
+            return;
+        }
+
+        self.commands.forget(&group.id);
+
+        // Nothing left to turn off once the range ends
+        self.overnight_scenes
+            .retain(|_, overnight| overnight.group.as_ref() != Some(&group.id));
+
+        // Lights are read back once they're done fading out, nothing is turned off in a dry run
+        let Some(delay) = conf.scene_verification_delay.filter(|_| !conf.dry_run) else {
+            return;
+        };
+
+        self.commands.check_later(
+            &group.id,
+            Check::GroupOff {
+                group_id: group.id.clone(),
+            },
+            delay + conf.auto_off_transition.unwrap_or_default(),
+        );
+    }
+
+    /// Reads back the lights of a group that was turned off, those still on count as a failure
+    fn verify_group_off(&mut self, snapshot: &Snapshot, group: &Group, errors: &mut Vec<String>) {
+        let conf = self.context.conf.clone();
+        let command = Command::GroupOff {
+            group_id: group.id.clone(),
+        };
+
+        debug!("Bridge: GET /lights");
+
+        let still_on = match backend::get_all_lights(&self.context) {
+            Ok(all_lights) => all_lights
+                .into_iter()
+                .filter(|light| {
+                    group.lights.contains(&light.id)
+                        && light.state.reachable
+                        && utils::is_light_on(&conf, light) == Some(true)
+                })
+                .map(|light| format!("\"{}\"", light.name))
+                .collect::<Vec<String>>(),
+            Err(err) => {
+                errors.push(format!(
+                    "Failed to verify group \"{}\" is off: {}",
+                    group.name, err
+                ));
+                return;
+            }
+        };
+
+        if !still_on.is_empty() {
+            errors.push(format!(
+                "Lights {} of group \"{}\" are still on",
+                still_on.join(", "),
+                group.name
+            ));
+            self.retry(snapshot, command, errors);
+        }
+    }
+
+    /// Runs the checks whose lights had time to settle
+    fn run_checks(&mut self, snapshot: &Snapshot, errors: &mut Vec<String>) {
+        for check in self.commands.due_checks() {
+            match check {
+                Check::Scene {
+                    scene_id,
+                    lights,
+                    expected,
+                } => {
+                    let Some(scene) = snapshot.scenes.iter().find(|scene| scene.id == scene_id)
+                    else {
+                        continue;
+                    };
+
+                    self.verify_scene(scene, &lights, &expected, errors);
+                }
+                Check::GroupOff { group_id } => {
+                    let Some(group) = snapshot.groups.iter().find(|group| group.id == group_id)
+                    else {
+                        continue;
+                    };
+
+                    self.verify_group_off(snapshot, group, errors);
+                }
+            }
+        }
+    }
+
+    /// Queues a command that failed to be sent again later, see `COMMAND_RETRIES`
+    fn retry(&mut self, snapshot: &Snapshot, command: Command, errors: &mut Vec<String>) {
+        let group_id = command.group_id();
+        let group = snapshot
+            .groups
+            .iter()
+            .find(|group| group.id == group_id)
+            .map(|group| group.name.clone())
+            .unwrap_or(group_id.to_string());
+
+        self.context
+            .runtime
+            .status()
+            .record_group_error(&engine_key(&self.context, &self.room), &group);
+
+        match self
+            .commands
+            .failed(command, self.context.conf.command_retries)
+        {
+            Ok(delay) => debug!("Sending it again in {}s", delay.as_secs()),
+            Err(attempts) => errors.push(format!("Gave up after {} attempts", attempts)),
+        }
+    }
+
+    /// Sends commands that failed before once more, unless their lights were switched since
+    fn retry_commands(&mut self, snapshot: &Snapshot, errors: &mut Vec<String>) {
+        let reachable_light_ids = snapshot
+            .lights
+            .iter()
+            .filter(|light| {
+                !snapshot.is_attached(light)
+                    && self
+                        .light_states
+                        .get(&light.id)
+                        .is_some_and(|state| state.reachable)
+            })
+            .map(|light| light.id.clone())
+            .collect::<HashSet<String>>();
+        let reachable = |light_id: &String| reachable_light_ids.contains(light_id);
+
+        for command in self.commands.due() {
+            match command {
+                Command::Scene {
+                    ref scene_id,
+                    ref trigger_light_ids,
+                    ..
+                } => {
+                    let Some(scene) = snapshot.scenes.iter().find(|scene| scene.id == *scene_id)
+                    else {
+                        continue;
+                    };
+
+                    if !scene.lights.iter().flatten().any(reachable) {
+                        continue;
+                    }
+
+                    info!("Applying scene \"{}\" once more", scene.name);
+                    self.apply_scene(snapshot, scene, trigger_light_ids, errors);
+                }
+                Command::GroupOff { ref group_id } => {
+                    let Some(group) = snapshot.groups.iter().find(|group| group.id == *group_id)
+                    else {
+                        continue;
+                    };
+
+                    if group.lights.iter().any(reachable) {
+                        continue;
+                    }
+
+                    info!("Turning off group \"{}\" once more", group.name);
+                    self.turn_off_group(snapshot, group, errors);
+                }
+            }
+        }
+
+        self.commands.settle();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::simulation;
+    use chrono::{NaiveDate, NaiveTime, TimeZone};
+    use serde_json::{json, Map, Value};
+    use std::sync::mpsc::Receiver;
+
+    /// A single room scheduled by its engine, with a simulated bridge in place of a real one
+    struct Home {
+        engine: Engine,
+        simulation: Arc<Simulation>,
+        events: Receiver<Event>,
+    }
+
+    fn light(name: &str, on: Option<bool>, reachable: bool) -> Value {
+        json!({
+            "name": name,
+            "type": "Extended color light",
+            "state": {
+                "on": on,
+                "bri": 200,
+                "ct": 300,
+                "alert": "none",
+                "colormode": "ct",
+                "reachable": reachable
+            },
+            "modelid": "LCT015",
+            "swversion": "1.0",
+            "swupdate": { "state": "noupdates", "lastinstall": null },
+            "config": { "archetype": "sultanbulb", "function": "mixed", "direction": "omnidirectional" },
+            "capabilities": { "certified": true, "control": {}, "streaming": { "renderer": true, "proxy": false } }
+        })
+    }
+
+    fn scene(name: &str, lights: &[&str]) -> Value {
+        json!({
+            "name": name,
+            "type": "GroupScene",
+            "group": "1",
+            "lights": lights,
+            "owner": "",
+            "recycle": false,
+            "locked": false,
+            "appdata": {},
+            "picture": "",
+            "lastupdate": null,
+            "version": 2
+        })
+    }
+
+    impl Home {
+        /// Living room with all of `lights`, their ids are their position starting at 1
+        fn new(variables: &[(&str, &str)], lights: Vec<Value>, scenes: &[(&str, &[&str])]) -> Home {
+            // Lights of the simulation take scenes right away, there's nothing to verify
+            let conf = config::load_test_config(
+                &[("SCENE_VERIFICATION_DELAY", "0")]
+                    .iter()
+                    .chain(variables)
+                    .copied()
+                    .collect::<Vec<_>>(),
+            );
+
+            let light_ids = (1..=lights.len())
+                .map(|id| id.to_string())
+                .collect::<Vec<String>>();
+
+            let fixtures = json!({
+                "lights": light_ids.iter().cloned().zip(lights).collect::<Map<String, Value>>(),
+                "groups": {
+                    "1": {
+                        "name": "Living room",
+                        "lights": light_ids,
+                        "sensors": [],
+                        "type": "Room",
+                        "class": "Living room",
+                        "recycle": false
+                    }
+                },
+                "scenes": scenes
+                    .iter()
+                    .enumerate()
+                    .map(|(id, (name, lights))| (format!("s{}", id + 1), scene(name, lights)))
+                    .collect::<Map<String, Value>>()
+            });
+
+            let (context, simulation) = simulation::test_context(conf, fixtures);
+            let events = context.runtime.events.subscribe();
+
+            Home {
+                engine: Engine::new(Some("1".to_string()), "Living room".to_string(), context),
+                simulation,
+                events,
+            }
+        }
+
+        /// Moves the clock to a time of Monday, 4 March 2024 or the days after
+        fn at(&self, day: u32, time: &str) {
+            let date = NaiveDate::from_ymd_opt(2024, 3, 4 + day).unwrap();
+            let time = NaiveTime::parse_from_str(time, "%H:%M:%S").unwrap();
+
+            utils::set_simulated_time(
+                self.engine
+                    .context
+                    .conf
+                    .home_timezone
+                    .from_local_datetime(&date.and_time(time))
+                    .unwrap()
+                    .to_utc(),
+            );
+        }
+
+        /// Hands the engine what the bridge shows now, `changed` is false for wake-ups in between snapshots
+        fn step(&mut self, changed: bool) -> Vec<String> {
+            let conf = &self.engine.context.conf;
+            let snapshot = Snapshot::new(
+                self.simulation.get_all_lights(),
+                self.simulation.get_all_scenes(),
+                self.simulation.get_all_groups(),
+                utils::create_parser(conf).unwrap(),
+                HashMap::new(),
+                HashMap::new(),
+                Environment::default(),
+            );
+
+            self.engine.step(&snapshot, changed);
+
+            // Lights coming and going are left out, only what the engine decided counts
+            self.events
+                .try_iter()
+                .filter(|event| {
+                    !matches!(
+                        event,
+                        Event::LightReachable { .. } | Event::LightUnreachable { .. }
+                    )
+                })
+                .map(|event| event.to_string())
+                .collect()
+        }
+
+        fn switch(&self, names: &[&str], on: bool) {
+            self.simulation.switch(names, on);
+        }
+    }
+
+    #[test]
+    fn test_scene_selection() {
+        let mut home = Home::new(
+            &[],
+            vec![
+                light("Lamp", Some(false), false),
+                light("Ceiling", Some(false), false),
+                light("Strip (att)", Some(true), true),
+            ],
+            &[
+                ("Day (8h-18h)", &["1", "2"]),
+                ("Evening (18h-22h)", &["1", "2", "3"]),
+                // Light 9 was deleted from the bridge
+                ("Night (22h-8h)", &["1", "2", "9"]),
+            ],
+        );
+
+        home.at(0, "19:00:00");
+        assert!(home.step(true).is_empty());
+
+        // Scenes wait for all of their lights, attached ones don't count
+        home.switch(&["Lamp"], true);
+        assert!(home.step(true).is_empty());
+
+        home.at(0, "19:00:02");
+        home.switch(&["Ceiling"], true);
+        assert_eq!(
+            home.step(true),
+            vec!["Lights \"Lamp\", \"Ceiling\" reachable → scene \"Evening (18h-22h)\" applied"]
+        );
+
+        // Lights switched on one after another outside the reachability window are ignored
+        home.switch(&["Lamp", "Ceiling"], false);
+        home.step(true);
+        home.at(0, "23:00:00");
+        home.switch(&["Lamp"], true);
+        home.step(true);
+        home.at(0, "23:00:10");
+        home.switch(&["Ceiling"], true);
+        assert!(home.step(true).is_empty());
+
+        home.switch(&["Lamp", "Ceiling"], false);
+        home.step(true);
+        home.at(0, "23:01:00");
+        home.switch(&["Lamp", "Ceiling"], true);
+        assert_eq!(
+            home.step(true),
+            vec!["Lights \"Lamp\", \"Ceiling\" reachable → scene \"Night (22h-8h)\" applied"]
+        );
+    }
+
+    #[test]
+    fn test_scene_transitions() {
+        let lights = || {
+            vec![
+                light("Lamp", Some(false), false),
+                light("Ceiling", Some(false), false),
+            ]
+        };
+        let scenes: &[(&str, &[&str])] = &[
+            ("Evening (18h-22h)", &["1", "2"]),
+            ("Night (22h-8h)", &["1", "2"]),
+        ];
+
+        for transitions in [true, false] {
+            let mut home = Home::new(
+                &[("SCENE_TRANSITIONS", &transitions.to_string())],
+                lights(),
+                scenes,
+            );
+
+            home.at(0, "21:50:00");
+            home.step(true);
+            home.switch(&["Lamp", "Ceiling"], true);
+            assert_eq!(
+                home.step(true),
+                vec![
+                    "Lights \"Lamp\", \"Ceiling\" reachable → scene \"Evening (18h-22h)\" applied"
+                ]
+            );
+
+            home.at(0, "21:59:00");
+            assert!(home.step(false).is_empty());
+
+            // Lights that are still on switch to the next scene once the range of theirs ends
+            home.at(0, "22:00:00");
+            let expected = match transitions {
+                true => vec!["Scene \"Night (22h-8h)\" applied"],
+                false => vec![],
+            };
+            assert_eq!(home.step(false), expected);
+        }
+
+        // Lights turned off in the meantime stay off
+        let mut home = Home::new(&[("SCENE_TRANSITIONS", "true")], lights(), scenes);
+
+        home.at(0, "21:50:00");
+        home.step(true);
+        home.switch(&["Lamp", "Ceiling"], true);
+        home.step(true);
+        home.switch(&["Lamp", "Ceiling"], false);
+        home.step(true);
+
+        home.at(0, "22:00:00");
+        assert!(home.step(false).is_empty());
+    }
+
+    #[test]
+    fn test_auto_off() {
+        let turned_off = "All non-attached lights are unreachable, turning off group: Living room";
+        let lights = || {
+            vec![
+                light("Lamp", Some(true), true),
+                light("Ceiling", Some(true), true),
+                light("Strip (att)", Some(true), true),
+                // Doesn't report whether it's on
+                light("Bulb", None, true),
+            ]
+        };
+
+        for (unknown_on_state, expected) in [("ignore", vec![turned_off]), ("on", vec![])] {
+            let mut home = Home::new(&[("UNKNOWN_ON_STATE", unknown_on_state)], lights(), &[]);
+
+            home.at(0, "19:00:00");
+            assert!(home.step(true).is_empty());
+
+            // Attached lights and those with an unknown state don't keep the group on
+            home.switch(&["Lamp"], false);
+            assert!(home.step(true).is_empty());
+            home.switch(&["Ceiling"], false);
+            assert_eq!(home.step(true), expected);
+        }
+
+        // Groups are only turned off once their lights were off for the minimum on duration
+        let mut home = Home::new(
+            &[("UNKNOWN_ON_STATE", "ignore"), ("MIN_ON_DURATION", "60")],
+            lights(),
+            &[],
+        );
+
+        home.at(0, "19:00:00");
+        home.step(true);
+        home.switch(&["Lamp", "Ceiling"], false);
+        assert!(home.step(true).is_empty());
+
+        home.at(0, "19:00:30");
+        assert!(home.step(false).is_empty());
+
+        home.at(0, "19:01:00");
+        assert_eq!(home.step(false), vec![turned_off]);
+
+        // Lights that come back in time keep it on
+        home.switch(&["Lamp", "Ceiling", "Strip (att)"], true);
+        home.step(true);
+        home.at(0, "19:10:00");
+        home.switch(&["Lamp", "Ceiling"], false);
+        home.step(true);
+
+        home.at(0, "19:10:30");
+        home.switch(&["Lamp"], true);
+        assert!(home.step(true).is_empty());
+
+        home.at(0, "19:11:00");
+        assert!(home.step(false).is_empty());
+    }
+
+    #[test]
+    fn test_max_activations() {
+        let mut home = Home::new(
+            &[],
+            vec![light("Lamp", Some(false), false)],
+            &[
+                ("Wake up [p1] (6h-9h, max=1)", &["1"]),
+                ("Morning (6h-9h)", &["1"]),
+            ],
+        );
+
+        home.at(0, "06:00:00");
+        home.step(true);
+
+        let mut wake_up = |day: u32, time: &str| {
+            home.at(day, time);
+            home.switch(&["Lamp"], true);
+            let events = home.step(true);
+            home.switch(&["Lamp"], false);
+            home.step(true);
+            events
+        };
+
+        assert_eq!(
+            wake_up(0, "07:00:00"),
+            vec!["Light \"Lamp\" reachable → scene \"Wake up [p1] (6h-9h, max=1)\" applied"]
+        );
+
+        // Once it's used up the next scene scheduled for the light takes over until the next day
+        assert_eq!(
+            wake_up(0, "07:30:00"),
+            vec!["Light \"Lamp\" reachable → scene \"Morning (6h-9h)\" applied"]
+        );
+        assert_eq!(
+            wake_up(1, "07:00:00"),
+            vec!["Light \"Lamp\" reachable → scene \"Wake up [p1] (6h-9h, max=1)\" applied"]
+        );
+    }
+}
@@ -0,0 +1,31 @@
+use crate::debug;
+use crate::hue_api::HueApi;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Deserialize)]
+struct Stream {
+    active: bool,
+}
+
+/// Part of a group huelib2 doesn't expose, only entertainment areas have a stream
+#[derive(Deserialize)]
+struct StreamingGroup {
+    #[serde(default)]
+    lights: Vec<String>,
+    stream: Option<Stream>,
+}
+
+/// Returns the ids of all lights in entertainment areas that are streaming right now, e.g. for Hue Sync
+pub fn get_streaming_lights(hue: &HueApi) -> Result<HashSet<String>, huelib2::Error> {
+    let groups = hue.get::<HashMap<String, StreamingGroup>>("groups")?;
+
+    let lights = groups
+        .into_values()
+        .filter(|group| group.stream.as_ref().is_some_and(|stream| stream.active))
+        .flat_map(|group| group.lights)
+        .collect::<HashSet<String>>();
+
+    debug!("Bridge: GET /groups → {} lights streaming", lights.len());
+    Ok(lights)
+}
@@ -0,0 +1,130 @@
+use crate::info;
+use serde::Serialize;
+use std::fmt;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Mutex, PoisonError};
+
+/// A decision taken by the scheduler, printed to stdout and streamed to all watchers
+#[derive(Clone, PartialEq, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    LightReachable { light: String },
+    LightUnreachable { light: String },
+    SceneApplied { scene: String, lights: Vec<String> },
+    SceneReapplied { scene: String, light: String },
+    SceneEnded { scene: String },
+    BrightApplied { lights: Vec<String> },
+    GroupTurnedOff { group: String },
+    GroupLocked { group: String, holder: String },
+    Arrived,
+    ModeChanged { mode: String },
+    SafeModeEntered { restarts: usize },
+    BridgeDegraded { bridge: String, error: String },
+    BridgeRecovered { bridge: String, failures: u32 },
+    BridgeMoved { bridge: String, address: String },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::LightReachable { light } => write!(f, "Light \"{}\" is reachable again", light),
+            Event::LightUnreachable { light } => {
+                write!(f, "Light \"{}\" is not reachable anymore", light)
+            }
+            Event::SceneApplied { scene, lights } => {
+                let names = lights
+                    .iter()
+                    .map(|name| format!("\"{}\"", name))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+
+                match lights.len() {
+                    0 => write!(f, "Scene \"{}\" applied", scene),
+                    1 => write!(f, "Light {} reachable → scene \"{}\" applied", names, scene),
+                    _ => write!(
+                        f,
+                        "Lights {} reachable → scene \"{}\" applied",
+                        names, scene
+                    ),
+                }
+            }
+            Event::SceneReapplied { scene, light } => write!(
+                f,
+                "Light \"{}\" didn't take scene \"{}\", re-applied its state",
+                light, scene
+            ),
+            Event::BrightApplied { lights } => write!(
+                f,
+                "No scene scheduled for {}, turned them on bright",
+                lights
+                    .iter()
+                    .map(|name| format!("\"{}\"", name))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Event::SceneEnded { scene } => {
+                write!(f, "Scene \"{}\" ended, turning off its lights", scene)
+            }
+            Event::GroupLocked { group, holder } => write!(
+                f,
+                "Group \"{}\" is locked by {}, leaving it alone",
+                group, holder
+            ),
+            Event::Arrived => write!(f, "Someone arrived home"),
+            Event::ModeChanged { mode } => write!(f, "Switched to mode {}", mode),
+            Event::SafeModeEntered { restarts } => write!(
+                f,
+                "Restarted {} times within a short time, running in safe mode without writing to the bridge",
+                restarts
+            ),
+            Event::BridgeDegraded { bridge, error } => write!(
+                f,
+                "Bridge {} keeps failing: {}, retrying less often",
+                bridge, error
+            ),
+            Event::BridgeRecovered { bridge, failures } => write!(
+                f,
+                "Bridge {} responds again after {} failed attempts",
+                bridge, failures
+            ),
+            Event::BridgeMoved { bridge, address } => write!(
+                f,
+                "Bridge {} moved to {}, switched over to it",
+                bridge, address
+            ),
+            Event::GroupTurnedOff { group } => write!(
+                f,
+                "All non-attached lights are unreachable, turning off group: {}",
+                group
+            ),
+        }
+    }
+}
+
+/// Fans out events to everyone watching the daemon
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<Sender<Event>>>,
+}
+
+impl EventBus {
+    /// Registers a new watcher, the receiver is dropped from the bus once it hangs up
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (sender, receiver) = channel();
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(sender);
+        receiver
+    }
+
+    /// Prints an event and forwards it to all watchers
+    pub fn publish(&self, event: Event) {
+        info!("{}", event);
+
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
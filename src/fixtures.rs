@@ -0,0 +1,111 @@
+use crate::conditions;
+use crate::config::Config;
+use crate::debug;
+use crate::hue_api::{self, HueApi};
+use huelib2::Bridge;
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+
+/// Fields that identify a bridge, its devices or its owner, they're left out of fixtures
+const PRIVATE_FIELDS: [&str; 8] = [
+    "uniqueid",
+    "swconfigid",
+    "productid",
+    "owner",
+    "appdata",
+    "picture",
+    "image",
+    "lastupdate",
+];
+
+/// Fetches all resources of a kind, keyed by their id, the way the v1 api returns them
+fn get_resources(hue: &HueApi, kind: &str) -> Result<Map<String, Value>, huelib2::Error> {
+    debug!("Bridge: GET /{}", kind);
+    hue.get(kind)
+}
+
+/// Replaces the name with a generic one. Only what the scheduler looks at is kept: the `(att)` marker of lights,
+/// the priority of scenes and everything from the first parenthesis on, e.g. the time-ranges of scenes.
+fn anonymize_name(name: &str, replacement: String) -> String {
+    let marker = name.trim_start().starts_with("(att)");
+    let priority = conditions::parse_priority(name);
+    let name = name.trim_start().trim_start_matches("(att)");
+
+    let mut name = match name.find("(") {
+        Some(index) => format!("{} {}", replacement, &name[index..]),
+        None => replacement,
+    };
+
+    if let Some(priority) = priority.filter(|_| conditions::parse_priority(&name).is_none()) {
+        name = format!("{} [p{}]", name, priority);
+    }
+
+    if marker {
+        format!("(att) {}", name)
+    } else {
+        name
+    }
+}
+
+/// Strips private fields and names off all resources of a kind, ids stay as they are since resources refer to them
+fn anonymize(resources: Map<String, Value>, label: &str) -> Map<String, Value> {
+    resources
+        .into_iter()
+        .enumerate()
+        .map(|(index, (id, mut resource))| {
+            if let Some(resource) = resource.as_object_mut() {
+                for field in PRIVATE_FIELDS {
+                    resource.remove(field);
+                }
+
+                if let Some(Value::String(name)) = resource.get("name") {
+                    let name = anonymize_name(name, format!("{} {}", label, index + 1));
+                    resource.insert("name".to_string(), Value::String(name));
+                }
+            }
+
+            (id, resource)
+        })
+        .collect()
+}
+
+/// Writes anonymized lights, groups and scenes of the bridge to a file, in the shape the v1 api returns them.
+/// A bridge mock serving it reproduces the setup, e.g. to contribute a test case for an edge case.
+pub fn record(conf: &Config, path: &Path) {
+    let hue = match hue_api::agent(conf) {
+        Ok(agent) => HueApi::new(agent, &Bridge::new(conf.bridge_ip, &conf.bridge_username)),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut fixtures = Map::new();
+
+    for (kind, label) in [
+        ("lights", "Light"),
+        ("groups", "Group"),
+        ("scenes", "Scene"),
+    ] {
+        let resources = match get_resources(&hue, kind) {
+            Ok(resources) => resources,
+            Err(err) => {
+                eprintln!("Failed to retrieve {}: {}", kind, err);
+                std::process::exit(1);
+            }
+        };
+
+        println!("Recorded {} {}", resources.len(), kind);
+        fixtures.insert(kind.to_string(), Value::Object(anonymize(resources, label)));
+    }
+
+    let content = serde_json::to_string_pretty(&fixtures).unwrap_or_default();
+
+    if let Err(err) = fs::write(path, content) {
+        eprintln!("Failed to write {}: {}", path.display(), err);
+        std::process::exit(1);
+    }
+
+    println!("Fixtures written to {}", path.display());
+}
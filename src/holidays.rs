@@ -0,0 +1,305 @@
+use crate::debug;
+use chrono::{Datelike, Days, NaiveDate};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Where public holidays come from, a country with an optional region like `DE-BW` or an ICS file
+#[derive(Clone, PartialEq, Debug)]
+pub enum HolidayCalendar {
+    Country {
+        country: String,
+        region: Option<String>,
+    },
+    File(PathBuf),
+}
+
+impl FromStr for HolidayCalendar {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+
+        if value.to_lowercase().ends_with(".ics") {
+            return Ok(HolidayCalendar::File(PathBuf::from(value)));
+        }
+
+        let country = value.split("-").next().unwrap_or_default();
+
+        if country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(format!(
+                "expected a country code or a path to an .ics file: {}",
+                value
+            ));
+        }
+
+        Ok(HolidayCalendar::Country {
+            country: country.to_uppercase(),
+            region: value.contains("-").then(|| value.to_uppercase()),
+        })
+    }
+}
+
+/// A public holiday as returned by [Nager.Date](https://date.nager.at)
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PublicHoliday {
+    date: NaiveDate,
+    global: bool,
+    counties: Option<Vec<String>>,
+    types: Vec<String>,
+}
+
+/// Fetches the public holidays of a country in a year, only those of the region if one is given
+fn get_public_holidays(
+    country: &str,
+    region: Option<&str>,
+    year: i32,
+) -> Result<Vec<NaiveDate>, Box<ureq::Error>> {
+    debug!("Holidays: GET /PublicHolidays/{}/{}", year, country);
+
+    // Scenes aren't scheduled while waiting, so don't wait for long
+    let holidays = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .get(&format!(
+            "https://date.nager.at/api/v3/PublicHolidays/{}/{}",
+            year, country
+        ))
+        .call()?
+        .into_json::<Vec<PublicHoliday>>()
+        .map_err(|err| Box::new(ureq::Error::from(err)))?;
+
+    Ok(public_dates(holidays, region))
+}
+
+/// Dates of public holidays that apply to the whole country or the given region
+fn public_dates(holidays: Vec<PublicHoliday>, region: Option<&str>) -> Vec<NaiveDate> {
+    holidays
+        .into_iter()
+        .filter(|holiday| holiday.types.iter().any(|kind| kind == "Public"))
+        .filter(|holiday| {
+            holiday.global
+                || region.is_some_and(|region| {
+                    holiday
+                        .counties
+                        .as_ref()
+                        .is_some_and(|counties| counties.iter().any(|county| county == region))
+                })
+        })
+        .map(|holiday| holiday.date)
+        .collect()
+}
+
+/// Extracts the date of a `DTSTART` or `DTEND` value, e.g. `20241225` or `20241225T000000Z`
+fn parse_ics_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value.get(..8)?, "%Y%m%d").ok()
+}
+
+/// Returns all days covered by the events of an ICS file, the end of an event is exclusive.
+/// Recurring events only count on the day they start on.
+fn parse_ics(content: &str) -> Vec<NaiveDate> {
+    let mut dates = vec![];
+    let mut start = None;
+    let mut end = None;
+
+    for line in content.lines().map(str::trim) {
+        let Some((property, value)) = line.split_once(":") else {
+            continue;
+        };
+
+        // Parameters follow the name, e.g. `DTSTART;VALUE=DATE:20241225`
+        match property.split(";").next().unwrap_or_default() {
+            "BEGIN" if value == "VEVENT" => (start, end) = (None, None),
+            "DTSTART" => start = parse_ics_date(value),
+            "DTEND" => end = parse_ics_date(value),
+            "END" if value == "VEVENT" => {
+                let Some(mut date) = start else {
+                    continue;
+                };
+
+                let end = end.filter(|end| *end > date).unwrap_or(date + Days::new(1));
+
+                while date < end {
+                    dates.push(date);
+                    date = date + Days::new(1);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    dates
+}
+
+/// Public holidays of the configured calendar, fetched per year
+pub struct Holidays {
+    calendar: HolidayCalendar,
+    dates: HashSet<NaiveDate>,
+    years: HashSet<i32>,
+}
+
+impl Holidays {
+    pub fn new(calendar: HolidayCalendar) -> Holidays {
+        Holidays {
+            calendar,
+            dates: HashSet::new(),
+            years: HashSet::new(),
+        }
+    }
+
+    /// Loads the holidays of this and the next year if they're missing, ICS files are read again
+    /// to pick up changes. Called once a day.
+    pub fn update(&mut self, today: NaiveDate) {
+        match &self.calendar {
+            HolidayCalendar::File(path) => match fs::read_to_string(path) {
+                Ok(content) => {
+                    self.dates = HashSet::from_iter(parse_ics(&content));
+                    debug!("Holidays: {} days in {}", self.dates.len(), path.display());
+                }
+                Err(err) => eprintln!("Failed to read holidays from {}: {}", path.display(), err),
+            },
+            HolidayCalendar::Country { country, region } => {
+                for year in [today.year(), today.year() + 1] {
+                    if self.years.contains(&year) {
+                        continue;
+                    }
+
+                    match get_public_holidays(country, region.as_deref(), year) {
+                        Ok(dates) => {
+                            debug!("Holidays: {} public holidays in {}", dates.len(), year);
+                            self.dates.extend(dates);
+                            self.years.insert(year);
+                        }
+                        Err(err) => eprintln!("Failed to retrieve holidays of {}: {}", year, err),
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn dates(&self) -> &HashSet<NaiveDate> {
+        &self.dates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time_range_parser::d;
+
+    #[test]
+    fn test_holiday_calendar() {
+        let hc = |v: &str| v.parse::<HolidayCalendar>();
+        let country = |country: &str, region: Option<&str>| {
+            Ok(HolidayCalendar::Country {
+                country: country.to_string(),
+                region: region.map(str::to_string),
+            })
+        };
+
+        assert_eq!(hc("DE"), country("DE", None));
+        assert_eq!(hc("de-bw"), country("DE", Some("DE-BW")));
+        assert_eq!(hc(" AT "), country("AT", None));
+        assert_eq!(
+            hc("holidays.ICS"),
+            Ok(HolidayCalendar::File(PathBuf::from("holidays.ICS")))
+        );
+        assert!(hc("GER").is_err());
+        assert!(hc("D1").is_err());
+        assert!(hc("").is_err());
+    }
+
+    #[test]
+    fn test_public_dates() {
+        // Easter and the holidays moving with it, as returned for Germany in 2024 and 2025
+        let holidays = serde_json::from_str::<Vec<PublicHoliday>>(
+            r#"[
+                {"date": "2024-03-29", "global": true, "counties": null, "types": ["Public"]},
+                {"date": "2024-03-31", "global": false, "counties": ["DE-BB"], "types": ["Public"]},
+                {"date": "2024-04-01", "global": true, "counties": null, "types": ["Public"]},
+                {"date": "2024-05-09", "global": true, "counties": null, "types": ["Public"]},
+                {"date": "2024-05-20", "global": true, "counties": null, "types": ["Public"]},
+                {"date": "2024-05-30", "global": false, "counties": ["DE-BW", "DE-BY"], "types": ["Public"]},
+                {"date": "2024-10-31", "global": false, "counties": ["DE-BB"], "types": ["Observance"]}
+            ]"#,
+        )
+        .unwrap();
+
+        let pd = |region: Option<&str>| public_dates(holidays.clone(), region);
+
+        assert_eq!(
+            pd(None),
+            vec![d(2024, 3, 29), d(2024, 4, 1), d(2024, 5, 9), d(2024, 5, 20)]
+        );
+        assert_eq!(
+            pd(Some("DE-BW")),
+            vec![
+                d(2024, 3, 29),
+                d(2024, 4, 1),
+                d(2024, 5, 9),
+                d(2024, 5, 20),
+                d(2024, 5, 30)
+            ]
+        );
+        assert_eq!(
+            pd(Some("DE-BB")),
+            vec![
+                d(2024, 3, 29),
+                d(2024, 3, 31),
+                d(2024, 4, 1),
+                d(2024, 5, 9),
+                d(2024, 5, 20)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_ics() {
+        let ics = |events: &[(&str, Option<&str>)]| {
+            events
+                .iter()
+                .map(|(start, end)| {
+                    let end = end
+                        .map(|end| format!("DTEND;VALUE=DATE:{}\n", end))
+                        .unwrap_or_default();
+
+                    format!(
+                        "BEGIN:VEVENT\nDTSTART;VALUE=DATE:{}\n{}END:VEVENT\n",
+                        start, end
+                    )
+                })
+                .collect::<String>()
+        };
+
+        // Good Friday, Easter Sunday and Monday, Ascension and Whit Monday of 2025
+        assert_eq!(
+            parse_ics(&ics(&[
+                ("20250418", Some("20250419")),
+                ("20250420", Some("20250422")),
+                ("20250529", None),
+                ("20250609T000000Z", Some("20250610T000000Z")),
+            ])),
+            vec![
+                d(2025, 4, 18),
+                d(2025, 4, 20),
+                d(2025, 4, 21),
+                d(2025, 5, 29),
+                d(2025, 6, 9)
+            ]
+        );
+
+        // Events ending before they start only count on their first day
+        assert_eq!(
+            parse_ics(&ics(&[("20241225", Some("20241224"))])),
+            vec![d(2024, 12, 25)]
+        );
+        assert_eq!(parse_ics(&ics(&[("2024", None)])), vec![]);
+        assert_eq!(parse_ics("BEGIN:VCALENDAR\nEND:VCALENDAR"), vec![]);
+        assert_eq!(parse_ics_date("20240331"), Some(d(2024, 3, 31)));
+        assert_eq!(parse_ics_date("20240231"), None);
+    }
+}
@@ -0,0 +1,574 @@
+use crate::backend;
+use crate::debug;
+use crate::websocket;
+use huelib2::resource::group::{
+    CreatableKind, Kind as GroupKind, State as GroupState, StateModifier as GroupStateModifier,
+};
+use huelib2::resource::light::{State as LightState, StateModifier as LightStateModifier};
+use huelib2::resource::scene::{AppData, Kind as SceneKind, Version};
+use huelib2::resource::{Adjust, ColorMode, Group, Light, Scene};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait before connecting again after the connection was lost
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// A ping is sent if nothing was received for this long
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Color modes of lights that take colors, besides `color_temp`, `brightness` and `onoff`
+const COLOR_MODES: [&str; 5] = ["xy", "hs", "rgb", "rgbw", "rgbww"];
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+struct Attributes {
+    friendly_name: Option<String>,
+    brightness: Option<u8>,
+    /// Mired, only reported by older versions
+    color_temp: Option<u16>,
+    color_temp_kelvin: Option<u32>,
+    xy_color: Option<(f32, f32)>,
+    color_mode: Option<String>,
+    supported_color_modes: Vec<String>,
+    /// Entities of a scene
+    entity_id: Vec<String>,
+}
+
+#[derive(Clone, Deserialize)]
+struct EntityState {
+    entity_id: String,
+    state: String,
+    #[serde(default)]
+    attributes: Attributes,
+}
+
+#[derive(Deserialize)]
+struct Area {
+    area_id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Entity {
+    entity_id: String,
+    area_id: Option<String>,
+    device_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Device {
+    id: String,
+    area_id: Option<String>,
+}
+
+/// What a pending request of the connection asked for
+#[derive(Clone, Copy)]
+enum Request {
+    Areas,
+    Entities,
+    Devices,
+    States,
+    Command,
+}
+
+/// Everything retrieved from Home Assistant, states are updated as they change
+#[derive(Default)]
+struct Model {
+    areas: Vec<Area>,
+    entities: HashMap<String, Entity>,
+    devices: HashMap<String, Device>,
+    states: HashMap<String, EntityState>,
+}
+
+impl Model {
+    /// Entities inherit the area of their device unless they're assigned one themselves
+    fn area_of(&self, entity_id: &str) -> Option<&str> {
+        let entity = self.entities.get(entity_id)?;
+
+        entity.area_id.as_deref().or(entity
+            .device_id
+            .as_ref()
+            .and_then(|device_id| self.devices.get(device_id))
+            .and_then(|device| device.area_id.as_deref()))
+    }
+
+    fn lights(&self) -> impl Iterator<Item = &EntityState> {
+        self.states
+            .values()
+            .filter(|state| state.entity_id.starts_with("light."))
+    }
+}
+
+/// Converts a light into the shape of the hue api, its entity id serves as id
+fn into_light(state: &EntityState) -> Light {
+    let attributes = &state.attributes;
+    let modes = &attributes.supported_color_modes;
+    let color = modes
+        .iter()
+        .any(|mode| COLOR_MODES.contains(&mode.as_str()));
+    let temperature = modes.iter().any(|mode| mode == "color_temp");
+
+    let kind = match (color, temperature) {
+        (true, true) => "Extended color light",
+        (true, false) => "Color light",
+        (false, true) => "Color temperature light",
+        (false, false) if modes.iter().any(|mode| mode == "brightness") => "Dimmable light",
+        (false, false) => "On/Off plug-in unit",
+    };
+
+    let light_state = LightState {
+        on: match state.state.as_str() {
+            "on" => Some(true),
+            "off" => Some(false),
+            _ => None,
+        },
+        // Home Assistant goes up to 255, the hue api to 254
+        brightness: attributes.brightness.map(|brightness| brightness.min(254)),
+        hue: None,
+        saturation: None,
+        color_space_coordinates: attributes.xy_color,
+        color_temperature: attributes.color_temp.or(attributes
+            .color_temp_kelvin
+            .filter(|kelvin| *kelvin > 0)
+            .map(|kelvin| (1_000_000 / kelvin) as u16)),
+        alert: None,
+        effect: None,
+        color_mode: match attributes.color_mode.as_deref() {
+            Some("color_temp") => Some(ColorMode::ColorTemperature),
+            Some("hs") => Some(ColorMode::HueAndSaturation),
+            Some(mode) if COLOR_MODES.contains(&mode) => Some(ColorMode::ColorSpaceCoordinates),
+            _ => None,
+        },
+        reachable: state.state != "unavailable",
+    };
+
+    backend::new_light(
+        state.entity_id.clone(),
+        attributes
+            .friendly_name
+            .clone()
+            .unwrap_or(state.entity_id.clone()),
+        kind,
+        light_state,
+    )
+}
+
+/// Converts a modifier of the hue api into the service and its data, only absolute values are supported
+fn service(
+    on: Option<bool>,
+    brightness: &Option<Adjust<u8>>,
+    color_space_coordinates: &Option<Adjust<(f32, f32)>>,
+    color_temperature: &Option<Adjust<u16>>,
+    transition_time: Option<u16>,
+) -> (&'static str, Map<String, Value>) {
+    let mut data = Map::new();
+
+    // The hue api counts in 100ms, Home Assistant in seconds
+    if let Some(transition_time) = transition_time {
+        data.insert(
+            "transition".to_string(),
+            json!(transition_time as f32 / 10.0),
+        );
+    }
+
+    if on == Some(false) {
+        return ("turn_off", data);
+    }
+
+    if let Some(Adjust::Override(brightness)) = brightness {
+        data.insert("brightness".to_string(), json!(brightness));
+    }
+
+    if let Some(Adjust::Override((x, y))) = color_space_coordinates {
+        data.insert("xy_color".to_string(), json!([x, y]));
+    }
+
+    if let Some(Adjust::Override(color_temperature)) = color_temperature {
+        data.insert(
+            "color_temp_kelvin".to_string(),
+            json!(1_000_000 / (*color_temperature).max(1) as u32),
+        );
+    }
+
+    ("turn_on", data)
+}
+
+/// Backend talking to Home Assistant through its WebSocket api instead of a bridge, see `BRIDGE_BACKEND`.
+/// Lights of every integration are scheduled, areas act as rooms and scenes are recalled by Home Assistant.
+pub struct HomeAssistant {
+    stream: Mutex<Option<TcpStream>>,
+    next_id: AtomicU64,
+    pending: Mutex<HashMap<u64, Request>>,
+    model: Mutex<Model>,
+}
+
+impl HomeAssistant {
+    /// Connects in the background, e.g. to `homeassistant.local:8123` with a long-lived access token
+    pub fn connect(address: String, token: String) -> Arc<HomeAssistant> {
+        let home_assistant = Arc::new(HomeAssistant {
+            stream: Mutex::new(None),
+            next_id: AtomicU64::new(1),
+            pending: Mutex::new(HashMap::new()),
+            model: Mutex::new(Model::default()),
+        });
+
+        let connection = home_assistant.clone();
+        thread::spawn(move || {
+            let mut connected_before = false;
+
+            loop {
+                match connection.run(&address, &token) {
+                    Ok(()) => {}
+                    Err(err) if connected_before => {
+                        eprintln!("Lost connection to Home Assistant {}: {}", address, err)
+                    }
+                    Err(err) => {
+                        eprintln!("Failed to connect to Home Assistant {}: {}", address, err)
+                    }
+                }
+
+                connected_before = true;
+                *connection
+                    .stream
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner) = None;
+                thread::sleep(RECONNECT_DELAY);
+            }
+        });
+
+        home_assistant
+    }
+
+    fn model(&self) -> MutexGuard<'_, Model> {
+        self.model.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Authenticates, retrieves everything and follows state changes until the connection breaks
+    fn run(&self, address: &str, token: &str) -> io::Result<()> {
+        let mut stream = websocket::connect(address, "/api/websocket")?;
+
+        // Pings before authentication are answered right away, nothing else is sent yet
+        let mut writer = stream.try_clone()?;
+        let mut pong =
+            move |payload: &[u8]| writer.write_all(&websocket::frame(websocket::PONG, payload));
+
+        // The server asks for authentication right away
+        websocket::read_message(&mut stream, &mut pong)?;
+        let auth = json!({ "type": "auth", "access_token": token }).to_string();
+        stream.write_all(&websocket::frame(websocket::TEXT, auth.as_bytes()))?;
+
+        let (_, response) = websocket::read_message(&mut stream, &mut pong)?;
+        let response = serde_json::from_slice::<Value>(&response).unwrap_or_default();
+
+        if response["type"] != "auth_ok" {
+            return Err(io::Error::other(format!(
+                "authentication failed: {}",
+                response["message"].as_str().unwrap_or("unknown reason")
+            )));
+        }
+
+        // Requests of a previous connection are never answered
+        self.pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+
+        stream.set_read_timeout(Some(PING_INTERVAL))?;
+        *self.stream.lock().unwrap_or_else(PoisonError::into_inner) = Some(stream.try_clone()?);
+
+        self.send(
+            Request::Areas,
+            json!({ "type": "config/area_registry/list" }),
+        )?;
+        self.send(
+            Request::Entities,
+            json!({ "type": "config/entity_registry/list" }),
+        )?;
+        self.send(
+            Request::Devices,
+            json!({ "type": "config/device_registry/list" }),
+        )?;
+        self.send(Request::States, json!({ "type": "get_states" }))?;
+        self.send(
+            Request::Command,
+            json!({ "type": "subscribe_events", "event_type": "state_changed" }),
+        )?;
+
+        loop {
+            let pong = |payload: &[u8]| self.write(&websocket::frame(websocket::PONG, payload));
+
+            match websocket::read_message(&mut stream, pong) {
+                Ok((websocket::TEXT, message)) => self.receive(&message),
+                Ok((websocket::CLOSE, _)) => {
+                    return Err(io::Error::other("connection closed by Home Assistant"))
+                }
+                Ok(_) => {}
+                Err(err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::TimedOut =>
+                {
+                    self.write(&websocket::frame(websocket::PING, &[]))?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Stores results and state changes, rejected commands are logged
+    fn receive(&self, message: &[u8]) {
+        let Ok(mut message) = serde_json::from_slice::<Value>(message) else {
+            return;
+        };
+
+        if message["type"] == "event" {
+            let data = message["event"]["data"].take();
+
+            let entity_id = data["entity_id"].as_str().unwrap_or_default().to_string();
+            let mut model = self.model();
+
+            match serde_json::from_value::<EntityState>(data["new_state"].clone()) {
+                Ok(state) => model.states.insert(entity_id, state),
+                Err(_) => model.states.remove(&entity_id),
+            };
+
+            return;
+        }
+
+        let request = message["id"].as_u64().and_then(|id| {
+            self.pending
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(&id)
+        });
+
+        let Some(request) = request.filter(|_| message["type"] == "result") else {
+            return;
+        };
+
+        if message["success"] != true {
+            eprintln!(
+                "Home Assistant rejected a request: {}",
+                message["error"]["message"]
+                    .as_str()
+                    .unwrap_or("unknown reason")
+            );
+            return;
+        }
+
+        let result = message["result"].take();
+        let mut model = self.model();
+
+        let parsed = match request {
+            Request::Areas => serde_json::from_value(result).map(|areas| model.areas = areas),
+            Request::Entities => serde_json::from_value::<Vec<Entity>>(result).map(|entities| {
+                model.entities = entities
+                    .into_iter()
+                    .map(|entity| (entity.entity_id.clone(), entity))
+                    .collect()
+            }),
+            Request::Devices => serde_json::from_value::<Vec<Device>>(result).map(|devices| {
+                model.devices = devices
+                    .into_iter()
+                    .map(|device| (device.id.clone(), device))
+                    .collect()
+            }),
+            Request::States => serde_json::from_value::<Vec<EntityState>>(result).map(|states| {
+                model.states = states
+                    .into_iter()
+                    .map(|state| (state.entity_id.clone(), state))
+                    .collect()
+            }),
+            Request::Command => Ok(()),
+        };
+
+        if let Err(err) = parsed {
+            eprintln!("Failed to parse response of Home Assistant: {}", err);
+        }
+    }
+
+    fn write(&self, frame: &[u8]) -> io::Result<()> {
+        let mut stream = self.stream.lock().unwrap_or_else(PoisonError::into_inner);
+
+        match stream.as_mut() {
+            Some(stream) => stream.write_all(frame),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "not connected to Home Assistant",
+            )),
+        }
+    }
+
+    /// Sends a request, its result is handled once it's received
+    fn send(&self, request: Request, mut message: Value) -> io::Result<()> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        message["id"] = json!(id);
+
+        self.pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id, request);
+
+        self.write(&websocket::frame(
+            websocket::TEXT,
+            message.to_string().as_bytes(),
+        ))
+    }
+
+    fn call_service(
+        &self,
+        domain: &str,
+        service: &str,
+        target: Value,
+        data: Map<String, Value>,
+    ) -> Result<(), String> {
+        debug!(
+            "Home Assistant: {}.{} {} {}",
+            domain,
+            service,
+            target,
+            Value::Object(data.clone())
+        );
+
+        self.send(
+            Request::Command,
+            json!({
+                "type": "call_service",
+                "domain": domain,
+                "service": service,
+                "target": target,
+                "service_data": data,
+            }),
+        )
+        .map_err(|err| err.to_string())
+    }
+
+    /// Returns all lights in the shape of the hue api, they're unreachable while Home Assistant reports them as unavailable
+    pub fn get_all_lights(&self) -> Vec<Light> {
+        self.model().lights().map(into_light).collect()
+    }
+
+    /// Returns a single light in the shape of the hue api
+    pub fn get_light(&self, id: &str) -> Result<Light, String> {
+        self.model()
+            .states
+            .get(id)
+            .map(into_light)
+            .ok_or(format!("unknown entity {}", id))
+    }
+
+    /// Returns all areas with lights in the shape of the hue api, they count as rooms
+    pub fn get_all_groups(&self) -> Vec<Group> {
+        let model = self.model();
+
+        model
+            .areas
+            .iter()
+            .map(|area| {
+                let lights = model
+                    .lights()
+                    .filter(|light| model.area_of(&light.entity_id) == Some(&area.area_id))
+                    .collect::<Vec<&EntityState>>();
+
+                Group {
+                    id: area.area_id.clone(),
+                    name: area.name.clone(),
+                    lights: lights.iter().map(|light| light.entity_id.clone()).collect(),
+                    sensors: vec![],
+                    kind: GroupKind::Creatable(CreatableKind::Room),
+                    class: None,
+                    state: Some(GroupState {
+                        any_on: lights.iter().any(|light| light.state == "on"),
+                        all_on: !lights.is_empty()
+                            && lights.iter().all(|light| light.state == "on"),
+                    }),
+                    model_id: None,
+                    unique_id: None,
+                    recycle: None,
+                }
+            })
+            .filter(|group| !group.lights.is_empty())
+            .collect()
+    }
+
+    /// Returns all scenes in the shape of the hue api, they belong to the area they're assigned to
+    pub fn get_all_scenes(&self) -> Vec<Scene> {
+        let model = self.model();
+
+        model
+            .states
+            .values()
+            .filter(|state| state.entity_id.starts_with("scene."))
+            .map(|scene| Scene {
+                id: scene.entity_id.clone(),
+                name: scene
+                    .attributes
+                    .friendly_name
+                    .clone()
+                    .unwrap_or(scene.entity_id.clone()),
+                kind: SceneKind::GroupScene,
+                group: model.area_of(&scene.entity_id).map(String::from),
+                lights: Some(
+                    scene
+                        .attributes
+                        .entity_id
+                        .iter()
+                        .filter(|entity_id| entity_id.starts_with("light."))
+                        .cloned()
+                        .collect(),
+                ),
+                owner: None,
+                recycle: false,
+                locked: false,
+                app_data: AppData::default(),
+                picture: None,
+                last_update: None,
+                version: Version::Post,
+            })
+            .collect()
+    }
+
+    pub fn set_light_state(&self, id: &str, modifier: &LightStateModifier) -> Result<(), String> {
+        let (service, data) = service(
+            modifier.on,
+            &modifier.brightness,
+            &modifier.color_space_coordinates,
+            &modifier.color_temperature,
+            modifier.transition_time,
+        );
+
+        self.call_service("light", service, json!({ "entity_id": id }), data)
+    }
+
+    /// Groups are areas, all lights in them are switched at once
+    pub fn set_group_state(
+        &self,
+        group_id: &str,
+        modifier: &GroupStateModifier,
+    ) -> Result<(), String> {
+        if let Some(scene_id) = &modifier.scene {
+            return self.call_service(
+                "scene",
+                "turn_on",
+                json!({ "entity_id": scene_id }),
+                Map::new(),
+            );
+        }
+
+        let (service, data) = service(
+            modifier.on,
+            &modifier.brightness,
+            &modifier.color_space_coordinates,
+            &modifier.color_temperature,
+            modifier.transition_time,
+        );
+
+        self.call_service("light", service, json!({ "area_id": group_id }), data)
+    }
+}
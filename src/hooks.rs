@@ -0,0 +1,72 @@
+use crate::config::Config;
+use crate::debug;
+use crate::events::Event;
+use crate::plugins::{Plugin, Sink};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Shell commands run on events, see `ON_SCENE_APPLIED` and `ON_GROUP_OFF`
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Hooks {
+    pub on_scene_applied: Option<String>,
+    pub on_group_off: Option<String>,
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self.on_scene_applied.is_none() && self.on_group_off.is_none()
+    }
+
+    fn command(&self, event: &Event) -> Option<&String> {
+        match event {
+            Event::SceneApplied { .. } => self.on_scene_applied.as_ref(),
+            Event::GroupTurnedOff { .. } => self.on_group_off.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// Runs a command with `sh -c`, the event is passed as json on stdin
+fn run_command(command: &str, event: &Event) -> Result<(), String> {
+    let payload = serde_json::to_string(event).map_err(|err| err.to_string())?;
+
+    debug!("Hooks: {} <- {}", command, payload);
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|err| err.to_string())?;
+
+    // Commands not reading their input close it early, that's fine
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(payload.as_bytes());
+    }
+
+    let status = child.wait().map_err(|err| err.to_string())?;
+
+    match status.success() {
+        true => Ok(()),
+        false => Err(status.to_string()),
+    }
+}
+
+/// Hooks run one after another, so side effects happen in the order of the events
+impl Sink for Hooks {
+    fn handle(&mut self, event: &Event) -> Result<(), String> {
+        let Some(command) = self.command(event) else {
+            return Ok(());
+        };
+
+        run_command(command, event).map_err(|err| format!("Hook \"{}\" failed: {}", command, err))
+    }
+}
+
+/// Built-in plugin running `ON_SCENE_APPLIED` and `ON_GROUP_OFF`
+pub fn plugin(conf: &Config) -> Result<Plugin, String> {
+    match conf.hooks.is_empty() {
+        true => Err("neither ON_SCENE_APPLIED nor ON_GROUP_OFF is set".to_string()),
+        false => Ok(Plugin::Sink(Box::new(conf.hooks.clone()))),
+    }
+}
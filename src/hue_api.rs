@@ -0,0 +1,232 @@
+use crate::api_usage;
+#[cfg(feature = "clip-v2")]
+use crate::clip_v2;
+use crate::config::Config;
+#[cfg(not(feature = "clip-v2"))]
+use crate::watchdog;
+use huelib2::resource::{
+    group, light, resourcelink, Config as BridgeConfig, Group, Light, Resourcelink, Scene, Sensor,
+};
+use huelib2::response::Response;
+use huelib2::Bridge;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// The certificate of the bridge can only be checked with the verifier of the CLIP v2 client,
+/// builds without it send requests in plain HTTP the way huelib2 does
+#[cfg(feature = "clip-v2")]
+const SCHEME: &str = "https";
+#[cfg(not(feature = "clip-v2"))]
+const SCHEME: &str = "http";
+
+/// Client for the v1 api of a hue bridge, huelib2 always talks to it in plain HTTP.
+/// Requests time out with the agent, so they don't need to go through [crate::watchdog::track].
+#[derive(Clone)]
+pub struct HueApi {
+    agent: ureq::Agent,
+    api_url: String,
+}
+
+/// Agent for the bridge of `conf`, fails if `BRIDGE_CA_FILE` can't be read
+#[cfg_attr(not(feature = "clip-v2"), allow(unused_variables))]
+pub fn agent(conf: &Config) -> Result<ureq::Agent, String> {
+    #[cfg(feature = "clip-v2")]
+    return clip_v2::tls_agent(
+        conf.bridge_id.clone(),
+        conf.bridge_tls_verify,
+        conf.bridge_ca_file.as_deref(),
+    );
+
+    #[cfg(not(feature = "clip-v2"))]
+    Ok(watchdog::agent().clone())
+}
+
+/// Turns the error a bridge answers with, e.g. `[{"error":{"type":1,…}}]`, into an error
+fn check_response(response: &Value) -> huelib2::Result<()> {
+    if let Ok(responses) = serde_json::from_value::<Vec<Response<Value>>>(response.clone()) {
+        for response in responses {
+            response.into_result()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Resources keyed by their id, the way the bridge lists them
+fn with_ids<T>(resources: HashMap<String, T>, set_id: impl Fn(&mut T, String)) -> Vec<T> {
+    resources
+        .into_iter()
+        .map(|(id, mut resource)| {
+            set_id(&mut resource, id);
+            resource
+        })
+        .collect()
+}
+
+impl HueApi {
+    pub fn new(agent: ureq::Agent, bridge: &Bridge) -> HueApi {
+        HueApi {
+            agent,
+            api_url: format!(
+                "{}://{}/api/{}",
+                SCHEME,
+                bridge.ip_address(),
+                bridge.username()
+            ),
+        }
+    }
+
+    /// Registers an application on the bridge at `bridge_ip`, fails unless its link button was pressed just before
+    pub fn register(
+        agent: &ureq::Agent,
+        bridge_ip: IpAddr,
+        device_type: &str,
+    ) -> huelib2::Result<String> {
+        #[derive(Deserialize)]
+        struct User {
+            username: String,
+        }
+
+        let url = format!("{}://{}/api", SCHEME, bridge_ip);
+
+        let response = api_usage::track(|| {
+            agent
+                .post(&url)
+                .send_json(json!({ "devicetype": device_type }))
+                .map_err(Box::new)
+        })?
+        .into_json::<Value>()?;
+
+        check_response(&response)?;
+
+        serde_json::from_value::<Vec<Response<User>>>(response)?
+            .pop()
+            .ok_or(huelib2::Error::GetUsername)
+            .and_then(|response| Ok(response.into_result()?.username))
+    }
+
+    fn request(&self, method: &str, path: &str, body: Option<Value>) -> huelib2::Result<Value> {
+        let url = format!("{}/{}", self.api_url, path);
+        let request = self.agent.request(method, &url);
+
+        let response = api_usage::track(|| {
+            match body {
+                Some(body) => request.send_json(body),
+                None => request.call(),
+            }
+            .map_err(Box::new)
+        })?
+        .into_json::<Value>()?;
+
+        check_response(&response)?;
+        Ok(response)
+    }
+
+    /// Reads a resource, e.g. `scenes/1` or `lights`
+    pub fn get<T: DeserializeOwned>(&self, path: &str) -> huelib2::Result<T> {
+        Ok(serde_json::from_value(self.request("GET", path, None)?)?)
+    }
+
+    /// Changes a resource, the bridge answers with an error in place of each attribute it couldn't set
+    pub fn put(&self, path: &str, body: &impl Serialize) -> huelib2::Result<()> {
+        self.request("PUT", path, Some(serde_json::to_value(body)?))
+            .map(|_| ())
+    }
+
+    /// Creates a resource and returns its id
+    fn post(&self, path: &str, body: &impl Serialize) -> huelib2::Result<String> {
+        #[derive(Deserialize)]
+        struct Created {
+            id: String,
+        }
+
+        serde_json::from_value::<Vec<Response<Created>>>(self.request(
+            "POST",
+            path,
+            Some(serde_json::to_value(body)?),
+        )?)?
+        .pop()
+        .ok_or(huelib2::Error::GetCreatedId)
+        .and_then(|response| Ok(response.into_result()?.id))
+    }
+
+    fn delete(&self, path: &str) -> huelib2::Result<()> {
+        self.request("DELETE", path, None).map(|_| ())
+    }
+
+    pub fn get_config(&self) -> huelib2::Result<BridgeConfig> {
+        self.get("config")
+    }
+
+    pub fn get_light(&self, id: &str) -> huelib2::Result<Light> {
+        let mut light: Light = self.get(&format!("lights/{}", id))?;
+        light.id = id.to_string();
+        Ok(light)
+    }
+
+    pub fn get_all_lights(&self) -> huelib2::Result<Vec<Light>> {
+        Ok(with_ids(self.get("lights")?, |light: &mut Light, id| {
+            light.id = id
+        }))
+    }
+
+    pub fn get_all_groups(&self) -> huelib2::Result<Vec<Group>> {
+        Ok(with_ids(self.get("groups")?, |group: &mut Group, id| {
+            group.id = id
+        }))
+    }
+
+    pub fn get_all_scenes(&self) -> huelib2::Result<Vec<Scene>> {
+        Ok(with_ids(self.get("scenes")?, |scene: &mut Scene, id| {
+            scene.id = id
+        }))
+    }
+
+    pub fn get_all_sensors(&self) -> huelib2::Result<Vec<Sensor>> {
+        Ok(with_ids(self.get("sensors")?, |sensor: &mut Sensor, id| {
+            sensor.id = id
+        }))
+    }
+
+    pub fn get_all_resourcelinks(&self) -> huelib2::Result<Vec<Resourcelink>> {
+        Ok(with_ids(
+            self.get("resourcelinks")?,
+            |link: &mut Resourcelink, id| link.id = id,
+        ))
+    }
+
+    pub fn set_light_state(
+        &self,
+        id: &str,
+        modifier: &light::StateModifier,
+    ) -> huelib2::Result<()> {
+        self.put(&format!("lights/{}/state", id), modifier)
+    }
+
+    pub fn set_group_state(
+        &self,
+        id: &str,
+        modifier: &group::StateModifier,
+    ) -> huelib2::Result<()> {
+        self.put(&format!("groups/{}/action", id), modifier)
+    }
+
+    pub fn create_resourcelink(&self, creator: &resourcelink::Creator) -> huelib2::Result<String> {
+        self.post("resourcelinks", creator)
+    }
+
+    pub fn set_resourcelink(
+        &self,
+        id: &str,
+        modifier: &resourcelink::Modifier,
+    ) -> huelib2::Result<()> {
+        self.put(&format!("resourcelinks/{}", id), modifier)
+    }
+
+    pub fn delete_resourcelink(&self, id: &str) -> huelib2::Result<()> {
+        self.delete(&format!("resourcelinks/{}", id))
+    }
+}
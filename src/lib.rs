@@ -0,0 +1,69 @@
+//! Schedules scenes of a hue bridge, or of deCONZ, zigbee2mqtt and Home Assistant, based on when lights become reachable.
+//! The binary is a thin wrapper around [daemon::run], everything else can be embedded as well,
+//! e.g. [time_range_parser] and [utils::get_scheduled_scenes] to resolve which scenes are scheduled right now.
+
+pub mod api_usage;
+pub mod backend;
+pub mod backoff;
+pub mod calibration;
+#[cfg(feature = "clip-v2")]
+pub mod clip_v2;
+#[cfg(feature = "clip-v2")]
+pub mod clip_v2_resources;
+pub mod clock;
+pub mod commands;
+pub mod conditions;
+pub mod config;
+#[cfg(feature = "control")]
+pub mod control;
+pub mod cron;
+pub mod daemon;
+pub mod debounce;
+#[cfg(feature = "deconz")]
+pub mod deconz;
+pub mod discovery;
+pub mod engine;
+pub mod entertainment;
+pub mod events;
+pub mod fixtures;
+pub mod holidays;
+#[cfg(feature = "home-assistant")]
+pub mod home_assistant;
+#[cfg(feature = "hooks")]
+pub mod hooks;
+pub mod hue_api;
+pub mod locks;
+pub mod logging;
+#[cfg(feature = "zigbee2mqtt")]
+pub mod mqtt;
+pub mod network_health;
+pub mod pairing;
+pub mod plugins;
+#[cfg(feature = "clip-v2")]
+pub mod preferences;
+pub mod presence;
+pub mod rate_limit;
+pub mod reload;
+pub mod resource_cache;
+pub mod runtime;
+pub mod safe_mode;
+pub mod scene_states;
+pub mod schedule_report;
+pub mod schedules;
+pub mod shutdown;
+pub mod simulation;
+#[cfg(feature = "clip-v2")]
+pub mod smart_scenes;
+pub mod solar;
+pub mod state;
+pub mod status;
+pub mod systemd;
+pub mod time_range_parser;
+pub mod utils;
+pub mod watchdog;
+#[cfg(feature = "hooks")]
+pub mod webhook;
+#[cfg(feature = "home-assistant")]
+pub mod websocket;
+#[cfg(feature = "zigbee2mqtt")]
+pub mod zigbee2mqtt;
@@ -0,0 +1,167 @@
+use crate::debug;
+use crate::hue_api::HueApi;
+use chrono::Utc;
+use huelib2::resource::resourcelink::{self, Link, LinkKind};
+use huelib2::resource::Resourcelink;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// Name and class id of the resourcelinks used as locks. Any tool following the same convention can
+/// take part: a lock links a single group and its description is `<holder>;<unix time it expires at>`.
+const LOCK_NAME: &str = "group lock";
+const LOCK_CLASS_ID: u16 = 7410;
+
+/// Locks of backends that can't store them, by bridge and group.
+/// Only the homes of this process take part then, see `PROFILES`.
+static IN_MEMORY: Mutex<BTreeMap<(String, String), (String, i64)>> = Mutex::new(BTreeMap::new());
+
+/// An advisory lock on a group, stored on the bridge
+#[derive(Clone, PartialEq, Debug)]
+struct Lock {
+    id: String,
+    group_id: String,
+    holder: String,
+    expires_at: i64,
+}
+
+fn parse_lock(link: &Resourcelink) -> Option<Lock> {
+    if link.name != LOCK_NAME || link.class_id != LOCK_CLASS_ID {
+        return None;
+    }
+
+    let (holder, expires_at) = link.description.rsplit_once(";")?;
+    let group = link
+        .links
+        .iter()
+        .find(|link| link.kind == LinkKind::Group)?;
+
+    Some(Lock {
+        id: link.id.clone(),
+        group_id: group.id.clone(),
+        holder: holder.to_string(),
+        expires_at: expires_at.parse().ok()?,
+    })
+}
+
+/// Returns all unexpired locks of a group, oldest first
+fn get_locks(hue: &HueApi, group_id: &str) -> Result<Vec<Lock>, huelib2::Error> {
+    let now = Utc::now().timestamp();
+
+    let mut locks = hue
+        .get_all_resourcelinks()?
+        .iter()
+        .filter_map(parse_lock)
+        .filter(|lock| lock.group_id == group_id && lock.expires_at > now)
+        .collect::<Vec<Lock>>();
+
+    // Ids are assigned in ascending order, the lock created first wins a race
+    locks.sort_by_key(|lock| lock.id.parse::<u64>().unwrap_or(u64::MAX));
+    Ok(locks)
+}
+
+/// Takes or extends the lock of a group for `ttl`.
+/// Returns the holder of the lock if someone else has it, writing to the group should be skipped then.
+pub fn acquire(
+    hue: &HueApi,
+    holder: &str,
+    ttl: Duration,
+    group_id: &str,
+) -> Result<Option<String>, huelib2::Error> {
+    let locks = get_locks(hue, group_id)?;
+
+    if let Some(lock) = locks.first().filter(|lock| lock.holder != holder) {
+        return Ok(Some(lock.holder.clone()));
+    }
+
+    let description = format!(
+        "{};{}",
+        holder,
+        Utc::now().timestamp() + ttl.as_secs() as i64
+    );
+
+    if let Some(lock) = locks.first() {
+        debug!("Bridge: PUT /resourcelinks/{} {}", lock.id, description);
+
+        hue.set_resourcelink(
+            &lock.id,
+            &resourcelink::Modifier::new().with_description(description),
+        )?;
+
+        return Ok(None);
+    }
+
+    debug!(
+        "Bridge: POST /resourcelinks group {} {}",
+        group_id, description
+    );
+
+    let link = Link {
+        kind: LinkKind::Group,
+        id: group_id.to_string(),
+    };
+
+    let id = hue.create_resourcelink(
+        &resourcelink::Creator::new(LOCK_NAME.to_string(), LOCK_CLASS_ID, vec![link])
+            .with_description(description),
+    )?;
+
+    // Someone else may have created a lock at the same time, only the first one counts
+    let locks = get_locks(hue, group_id)?;
+
+    match locks.first() {
+        Some(lock) if lock.id != id && lock.holder != holder => {
+            hue.delete_resourcelink(&id)?;
+            Ok(Some(lock.holder.clone()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Same as [acquire] with the lock kept in memory, for backends that can't store it
+pub fn acquire_in_memory(
+    bridge: &str,
+    holder: &str,
+    ttl: Duration,
+    group_id: &str,
+) -> Option<String> {
+    let now = Utc::now().timestamp();
+    let mut locks = IN_MEMORY.lock().unwrap_or_else(PoisonError::into_inner);
+    let key = (bridge.to_string(), group_id.to_string());
+
+    if let Some((other, _)) = locks
+        .get(&key)
+        .filter(|(other, expires_at)| other != holder && *expires_at > now)
+    {
+        return Some(other.clone());
+    }
+
+    locks.insert(key, (holder.to_string(), now + ttl.as_secs() as i64));
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acquire_in_memory() {
+        let ttl = Duration::from_secs(60);
+
+        assert_eq!(acquire_in_memory("gateway", "flat", ttl, "1"), None);
+        assert_eq!(acquire_in_memory("gateway", "flat", ttl, "1"), None);
+        assert_eq!(
+            acquire_in_memory("gateway", "parents", ttl, "1"),
+            Some("flat".to_string())
+        );
+        assert_eq!(acquire_in_memory("gateway", "parents", ttl, "2"), None);
+        assert_eq!(acquire_in_memory("other", "parents", ttl, "1"), None);
+
+        // Expired locks are taken over
+        assert_eq!(
+            acquire_in_memory("gateway", "flat", Duration::ZERO, "3"),
+            None
+        );
+        assert_eq!(acquire_in_memory("gateway", "parents", ttl, "3"), None);
+    }
+}
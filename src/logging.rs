@@ -0,0 +1,122 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Verbosity of the log, errors are always printed
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum LogLevel {
+    Error = 0,
+    Info = 1,
+    Debug = 2,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+/// Level configured at startup, toggling debug output returns to it
+static CONFIGURED_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+impl LogLevel {
+    fn from_u8(value: u8) -> LogLevel {
+        match value {
+            0 => LogLevel::Error,
+            1 => LogLevel::Info,
+            _ => LogLevel::Debug,
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "error" => Ok(LogLevel::Error),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(format!("unknown log level: {}", value.trim())),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogLevel::Error => write!(f, "error"),
+            LogLevel::Info => write!(f, "info"),
+            LogLevel::Debug => write!(f, "debug"),
+        }
+    }
+}
+
+/// Sets the level at startup
+pub fn init(level: LogLevel) {
+    CONFIGURED_LEVEL.store(level as u8, Ordering::Relaxed);
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn level() -> LogLevel {
+    LogLevel::from_u8(LEVEL.load(Ordering::Relaxed))
+}
+
+/// Changes the level of a running scheduler
+pub fn set_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+    eprintln!("Log level set to {}", level);
+}
+
+pub fn enabled(level: LogLevel) -> bool {
+    level <= self::level()
+}
+
+/// Switches between debug output and the configured level
+pub fn toggle_debug() {
+    if level() == LogLevel::Debug {
+        set_level(LogLevel::from_u8(CONFIGURED_LEVEL.load(Ordering::Relaxed)));
+    } else {
+        set_level(LogLevel::Debug);
+    }
+}
+
+/// Toggles debug output whenever the process receives SIGUSR1
+#[cfg(unix)]
+pub fn listen_for_signals() {
+    use signal_hook::consts::SIGUSR1;
+    use signal_hook::iterator::Signals;
+
+    let mut signals = match Signals::new([SIGUSR1]) {
+        Ok(signals) => signals,
+        Err(err) => {
+            eprintln!("Failed to listen for SIGUSR1: {}", err);
+            return;
+        }
+    };
+
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            toggle_debug();
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn listen_for_signals() {}
+
+/// Prints a message unless the log level is set to `error`
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::LogLevel::Info) {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Prints a message if the log level is set to `debug`, used to dump the traffic with the bridge
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::LogLevel::Debug) {
+            println!($($arg)*);
+        }
+    };
+}
@@ -0,0 +1,326 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The broker drops the connection if nothing is sent for this long, a ping is sent after half of it.
+/// A packet that isn't complete within it counts as a lost connection as well.
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+/// How long to wait before connecting again after the connection was lost
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82;
+const PINGREQ: u8 = 0xc0;
+
+/// Where and how to connect to the broker
+#[derive(Clone, PartialEq, Debug)]
+pub struct Options {
+    /// E.g. `localhost:1883`
+    pub address: String,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// Appends a string prefixed by its length
+fn write_string(buffer: &mut Vec<u8>, value: &str) {
+    buffer.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(value.as_bytes());
+}
+
+/// Builds a packet out of its type and everything following the remaining length
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![kind];
+    let mut length = body.len();
+
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+
+        if length > 0 {
+            byte |= 0x80;
+        }
+
+        packet.push(byte);
+
+        if length == 0 {
+            break;
+        }
+    }
+
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// Reads a packet, returns its type and everything following the remaining length.
+/// Returns `None` if none started within `timeout`, once one did the rest of it has to follow within `KEEP_ALIVE`.
+fn read_packet(stream: &mut TcpStream, timeout: Duration) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut kind = [0u8];
+    stream.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+
+    match stream.read(&mut kind) {
+        Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+        Ok(_) => {}
+        Err(err)
+            if err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut =>
+        {
+            return Ok(None)
+        }
+        Err(err) => return Err(err),
+    }
+
+    // Timing out from here on would leave the stream in the middle of a packet
+    stream.set_read_timeout(Some(KEEP_ALIVE))?;
+
+    let mut length = 0usize;
+
+    for shift in 0..4 {
+        let mut byte = [0u8];
+        stream.read_exact(&mut byte)?;
+        length |= ((byte[0] & 0x7f) as usize) << (7 * shift);
+
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+
+    let mut body = vec![0u8; length];
+    stream.read_exact(&mut body)?;
+    Ok(Some((kind[0], body)))
+}
+
+/// Splits a received publish packet into its topic and payload
+fn parse_publish(flags: u8, body: &[u8]) -> Option<(String, &[u8])> {
+    let length = u16::from_be_bytes([*body.first()?, *body.get(1)?]) as usize;
+    let topic = String::from_utf8(body.get(2..2 + length)?.to_vec()).ok()?;
+
+    // Messages with a quality of service above 0 carry a packet id
+    let offset = match (flags >> 1) & 0x03 {
+        0 => 2 + length,
+        _ => 4 + length,
+    };
+
+    Some((topic, body.get(offset..)?))
+}
+
+/// Minimal MQTT 3.1.1 client, it only subscribes and publishes with a quality of service of 0.
+/// The connection is kept up in a separate thread, subscriptions are renewed whenever it's established again.
+pub struct Client {
+    stream: Mutex<Option<TcpStream>>,
+    /// When a packet was sent last, the broker only counts packets sent to it
+    last_sent: Mutex<Instant>,
+}
+
+impl Client {
+    /// Connects in the background, `on_message` is called with the topic and payload of every received message
+    pub fn connect(
+        options: Options,
+        subscriptions: Vec<String>,
+        on_message: impl Fn(&str, &[u8]) + Send + 'static,
+    ) -> Arc<Client> {
+        let client = Arc::new(Client {
+            stream: Mutex::new(None),
+            last_sent: Mutex::new(Instant::now()),
+        });
+
+        let connection = client.clone();
+        thread::spawn(move || {
+            loop {
+                let result = connection.run(&options, &subscriptions, &on_message);
+
+                // The stream is only set once the broker accepted the connection
+                let connected = connection
+                    .stream
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .take()
+                    .is_some();
+
+                match result {
+                    Ok(()) => {}
+                    Err(err) if connected => {
+                        eprintln!(
+                            "Lost connection to MQTT broker {}: {}",
+                            options.address, err
+                        )
+                    }
+                    Err(err) => eprintln!(
+                        "Failed to connect to MQTT broker {}: {}",
+                        options.address, err
+                    ),
+                }
+
+                thread::sleep(RECONNECT_DELAY);
+            }
+        });
+
+        client
+    }
+
+    /// Connects, subscribes and receives messages until the connection breaks
+    fn run(
+        &self,
+        options: &Options,
+        subscriptions: &[String],
+        on_message: &impl Fn(&str, &[u8]),
+    ) -> io::Result<()> {
+        let mut stream = TcpStream::connect(&options.address)?;
+
+        let mut connect = vec![];
+        write_string(&mut connect, "MQTT");
+        connect.push(4);
+        // A password is only allowed with a username, see `MQTT_PASSWORD`
+        connect.push(
+            0x02 | options.username.as_ref().map(|_| 0x80).unwrap_or(0)
+                | options.password.as_ref().map(|_| 0x40).unwrap_or(0),
+        );
+        connect.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+        write_string(&mut connect, &options.client_id);
+
+        for value in [&options.username, &options.password].into_iter().flatten() {
+            write_string(&mut connect, value);
+        }
+
+        stream.write_all(&packet(CONNECT, &connect))?;
+
+        match read_packet(&mut stream, KEEP_ALIVE)? {
+            Some((CONNACK, body)) if body.get(1) == Some(&0) => {}
+            Some((_, body)) => {
+                return Err(io::Error::other(format!(
+                    "connection refused with code {}",
+                    body.get(1).copied().unwrap_or_default()
+                )))
+            }
+            None => return Err(io::Error::other("no answer to connecting")),
+        }
+
+        let mut subscribe = 1u16.to_be_bytes().to_vec();
+
+        for topic in subscriptions {
+            write_string(&mut subscribe, topic);
+            subscribe.push(0);
+        }
+
+        *self.stream.lock().unwrap_or_else(PoisonError::into_inner) = Some(stream.try_clone()?);
+        self.send(&packet(SUBSCRIBE, &subscribe))?;
+
+        loop {
+            // Incoming messages don't keep the connection alive, only what's sent to the broker does
+            let ping_in = (KEEP_ALIVE / 2).saturating_sub(self.since_sent());
+
+            if ping_in.is_zero() {
+                self.send(&packet(PINGREQ, &[]))?;
+                continue;
+            }
+
+            if let Some((kind, body)) = read_packet(&mut stream, ping_in)? {
+                if kind & 0xf0 == PUBLISH {
+                    if let Some((topic, payload)) = parse_publish(kind & 0x0f, &body) {
+                        on_message(&topic, payload);
+                    }
+                }
+            }
+        }
+    }
+
+    fn since_sent(&self) -> Duration {
+        self.last_sent
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .elapsed()
+    }
+
+    fn send(&self, packet: &[u8]) -> io::Result<()> {
+        let mut stream = self.stream.lock().unwrap_or_else(PoisonError::into_inner);
+
+        match stream.as_mut() {
+            Some(stream) => {
+                stream.write_all(packet)?;
+                *self
+                    .last_sent
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner) = Instant::now();
+                Ok(())
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "not connected to the broker",
+            )),
+        }
+    }
+
+    /// Publishes a message, it's lost if the broker isn't connected
+    pub fn publish(&self, topic: &str, payload: &str) -> io::Result<()> {
+        let mut body = vec![];
+        write_string(&mut body, topic);
+        body.extend_from_slice(payload.as_bytes());
+
+        self.send(&packet(PUBLISH, &body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_packets() {
+        let mut body = vec![];
+        write_string(&mut body, "zigbee2mqtt/Hall");
+        body.extend_from_slice(br#"{"state":"ON"}"#);
+
+        let publish = packet(PUBLISH, &body);
+        assert_eq!(publish[..2], [PUBLISH, 32]);
+        assert_eq!(
+            parse_publish(0, &publish[2..]),
+            Some(("zigbee2mqtt/Hall".to_string(), &br#"{"state":"ON"}"#[..]))
+        );
+
+        // Lengths above 127 take several bytes
+        assert_eq!(packet(PUBLISH, &[0; 200])[..3], [PUBLISH, 0xc8, 0x01]);
+        assert_eq!(packet(PINGREQ, &[]), vec![PINGREQ, 0]);
+    }
+
+    #[test]
+    fn test_read_packet() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut broker, _) = listener.accept().unwrap();
+
+        assert_eq!(
+            read_packet(&mut stream, Duration::from_millis(10)).unwrap(),
+            None
+        );
+
+        // A packet arriving in pieces isn't cut off by the timeout waiting for the next one
+        let publish = packet(PUBLISH, &[0, 1, b'a', b'b']);
+        broker.write_all(&publish[..1]).unwrap();
+
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            broker.write_all(&publish[1..]).unwrap();
+            broker.write_all(&packet(PINGREQ, &[])).unwrap();
+            broker
+        });
+
+        let received =
+            (0..100).find_map(|_| read_packet(&mut stream, Duration::from_millis(10)).unwrap());
+
+        assert_eq!(received, Some((PUBLISH, vec![0, 1, b'a', b'b'])));
+
+        let broker = sender.join().unwrap();
+        assert_eq!(
+            read_packet(&mut stream, KEEP_ALIVE).unwrap(),
+            Some((PINGREQ, vec![]))
+        );
+
+        drop(broker);
+        assert!(read_packet(&mut stream, KEEP_ALIVE).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+#[cfg(feature = "clip-v2")]
+use crate::clip_v2::{ClipV2, ResourceIdentifier};
+use crate::engine::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+#[cfg(feature = "clip-v2")]
+use std::collections::HashMap;
+use std::fmt;
+
+/// Channels that don't overlap with the common Wi-Fi channels 1, 6 and 11
+const RECOMMENDED_CHANNELS: [u8; 4] = [11, 15, 20, 25];
+
+#[cfg(feature = "clip-v2")]
+#[derive(Deserialize)]
+struct ZigbeeConnectivityResource {
+    owner: ResourceIdentifier,
+    status: String,
+}
+
+#[cfg(feature = "clip-v2")]
+#[derive(Deserialize)]
+struct Metadata {
+    name: String,
+}
+
+#[cfg(feature = "clip-v2")]
+#[derive(Deserialize)]
+struct DeviceResource {
+    id: String,
+    metadata: Metadata,
+}
+
+/// Health of the zigbee mesh, most "scheduler bugs" turn out to be mesh problems
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct NetworkHealth {
+    pub zigbee_channel: Option<u8>,
+    pub connectivity: BTreeMap<String, String>,
+    pub hints: Vec<String>,
+    pub updated: Option<DateTime<Utc>>,
+}
+
+/// Returns the name and zigbee connectivity of every device (v2)
+#[cfg(feature = "clip-v2")]
+fn get_connectivity(clip: &ClipV2) -> Result<BTreeMap<String, String>, Box<ureq::Error>> {
+    let names = clip
+        .get_resources::<DeviceResource>("device")?
+        .into_iter()
+        .map(|device| (device.id, device.metadata.name))
+        .collect::<HashMap<String, String>>();
+
+    Ok(clip
+        .get_resources::<ZigbeeConnectivityResource>("zigbee_connectivity")?
+        .into_iter()
+        .filter_map(|connectivity| {
+            Some((
+                names.get(&connectivity.owner.rid)?.clone(),
+                connectivity.status,
+            ))
+        })
+        .collect())
+}
+
+/// Collects the zigbee channel (v1) and, if available, the connectivity per device (v2)
+pub fn get_network_health(context: &Context) -> NetworkHealth {
+    let mut health = NetworkHealth {
+        updated: Some(Utc::now()),
+        ..NetworkHealth::default()
+    };
+
+    match context.hue().get_config() {
+        Ok(config) => health.zigbee_channel = Some(config.zigbee_channel),
+        Err(err) => eprintln!("Failed to retrieve zigbee channel: {}", err),
+    }
+
+    #[cfg(feature = "clip-v2")]
+    match get_connectivity(&context.clip) {
+        Ok(connectivity) => health.connectivity = connectivity,
+        Err(err) => eprintln!("Failed to retrieve zigbee connectivity: {}", err),
+    }
+
+    if let Some(channel) = health.zigbee_channel {
+        if !RECOMMENDED_CHANNELS.contains(&channel) {
+            health.hints.push(format!(
+                "Zigbee channel {} may overlap with Wi-Fi, channels 11, 15, 20 and 25 are recommended",
+                channel
+            ));
+        }
+    }
+
+    let troubled = health
+        .connectivity
+        .iter()
+        .filter(|(_, status)| *status != "connected")
+        .map(|(name, status)| format!("{} ({})", name, status))
+        .collect::<Vec<String>>();
+
+    if !troubled.is_empty() {
+        health.hints.push(format!(
+            "Devices with connectivity issues: {}",
+            troubled.join(", ")
+        ));
+    }
+
+    health
+}
+
+impl fmt::Display for NetworkHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.zigbee_channel {
+            Some(channel) => writeln!(f, "Zigbee channel: {}", channel)?,
+            None => writeln!(f, "Zigbee channel: unknown")?,
+        }
+
+        let connected = self
+            .connectivity
+            .values()
+            .filter(|status| *status == "connected")
+            .count();
+
+        writeln!(
+            f,
+            "Devices connected: {}/{}",
+            connected,
+            self.connectivity.len()
+        )?;
+
+        for hint in self.hints.iter() {
+            writeln!(f, "Hint: {}", hint)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+#[cfg(feature = "clip-v2")]
+use crate::clip_v2;
+#[cfg(feature = "clip-v2")]
+use crate::config;
+use crate::hue_api::HueApi;
+#[cfg(not(feature = "clip-v2"))]
+use crate::watchdog;
+use huelib2::bridge;
+use huelib2::response::ErrorKind;
+use std::env;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for the link button to be pressed
+const LINK_BUTTON_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Name of the application shown in the app under "Other apps"
+const DEVICE_TYPE: &str = "hue-scheduler";
+
+/// Bridge to pair with, `BRIDGE_IP` or the first one found on the network
+fn find_bridge() -> Option<IpAddr> {
+    if let Some(value) = env::var("BRIDGE_IP").ok().filter(|value| !value.is_empty()) {
+        return Some(IpAddr::from_str(&value).expect("failed to parse BRIDGE_IP"));
+    }
+
+    println!("BRIDGE_IP missing, searching for bridges...");
+
+    match bridge::discover_nupnp() {
+        Ok(bridges) => bridges.into_iter().next(),
+        Err(err) => {
+            eprintln!("Failed to search for bridges: {}", err);
+            None
+        }
+    }
+}
+
+/// Agent for registering, the username is sent back through HTTPS if `clip-v2` is built in.
+/// The id of the bridge isn't known before it's paired, so it's looked up through discovery unless `BRIDGE_ID` is set.
+fn agent() -> Result<ureq::Agent, String> {
+    #[cfg(feature = "clip-v2")]
+    return clip_v2::tls_agent(
+        config::load_bridge_id(),
+        config::load_bridge_tls_verify(),
+        config::load_bridge_ca_file().as_deref(),
+    );
+
+    #[cfg(not(feature = "clip-v2"))]
+    Ok(watchdog::agent().clone())
+}
+
+/// Sets a variable in an env file, an existing line is replaced and the file is created if needed
+fn set_variable(path: &Path, key: &str, value: &str) -> std::io::Result<()> {
+    let content = fs::read_to_string(path).unwrap_or_default();
+    let prefix = format!("{}=", key);
+    let mut lines = content.lines().map(String::from).collect::<Vec<String>>();
+    let line = format!("{}={}", key, value);
+
+    match lines
+        .iter_mut()
+        .find(|current| current.trim_start().starts_with(&prefix))
+    {
+        Some(current) => *current = line,
+        None => lines.push(line),
+    }
+
+    fs::write(path, lines.join("\n") + "\n")
+}
+
+/// Registers the scheduler on the bridge once its link button is pressed and writes the username to the env file
+pub fn pair(path: &Path) {
+    let Some(bridge_ip) = find_bridge() else {
+        eprintln!("No bridge found, set BRIDGE_IP in {}", path.display());
+        std::process::exit(1);
+    };
+
+    println!(
+        "Press the link button on the bridge at {}, waiting up to {}s...",
+        bridge_ip,
+        LINK_BUTTON_TIMEOUT.as_secs()
+    );
+
+    let agent = match agent() {
+        Ok(agent) => agent,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let started_at = Instant::now();
+
+    let username = loop {
+        match HueApi::register(&agent, bridge_ip, DEVICE_TYPE) {
+            Ok(username) => break username,
+            Err(huelib2::Error::Response(err)) if err.kind == ErrorKind::LinkButtonNotPressed => {
+                if started_at.elapsed() >= LINK_BUTTON_TIMEOUT {
+                    eprintln!("The link button wasn't pressed in time, try again");
+                    std::process::exit(1);
+                }
+
+                thread::sleep(Duration::from_secs(1));
+            }
+            Err(err) => {
+                eprintln!("Failed to register at the bridge: {}", err);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    let result = set_variable(path, "BRIDGE_IP", &bridge_ip.to_string())
+        .and_then(|_| set_variable(path, "BRIDGE_USERNAME", &username));
+
+    match result {
+        Ok(_) => println!("Paired, BRIDGE_USERNAME written to {}", path.display()),
+        Err(err) => {
+            eprintln!(
+                "Paired, but failed to write {}: {}, set BRIDGE_USERNAME={} yourself",
+                path.display(),
+                err,
+                username
+            );
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use crate::config::Config;
+use crate::events::Event;
+#[cfg(feature = "hooks")]
+use crate::hooks;
+use crate::runtime::Runtime;
+#[cfg(feature = "hooks")]
+use crate::webhook;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+
+/// Something a trigger asks the scheduler to do
+#[derive(Clone, PartialEq, Debug)]
+pub enum Signal {
+    /// Someone arrived home, the rooms in `ARRIVAL_ROOMS` get their scene within `ARRIVAL_TIME`
+    Arrived,
+    /// Switches to another mode, see `MODE`
+    Mode(String),
+}
+
+/// Handed to triggers to reach the scheduler
+#[derive(Clone)]
+pub struct Signals {
+    runtime: Arc<Runtime>,
+}
+
+impl Signals {
+    pub fn send(&self, signal: Signal) {
+        match signal {
+            Signal::Arrived => self.runtime.arrive(),
+            Signal::Mode(mode) => self.runtime.set_mode(mode),
+        }
+    }
+}
+
+/// Source of signals from outside the scheduler, e.g. a doorbell. It runs in a thread of its own.
+pub trait Trigger: Send {
+    fn run(self: Box<Self>, signals: Signals);
+}
+
+/// Receives every event of the scheduler, e.g. to send notifications. Events are handed over one after another.
+pub trait Sink: Send {
+    fn handle(&mut self, event: &Event) -> Result<(), String>;
+}
+
+pub enum Plugin {
+    Trigger(Box<dyn Trigger>),
+    Sink(Box<dyn Sink>),
+}
+
+/// Builds a plugin from the configuration, fails if something it needs isn't configured
+pub type Factory = fn(&Config) -> Result<Plugin, String>;
+
+/// Built-in plugins, `webhook` and `hooks` are started whenever they're configured
+const BUILTIN: &[(&str, Factory)] = &[
+    #[cfg(feature = "hooks")]
+    ("webhook", webhook::plugin),
+    #[cfg(feature = "hooks")]
+    ("hooks", hooks::plugin),
+    ("signal", signal),
+];
+
+static REGISTERED: Mutex<Vec<(String, Factory)>> = Mutex::new(Vec::new());
+
+/// Adds a plugin to binaries built on top of this crate, it's selected by its name in `PLUGINS` like built-in ones
+pub fn register(name: &str, factory: Factory) {
+    REGISTERED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push((name.to_string(), factory));
+}
+
+fn factory(name: &str) -> Option<Factory> {
+    let registered = REGISTERED.lock().unwrap_or_else(PoisonError::into_inner);
+
+    registered
+        .iter()
+        .map(|(name, factory)| (name.as_str(), *factory))
+        .chain(BUILTIN.iter().copied())
+        .find(|(candidate, _)| *candidate == name)
+        .map(|(_, factory)| factory)
+}
+
+/// Starts the plugins selected in `PLUGINS` and the ones implied by `WEBHOOK_URL`, `ON_SCENE_APPLIED` and `ON_GROUP_OFF`
+pub fn start(conf: &Config, runtime: Arc<Runtime>) {
+    #[cfg_attr(not(feature = "hooks"), allow(unused_mut))]
+    let mut names = conf.plugins.clone();
+
+    #[cfg(feature = "hooks")]
+    if conf.webhook_url.is_some() && !names.iter().any(|name| name == "webhook") {
+        names.push("webhook".to_string());
+    }
+
+    #[cfg(feature = "hooks")]
+    if !conf.hooks.is_empty() && !names.iter().any(|name| name == "hooks") {
+        names.push("hooks".to_string());
+    }
+
+    for name in names.iter() {
+        let factory =
+            factory(name).unwrap_or_else(|| panic!("unknown plugin in PLUGINS: {}", name));
+        let plugin =
+            factory(conf).unwrap_or_else(|err| panic!("failed to start plugin {}: {}", name, err));
+
+        match plugin {
+            Plugin::Trigger(trigger) => {
+                let signals = Signals {
+                    runtime: runtime.clone(),
+                };
+
+                thread::spawn(move || trigger.run(signals));
+            }
+            Plugin::Sink(mut sink) => {
+                let events = runtime.events.subscribe();
+
+                thread::spawn(move || {
+                    for event in events {
+                        if let Err(err) = sink.handle(&event) {
+                            eprintln!("{}", err);
+                        }
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Reports an arrival on SIGUSR2, e.g. `pkill -USR2 hue-scheduler` from a doorbell script
+#[cfg(unix)]
+struct SignalTrigger;
+
+#[cfg(unix)]
+impl Trigger for SignalTrigger {
+    fn run(self: Box<Self>, signals: Signals) {
+        use signal_hook::consts::SIGUSR2;
+        use signal_hook::iterator::Signals as UnixSignals;
+
+        let mut unix_signals = match UnixSignals::new([SIGUSR2]) {
+            Ok(unix_signals) => unix_signals,
+            Err(err) => {
+                eprintln!("Failed to listen for SIGUSR2: {}", err);
+                return;
+            }
+        };
+
+        for _ in unix_signals.forever() {
+            signals.send(Signal::Arrived);
+        }
+    }
+}
+
+fn signal(_conf: &Config) -> Result<Plugin, String> {
+    #[cfg(unix)]
+    return Ok(Plugin::Trigger(Box::new(SignalTrigger)));
+
+    #[cfg(not(unix))]
+    Err("signals are only available on unix".to_string())
+}
@@ -0,0 +1,140 @@
+use crate::clip_v2::ClipV2;
+use crate::runtime::Runtime;
+use crate::{debug, info};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Activations within this time after the scheduler applied a scene are its own
+const OWN_ACTIVATION_WINDOW: i64 = 3 * 60;
+
+/// How often each scene was selected manually, counted per hour of the day
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct Preferences {
+    scenes: HashMap<String, [u32; 24]>,
+}
+
+impl Preferences {
+    /// Loads previously learned preferences, starts over if there are none
+    pub fn load(path: &Path) -> Preferences {
+        let Ok(content) = fs::read_to_string(path) else {
+            return Preferences::default();
+        };
+
+        serde_json::from_str(&content).unwrap_or_else(|err| {
+            eprintln!("Failed to parse {}: {}, starting over", path.display(), err);
+            Preferences::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) {
+        let content = serde_json::to_string_pretty(self).unwrap_or_default();
+
+        if let Err(err) = fs::write(path, content) {
+            eprintln!("Failed to write {}: {}", path.display(), err);
+        }
+    }
+
+    pub fn record(&mut self, scene_id: &str, hour: u32) {
+        self.scenes.entry(scene_id.to_string()).or_default()[hour as usize % 24] += 1;
+    }
+
+    /// Weight of each scene within the given hour, scenes never selected manually are left out
+    pub fn weights(&self, hour: u32) -> HashMap<String, u32> {
+        self.scenes
+            .iter()
+            .map(|(scene_id, counts)| (scene_id.clone(), counts[hour as usize % 24]))
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+struct SceneStatus {
+    active: String,
+}
+
+#[derive(Deserialize)]
+struct SceneResource {
+    id_v1: Option<String>,
+    status: Option<SceneStatus>,
+}
+
+/// Returns the (v1) ids of all scenes the bridge reports as active
+fn get_active_scenes(clip: &ClipV2) -> Result<HashSet<String>, Box<ureq::Error>> {
+    Ok(clip
+        .get_resources::<SceneResource>("scene")?
+        .into_iter()
+        .filter(|scene| {
+            scene
+                .status
+                .as_ref()
+                .map(|status| status.active != "inactive")
+                .unwrap_or(false)
+        })
+        .filter_map(|scene| Some(scene.id_v1?.trim_start_matches("/scenes/").to_string()))
+        .collect())
+}
+
+/// Learns which scenes are selected manually, e.g. via the Hue app or a dimmer switch
+pub struct Learner {
+    path: PathBuf,
+    pub preferences: Preferences,
+    active_scenes: Option<HashSet<String>>,
+}
+
+impl Learner {
+    pub fn new(path: PathBuf) -> Learner {
+        Learner {
+            preferences: Preferences::load(&path),
+            path,
+            active_scenes: None,
+        }
+    }
+
+    /// Counts scheduled scenes that became active without the scheduler applying them
+    pub fn update(&mut self, clip: &ClipV2, runtime: &Runtime, hour: u32) {
+        let active_scenes = match get_active_scenes(clip) {
+            Ok(active_scenes) => active_scenes,
+            Err(err) => {
+                eprintln!("Failed to retrieve active scenes: {}", err);
+                return;
+            }
+        };
+
+        debug!("Bridge: {} active scenes", active_scenes.len());
+
+        // Scenes active on startup could have been selected at any time
+        let Some(previous) = self.active_scenes.replace(active_scenes.clone()) else {
+            return;
+        };
+
+        let mut changed = false;
+
+        for scene_id in active_scenes.difference(&previous) {
+            let status = runtime.status();
+
+            // Only scheduled scenes are candidates for the scheduler
+            let Some(activity) = status.scenes.get(scene_id) else {
+                continue;
+            };
+
+            let applied_by_scheduler = activity
+                .last_activated
+                .map(|at| Utc::now() - at.to_utc() < Duration::seconds(OWN_ACTIVATION_WINDOW))
+                .unwrap_or(false);
+
+            if !applied_by_scheduler {
+                info!("Scene \"{}\" was selected manually", activity.name);
+                self.preferences.record(scene_id, hour);
+                changed = true;
+            }
+        }
+
+        if changed {
+            self.preferences.save(&self.path);
+        }
+    }
+}
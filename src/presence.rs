@@ -0,0 +1,26 @@
+use huelib2::resource::Sensor;
+use regex::Regex;
+
+/// Sensor types that tell whether someone is home, e.g. set by the geofencing of the Hue app
+const PRESENCE_SENSOR_TYPES: [&str; 2] = ["Geofence", "CLIPPresence"];
+
+/// Returns whether anyone is home, `None` if there are no presence sensors.
+/// Motion sensors are left out, they only tell about a single room.
+pub fn is_home(sensors: &[Sensor], names: Option<&Regex>) -> Option<bool> {
+    let presences = sensors
+        .iter()
+        .filter(|sensor| {
+            PRESENCE_SENSOR_TYPES.contains(&sensor.type_name.as_str())
+                && names
+                    .map(|pattern| pattern.is_match(&sensor.name))
+                    .unwrap_or(true)
+        })
+        .filter_map(|sensor| sensor.state.presence)
+        .collect::<Vec<bool>>();
+
+    if presences.is_empty() {
+        None
+    } else {
+        Some(presences.contains(&true))
+    }
+}
@@ -0,0 +1,98 @@
+use crate::debug;
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Spreads commands to a bridge evenly, it drops commands if it receives more than about 10 per second.
+/// Engines of all rooms share one, commands exceeding the limit wait for their turn.
+pub struct RateLimiter {
+    interval: Option<Duration>,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// Allows up to `per_second` commands per second, `0` doesn't limit them
+    pub fn new(per_second: u32) -> RateLimiter {
+        RateLimiter {
+            interval: (per_second > 0).then(|| Duration::from_secs(1) / per_second),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Blocks until the next command may be sent
+    pub fn wait(&self) {
+        let Some(at) = self.reserve(Instant::now()) else {
+            return;
+        };
+
+        let delay = at.saturating_duration_since(Instant::now());
+
+        if !delay.is_zero() {
+            debug!("Bridge: command queued for {}ms", delay.as_millis());
+            thread::sleep(delay);
+        }
+    }
+
+    /// Takes the next free slot for a command asked to be sent at `now`, slots are handed out in the order they're asked for
+    fn reserve(&self, now: Instant) -> Option<Instant> {
+        let interval = self.interval?;
+        let mut next = self.next.lock().unwrap_or_else(PoisonError::into_inner);
+        let at = (*next).max(now);
+        *next = at + interval;
+        Some(at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_spacing() {
+        let ms = Duration::from_millis;
+        let limiter = RateLimiter::new(50);
+        let start = *limiter.next.lock().unwrap();
+        let at = |elapsed| limiter.reserve(start + elapsed).unwrap() - start;
+
+        // Commands asked for at once are sent 20ms apart
+        assert_eq!(at(ms(0)), ms(0));
+        assert_eq!(at(ms(0)), ms(20));
+        assert_eq!(at(ms(5)), ms(40));
+
+        // Once the queue is through, commands go out right away again
+        assert_eq!(at(ms(500)), ms(500));
+        assert_eq!(at(ms(530)), ms(530));
+        assert_eq!(at(ms(531)), ms(550));
+
+        assert_eq!(RateLimiter::new(0).reserve(start), None);
+    }
+
+    #[test]
+    fn test_shared() {
+        // Engines of all rooms share one limiter, none of their commands get the same slot
+        let limiter = Arc::new(RateLimiter::new(50));
+        let groups = (0..4)
+            .map(|_| {
+                let limiter = limiter.clone();
+                thread::spawn(move || {
+                    (0..5)
+                        .map(|_| limiter.reserve(Instant::now()).unwrap())
+                        .collect::<Vec<Instant>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut all = groups
+            .into_iter()
+            .flat_map(|group| group.join().unwrap())
+            .collect::<Vec<Instant>>();
+
+        all.sort();
+
+        assert_eq!(all.len(), 20);
+        assert!(all
+            .windows(2)
+            .all(|pair| pair[1] - pair[0] >= Duration::from_millis(20)));
+    }
+}
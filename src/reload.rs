@@ -0,0 +1,104 @@
+use crate::config::{self, Config};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+
+/// SIGHUPs received so far, each home compares it with the count it saw last
+static SIGNALS: AtomicU64 = AtomicU64::new(0);
+
+/// Configuration of a home with the generation it was published in
+type Published = (u64, Arc<Config>);
+
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+/// Latest configuration of each home, by the name of its profile, see `PROFILES`
+static LATEST: Mutex<BTreeMap<Option<String>, Published>> = Mutex::new(BTreeMap::new());
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).ok()?.modified().ok()
+}
+
+/// Listens for SIGHUP, every home reloads its configuration then
+pub fn watch() {
+    #[cfg(unix)]
+    {
+        use signal_hook::consts::SIGHUP;
+        use signal_hook::iterator::Signals;
+
+        match Signals::new([SIGHUP]) {
+            Ok(mut signals) => {
+                std::thread::spawn(move || {
+                    for _ in signals.forever() {
+                        SIGNALS.fetch_add(1, Ordering::SeqCst);
+                    }
+                });
+            }
+            Err(err) => eprintln!("Failed to listen for SIGHUP: {}", err),
+        }
+    }
+}
+
+/// Notices when the configuration of a home should be loaded again, on SIGHUP or once the .env file or its profile changes
+pub struct Watcher {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    signals: u64,
+}
+
+impl Watcher {
+    pub fn new(conf: &Config) -> Watcher {
+        Watcher {
+            files: config::env_file()
+                .map(Path::to_path_buf)
+                .into_iter()
+                .chain(conf.profile_file.clone())
+                .map(|path| {
+                    let modified = modified(&path);
+                    (path, modified)
+                })
+                .collect(),
+            signals: SIGNALS.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Whether the configuration should be loaded again since the last call
+    pub fn requested(&mut self) -> bool {
+        let mut changed = false;
+
+        for (path, last_modified) in self.files.iter_mut() {
+            let modified = modified(path);
+            changed |= modified != *last_modified;
+            *last_modified = modified;
+        }
+
+        let signals = SIGNALS.load(Ordering::SeqCst);
+        let signaled = signals != self.signals;
+        self.signals = signals;
+
+        signaled || changed
+    }
+}
+
+/// Hands a reloaded configuration to all bridges and engines of its home, they pick it up on their next iteration
+pub fn publish(conf: Arc<Config>) {
+    let generation = GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+
+    LATEST
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(conf.profile.clone(), (generation, conf));
+}
+
+/// Returns the latest configuration of `profile` if it was reloaded since `generation`, which is updated then
+pub fn newer(generation: &mut u64, profile: Option<&String>) -> Option<Arc<Config>> {
+    let latest = LATEST.lock().unwrap_or_else(PoisonError::into_inner);
+    let (published, conf) = latest.get(&profile.cloned())?;
+
+    if *published == *generation {
+        return None;
+    }
+
+    *generation = *published;
+    Some(conf.clone())
+}
@@ -0,0 +1,162 @@
+use huelib2::resource::group::State as GroupState;
+use huelib2::resource::{Group, Light, Scene};
+use std::time::{Duration, Instant};
+
+fn light_ids(lights: &[Light]) -> Vec<String> {
+    let mut light_ids = lights
+        .iter()
+        .map(|light| light.id.clone())
+        .collect::<Vec<String>>();
+    light_ids.sort();
+    light_ids
+}
+
+/// Group states change with every switch, they're taken from the lights of the current poll instead of the cache
+fn with_states(groups: &[Group], lights: &[Light]) -> Vec<Group> {
+    groups
+        .iter()
+        .cloned()
+        .map(|mut group| {
+            if group.state.is_some() {
+                let on = group
+                    .lights
+                    .iter()
+                    .filter_map(|light_id| lights.iter().find(|light| light.id == *light_id))
+                    .map(|light| light.state.on == Some(true))
+                    .collect::<Vec<bool>>();
+
+                group.state = Some(GroupState {
+                    any_on: on.iter().any(|on| *on),
+                    all_on: !on.is_empty() && on.iter().all(|on| *on),
+                });
+            }
+
+            group
+        })
+        .collect()
+}
+
+struct Cached {
+    retrieved_at: Instant,
+    light_ids: Vec<String>,
+    scenes: Vec<Scene>,
+    groups: Vec<Group>,
+}
+
+/// Scenes and groups of a bridge, they rarely change but take long to retrieve from bridges with many scenes.
+/// They're retrieved again once they expire or lights were added or removed, see `SCENE_CACHE_TTL`.
+/// Only which lights belong to a group is cached, whether they're on is always up to date.
+pub struct ResourceCache {
+    ttl: Duration,
+    cached: Option<Cached>,
+}
+
+impl ResourceCache {
+    pub fn new(ttl: Duration) -> ResourceCache {
+        ResourceCache { ttl, cached: None }
+    }
+
+    /// Forces the next call of `get` to retrieve everything again
+    pub fn invalidate(&mut self) {
+        self.cached = None;
+    }
+
+    /// Whether the cached scenes and groups are still up to date for these lights
+    pub fn is_valid(&self, lights: &[Light]) -> bool {
+        let light_ids = light_ids(lights);
+
+        self.cached.as_ref().is_some_and(|cached| {
+            cached.retrieved_at.elapsed() < self.ttl && cached.light_ids == light_ids
+        })
+    }
+
+    /// Returns the cached scenes and groups with the states of `lights`, `retrieve` is only called if they're outdated
+    pub fn get(
+        &mut self,
+        lights: &[Light],
+        retrieve: impl FnOnce() -> Result<(Vec<Scene>, Vec<Group>), String>,
+    ) -> Result<(Vec<Scene>, Vec<Group>), String> {
+        if !self.is_valid(lights) {
+            let (scenes, groups) = retrieve()?;
+
+            self.cached = Some(Cached {
+                retrieved_at: Instant::now(),
+                light_ids: light_ids(lights),
+                scenes,
+                groups,
+            });
+        }
+
+        let cached = self.cached.as_ref().expect("cache was just filled");
+        Ok((cached.scenes.clone(), with_states(&cached.groups, lights)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend;
+    use huelib2::resource::group::{CreatableKind, Kind};
+    use huelib2::resource::light::State as LightState;
+
+    fn light(id: &str, on: bool) -> Light {
+        backend::new_light(
+            id.to_string(),
+            id.to_string(),
+            "Dimmable light",
+            LightState {
+                on: Some(on),
+                brightness: None,
+                hue: None,
+                saturation: None,
+                color_space_coordinates: None,
+                color_temperature: None,
+                alert: None,
+                effect: None,
+                color_mode: None,
+                reachable: true,
+            },
+        )
+    }
+
+    fn group(lights: &[&str], any_on: bool) -> Group {
+        Group {
+            id: "1".to_string(),
+            name: "Hall".to_string(),
+            lights: lights.iter().map(|id| id.to_string()).collect(),
+            sensors: vec![],
+            kind: Kind::Creatable(CreatableKind::Room),
+            class: None,
+            state: Some(GroupState {
+                any_on,
+                all_on: any_on,
+            }),
+            model_id: None,
+            unique_id: None,
+            recycle: None,
+        }
+    }
+
+    #[test]
+    fn test_group_states() {
+        let mut cache = ResourceCache::new(Duration::from_secs(3600));
+        let retrieve = || Ok((vec![], vec![group(&["1", "2"], false)]));
+
+        let lights = [light("1", false), light("2", false)];
+        let (_, groups) = cache.get(&lights, retrieve).unwrap();
+        assert_eq!(
+            groups[0].state.as_ref().map(|state| state.any_on),
+            Some(false)
+        );
+
+        // Lights were switched on since the groups were cached
+        let lights = [light("1", true), light("2", false)];
+        let (_, groups) = cache
+            .get(&lights, || panic!("groups are still cached"))
+            .unwrap();
+
+        let state = groups[0].state.as_ref().unwrap();
+        assert!(state.any_on);
+        assert!(!state.all_on);
+    }
+}
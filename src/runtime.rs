@@ -0,0 +1,82 @@
+use crate::events::{Event, EventBus};
+use crate::state::EngineState;
+use crate::status::Status;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// State shared between the scheduler loop and the control api
+pub struct Runtime {
+    pub events: EventBus,
+    status: Mutex<Status>,
+    /// Arrivals reported by triggers, each bridge compares it with the count it saw last
+    arrivals: AtomicU64,
+    /// What each engine knew after its last tick, by its key. It's written to the state file on shutdown.
+    engines: Mutex<BTreeMap<String, EngineState>>,
+}
+
+impl Runtime {
+    pub fn new(mode: String) -> Runtime {
+        Runtime {
+            events: EventBus::default(),
+            status: Mutex::new(Status {
+                mode,
+                ..Status::default()
+            }),
+            arrivals: AtomicU64::new(0),
+            engines: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Mode deciding which scenes are scheduled, see `MODE`
+    pub fn mode(&self) -> String {
+        self.status().mode.clone()
+    }
+
+    /// Switches to another set of schedules, it applies from the next decision on
+    pub fn set_mode(&self, mode: String) {
+        self.status().mode = mode.clone();
+        self.events.publish(Event::ModeChanged { mode });
+    }
+
+    /// Reports that someone arrived home, see `ARRIVAL_ROOMS`
+    pub fn arrive(&self) {
+        self.arrivals.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn arrivals(&self) -> u64 {
+        self.arrivals.load(Ordering::SeqCst)
+    }
+
+    /// Hands over what engines knew before a restart, each of them takes its part once it starts
+    pub fn restore_engines(&self, engines: BTreeMap<String, EngineState>) {
+        *self.engines.lock().unwrap_or_else(PoisonError::into_inner) = engines;
+    }
+
+    pub fn save_engine(&self, key: &str, state: EngineState) {
+        self.engines
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(key.to_string(), state);
+    }
+
+    pub fn saved_engine(&self, key: &str) -> Option<EngineState> {
+        self.engines
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(key)
+            .cloned()
+    }
+
+    pub fn saved_engines(&self) -> BTreeMap<String, EngineState> {
+        self.engines
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Locks the status, a panic of another thread holding the lock doesn't make it unusable
+    pub fn status(&self) -> MutexGuard<'_, Status> {
+        self.status.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
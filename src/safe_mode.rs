@@ -0,0 +1,27 @@
+use crate::state;
+use chrono::Utc;
+use std::path::Path;
+use std::time::Duration;
+
+/// Records this start in the state file and returns how often the scheduler was started within the window before
+pub fn record_start(path: &Path, window: Duration) -> usize {
+    let now = Utc::now();
+    let window = chrono::Duration::from_std(window).unwrap_or(chrono::Duration::MAX);
+
+    state::update(path, |state| {
+        state
+            .starts
+            .retain(|started_at| now - *started_at <= window);
+        let restarts = state.starts.len();
+        state.starts.push(now);
+
+        restarts
+    })
+}
+
+/// Forgets the latest start, stopping the scheduler on purpose doesn't count towards safe mode
+pub fn record_stop(path: &Path) {
+    if path.exists() {
+        state::update(path, |state| state.starts.pop());
+    }
+}
@@ -0,0 +1,176 @@
+use crate::debug;
+use crate::hue_api::HueApi;
+use huelib2::resource::light::{State, StateModifier};
+use huelib2::resource::{Adjust, ColorMode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How far a light may be off from its stored state, bulbs don't report back exactly what was sent
+const BRIGHTNESS_TOLERANCE: i32 = 10;
+const HUE_TOLERANCE: i32 = 1000;
+const SATURATION_TOLERANCE: i32 = 15;
+const XY_TOLERANCE: f32 = 0.02;
+const COLOR_TEMPERATURE_TOLERANCE: i32 = 10;
+
+/// State a light is stored with in a scene
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SceneLightState {
+    pub on: Option<bool>,
+    pub bri: Option<u8>,
+    pub hue: Option<u16>,
+    pub sat: Option<u8>,
+    pub xy: Option<(f32, f32)>,
+    pub ct: Option<u16>,
+}
+
+impl SceneLightState {
+    /// Creates a modifier to send this state to a light
+    pub fn modifier(&self) -> StateModifier {
+        StateModifier {
+            on: self.on,
+            brightness: self.bri.map(Adjust::Override),
+            hue: self.hue.map(Adjust::Override),
+            saturation: self.sat.map(Adjust::Override),
+            color_space_coordinates: self.xy.map(Adjust::Override),
+            color_temperature: self.ct.map(Adjust::Override),
+            ..StateModifier::default()
+        }
+    }
+
+    /// What a light currently shows, its color only in the mode the light is in
+    pub fn of(state: &State) -> Self {
+        let mode = state.color_mode;
+
+        SceneLightState {
+            on: state.on,
+            bri: state.brightness,
+            hue: state
+                .hue
+                .filter(|_| mode == Some(ColorMode::HueAndSaturation)),
+            sat: state
+                .saturation
+                .filter(|_| mode == Some(ColorMode::HueAndSaturation)),
+            xy: state
+                .color_space_coordinates
+                .filter(|_| mode == Some(ColorMode::ColorSpaceCoordinates)),
+            ct: state
+                .color_temperature
+                .filter(|_| mode == Some(ColorMode::ColorTemperature)),
+        }
+    }
+
+    /// Checks if a light roughly shows this state, colors are only compared in the mode the light is in
+    pub fn matches(&self, state: &State) -> bool {
+        self.diff(state).is_empty()
+    }
+
+    /// Describes how a light differs from this state beyond the tolerances, e.g. `brightness 254 → 120`
+    pub fn diff(&self, state: &State) -> Vec<String> {
+        fn close<T: Into<i32>>(expected: Option<T>, actual: Option<T>, tolerance: i32) -> bool {
+            match (expected, actual) {
+                (Some(expected), Some(actual)) => {
+                    (expected.into() - actual.into()).abs() <= tolerance
+                }
+                _ => true,
+            }
+        }
+
+        fn describe<T: std::fmt::Display>(
+            name: &str,
+            expected: Option<T>,
+            actual: Option<T>,
+        ) -> String {
+            let value = |value: Option<T>| {
+                value
+                    .map(|value| value.to_string())
+                    .unwrap_or("?".to_string())
+            };
+            format!("{} {} → {}", name, value(expected), value(actual))
+        }
+
+        // Some third-party bulbs don't report whether they're on
+        if let (Some(expected), Some(actual)) = (self.on, state.on) {
+            if expected != actual {
+                let on = |on: bool| if on { "on" } else { "off" };
+                return vec![format!("{} → {}", on(expected), on(actual))];
+            }
+        }
+
+        // Nothing else is visible if the light is off
+        if self.on == Some(false) {
+            return vec![];
+        }
+
+        let mut diff = vec![];
+
+        if !close(self.bri, state.brightness, BRIGHTNESS_TOLERANCE) {
+            diff.push(describe("brightness", self.bri, state.brightness));
+        }
+
+        match state.color_mode {
+            Some(ColorMode::ColorTemperature)
+                if !close(
+                    self.ct,
+                    state.color_temperature,
+                    COLOR_TEMPERATURE_TOLERANCE,
+                ) =>
+            {
+                diff.push(describe(
+                    "color temperature",
+                    self.ct,
+                    state.color_temperature,
+                ));
+            }
+            Some(ColorMode::HueAndSaturation) => {
+                // Hue wraps around, 0 and 65535 are both red
+                let hue_matches = match (self.hue, state.hue) {
+                    (Some(expected), Some(actual)) => {
+                        let distance = (expected as i32 - actual as i32).abs();
+                        distance.min(65536 - distance) <= HUE_TOLERANCE
+                    }
+                    _ => true,
+                };
+
+                if !hue_matches {
+                    diff.push(describe("hue", self.hue, state.hue));
+                }
+
+                if !close(self.sat, state.saturation, SATURATION_TOLERANCE) {
+                    diff.push(describe("saturation", self.sat, state.saturation));
+                }
+            }
+            Some(ColorMode::ColorSpaceCoordinates) => {
+                if let (Some((x, y)), Some((actual_x, actual_y))) =
+                    (self.xy, state.color_space_coordinates)
+                {
+                    if (x - actual_x).abs() > XY_TOLERANCE || (y - actual_y).abs() > XY_TOLERANCE {
+                        diff.push(format!(
+                            "xy {:.3},{:.3} → {:.3},{:.3}",
+                            x, y, actual_x, actual_y
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        diff
+    }
+}
+
+#[derive(Deserialize)]
+struct SceneDetails {
+    #[serde(default)]
+    lightstates: HashMap<String, SceneLightState>,
+}
+
+/// Returns the stored light states of a scene, huelib2 doesn't expose them
+pub fn get_scene_light_states(
+    hue: &HueApi,
+    scene_id: &str,
+) -> Result<HashMap<String, SceneLightState>, huelib2::Error> {
+    debug!("Bridge: GET /scenes/{}", scene_id);
+
+    let details = hue.get::<SceneDetails>(&format!("scenes/{}", scene_id))?;
+    Ok(details.lightstates)
+}
@@ -0,0 +1,230 @@
+use crate::conditions::{self, Environment};
+use crate::config::Config;
+use crate::time_range_parser::{TimeRange, TimeRangeParser};
+use crate::utils;
+use chrono::Days;
+use huelib2::resource::{Group, Light, Scene};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Seconds of a day, time-ranges are given in seconds since midnight
+const SECONDS_OF_DAY: u32 = 24 * 60 * 60;
+
+/// Merges overlapping and adjacent ranges which don't wrap around midnight
+fn merge(mut ranges: Vec<TimeRange>) -> Vec<TimeRange> {
+    ranges.sort();
+
+    let mut merged = Vec::<TimeRange>::new();
+
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.0 <= last.1 => last.1 = last.1.max(range.1),
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
+
+/// Returns the parts two lists of ranges have in common, both must not wrap around midnight
+fn intersect(a: &[TimeRange], b: &[TimeRange]) -> Vec<TimeRange> {
+    let ranges = a
+        .iter()
+        .flat_map(|a| b.iter().map(move |b| (a.0.max(b.0), a.1.min(b.1))))
+        .filter(|(start, end)| start < end)
+        .collect();
+
+    merge(ranges)
+}
+
+/// Returns the parts of the day not covered by any of the ranges
+fn complement(ranges: &[TimeRange]) -> Vec<TimeRange> {
+    let mut gaps = Vec::<TimeRange>::new();
+    let mut start = 0;
+
+    for range in merge(ranges.to_vec()) {
+        if range.0 > start {
+            gaps.push((start, range.0));
+        }
+
+        start = start.max(range.1);
+    }
+
+    if start < SECONDS_OF_DAY {
+        gaps.push((start, SECONDS_OF_DAY));
+    }
+
+    gaps
+}
+
+fn format_ranges(ranges: &[TimeRange]) -> String {
+    ranges
+        .iter()
+        .map(utils::format_range)
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Name of the group a scene belongs to, followed by its lights if it only covers some of them
+fn label(scene: &Scene, groups: &[Group], lights: &[Light]) -> String {
+    let scene_lights = scene.lights.clone().unwrap_or_default();
+    let group = scene
+        .group
+        .as_ref()
+        .and_then(|id| groups.iter().find(|group| &group.id == id));
+
+    if let Some(group) = group.filter(|group| {
+        group.lights.len() == scene_lights.len()
+            && group.lights.iter().all(|id| scene_lights.contains(id))
+    }) {
+        return group.name.clone();
+    }
+
+    let names = scene_lights
+        .iter()
+        .map(|id| {
+            lights
+                .iter()
+                .find(|light| &light.id == id)
+                .map(|light| light.name.clone())
+                .unwrap_or_else(|| id.clone())
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    match group {
+        Some(group) => format!("{} ({})", group.name, names),
+        None => names,
+    }
+}
+
+/// Checks the schedules of all scenes and returns a line per finding: scenes not scheduled within the next week,
+/// overlapping scenes for the same lights, scenes which never win against others and times nothing is scheduled.
+/// Everything but the first is checked for today, scenes with conditions are assumed to be inactive.
+pub fn analyze(
+    conf: &Config,
+    parser: &TimeRangeParser,
+    scenes: &[Scene],
+    groups: &[Group],
+    lights: &[Light],
+    extra_ranges: &HashMap<String, Vec<TimeRange>>,
+) -> Vec<String> {
+    let now = utils::now_at_home(conf);
+    let today = now.date_naive();
+    let mut findings = vec![];
+
+    // Scenes are grouped by their lights, the same way they're picked
+    let mut by_lights = BTreeMap::<Vec<String>, Vec<(&Scene, Vec<TimeRange>)>>::new();
+
+    for scene in scenes {
+        if utils::is_manual_scene(conf, scene) || conditions::is_default_scene(&scene.name) {
+            continue;
+        }
+
+        let scheduled_this_week = (0..7)
+            .filter_map(|day| today.checked_add_days(Days::new(day)))
+            .any(|date| !utils::scene_ranges(conf, parser, scene, extra_ranges, date).is_empty());
+
+        if scene.name.contains("(") && !scheduled_this_week {
+            findings.push(format!(
+                "\"{}\" isn't scheduled within the next week, check its name",
+                scene.name
+            ));
+        }
+
+        let ranges = utils::scene_ranges(conf, parser, scene, extra_ranges, today)
+            .iter()
+            .flat_map(|range| parser.linearize(range))
+            .collect::<Vec<TimeRange>>();
+
+        let Some(scene_lights) = &scene.lights else {
+            continue;
+        };
+
+        if ranges.is_empty() {
+            continue;
+        }
+
+        let mut scene_lights = scene_lights.clone();
+        scene_lights.sort();
+
+        by_lights
+            .entry(scene_lights)
+            .or_default()
+            .push((scene, merge(ranges)));
+    }
+
+    for scheduled in by_lights.values() {
+        let label = label(scheduled[0].0, groups, lights);
+
+        for (index, (a, a_ranges)) in scheduled.iter().enumerate() {
+            for (b, b_ranges) in scheduled.iter().skip(index + 1) {
+                let overlap = intersect(a_ranges, b_ranges);
+
+                if !overlap.is_empty() {
+                    findings.push(format!(
+                        "{}: \"{}\" and \"{}\" overlap {}",
+                        label,
+                        a.name,
+                        b.name,
+                        format_ranges(&overlap)
+                    ));
+                }
+            }
+        }
+
+        // The scene that wins only changes at boundaries, so checking those is enough
+        let candidates = scheduled
+            .iter()
+            .map(|(scene, _)| (*scene).clone())
+            .collect::<Vec<Scene>>();
+        let boundaries = scheduled
+            .iter()
+            .flat_map(|(_, ranges)| ranges.iter().flat_map(|range| [range.0, range.1]))
+            .filter(|boundary| *boundary < SECONDS_OF_DAY)
+            .collect::<HashSet<u32>>();
+
+        let winners = boundaries
+            .iter()
+            .filter_map(|boundary| utils::at_seconds_of_day(conf.home_timezone, today, *boundary))
+            .flat_map(|at| {
+                utils::get_scheduled_scenes_at(
+                    conf,
+                    parser,
+                    &candidates,
+                    extra_ranges,
+                    &Environment::default(),
+                    &HashMap::new(),
+                    &at,
+                )
+            })
+            .map(|scheduled| scheduled.scene_id)
+            .collect::<HashSet<String>>();
+
+        for (scene, _) in scheduled.iter() {
+            if conditions::parse_conditions(&scene.name).is_empty() && !winners.contains(&scene.id)
+            {
+                findings.push(format!(
+                    "{}: \"{}\" is never active, other scenes always win",
+                    label, scene.name
+                ));
+            }
+        }
+
+        let gaps = complement(
+            &scheduled
+                .iter()
+                .flat_map(|(_, ranges)| ranges.clone())
+                .collect::<Vec<TimeRange>>(),
+        );
+
+        if !gaps.is_empty() {
+            findings.push(format!(
+                "{}: nothing scheduled {}",
+                label,
+                format_ranges(&gaps)
+            ));
+        }
+    }
+
+    findings
+}
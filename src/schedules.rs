@@ -0,0 +1,190 @@
+use crate::debug;
+use crate::utils;
+use huelib2::resource::{Group, Scene};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::LazyLock;
+
+static REGEX_PRIORITY: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s*\[p\d+\]").unwrap());
+
+/// Schedule of a scene, each value is written the same way as in scene names
+#[derive(Clone, PartialEq, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Schedule {
+    /// E.g. `sunset-23h`
+    pub ranges: Vec<String>,
+    /// Days all ranges are restricted to, e.g. `Mon-Fri` or `!holiday`
+    pub days: Vec<String>,
+    /// E.g. `<18°C`, `mode=vacation` or `max=1`
+    pub conditions: Vec<String>,
+    pub priority: Option<u32>,
+}
+
+/// Content of the `SCHEDULES_FILE`, schedules are looked up by the id, name or name without schedule of a scene.
+/// Names may be prefixed with the room or zone of the scene, e.g. `Kitchen/Evening`.
+#[derive(Clone, PartialEq, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ScheduleFile {
+    /// Ignore schedules in scene names, only scenes listed here are scheduled
+    pub replace_names: bool,
+    pub schedules: HashMap<String, Schedule>,
+}
+
+impl Schedule {
+    /// Values as they'd be written in parentheses, days are repeated for every range
+    fn values(&self) -> Vec<String> {
+        self.ranges
+            .iter()
+            .flat_map(|range| self.days.iter().chain([range]))
+            .chain(self.conditions.iter())
+            .map(|value| value.trim().to_string())
+            .collect()
+    }
+}
+
+/// Removes the schedule and priority from a scene name, e.g. `Movie [p1] (20h-22h)` turns into `Movie`
+fn strip_schedule(name: &str) -> String {
+    let name = name.split("(").next().unwrap_or_default();
+    REGEX_PRIORITY.replace_all(name, "").trim().to_string()
+}
+
+/// Merges a schedule into a scene name, e.g. `Evening (Sat,Sun)` with `sunset-23h` turns into `Evening (Sat,Sun, sunset-23h)`
+fn merge_schedule(name: &str, schedule: &Schedule) -> String {
+    let mut name = name.to_string();
+
+    if let Some(priority) = schedule.priority {
+        let base = name.split("(").next().unwrap_or_default();
+        let rest = &name[base.len()..];
+        name = format!(
+            "{} [p{}] {}",
+            REGEX_PRIORITY.replace_all(base, "").trim(),
+            priority,
+            rest
+        )
+        .trim()
+        .to_string();
+    }
+
+    let values = schedule.values().join(", ");
+
+    if values.is_empty() {
+        return name;
+    }
+
+    match name.find(")") {
+        Some(end) if name[..end].contains("(") => {
+            let separator = if name[..end].trim().ends_with("(") {
+                ""
+            } else {
+                ", "
+            };
+            format!("{}{}{}{}", &name[..end], separator, values, &name[end..])
+        }
+        _ => format!("{} ({})", name, values),
+    }
+}
+
+/// Replaces references to named schedules within the parentheses of a scene name by their values,
+/// e.g. `Living room (evening, <18°C)` with `evening=18h-23h, weekend 17h-1h` turns into `Living room (18h-23h, weekend 17h-1h, <18°C)`
+pub fn expand_named(scenes: &mut [Scene], named: &HashMap<String, String>) {
+    for scene in scenes.iter_mut() {
+        let Some(start) = scene.name.find("(") else {
+            continue;
+        };
+        let Some(end) = scene.name[start..].find(")").map(|end| start + end) else {
+            continue;
+        };
+
+        // Other values are kept as they are, cron expressions contain commas as well
+        let values = scene.name[start + 1..end]
+            .split(",")
+            .map(|value| match named.get(value.trim()) {
+                Some(schedule) => {
+                    let indent = &value[..value.len() - value.trim_start().len()];
+                    format!("{}{}", indent, schedule)
+                }
+                None => value.to_string(),
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+
+        scene.name = format!("{}{}{}", &scene.name[..=start], values, &scene.name[end..]);
+    }
+}
+
+/// Schedules from a file, it's read again whenever scenes are retrieved to pick up changes
+pub struct Schedules {
+    path: PathBuf,
+    file: ScheduleFile,
+}
+
+impl Schedules {
+    pub fn new(path: PathBuf) -> Schedules {
+        Schedules {
+            path,
+            file: ScheduleFile::default(),
+        }
+    }
+
+    /// Reads the file again, the previous schedules are kept if it's broken
+    pub fn update(&mut self) {
+        let file = fs::read_to_string(&self.path)
+            .map_err(|err| err.to_string())
+            .and_then(|content| {
+                serde_json::from_str::<ScheduleFile>(&content).map_err(|err| err.to_string())
+            });
+
+        match file {
+            Ok(file) => {
+                debug!(
+                    "Schedules: {} schedules in {}",
+                    file.schedules.len(),
+                    self.path.display()
+                );
+                self.file = file;
+            }
+            Err(err) => eprintln!(
+                "Failed to read schedules from {}: {}",
+                self.path.display(),
+                err
+            ),
+        }
+    }
+
+    /// Rewrites the names of scenes so they carry their schedule from the file, everything else parses names as usual
+    pub fn apply(&self, scenes: &mut [Scene], groups: &[Group]) {
+        for scene in scenes.iter_mut() {
+            let group = groups
+                .iter()
+                .filter(|group| utils::is_room_or_zone(group))
+                .find(|group| scene.group.as_ref() == Some(&group.id));
+
+            // Scenes with the same name in several rooms are told apart by the name of their room or zone
+            let scoped = |name: String| {
+                group.and_then(|group| self.file.schedules.get(&format!("{}/{}", group.name, name)))
+            };
+
+            let schedule = self
+                .file
+                .schedules
+                .get(&scene.id)
+                .or_else(|| scoped(scene.name.clone()))
+                .or_else(|| scoped(strip_schedule(&scene.name)))
+                .or_else(|| self.file.schedules.get(&scene.name))
+                .or_else(|| self.file.schedules.get(&strip_schedule(&scene.name)));
+
+            let name = match self.file.replace_names {
+                true => strip_schedule(&scene.name),
+                false => scene.name.clone(),
+            };
+
+            scene.name = match schedule {
+                Some(schedule) => merge_schedule(&name, schedule),
+                None => name,
+            };
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use crate::info;
+use crate::runtime::Runtime;
+use crate::safe_mode;
+use crate::state;
+use crate::systemd;
+use crate::utils;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long commands sent to the bridge may take to finish once the scheduler is asked to stop
+const GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+static STOPPING: AtomicBool = AtomicBool::new(false);
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/// Held while a command is sent, stopping waits until it's dropped
+pub struct Command;
+
+impl Drop for Command {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Registers a command about to be sent, there's none once the scheduler is stopping
+pub fn command() -> Option<Command> {
+    IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+
+    if STOPPING.load(Ordering::SeqCst) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+        return None;
+    }
+
+    Some(Command)
+}
+
+/// What's left behind once the scheduler of a home stops
+pub struct Cleanup {
+    /// Name of the home, see `PROFILES`
+    pub profile: Option<String>,
+    pub runtime: Arc<Runtime>,
+    pub started_at: Instant,
+    /// This start doesn't count towards safe mode, see `STATE_FILE`
+    pub state_file: Option<PathBuf>,
+    pub control_socket: Option<PathBuf>,
+}
+
+/// Waits for commands in flight, updates the state files and prints a summary of each home
+fn stop(cleanups: Vec<Cleanup>) {
+    info!("Stopping, waiting for commands sent to the bridge to finish");
+    systemd::notify("STOPPING=1");
+
+    let waiting_since = Instant::now();
+
+    while IN_FLIGHT.load(Ordering::SeqCst) > 0 && waiting_since.elapsed() < GRACE_PERIOD {
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let in_flight = IN_FLIGHT.load(Ordering::SeqCst);
+
+    if in_flight > 0 {
+        eprintln!(
+            "{} commands didn't finish within {}s, stopping anyway",
+            in_flight,
+            GRACE_PERIOD.as_secs()
+        );
+    }
+
+    for cleanup in cleanups {
+        if let Some(path) = cleanup.state_file {
+            safe_mode::record_stop(&path);
+            state::record_engines(&path, cleanup.runtime.saved_engines());
+        }
+
+        // A file that was there before the control server refused to start is kept
+        if let Some(path) = cleanup.control_socket.filter(|path| utils::is_socket(path)) {
+            let _ = std::fs::remove_file(path);
+        }
+
+        info!(
+            "Stopped{} after {}m:\n{}",
+            cleanup
+                .profile
+                .map(|profile| format!(" {}", profile))
+                .unwrap_or_default(),
+            cleanup.started_at.elapsed().as_secs() / 60,
+            cleanup.runtime.status()
+        );
+    }
+}
+
+/// Stops gracefully on SIGINT and SIGTERM, a second one stops right away
+#[cfg(unix)]
+pub fn listen(cleanups: Vec<Cleanup>) {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+
+    let mut signals = match Signals::new([SIGINT, SIGTERM]) {
+        Ok(signals) => signals,
+        Err(err) => {
+            eprintln!("Failed to listen for SIGINT and SIGTERM: {}", err);
+            return;
+        }
+    };
+
+    let mut cleanups = Some(cleanups);
+
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            if STOPPING.swap(true, Ordering::SeqCst) {
+                eprintln!("Stopping right away");
+                std::process::exit(1);
+            }
+
+            if let Some(cleanups) = cleanups.take() {
+                thread::spawn(move || {
+                    stop(cleanups);
+                    std::process::exit(0);
+                });
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn listen(_cleanups: Vec<Cleanup>) {}
@@ -0,0 +1,535 @@
+use crate::config::Config;
+use crate::engine::{Context, Engine, Snapshot};
+use crate::rate_limit::RateLimiter;
+use crate::runtime::Runtime;
+use crate::scene_states::SceneLightState;
+use crate::schedule_report;
+use crate::schedules;
+use crate::utils::{self, Backend};
+use crate::{info, logging};
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use huelib2::resource::{group, light, Group, Light, Scene};
+use huelib2::Bridge;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// How often the virtual clock moves on between events, engines wake up as often to follow schedules
+const STEP: Duration = Duration::minutes(1);
+
+/// A day to simulate, e.g. written by hand next to fixtures recorded with `hue-scheduler record-fixtures`
+#[derive(Deserialize)]
+struct Script {
+    /// Lights, groups and scenes, relative to the script
+    fixtures: PathBuf,
+    /// Day to simulate, today if it's left out
+    date: Option<NaiveDate>,
+    /// Replaces the sunrise of the location, e.g. `7:30h`
+    sunrise: Option<String>,
+    /// Replaces the sunset of the location, e.g. `sunrise+10h`
+    sunset: Option<String>,
+    #[serde(default)]
+    events: Vec<ScriptEvent>,
+}
+
+/// Lights switched on or off at the light switch at a time of the day, by their name or id
+#[derive(Deserialize)]
+struct ScriptEvent {
+    at: String,
+    #[serde(default)]
+    on: Vec<String>,
+    #[serde(default)]
+    off: Vec<String>,
+    /// Switches to a mode, see `MODE`
+    mode: Option<String>,
+}
+
+/// Bridge of a simulation, it only changes through the script and commands of the scheduler
+pub struct Simulation {
+    conf: Arc<Config>,
+    lights: Mutex<Vec<Light>>,
+    groups: Vec<Group>,
+    scenes: Vec<Scene>,
+    scene_states: HashMap<String, HashMap<String, SceneLightState>>,
+}
+
+/// Fixtures are keyed by id the way the v1 api returns them, ids aren't part of the resources themselves.
+/// Private fields left out by `record-fixtures` are filled in, the scheduler doesn't look at them.
+fn parse_resources<T: for<'de> Deserialize<'de>>(
+    fixtures: &Map<String, Value>,
+    kind: &str,
+    with_id: impl Fn(&mut T, String),
+) -> Result<Vec<T>, String> {
+    let resources = fixtures
+        .get(kind)
+        .and_then(Value::as_object)
+        .ok_or(format!("{} missing", kind))?;
+
+    resources
+        .iter()
+        .map(|(id, resource)| {
+            let mut resource = resource.clone();
+
+            if let Some(fields) = resource.as_object_mut() {
+                for (field, value) in [
+                    ("uniqueid", Value::String(id.clone())),
+                    ("owner", Value::String(String::new())),
+                    ("appdata", Value::Object(Map::new())),
+                ] {
+                    fields.entry(field).or_insert(value);
+                }
+            }
+
+            let mut resource = serde_json::from_value::<T>(resource)
+                .map_err(|err| format!("failed to parse {} {}: {}", kind, id, err))?;
+            with_id(&mut resource, id.clone());
+            Ok(resource)
+        })
+        .collect()
+}
+
+impl Simulation {
+    fn new(conf: Arc<Config>, fixtures: &Map<String, Value>) -> Result<Simulation, String> {
+        let mut lights =
+            parse_resources(fixtures, "lights", |light: &mut Light, id| light.id = id)?;
+        let groups = parse_resources(fixtures, "groups", |group: &mut Group, id| group.id = id)?;
+        let scenes = parse_resources(fixtures, "scenes", |scene: &mut Scene, id| scene.id = id)?;
+
+        // Recorded scenes usually don't include their states, only those retrieved one by one do
+        #[derive(Deserialize)]
+        struct SceneStates {
+            #[serde(default)]
+            lightstates: HashMap<String, SceneLightState>,
+        }
+
+        let scene_states = parse_resources(fixtures, "scenes", |_: &mut SceneStates, _| {})?
+            .into_iter()
+            .zip(scenes.iter())
+            .map(|(states, scene)| (scene.id.clone(), states.lightstates))
+            .collect();
+
+        lights.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Ok(Simulation {
+            conf,
+            lights: Mutex::new(lights),
+            groups,
+            scenes,
+            scene_states,
+        })
+    }
+
+    fn lights(&self) -> std::sync::MutexGuard<'_, Vec<Light>> {
+        self.lights.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn get_all_lights(&self) -> Vec<Light> {
+        self.lights().clone()
+    }
+
+    pub fn get_all_groups(&self) -> Vec<Group> {
+        self.groups.clone()
+    }
+
+    pub fn get_all_scenes(&self) -> Vec<Scene> {
+        self.scenes.clone()
+    }
+
+    pub fn get_light(&self, id: &str) -> Result<Light, String> {
+        self.lights()
+            .iter()
+            .find(|light| light.id == id)
+            .cloned()
+            .ok_or(format!("light {} doesn't exist", id))
+    }
+
+    pub fn get_scene_light_states(&self, id: &str) -> HashMap<String, SceneLightState> {
+        self.scene_states.get(id).cloned().unwrap_or_default()
+    }
+
+    /// Prints a command of the scheduler at the time it's sent
+    fn print(&self, action: String) {
+        let now = utils::now_at_home(&self.conf);
+        println!("{} → {}", utils::format_time(&now, true), action);
+    }
+
+    fn light_name(&self, id: &str) -> String {
+        self.lights()
+            .iter()
+            .find(|light| light.id == id)
+            .map(|light| format!("\"{}\"", light.name))
+            .unwrap_or(id.to_string())
+    }
+
+    /// Lights that are off at the light switch can't be turned on
+    fn switch_lights(&self, ids: &[String], on: bool) {
+        for light in self
+            .lights()
+            .iter_mut()
+            .filter(|light| ids.contains(&light.id) && light.state.reachable)
+        {
+            light.state.on = Some(on);
+        }
+    }
+
+    pub fn set_light_state(&self, id: &str, modifier: &light::StateModifier) -> Result<(), String> {
+        let body = serde_json::to_value(modifier).map_err(|err| err.to_string())?;
+        let name = self.light_name(id);
+
+        match body.get("on").and_then(Value::as_bool) {
+            Some(false) => self.print(format!("Turn off light {}", name)),
+            _ => self.print(format!("Set light {} to {}", name, body)),
+        }
+
+        if let Some(on) = body.get("on").and_then(Value::as_bool) {
+            self.switch_lights(&[id.to_string()], on);
+        }
+
+        Ok(())
+    }
+
+    pub fn set_group_state(&self, id: &str, modifier: &group::StateModifier) -> Result<(), String> {
+        let body = serde_json::to_value(modifier).map_err(|err| err.to_string())?;
+
+        // Group 0 consists of all lights
+        let (name, lights) = match self.groups.iter().find(|group| group.id == id) {
+            Some(group) => (format!("\"{}\"", group.name), group.lights.clone()),
+            None => (
+                "all lights".to_string(),
+                self.lights().iter().map(|light| light.id.clone()).collect(),
+            ),
+        };
+
+        if let Some(scene_id) = body.get("scene").and_then(Value::as_str) {
+            let Some(scene) = self.scenes.iter().find(|scene| scene.id == scene_id) else {
+                return Err(format!("scene {} doesn't exist", scene_id));
+            };
+
+            self.print(format!("Recall scene \"{}\" in {}", scene.name, name));
+
+            let states = self.get_scene_light_states(scene_id);
+            for light_id in scene.lights.iter().flatten() {
+                let on = states
+                    .get(light_id)
+                    .and_then(|state| state.on)
+                    .unwrap_or(true);
+                self.switch_lights(std::slice::from_ref(light_id), on);
+            }
+
+            return Ok(());
+        }
+
+        match body.get("on").and_then(Value::as_bool) {
+            Some(false) => self.print(format!("Turn off {}", name)),
+            _ => self.print(format!("Set {} to {}", name, body)),
+        }
+
+        if let Some(on) = body.get("on").and_then(Value::as_bool) {
+            self.switch_lights(&lights, on);
+        }
+
+        Ok(())
+    }
+
+    /// Switches lights at the light switch, lights that are off there aren't reachable
+    fn set_reachable(&self, ids: &[String], reachable: bool) {
+        for light in self
+            .lights()
+            .iter_mut()
+            .filter(|light| ids.contains(&light.id))
+        {
+            light.state.reachable = reachable;
+            light.state.on = Some(reachable);
+        }
+    }
+
+    /// Utility function for tests to switch lights at the light switch by their name or id
+    #[cfg(test)]
+    pub(crate) fn switch(&self, names: &[&str], on: bool) {
+        let names = names
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        self.set_reachable(&self.light_ids(&names).unwrap(), on);
+    }
+
+    /// Resolves lights given by their name or id
+    fn light_ids(&self, names: &[String]) -> Result<Vec<String>, String> {
+        let lights = self.lights();
+
+        names
+            .iter()
+            .map(|name| {
+                lights
+                    .iter()
+                    .find(|light| light.name == *name || light.id == *name)
+                    .map(|light| light.id.clone())
+                    .ok_or(format!("light \"{}\" doesn't exist", name))
+            })
+            .collect()
+    }
+}
+
+/// Everything engines need to run against `simulation` instead of a bridge
+fn context(conf: &Arc<Config>, simulation: &Arc<Simulation>) -> Result<Context, String> {
+    let bridge = Bridge::new(conf.bridge_ip, &conf.bridge_username);
+
+    Ok(Context {
+        conf: conf.clone(),
+        bridge: bridge.clone(),
+        bridge_ip: conf.bridge_ip,
+        limiter: Arc::new(RateLimiter::new(conf.bridge_rate_limit)),
+        #[cfg(feature = "clip-v2")]
+        clip: Arc::new(crate::clip_v2::ClipV2::new(
+            &bridge,
+            conf.bridge_id.clone(),
+            conf,
+        )?),
+        #[cfg(feature = "zigbee2mqtt")]
+        zigbee2mqtt: None,
+        #[cfg(feature = "home-assistant")]
+        home_assistant: None,
+        simulation: Some(simulation.clone()),
+        runtime: Arc::new(Runtime::new(conf.mode.clone())),
+        started_at: utils::instant_now(),
+    })
+}
+
+/// Utility function for tests to run engines against fixtures in the shape of `record-fixtures`
+#[cfg(test)]
+pub(crate) fn test_context(mut conf: Config, fixtures: Value) -> (Context, Arc<Simulation>) {
+    conf.backend = Backend::Simulation;
+
+    let conf = Arc::new(conf);
+    let fixtures = fixtures.as_object().cloned().unwrap_or_default();
+    let simulation = Arc::new(Simulation::new(conf.clone(), &fixtures).unwrap());
+
+    (context(&conf, &simulation).unwrap(), simulation)
+}
+
+/// Parses the time of an event, e.g. `18:30` or `18:30:15`
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+        .ok()
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+/// Replays a scripted day against the engines with a virtual clock and prints what they'd send to the bridge.
+/// Nothing is sent to a bridge, everything is read from the fixtures.
+pub fn run(mut conf: Config, path: &Path) {
+    let script = fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|content| serde_json::from_str::<Script>(&content).map_err(|err| err.to_string()))
+        .unwrap_or_else(|err| fail(format!("Failed to read {}: {}", path.display(), err)));
+
+    let fixtures_path = path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join(&script.fixtures);
+    let fixtures = fs::read_to_string(&fixtures_path)
+        .map_err(|err| err.to_string())
+        .and_then(|content| {
+            serde_json::from_str::<Map<String, Value>>(&content).map_err(|err| err.to_string())
+        })
+        .unwrap_or_else(|err| {
+            fail(format!(
+                "Failed to read {}: {}",
+                fixtures_path.display(),
+                err
+            ))
+        });
+
+    // Everything that would reach out to the bridge or wait in real time is left out
+    conf.backend = Backend::Simulation;
+    conf.dry_run = false;
+    conf.scene_verification_delay = None;
+    conf.group_lock_ttl = None;
+    conf.startup_grace_period = std::time::Duration::ZERO;
+
+    // Time variables of the same name replace solar events, later ones may refer to them
+    for (name, value) in [("sunset", &script.sunset), ("sunrise", &script.sunrise)] {
+        if let Some(value) = value {
+            conf.time_variables
+                .insert(0, (name.to_string(), value.clone()));
+        }
+    }
+
+    let date = script
+        .date
+        .unwrap_or_else(|| utils::now_at_home(&conf).date_naive());
+    let start = conf
+        .home_timezone
+        .from_local_datetime(&date.and_time(NaiveTime::MIN))
+        .earliest()
+        .unwrap_or_else(|| fail(format!("{} doesn't start at midnight", date)))
+        .to_utc();
+    let end = start + Duration::days(1);
+
+    let conf = Arc::new(conf);
+    logging::init(conf.log_level);
+    utils::set_simulated_time(start);
+
+    let simulation = Arc::new(
+        Simulation::new(conf.clone(), &fixtures).unwrap_or_else(|err| {
+            fail(format!(
+                "Failed to read {}: {}",
+                fixtures_path.display(),
+                err
+            ))
+        }),
+    );
+
+    // Events are sorted by their time, several at the same time are handled together
+    let mut events = BTreeMap::<DateTime<Utc>, Vec<ScriptEvent>>::new();
+
+    for event in script.events {
+        let at = parse_time(&event.at)
+            .and_then(|time| {
+                conf.home_timezone
+                    .from_local_datetime(&date.and_time(time))
+                    .earliest()
+            })
+            .unwrap_or_else(|| fail(format!("Failed to parse time of event: {}", event.at)));
+
+        for names in [&event.on, &event.off] {
+            simulation
+                .light_ids(names)
+                .unwrap_or_else(|err| fail(format!("Event at {}: {}", event.at, err)));
+        }
+
+        events.entry(at.to_utc()).or_default().push(event);
+    }
+
+    let context = context(&conf, &simulation).unwrap_or_else(|err| fail(err));
+
+    let mut schedules = conf.schedules_file.clone().map(schedules::Schedules::new);
+    let mut engines = Vec::<(Option<String>, Engine)>::new();
+    let mut snapshot: Option<Arc<Snapshot>> = None;
+    let mut now = start;
+
+    info!(
+        "Simulating {} in {}",
+        date.format("%Y-%m-%d"),
+        conf.home_timezone
+    );
+
+    while now < end {
+        utils::set_simulated_time(now);
+        let time = utils::format_time(&now.with_timezone(&conf.home_timezone), true);
+
+        for event in events.remove(&now).unwrap_or_default() {
+            if let Some(mode) = event.mode {
+                println!("{} Mode {}", time, mode);
+                context.runtime.set_mode(mode);
+            }
+
+            for (names, reachable) in [(&event.on, true), (&event.off, false)] {
+                if names.is_empty() {
+                    continue;
+                }
+
+                println!(
+                    "{} Switched {} {}",
+                    time,
+                    if reachable { "on" } else { "off" },
+                    names
+                        .iter()
+                        .map(|name| format!("\"{}\"", name))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                );
+
+                let ids = simulation.light_ids(names).unwrap_or_default();
+                simulation.set_reachable(&ids, reachable);
+            }
+
+            // Snapshots are only taken once reachability changes, same as while polling the bridge
+            snapshot = None;
+        }
+
+        let changed = snapshot.is_none();
+
+        if changed {
+            let lights = simulation.get_all_lights();
+            let groups = simulation.get_all_groups();
+            let mut scenes = simulation.get_all_scenes();
+
+            if let Some(ref mut schedules) = schedules {
+                schedules.update();
+                schedules.apply(&mut scenes, &groups);
+            }
+
+            if !conf.named_schedules.is_empty() {
+                schedules::expand_named(&mut scenes, &conf.named_schedules);
+            }
+
+            let Some(parser) = utils::create_parser(&conf) else {
+                fail("Failed to calculate sunrise/sunset, set them in the script".to_string());
+            };
+
+            if engines.is_empty() {
+                let findings = schedule_report::analyze(
+                    &conf,
+                    &parser,
+                    &scenes,
+                    &groups,
+                    &lights,
+                    &HashMap::new(),
+                );
+
+                if !findings.is_empty() {
+                    info!("Schedule report:\n  {}", findings.join("\n  "));
+                }
+            }
+
+            let new_snapshot = Arc::new(Snapshot::new(
+                lights,
+                scenes,
+                groups,
+                parser,
+                HashMap::new(),
+                HashMap::new(),
+                Default::default(),
+            ));
+
+            // Every room gets its own engine, same as for a bridge
+            for (room, name) in new_snapshot
+                .rooms()
+                .into_iter()
+                .map(|(id, name)| (Some(id), name))
+                .chain([(None, "Other".to_string())])
+            {
+                if !engines.iter().any(|(id, _)| *id == room) {
+                    engines.push((room.clone(), Engine::new(room, name, context.clone())));
+                }
+            }
+
+            snapshot = Some(new_snapshot);
+        }
+
+        if let Some(ref snapshot) = snapshot {
+            for (_, engine) in engines.iter_mut() {
+                engine.step(snapshot, changed);
+            }
+        }
+
+        // Move on to the next step, or the next event if it comes first
+        let next_step = now + STEP;
+        now = events
+            .keys()
+            .next()
+            .filter(|at| **at < next_step)
+            .copied()
+            .unwrap_or(next_step);
+    }
+
+    info!("Simulated day:\n{}", context.runtime.status());
+}
@@ -0,0 +1,165 @@
+use crate::clip_v2::{ClipV2, ResourceIdentifier};
+use crate::time_range_parser::{TimeRange, TimeRangeParser};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+struct TimeOfDay {
+    hour: u32,
+    minute: u32,
+    #[serde(default)]
+    second: u32,
+}
+
+#[derive(Deserialize)]
+struct StartTime {
+    kind: String,
+    time: Option<TimeOfDay>,
+}
+
+#[derive(Deserialize)]
+struct Timeslot {
+    start_time: StartTime,
+    target: ResourceIdentifier,
+}
+
+#[derive(Deserialize)]
+struct WeekTimeslots {
+    timeslots: Vec<Timeslot>,
+    recurrence: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct SmartSceneResource {
+    id: String,
+    metadata: Metadata,
+    week_timeslots: Vec<WeekTimeslots>,
+}
+
+#[derive(Deserialize)]
+struct SceneResource {
+    id: String,
+    id_v1: Option<String>,
+}
+
+/// Time-ranges of a (v1) scene, taken from the timeslots of a smart scene
+#[derive(Clone, PartialEq, Debug)]
+pub struct SmartSchedule {
+    pub smart_scene_id: String,
+    pub smart_scene_name: String,
+    pub scene_id: String,
+    pub ranges: Vec<TimeRange>,
+}
+
+/// Converts the timeslots of a day into ranges, each timeslot lasts until the next one starts
+fn timeslot_ranges(timeslots: &[Timeslot], parser: &TimeRangeParser) -> Vec<(String, TimeRange)> {
+    let mut starts = timeslots
+        .iter()
+        .filter_map(|timeslot| {
+            let start = match (timeslot.start_time.kind.as_str(), &timeslot.start_time.time) {
+                ("sunset", _) => parser.variable("sunset")?,
+                (_, Some(time)) => time.hour * 3600 + time.minute * 60 + time.second,
+                _ => return None,
+            };
+
+            Some((start, timeslot.target.rid.clone()))
+        })
+        .collect::<Vec<(u32, String)>>();
+
+    starts.sort();
+
+    starts
+        .iter()
+        .enumerate()
+        .map(|(index, (start, target))| {
+            let (end, _) = &starts[(index + 1) % starts.len()];
+            (target.clone(), (*start, *end))
+        })
+        .collect()
+}
+
+/// Returns the schedules of all smart scenes for a weekday, e.g. `monday`
+pub fn get_smart_schedules(
+    client: &ClipV2,
+    parser: &TimeRangeParser,
+    weekday: &str,
+) -> Result<Vec<SmartSchedule>, Box<ureq::Error>> {
+    let smart_scenes = client.get_resources::<SmartSceneResource>("smart_scene")?;
+
+    if smart_scenes.is_empty() {
+        return Ok(vec![]);
+    }
+
+    // Timeslots target v2 scenes, the rest of the scheduler works with v1 ids
+    let scene_ids = client
+        .get_resources::<SceneResource>("scene")?
+        .into_iter()
+        .filter_map(|scene| {
+            let id_v1 = scene.id_v1?.strip_prefix("/scenes/")?.to_string();
+            Some((scene.id, id_v1))
+        })
+        .collect::<HashMap<String, String>>();
+
+    let mut schedules = Vec::<SmartSchedule>::new();
+
+    for smart_scene in smart_scenes {
+        let Some(week) = smart_scene
+            .week_timeslots
+            .iter()
+            .find(|week| week.recurrence.iter().any(|day| day == weekday))
+        else {
+            continue;
+        };
+
+        for (target, range) in timeslot_ranges(&week.timeslots, parser) {
+            let Some(scene_id) = scene_ids.get(&target) else {
+                continue;
+            };
+
+            match schedules
+                .iter_mut()
+                .find(|schedule| schedule.scene_id == *scene_id)
+            {
+                Some(schedule) => schedule.ranges.push(range),
+                None => schedules.push(SmartSchedule {
+                    smart_scene_id: smart_scene.id.clone(),
+                    smart_scene_name: smart_scene.metadata.name.clone(),
+                    scene_id: scene_id.clone(),
+                    ranges: vec![range],
+                }),
+            }
+        }
+    }
+
+    Ok(schedules)
+}
+
+/// Splits schedules into the ranges per scene and the smart scene to activate for it
+pub fn into_ranges(
+    schedules: Vec<SmartSchedule>,
+) -> (HashMap<String, Vec<TimeRange>>, HashMap<String, String>) {
+    let mut ranges = HashMap::<String, Vec<TimeRange>>::new();
+    let mut smart_scene_ids = HashMap::<String, String>::new();
+
+    for schedule in schedules {
+        smart_scene_ids.insert(schedule.scene_id.clone(), schedule.smart_scene_id);
+        ranges.insert(schedule.scene_id, schedule.ranges);
+    }
+
+    (ranges, smart_scene_ids)
+}
+
+/// Activates a smart scene, the bridge picks the timeslot and keeps following the schedule
+pub fn activate(client: &ClipV2, smart_scene_id: &str) -> Result<(), Box<ureq::Error>> {
+    client.put_resource(
+        "smart_scene",
+        smart_scene_id,
+        json!({ "recall": { "action": "activate" } }),
+    )
+}
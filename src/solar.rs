@@ -0,0 +1,346 @@
+use crate::config::Config;
+use crate::utils;
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::str::FromStr;
+
+const UNIX_EPOCH_JULIAN: f64 = 2440587.5;
+const JAN_2000_JULIAN: f64 = 2451545.0;
+const LEAP_SECONDS_JULIAN: f64 = 0.0008;
+const SECONDS_PER_DAY: f64 = 24.0 * 60.0 * 60.0;
+const OBLIQUITY_OF_THE_ECLIPTIC: f64 = 23.44;
+const ARGUMENT_OF_PERIHELION: f64 = 102.9372;
+
+/// Altitude of the sun at sunrise and sunset, corrected for atmospheric refraction
+const SUNRISE_ALTITUDE: f64 = -0.83;
+
+/// Solar events by their name in time-ranges and the altitude of the sun in degrees they happen at.
+/// Each one has a morning and an evening variant, `dawn` and `dusk` are short for the civil twilight.
+const SOLAR_EVENTS: [(&str, &str, f64); 5] = [
+    ("sunrise", "sunset", SUNRISE_ALTITUDE),
+    ("dawn", "dusk", -6.0),
+    ("civil_dawn", "civil_dusk", -6.0),
+    ("nautical_dawn", "nautical_dusk", -12.0),
+    ("astronomical_dawn", "astronomical_dusk", -18.0),
+];
+
+/// Returns when the sun passes an altitude in the morning and the evening of a day, see the
+/// [sunrise equation](https://en.wikipedia.org/wiki/Sunrise_equation).
+/// `None` if it doesn't, e.g. astronomical twilight doesn't end during summer in northern Europe.
+fn get_event_times(
+    date: NaiveDate,
+    latitude: f64,
+    longitude: f64,
+    altitude: f64,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let julian_date = date.and_hms_opt(0, 0, 0)?.and_utc().timestamp() as f64 / SECONDS_PER_DAY
+        + UNIX_EPOCH_JULIAN;
+
+    let days_since_2000 = (julian_date - JAN_2000_JULIAN + LEAP_SECONDS_JULIAN).ceil();
+    let mean_solar_time = days_since_2000 - (longitude / 360.0);
+    let solar_mean_anomaly = (357.5291 + 0.98560028 * mean_solar_time).rem_euclid(360.0);
+    let center = 1.9148 * solar_mean_anomaly.to_radians().sin()
+        + 0.0200 * (2.0 * solar_mean_anomaly).to_radians().sin()
+        + 0.0003 * (3.0 * solar_mean_anomaly).to_radians().sin();
+    let ecliptic_longitude =
+        (solar_mean_anomaly + center + 180.0 + ARGUMENT_OF_PERIHELION).rem_euclid(360.0);
+
+    let declination = (ecliptic_longitude.to_radians().sin()
+        * OBLIQUITY_OF_THE_ECLIPTIC.to_radians().sin())
+    .asin();
+    let hour_angle = ((altitude.to_radians().sin()
+        - (latitude.to_radians().sin() * declination.sin()))
+        / (latitude.to_radians().cos() * declination.cos()))
+    .acos()
+    .to_degrees();
+
+    if hour_angle.is_nan() {
+        return None;
+    }
+
+    let solar_transit =
+        JAN_2000_JULIAN + mean_solar_time + 0.0053 * solar_mean_anomaly.to_radians().sin()
+            - 0.0069 * (2.0 * ecliptic_longitude).to_radians().sin();
+
+    let to_date_time = |julian: f64| {
+        Utc.timestamp_opt(
+            ((julian - UNIX_EPOCH_JULIAN) * SECONDS_PER_DAY).round() as i64,
+            0,
+        )
+        .single()
+    };
+
+    Some((
+        to_date_time(solar_transit - hour_angle / 360.0)?,
+        to_date_time(solar_transit + hour_angle / 360.0)?,
+    ))
+}
+
+/// Returns sunrise and sunset of a day as seconds since midnight in the home timezone
+pub fn get_sunrise_sunset(conf: &Config, date: NaiveDate) -> Option<(u32, u32)> {
+    let (sunrise, sunset) = get_event_times(
+        date,
+        conf.home_latitude,
+        conf.home_longitude,
+        SUNRISE_ALTITUDE,
+    )?;
+
+    Some((
+        utils::seconds_of_day(&sunrise.with_timezone(&conf.home_timezone)),
+        utils::seconds_of_day(&sunset.with_timezone(&conf.home_timezone)),
+    ))
+}
+
+/// Returns all solar events of a day that happen at the home location by their name,
+/// as seconds since midnight in the home timezone
+pub fn get_solar_events(conf: &Config, date: NaiveDate) -> Vec<(String, u32)> {
+    solar_events(
+        conf.home_latitude,
+        conf.home_longitude,
+        conf.home_timezone,
+        date,
+    )
+}
+
+/// Events the sun doesn't pass on that day are left out, e.g. all of them during polar day and night
+fn solar_events(
+    latitude: f64,
+    longitude: f64,
+    timezone: Tz,
+    date: NaiveDate,
+) -> Vec<(String, u32)> {
+    SOLAR_EVENTS
+        .iter()
+        .filter_map(|(morning, evening, altitude)| {
+            let (rise, set) = get_event_times(date, latitude, longitude, *altitude)?;
+
+            Some([
+                (
+                    morning.to_string(),
+                    utils::seconds_of_day(&rise.with_timezone(&timezone)),
+                ),
+                (
+                    evening.to_string(),
+                    utils::seconds_of_day(&set.with_timezone(&timezone)),
+                ),
+            ])
+        })
+        .flatten()
+        .collect()
+}
+
+/// Returns when the sun rises above an altitude in the morning or sinks below it in the evening of a day,
+/// as seconds since midnight in the given timezone. `None` if it doesn't pass the altitude on that day.
+pub fn get_altitude_time(
+    latitude: f64,
+    longitude: f64,
+    timezone: Tz,
+    date: NaiveDate,
+    altitude: f64,
+    rising: bool,
+) -> Option<u32> {
+    let (rise, set) = get_event_times(date, latitude, longitude, altitude)?;
+    let time = if rising { rise } else { set };
+
+    Some(utils::seconds_of_day(&time.with_timezone(&timezone)))
+}
+
+/// Mean length of a lunar cycle in days
+const SYNODIC_MONTH: f64 = 29.530588853;
+
+/// A new moon the lunar cycle is counted from, on 2000-01-06 18:14 UTC
+const NEW_MOON_JULIAN: f64 = 2451550.26;
+
+/// Phase of the moon for conditions like `fullmoon`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MoonPhase {
+    New,
+    Waxing,
+    Full,
+    Waning,
+}
+
+impl FromStr for MoonPhase {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "new" => Ok(MoonPhase::New),
+            "waxing" => Ok(MoonPhase::Waxing),
+            "full" => Ok(MoonPhase::Full),
+            "waning" => Ok(MoonPhase::Waning),
+            _ => Err(format!("unknown moon phase: {}", value.trim())),
+        }
+    }
+}
+
+/// Returns the phase of the moon at a point in time, new and full moon last about a day before and after
+pub fn get_moon_phase(at: DateTime<Utc>) -> MoonPhase {
+    let julian = at.timestamp() as f64 / SECONDS_PER_DAY + UNIX_EPOCH_JULIAN;
+    let age = (julian - NEW_MOON_JULIAN).rem_euclid(SYNODIC_MONTH);
+    let half = SYNODIC_MONTH / 2.0;
+
+    if !(1.0..=SYNODIC_MONTH - 1.0).contains(&age) {
+        MoonPhase::New
+    } else if (age - half).abs() <= 1.0 {
+        MoonPhase::Full
+    } else if age < half {
+        MoonPhase::Waxing
+    } else {
+        MoonPhase::Waning
+    }
+}
+
+/// Meteorological season for conditions like `season=summer`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Season {
+    Spring,
+    Summer,
+    Autumn,
+    Winter,
+}
+
+impl FromStr for Season {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "spring" => Ok(Season::Spring),
+            "summer" => Ok(Season::Summer),
+            "autumn" | "fall" => Ok(Season::Autumn),
+            "winter" => Ok(Season::Winter),
+            _ => Err(format!("unknown season: {}", value.trim())),
+        }
+    }
+}
+
+/// Returns the meteorological season of a day, e.g. summer from June to August.
+/// Seasons are swapped on the southern hemisphere.
+pub fn get_season(latitude: f64, date: NaiveDate) -> Season {
+    let seasons = match latitude < 0.0 {
+        true => [
+            Season::Autumn,
+            Season::Winter,
+            Season::Spring,
+            Season::Summer,
+        ],
+        false => [
+            Season::Spring,
+            Season::Summer,
+            Season::Autumn,
+            Season::Winter,
+        ],
+    };
+
+    // December belongs to the winter of the following year
+    seasons[((date.month0() + 10) % 12 / 3) as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time_range_parser::{d, h, m};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_solar_events() {
+        let events = |latitude, longitude, timezone, date| {
+            HashMap::<String, u32>::from_iter(solar_events(latitude, longitude, timezone, date))
+        };
+
+        // Published times are rounded to the minute, the sunrise equation is off by about a minute
+        let close = |events: &HashMap<String, u32>, name: &str, expected: u32| {
+            events
+                .get(name)
+                .is_some_and(|actual| actual.abs_diff(expected) <= m(2))
+        };
+
+        let berlin = |date| events(52.52, 13.405, Tz::Europe__Berlin, date);
+        let summer = berlin(d(2024, 6, 21));
+        let winter = berlin(d(2024, 12, 21));
+
+        assert!(close(&summer, "sunrise", h(4) + m(43)));
+        assert!(close(&summer, "sunset", h(21) + m(33)));
+        assert!(close(&summer, "dawn", h(3) + m(51)));
+        assert!(close(&summer, "civil_dusk", h(22) + m(24)));
+
+        // The sun doesn't sink far enough for astronomical twilight to end
+        assert!(!summer.contains_key("astronomical_dawn"));
+        assert!(!summer.contains_key("astronomical_dusk"));
+
+        assert!(close(&winter, "sunrise", h(8) + m(15)));
+        assert!(close(&winter, "sunset", h(15) + m(54)));
+        assert!(close(&winter, "dusk", h(16) + m(36)));
+        assert!(close(&winter, "nautical_dawn", h(6) + m(49)));
+        assert!(close(&winter, "astronomical_dusk", h(18) + m(1)));
+
+        let sydney = events(-33.87, 151.21, Tz::Australia__Sydney, d(2024, 12, 21));
+
+        assert!(close(&sydney, "sunrise", h(5) + m(41)));
+        assert!(close(&sydney, "sunset", h(20) + m(5)));
+    }
+
+    #[test]
+    fn test_polar_day_and_night() {
+        let tromso = |date| solar_events(69.65, 18.96, Tz::Europe__Oslo, date);
+
+        // Midnight sun, none of the events happen
+        assert_eq!(tromso(d(2024, 6, 21)), vec![]);
+
+        // Polar night, there's still twilight at noon
+        let names = tromso(d(2024, 12, 21))
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<String>>();
+
+        assert!(!names.contains(&"sunrise".to_string()));
+        assert!(!names.contains(&"sunset".to_string()));
+        assert!(names.contains(&"dawn".to_string()));
+        assert!(names.contains(&"astronomical_dusk".to_string()));
+
+        assert_eq!(
+            get_altitude_time(
+                69.65,
+                18.96,
+                Tz::Europe__Oslo,
+                d(2024, 6, 21),
+                SUNRISE_ALTITUDE,
+                true
+            ),
+            None
+        );
+        assert!(get_altitude_time(
+            52.52,
+            13.405,
+            Tz::Europe__Berlin,
+            d(2024, 6, 21),
+            10.0,
+            true
+        )
+        .is_some_and(|time| time > h(5) && time < h(7)));
+    }
+
+    #[test]
+    fn test_moon_phase() {
+        let mp = |v: &str| get_moon_phase(v.parse::<DateTime<Utc>>().unwrap());
+
+        assert_eq!(mp("2024-04-08T18:21:00Z"), MoonPhase::New);
+        assert_eq!(mp("2024-04-15T12:00:00Z"), MoonPhase::Waxing);
+        assert_eq!(mp("2024-04-23T23:49:00Z"), MoonPhase::Full);
+        assert_eq!(mp("2024-04-30T12:00:00Z"), MoonPhase::Waning);
+        assert_eq!("Full ".parse::<MoonPhase>(), Ok(MoonPhase::Full));
+        assert!("half".parse::<MoonPhase>().is_err());
+    }
+
+    #[test]
+    fn test_season() {
+        assert_eq!(get_season(52.5, d(2024, 3, 1)), Season::Spring);
+        assert_eq!(get_season(52.5, d(2024, 8, 31)), Season::Summer);
+        assert_eq!(get_season(52.5, d(2024, 11, 30)), Season::Autumn);
+        assert_eq!(get_season(52.5, d(2024, 12, 1)), Season::Winter);
+        assert_eq!(get_season(52.5, d(2024, 2, 29)), Season::Winter);
+        assert_eq!(get_season(-33.9, d(2024, 12, 1)), Season::Summer);
+        assert_eq!(get_season(-33.9, d(2024, 6, 1)), Season::Winter);
+        assert_eq!("fall".parse::<Season>(), Ok(Season::Autumn));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::scene_states::SceneLightState;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+
+/// Last known state of a light, see [EngineState]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SavedLight {
+    /// When it became reachable or unreachable, if that happened while the scheduler was watching
+    pub changed_at: Option<DateTime<Utc>>,
+    pub reachable: bool,
+    pub state: Option<SceneLightState>,
+}
+
+/// What the engine of a room knew when the scheduler stopped, it picks up from there on the next start
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct EngineState {
+    pub lights: BTreeMap<String, SavedLight>,
+    /// Groups waiting for `MIN_ON_DURATION` before they're turned off
+    pub delayed_group_offs: BTreeSet<String>,
+}
+
+/// State kept across restarts, see `STATE_FILE`
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    /// Recent starts, see `SAFE_MODE_RESTARTS`
+    #[serde(default)]
+    pub starts: Vec<DateTime<Utc>>,
+    /// How often each scene was activated on `day`, so `max=` holds across restarts
+    #[serde(default)]
+    pub activations: BTreeMap<String, u32>,
+    /// Day in the home timezone `activations` refers to
+    #[serde(default)]
+    pub day: Option<NaiveDate>,
+    /// By the key of the engine, see [crate::engine::engine_key]
+    #[serde(default)]
+    pub engines: BTreeMap<String, EngineState>,
+}
+
+/// Engines and the scheduler loop update the file from their own threads
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Reads the state file, a missing or broken one starts over
+pub fn load(path: &Path) -> State {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|content| {
+            serde_json::from_str::<State>(&content)
+                .inspect_err(|err| {
+                    eprintln!("Failed to parse {}: {}, starting over", path.display(), err)
+                })
+                .ok()
+        })
+        .unwrap_or_default()
+}
+
+/// Changes the state file and returns what `change` returns
+pub fn update<T>(path: &Path, change: impl FnOnce(&mut State) -> T) -> T {
+    let _lock = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+
+    let mut state = load(path);
+    let result = change(&mut state);
+    let content = serde_json::to_string_pretty(&state).unwrap_or_default();
+
+    // Stopping right away on a second signal must not leave a truncated file behind
+    let temporary = path.with_extension("tmp");
+
+    if let Err(err) = fs::write(&temporary, content).and_then(|_| fs::rename(&temporary, path)) {
+        eprintln!("Failed to write {}: {}", path.display(), err);
+    }
+
+    result
+}
+
+/// Activations of scenes on `day`, counts of other days are outdated
+pub fn activations_on(path: &Path, day: NaiveDate) -> BTreeMap<String, u32> {
+    let _lock = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+
+    let state = load(path);
+
+    match state.day == Some(day) {
+        true => state.activations,
+        false => BTreeMap::new(),
+    }
+}
+
+/// Stores how often a scene was activated on `day`, counts of earlier days are dropped
+pub fn record_activation(path: &Path, scene_id: &str, day: NaiveDate, activations: u32) {
+    update(path, |state| {
+        if state.day != Some(day) {
+            state.day = Some(day);
+            state.activations.clear();
+        }
+
+        state.activations.insert(scene_id.to_string(), activations);
+    });
+}
+
+/// Stores what the engines knew, it's done once the scheduler stops
+pub fn record_engines(path: &Path, engines: BTreeMap<String, EngineState>) {
+    update(path, |state| state.engines = engines);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_activations() {
+        let path =
+            std::env::temp_dir().join(format!("hue-scheduler-state-{}.json", std::process::id()));
+        let monday = NaiveDate::from_ymd_opt(2024, 3, 4).unwrap();
+        let tuesday = monday.succ_opt().unwrap();
+
+        assert!(activations_on(&path, monday).is_empty());
+
+        record_activation(&path, "1", monday, 1);
+        record_activation(&path, "2", monday, 2);
+        assert_eq!(
+            activations_on(&path, monday),
+            BTreeMap::from([("1".to_string(), 1), ("2".to_string(), 2)])
+        );
+        assert!(activations_on(&path, tuesday).is_empty());
+
+        // Starts are kept next to the activations
+        update(&path, |state| state.starts.push(Utc::now()));
+        record_activation(&path, "2", tuesday, 1);
+        assert_eq!(
+            activations_on(&path, tuesday),
+            BTreeMap::from([("2".to_string(), 1)])
+        );
+        assert_eq!(load(&path).starts.len(), 1);
+
+        let _ = fs::remove_file(&path);
+    }
+}
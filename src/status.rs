@@ -0,0 +1,315 @@
+use crate::api_usage::ApiUsage;
+use crate::network_health::NetworkHealth;
+use crate::utils;
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// How often and when a scheduled scene was activated
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct SceneActivity {
+    pub name: String,
+    pub activations: u32,
+    /// In the home timezone
+    pub last_activated: Option<DateTime<FixedOffset>>,
+    /// Day in the home timezone `activations_today` refers to
+    pub today: Option<NaiveDate>,
+    pub activations_today: u32,
+}
+
+/// Health of the engine scheduling a room
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct RoomHealth {
+    pub name: String,
+    pub healthy: bool,
+    pub ticks: u64,
+    pub errors: u64,
+    pub panics: u64,
+    pub restarts: u64,
+    pub last_error: Option<String>,
+    /// Commands that failed by the name of their group, a group the bridge keeps rejecting stands out here
+    pub group_errors: BTreeMap<String, u64>,
+}
+
+/// Scenes scheduled for a room right now and once its next boundary is reached
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct RoomSchedule {
+    pub name: String,
+    pub current: Vec<String>,
+    /// In the home timezone, like the ranges in scene names
+    pub next_change: Option<DateTime<FixedOffset>>,
+    pub next: Vec<String>,
+}
+
+/// A bridge whose requests keep failing, it's polled less often until it responds again
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct DegradedBridge {
+    pub failures: u32,
+    pub last_error: String,
+}
+
+/// Runtime information exposed by the control api
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct Status {
+    pub mode: String,
+    /// Scenes aren't applied after a crash loop, see `SAFE_MODE_RESTARTS`
+    pub safe_mode: bool,
+    pub scenes: BTreeMap<String, SceneActivity>,
+    pub rooms: BTreeMap<String, RoomHealth>,
+    pub schedules: BTreeMap<String, RoomSchedule>,
+    /// By the address of the bridge
+    pub degraded_bridges: BTreeMap<String, DegradedBridge>,
+    pub network: NetworkHealth,
+    pub api: ApiUsage,
+    pub panics: u64,
+}
+
+impl Status {
+    /// Makes a scheduled scene show up in the status, even if it never fired
+    pub fn register_scene(&mut self, scene_id: &str, name: &str) {
+        self.scenes.entry(scene_id.to_string()).or_default().name = name.to_string();
+    }
+
+    pub fn register_room(&mut self, key: &str, name: &str) {
+        let room = self.rooms.entry(key.to_string()).or_default();
+        room.name = name.to_string();
+        room.healthy = true;
+    }
+
+    /// Updates the health of a room after its engine processed a snapshot
+    pub fn record_room_tick(&mut self, key: &str, errors: &[String], panic: Option<String>) {
+        let room = self.rooms.entry(key.to_string()).or_default();
+        room.ticks += 1;
+        room.errors += errors.len() as u64;
+        room.healthy = errors.is_empty() && panic.is_none();
+
+        if panic.is_some() {
+            room.panics += 1;
+        }
+
+        if let Some(error) = panic.map(|message| format!("panic: {}", message)) {
+            room.last_error = Some(error);
+        } else if let Some(error) = errors.last() {
+            room.last_error = Some(error.clone());
+        }
+    }
+
+    /// Counts a scene or group-off the bridge failed to apply, see `COMMAND_RETRIES`
+    pub fn record_group_error(&mut self, key: &str, group: &str) {
+        let room = self.rooms.entry(key.to_string()).or_default();
+        *room.group_errors.entry(group.to_string()).or_default() += 1;
+    }
+
+    /// Counts an engine thread which died and had to be started again
+    pub fn record_room_restart(&mut self, key: &str) {
+        let room = self.rooms.entry(key.to_string()).or_default();
+        room.restarts += 1;
+        room.healthy = false;
+    }
+
+    pub fn record_room_schedule(&mut self, key: &str, schedule: RoomSchedule) {
+        self.schedules.insert(key.to_string(), schedule);
+    }
+
+    /// Counts a panic of the scheduler loop itself
+    pub fn record_panic(&mut self) {
+        self.panics += 1;
+    }
+
+    /// Records that a scene was applied, `now` is in the home timezone
+    pub fn record_scene_activation(
+        &mut self,
+        scene_id: &str,
+        name: &str,
+        now: DateTime<FixedOffset>,
+    ) {
+        let today = now.date_naive();
+        let activity = self.scenes.entry(scene_id.to_string()).or_default();
+        activity.name = name.to_string();
+        activity.activations += 1;
+        activity.last_activated = Some(now);
+
+        if activity.today != Some(today) {
+            activity.today = Some(today);
+            activity.activations_today = 0;
+        }
+
+        activity.activations_today += 1;
+    }
+
+    /// Takes over the activations of `day` from before a restart, see `STATE_FILE`
+    pub fn restore_activations(&mut self, day: NaiveDate, activations: BTreeMap<String, u32>) {
+        for (scene_id, count) in activations {
+            let activity = self.scenes.entry(scene_id).or_default();
+            activity.today = Some(day);
+            activity.activations_today = count;
+        }
+    }
+
+    /// How often a scene was activated on the given day
+    pub fn activations_on(&self, scene_id: &str, day: NaiveDate) -> u32 {
+        self.scenes
+            .get(scene_id)
+            .filter(|activity| activity.today == Some(day))
+            .map(|activity| activity.activations_today)
+            .unwrap_or(0)
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .scenes
+            .values()
+            .map(|activity| activity.name.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("Scene".len());
+
+        writeln!(f, "Mode: {}", self.mode)?;
+
+        if self.safe_mode {
+            writeln!(
+                f,
+                "Safe mode: on, nothing is written to the bridge until restarted"
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{:<width$}  Activations  Last activated", "Scene")?;
+
+        for activity in self.scenes.values() {
+            let last_activated = activity
+                .last_activated
+                .map(|timestamp| {
+                    format!(
+                        "{} {}",
+                        timestamp.format("%Y-%m-%d"),
+                        utils::format_time(&timestamp, true)
+                    )
+                })
+                .unwrap_or("never".to_string());
+
+            writeln!(
+                f,
+                "{:<width$}  {:>11}  {}",
+                activity.name, activity.activations, last_activated
+            )?;
+        }
+
+        writeln!(f)?;
+
+        let width = self
+            .rooms
+            .values()
+            .map(|room| room.name.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("Room".len());
+
+        writeln!(
+            f,
+            "{:<width$}  Health     Errors  Panics  Restarts  Last error",
+            "Room"
+        )?;
+
+        for room in self.rooms.values() {
+            writeln!(
+                f,
+                "{:<width$}  {:<9}  {:>6}  {:>6}  {:>8}  {}",
+                room.name,
+                if room.healthy { "healthy" } else { "unhealthy" },
+                room.errors,
+                room.panics,
+                room.restarts,
+                room.last_error.as_deref().unwrap_or("-")
+            )?;
+        }
+
+        for room in self
+            .rooms
+            .values()
+            .filter(|room| !room.group_errors.is_empty())
+        {
+            let groups = room
+                .group_errors
+                .iter()
+                .map(|(group, errors)| format!("\"{}\" {}", group, errors))
+                .collect::<Vec<String>>();
+
+            writeln!(
+                f,
+                "{}: failed commands by group {}",
+                room.name,
+                groups.join(", ")
+            )?;
+        }
+
+        if self.panics > 0 {
+            writeln!(f, "Scheduler panics: {}", self.panics)?;
+        }
+
+        for (bridge, degraded) in self.degraded_bridges.iter() {
+            writeln!(
+                f,
+                "Bridge {}: degraded, {} failed attempts, last error: {}",
+                bridge, degraded.failures, degraded.last_error
+            )?;
+        }
+
+        if !self.schedules.is_empty() {
+            writeln!(f)?;
+
+            for schedule in self
+                .schedules
+                .values()
+                .filter(|schedule| !schedule.is_empty())
+            {
+                writeln!(f, "{}", schedule)?;
+            }
+        }
+
+        writeln!(f)?;
+        write!(f, "{}", self.network)?;
+        writeln!(f)?;
+        writeln!(f, "{}", self.api)
+    }
+}
+
+/// Scene names for the log, e.g. `"Evening", "Desk"`
+fn format_scenes(scenes: &[String]) -> String {
+    if scenes.is_empty() {
+        return "nothing".to_string();
+    }
+
+    scenes
+        .iter()
+        .map(|scene| format!("\"{}\"", scene))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+impl RoomSchedule {
+    /// Whether the room has nothing scheduled at all
+    pub fn is_empty(&self) -> bool {
+        self.current.is_empty() && self.next_change.is_none()
+    }
+}
+
+/// E.g. `Kitchen: "Evening" until 23:00, then "Night"`
+impl fmt::Display for RoomSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.next_change {
+            Some(next_change) => write!(
+                f,
+                "{}: {} until {}, then {}",
+                self.name,
+                format_scenes(&self.current),
+                utils::format_time(&next_change, false),
+                format_scenes(&self.next)
+            ),
+            None => write!(f, "{}: {} all day", self.name, format_scenes(&self.current)),
+        }
+    }
+}
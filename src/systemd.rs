@@ -0,0 +1,98 @@
+use std::env;
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Sends a state to systemd, e.g. `READY=1`, if it started the scheduler as `Type=notify` service
+#[cfg(unix)]
+pub fn notify(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    let sent = UnixDatagram::unbound().and_then(|socket| {
+        match path.to_string_lossy().strip_prefix('@') {
+            // Sockets in the abstract namespace start with an @
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                use std::os::unix::net::SocketAddr;
+
+                let address = SocketAddr::from_abstract_name(name.as_bytes())?;
+                socket.send_to_addr(state.as_bytes(), &address)
+            }
+            _ => socket.send_to(state.as_bytes(), &path),
+        }
+    });
+
+    if let Err(err) = sent {
+        eprintln!("Failed to notify systemd: {}", err);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) {}
+
+/// How often systemd expects to hear from the scheduler, if `WatchdogSec` is set for the service
+fn watchdog_interval() -> Option<Duration> {
+    let pid = env::var("WATCHDOG_PID").ok();
+
+    if pid.is_some_and(|pid| pid != std::process::id().to_string()) {
+        return None;
+    }
+
+    env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| usec.parse::<u64>().ok())
+        .filter(|usec| *usec > 0)
+        .map(Duration::from_micros)
+}
+
+/// When each polling loop the watchdog waits for started its current iteration, `None` while it's sleeping
+static ITERATIONS: Mutex<Vec<Option<Instant>>> = Mutex::new(Vec::new());
+
+/// A polling loop the watchdog of systemd waits for, see [watch]
+pub struct Heartbeat(usize);
+
+impl Heartbeat {
+    pub fn register() -> Heartbeat {
+        let mut iterations = ITERATIONS.lock().unwrap_or_else(PoisonError::into_inner);
+        iterations.push(None);
+        Heartbeat(iterations.len() - 1)
+    }
+
+    /// The loop woke up, the watchdog isn't pinged anymore if it doesn't go back to sleep in time
+    pub fn start(&self) {
+        ITERATIONS.lock().unwrap_or_else(PoisonError::into_inner)[self.0] = Some(Instant::now());
+    }
+
+    /// The loop is sleeping until its next poll
+    pub fn finish(&self) {
+        ITERATIONS.lock().unwrap_or_else(PoisonError::into_inner)[self.0] = None;
+    }
+}
+
+/// Pings the watchdog of systemd as long as no polling loop hangs,
+/// it restarts the scheduler once one of them takes longer than `WatchdogSec`.
+pub fn watch() {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+
+    thread::spawn(move || loop {
+        let hanging = ITERATIONS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .flatten()
+            .any(|started| started.elapsed() >= interval / 2);
+
+        if !hanging {
+            notify("WATCHDOG=1");
+        }
+
+        thread::sleep(interval / 2);
+    });
+}
@@ -1,36 +1,168 @@
-use regex::Regex;
-use std::collections::HashMap;
+use crate::cron::Cron;
+use crate::solar;
+use crate::utils;
+use chrono::{Datelike, Days, Month, NaiveDate, Weekday};
+use chrono_tz::Tz;
+use regex::{Captures, Regex};
+use std::collections::{HashMap, HashSet};
 
 pub struct TimeRangeParser {
     regex_ranges: Regex,
-    regex_range: Regex,
     regex_24h: Regex,
     regex_12h: Regex,
+    regex_offset: Regex,
+    regex_jitter: Regex,
+    regex_weekdays: Regex,
+    regex_altitude: Regex,
+    regex_interval: Regex,
+    regex_duration: Regex,
+    regex_date: Regex,
+    regex_cron: Regex,
     variables: HashMap<String, u32>,
+    adjacent_variables: Option<AdjacentVariables>,
+    range_variables: HashMap<String, TimeRange>,
+    holidays: HashSet<NaiveDate>,
+    jitter_seed: String,
+    location: Option<Location>,
+}
+
+/// Where and around which day altitudes of the sun like `sun<-6°` are calculated
+struct Location {
+    latitude: f64,
+    longitude: f64,
+    timezone: Tz,
+    today: NaiveDate,
+}
+
+/// Variables of the previous and the next day, e.g. their sunrise, and the time they're resolved around
+struct AdjacentVariables {
+    now: u32,
+    previous: HashMap<String, u32>,
+    next: HashMap<String, u32>,
 }
 
 /// A time-range is a tuple of two timestamps, the first one is the start, the second one is the end.
-/// The timestamps are represented as minutes since midnight.
+/// The timestamps are represented as seconds since midnight, the end is exclusive.
+///
+/// - If the start is before the end, the range lies within a day, e.g. `(10h-20h)`.
+/// - If the start is after the end, the range lasts overnight, e.g. `(14:30h-13:55h)` ends the next day.
+/// - Midnight to midnight, e.g. `(0:00h-0:00h)` or `(0h-24h)`, covers the whole day.
+/// - Any other range of zero length, e.g. `(14h-14h)`, is rejected.
 pub type TimeRange = (u32, u32);
 
-/// Utility function to convert hours to minutes
+/// A window of the year as month and day of its first and last day, both inclusive.
+/// If the start is after the end, the window lasts over new year, e.g. `(Dec1-Jan6)`.
+pub type DateRange = ((u32, u32), (u32, u32));
+
+/// Days a time-range is restricted to, e.g. `Mon-Fri` or `holiday`.
+/// Public holidays count as Sunday, `holiday` includes them on any day of the week and `!holiday` excludes them.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct DayRestriction {
+    pub weekdays: Vec<Weekday>,
+    pub holiday: Option<bool>,
+}
+
+impl DayRestriction {
+    /// Checks if a day is included
+    /// # Examples
+    /// ```
+    /// let days = DayRestriction { weekdays: vec![Weekday::Sat, Weekday::Sun], holiday: None };
+    ///
+    /// assert!(days.includes(Weekday::Sat, false));
+    /// assert!(days.includes(Weekday::Mon, true));
+    /// assert!(!days.includes(Weekday::Mon, false));
+    /// ```
+    pub fn includes(&self, weekday: Weekday, holiday: bool) -> bool {
+        let weekday = if holiday { Weekday::Sun } else { weekday };
+
+        match self.holiday {
+            Some(included) if holiday => included,
+            Some(true) if self.weekdays.is_empty() => false,
+            _ => self.weekdays.is_empty() || self.weekdays.contains(&weekday),
+        }
+    }
+
+    fn merge(&mut self, other: DayRestriction) {
+        self.weekdays.extend(other.weekdays);
+        self.holiday = other.holiday.or(self.holiday);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.weekdays.is_empty() && self.holiday.is_none()
+    }
+}
+
+/// Seconds of a day, `24h` is the latest valid timestamp and equal to midnight of the next day
+const SECONDS_OF_DAY: u32 = 24 * 60 * 60;
+
+/// Utility function to convert hours to seconds
+/// # Examples
+///
+/// ```
+/// assert_eq!(h(10), 36000);
+/// ```
+pub(crate) fn h(hours: u32) -> u32 {
+    hours * 60 * 60
+}
+
+/// Utility function to convert minutes to seconds
 /// # Examples
 ///
 /// ```
-/// assert_eq!(h(10), 600);
+/// assert_eq!(m(10), 600);
 /// ```
-fn h(hours: u32) -> u32 {
-    hours * 60
+pub(crate) fn m(minutes: u32) -> u32 {
+    minutes * 60
+}
+
+/// Utility function for tests to create a date
+#[cfg(test)]
+pub(crate) fn d(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+impl Default for TimeRangeParser {
+    fn default() -> TimeRangeParser {
+        TimeRangeParser::new()
+    }
 }
 
 impl TimeRangeParser {
     pub fn new() -> TimeRangeParser {
         TimeRangeParser {
             regex_ranges: Regex::new(r"\((?<values>.*?)\)").unwrap(),
-            regex_range: Regex::new(r"^(?<from>.*?)-(?<to>.*?)$").unwrap(),
-            regex_24h: Regex::new(r"^(?<value>\d{1,2}(:\d{2})?)h$").unwrap(),
-            regex_12h: Regex::new(r"^(?<value>\d{1,2}(:\d{2})?)(?<format>AM|PM)$").unwrap(),
+            regex_24h: Regex::new(r"^(?<value>\d{1,2}(:\d{2}){0,2})h$").unwrap(),
+            regex_12h: Regex::new(r"(?i)^(?<value>\d{1,2}(:\d{2}){0,2})\s*(?<format>AM|PM)$")
+                .unwrap(),
+            regex_offset: Regex::new(
+                r"^(?<variable>[a-z_]+)(?<sign>[+-])((?<hours>\d+)h)?((?<minutes>\d+)m)?((?<seconds>\d+)s)?$",
+            )
+            .unwrap(),
+            regex_jitter: Regex::new(
+                r"^(?<value>.+?)(±|\+-)((?<hours>\d+)h)?((?<minutes>\d+)m)?((?<seconds>\d+)s)?$",
+            )
+                .unwrap(),
+            regex_weekdays: Regex::new(r"^(?<days>!?[A-Za-z]+(\s*-\s*[A-Za-z]+)?)\s+(?<range>.+)$")
+                .unwrap(),
+            regex_altitude: Regex::new(r"^sun(?<direction>[<>])(?<altitude>-?\d+([.,]\d+)?)°?$")
+                .unwrap(),
+            regex_interval: Regex::new(
+                r"^every\s+(?<interval>\S+)\s+for\s+(?<duration>\S+)(\s+between\s+(?<range>.+))?$",
+            )
+            .unwrap(),
+            regex_duration: Regex::new(r"^((?<hours>\d+)h)?((?<minutes>\d+)m)?((?<seconds>\d+)s)?$")
+                .unwrap(),
+            regex_date: Regex::new(r"^(?<month>[A-Za-z]+)\s*(?<day>\d{1,2})$").unwrap(),
+            regex_cron: Regex::new(
+                r"cron:\s*(?<expression>([^\s,]+(,[^\s,]+)*\s+){4}[^\s,]+(,[^\s,]+)*)",
+            )
+            .unwrap(),
             variables: HashMap::new(),
+            adjacent_variables: None,
+            range_variables: HashMap::new(),
+            holidays: HashSet::new(),
+            jitter_seed: String::new(),
+            location: None,
         }
     }
 
@@ -50,6 +182,81 @@ impl TimeRangeParser {
         self.variables = variables;
     }
 
+    /// Defines the variables of the previous and the next day, overnight ranges are resolved on the circular
+    /// timeline around `now` with them: once started, e.g. `sunset-sunrise` ends with the next day's sunrise,
+    /// before it ended it started with the previous day's sunset.
+    /// # Examples
+    /// ```
+    /// let mut parser = TimeRangeParser::new();
+    ///
+    /// parser.define_variables(HashMap::from([("sunrise".to_string(), h(6))]));
+    /// parser.define_adjacent_variables(
+    ///   h(22),
+    ///   HashMap::from([("sunrise".to_string(), h(6) - m(2))]),
+    ///   HashMap::from([("sunrise".to_string(), h(6) + m(2))]),
+    /// );
+    ///
+    /// assert_eq!(parser.extract_time_range("20h-sunrise"), Some((h(20), h(6) + m(2))));
+    /// ```
+    pub fn define_adjacent_variables(
+        &mut self,
+        now: u32,
+        previous: HashMap<String, u32>,
+        next: HashMap<String, u32>,
+    ) {
+        self.adjacent_variables = Some(AdjacentVariables {
+            now,
+            previous,
+            next,
+        });
+    }
+
+    /// Defines variables that stand for a whole time-range, e.g. `night`
+    /// # Examples
+    /// ```
+    /// let mut parser = TimeRangeParser::new();
+    ///
+    /// parser.define_range_variables(HashMap::from([
+    ///   ("night".to_string(), (h(20), h(6))),
+    /// ]));
+    ///
+    /// assert_eq!(parser.extract_time_range("night"), Some((h(20), h(6))));
+    /// ```
+    pub fn define_range_variables(&mut self, variables: HashMap<String, TimeRange>) {
+        self.range_variables = variables;
+    }
+
+    /// Defines the public holidays used for `holiday` and `!holiday`, they count as Sunday as well
+    pub fn define_holidays(&mut self, holidays: HashSet<NaiveDate>) {
+        self.holidays = holidays;
+    }
+
+    /// Defines what randomized boundaries like `sunset±20m` depend on besides the scene, e.g. the current day
+    pub fn define_jitter_seed(&mut self, seed: String) {
+        self.jitter_seed = seed;
+    }
+
+    /// Defines the location and the current day altitudes of the sun like `sun<-6°` are calculated for
+    pub fn define_location(
+        &mut self,
+        latitude: f64,
+        longitude: f64,
+        timezone: Tz,
+        today: NaiveDate,
+    ) {
+        self.location = Some(Location {
+            latitude,
+            longitude,
+            timezone,
+            today,
+        });
+    }
+
+    /// Returns the value of a variable
+    pub fn variable(&self, name: &str) -> Option<u32> {
+        self.variables.get(name).copied()
+    }
+
     /// Checks if a value is in a time-range
     /// # Examples
     /// ```
@@ -61,74 +268,251 @@ impl TimeRangeParser {
     /// assert!(!parser.matches_time_range(&(h(12), h(6)), h(8)));
     /// ```
     pub fn matches_time_range(&self, range: &TimeRange, value: u32) -> bool {
-        if range.0 < range.1 {
-            value >= range.0 && value < range.1
+        self.linearize(range)
+            .iter()
+            .any(|(start, end)| value >= *start && value < *end)
+    }
+
+    /// Splits a time-range into segments that don't wrap around midnight
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert_eq!(parser.linearize(&(h(10), h(20))), vec![(h(10), h(20))]);
+    /// assert_eq!(parser.linearize(&(h(20), h(6))), vec![(h(20), h(24)), (h(0), h(6))]);
+    /// assert_eq!(parser.linearize(&(h(0), h(0))), vec![(h(0), h(24))]);
+    /// ```
+    pub fn linearize(&self, range: &TimeRange) -> Vec<TimeRange> {
+        let start = range.0 % SECONDS_OF_DAY;
+        let end = range.1 % SECONDS_OF_DAY;
+
+        if start < end {
+            vec![(start, end)]
+        } else if start == 0 && end == 0 {
+            vec![(0, SECONDS_OF_DAY)]
+        } else if end == 0 {
+            vec![(start, SECONDS_OF_DAY)]
         } else {
-            value >= range.0 || value < range.1
+            vec![(start, SECONDS_OF_DAY), (0, end)]
+        }
+    }
+
+    /// Checks if a time-range has a defined meaning, see [TimeRange]
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert_eq!(parser.validate(&(h(10), h(20))), Ok(()));
+    /// assert_eq!(parser.validate(&(h(0), h(24))), Ok(()));
+    /// assert!(parser.validate(&(h(14), h(14))).is_err());
+    /// ```
+    pub fn validate(&self, range: &TimeRange) -> Result<(), String> {
+        if range.0 > SECONDS_OF_DAY || range.1 > SECONDS_OF_DAY {
+            return Err("timestamps must not be later than 24h".to_string());
+        }
+
+        let (start, end) = (range.0 % SECONDS_OF_DAY, range.1 % SECONDS_OF_DAY);
+
+        if start == end && start != 0 {
+            return Err("range has a length of zero, use 0h-0h for the whole day".to_string());
         }
+
+        Ok(())
     }
 
-    /// Converts a 24h timestamp to minutes
+    /// Converts a 24h timestamp to seconds
     /// # Examples
     /// ```
     /// let parser = TimeRangeParser::new();
     ///
-    /// assert_eq!(parser.extract_minutes("12:23", 24), Some(743));
-    /// assert_eq!(parser.extract_minutes("12", 24), Some(720));
-    /// assert_eq!(parser.extract_minutes("0:00", 24), Some(0));
+    /// assert_eq!(parser.extract_seconds("12:23", 24), Some(h(12) + m(23)));
+    /// assert_eq!(parser.extract_seconds("12:23:15", 24), Some(h(12) + m(23) + 15));
+    /// assert_eq!(parser.extract_seconds("12", 24), Some(h(12)));
+    /// assert_eq!(parser.extract_seconds("0:00", 24), Some(0));
+    /// assert_eq!(parser.extract_seconds("24:30", 24), None);
     /// ```
-    fn extract_minutes(&self, str: &str, max_hours: u32) -> Option<u32> {
+    fn extract_seconds(&self, str: &str, max_hours: u32) -> Option<u32> {
         let parts = str.split(":").collect::<Vec<&str>>();
 
-        if parts.len() > 0 && parts.len() < 3 {
+        if !parts.is_empty() && parts.len() < 4 {
             let hours = parts[0].parse::<u32>().ok()?;
-            let minutes = if parts.len() > 1 {
-                parts[1].parse::<u32>().ok()?
-            } else {
-                0
+            let minutes = match parts.get(1) {
+                Some(minutes) => minutes.parse::<u32>().ok()?,
+                None => 0,
+            };
+            let seconds = match parts.get(2) {
+                Some(seconds) => seconds.parse::<u32>().ok()?,
+                None => 0,
             };
 
-            if minutes > 59 || hours > max_hours {
+            if minutes > 59
+                || seconds > 59
+                || hours > max_hours
+                || (hours == 24 && minutes + seconds > 0)
+            {
                 None
             } else {
-                Some(h(hours) + minutes)
+                Some(h(hours) + m(minutes) + seconds)
             }
         } else {
             None
         }
     }
 
+    /// Converts the hours, minutes and seconds of a parsed duration like `1h30m` to seconds, `None` if all are missing
+    fn extract_duration_parts(&self, parsed: &Captures) -> Option<u32> {
+        let parts = [("hours", 60 * 60), ("minutes", 60), ("seconds", 1)];
+
+        if parts.iter().all(|(name, _)| parsed.name(name).is_none()) {
+            return None;
+        }
+
+        parts.iter().try_fold(0, |total, (name, factor)| {
+            let value = parsed
+                .name(name)
+                .map_or(Ok(0), |value| value.as_str().parse::<u32>())
+                .ok()?;
+
+            Some(total + value * factor)
+        })
+    }
+
+    /// Applies an offset like `+30m`, `-1h` or `+1h30m` in seconds to a variable, wrapping around midnight
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert_eq!(parser.extract_offset(h(20), "+", h(1) + m(30)), Some(h(21) + m(30)));
+    /// assert_eq!(parser.extract_offset(h(6), "-", m(30)), Some(h(5) + m(30)));
+    /// assert_eq!(parser.extract_offset(h(23), "+", h(2)), Some(h(1)));
+    /// ```
+    fn extract_offset(&self, value: u32, sign: &str, offset: u32) -> Option<u32> {
+        if offset >= SECONDS_OF_DAY {
+            return None;
+        }
+
+        Some(if sign == "+" {
+            (value + offset) % SECONDS_OF_DAY
+        } else {
+            (value + SECONDS_OF_DAY - offset) % SECONDS_OF_DAY
+        })
+    }
+
     /// Extracts a time-segment from a string, uses variables if defined
     /// # Examples
     /// ```
     /// let parser = TimeRangeParser::new();
     ///
-    /// assert_eq!(parser.extract_time_segment("12:23h"), Some(743));
-    /// assert_eq!(parser.extract_time_segment("12h"), Some(720));
+    /// assert_eq!(parser.extract_time_segment("12:23h"), Some(h(12) + m(23)));
+    /// assert_eq!(parser.extract_time_segment("12h"), Some(h(12)));
     /// assert_eq!(parser.extract_time_segment("0:00h"), Some(0));
-    /// assert_eq!(parser.extract_time_segment("5AM"), Some(300));
+    /// assert_eq!(parser.extract_time_segment("5AM"), Some(h(5)));
+    /// assert_eq!(parser.extract_time_segment("sunset+30m"), Some(h(20) + m(30)));
     /// ```
-    fn extract_time_segment(&self, str: &str) -> Option<u32> {
+    pub fn extract_time_segment(&self, str: &str) -> Option<u32> {
+        self.extract_time_segment_with(str, &self.variables, 0)
+    }
+
+    /// Extracts a time-segment from a string, resolves variables with the given values.
+    /// Altitudes of the sun are calculated for the day that many days from today, e.g. `-1` for yesterday.
+    pub fn extract_time_segment_with(
+        &self,
+        str: &str,
+        variables: &HashMap<String, u32>,
+        day: i64,
+    ) -> Option<u32> {
         if let Some(parsed) = self.regex_24h.captures(str) {
-            return self.extract_minutes(&parsed["value"], 24);
+            return self.extract_seconds(&parsed["value"], 24);
         } else if let Some(parsed) = self.regex_12h.captures(str) {
-            let minutes = self.extract_minutes(&parsed["value"], 12)?;
-            let format = &parsed["format"];
+            let seconds = self.extract_seconds(&parsed["value"], 12)?;
+            let pm = parsed["format"].eq_ignore_ascii_case("PM");
 
-            return if format == "AM" && minutes >= h(12) {
-                Some(minutes - h(12))
-            } else if format == "PM" && minutes < h(12) {
-                Some(minutes + h(12))
+            return if !pm && seconds >= h(12) {
+                Some(seconds - h(12))
+            } else if pm && seconds < h(12) {
+                Some(seconds + h(12))
             } else {
-                Some(minutes)
+                Some(seconds)
             };
-        } else if let Some(value) = self.variables.get(str) {
+        } else if let Some(value) = variables.get(str) {
             return Some(*value);
+        } else if let Some(parsed) = self.regex_offset.captures(str) {
+            let value = *variables.get(&parsed["variable"])?;
+
+            return self.extract_offset(
+                value,
+                &parsed["sign"],
+                self.extract_duration_parts(&parsed)?,
+            );
+        } else if let Some(parsed) = self.regex_altitude.captures(str) {
+            return self.extract_altitude_time(&parsed["direction"], &parsed["altitude"], day);
         }
 
         None
     }
 
+    /// Returns when the sun rises above (`>`) an altitude in the morning or sinks below (`<`) it in the evening
+    fn extract_altitude_time(&self, direction: &str, altitude: &str, day: i64) -> Option<u32> {
+        let location = self.location.as_ref()?;
+        let altitude = altitude
+            .replace(",", ".")
+            .parse::<f64>()
+            .ok()
+            .filter(|altitude| altitude.abs() <= 90.0)?;
+
+        let date = match day {
+            day if day < 0 => location
+                .today
+                .checked_sub_days(Days::new(day.unsigned_abs()))?,
+            day => location.today.checked_add_days(Days::new(day as u64))?,
+        };
+
+        solar::get_altitude_time(
+            location.latitude,
+            location.longitude,
+            location.timezone,
+            date,
+            altitude,
+            direction == ">",
+        )
+    }
+
+    /// Returns the comma separated values within the parentheses of a string, without a cron expression
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert_eq!(parser.extract_values("Test (Sat, 9h-12h)"), vec!["Sat", "9h-12h"]);
+    /// assert_eq!(parser.extract_values("Test (cron: 0,30 7 * * 1-5, max=1)"), vec!["max=1"]);
+    /// ```
+    fn extract_values(&self, str: &str) -> Vec<String> {
+        let Some(parsed) = self.regex_ranges.captures(str) else {
+            return vec![];
+        };
+
+        self.regex_cron
+            .replace(&parsed["values"], "")
+            .split(",")
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .collect()
+    }
+
+    /// Extracts a cron expression from a string, e.g. `(cron: * 7-9 * * 1-5)`
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert!(parser.extract_cron("Test (cron: 0,30 7 * * 1-5, max=1)").is_some());
+    /// assert!(parser.extract_cron("Test (cron: 0 7 * *)").is_none());
+    /// ```
+    pub fn extract_cron(&self, str: &str) -> Option<Cron> {
+        let values = self.regex_ranges.captures(str)?;
+        let parsed = self.regex_cron.captures(&values["values"])?;
+
+        Cron::parse(&parsed["expression"])
+    }
+
     /// Extracts a time-range from a string
     /// # Examples
     /// ```
@@ -136,121 +520,1248 @@ impl TimeRangeParser {
     ///
     /// assert_eq!(parser.extract_time_range("Test"), None);
     /// assert_eq!(parser.extract_time_range("Test (10h-20h)"), Some((h(10), h(20))));
-    /// assert_eq!(parser.extract_time_range("Test (12:23h-20h)"), Some((h(12) + 23, h(20))));
-    /// assert_eq!(parser.extract_time_range("Test (12:23h-20:59h)"), Some((h(12) + 23, h(20) + 59)));
+    /// assert_eq!(parser.extract_time_range("Test (12:23h-20h)"), Some((h(12) + m(23), h(20))));
+    /// assert_eq!(parser.extract_time_range("Test (12:23h-20:59h)"), Some((h(12) + m(23), h(20) + m(59))));
     /// assert_eq!(parser.extract_time_range("Test (5AM-6PM)"), Some((h(5), h(18))));
     /// assert_eq!(parser.extract_time_range("Test (12AM-12PM)"), Some((h(0), h(12))));
-    /// assert_eq!(parser.extract_time_range("Test (12:59AM-12:59PM)"), Some((h(0) + 59, h(12) + 59)));
+    /// assert_eq!(parser.extract_time_range("Test (12:59AM-12:59PM)"), Some((h(0) + m(59), h(12) + m(59))));
+    /// assert_eq!(parser.extract_time_range("Test (sunrise-1h-9h)"), Some((h(5), h(9))));
     /// ```
     pub fn extract_time_range(&self, str: &str) -> Option<TimeRange> {
-        let parsed = self.regex_range.captures(str)?;
-
-        Some((
-            self.extract_time_segment(&parsed["from"])?,
-            self.extract_time_segment(&parsed["to"])?,
-        ))
+        self.extract_jittered_time_range(str, "")
     }
 
-    /// Extracts multiple time-ranges from a string
+    /// Extracts a time-range whose boundaries may be randomized within a window, e.g. `sunset±20m-23h±15m`.
+    /// The shift depends on the seed and `key`, e.g. the name of the scene, so it stays the same over the day.
     /// # Examples
     /// ```
     /// let parser = TimeRangeParser::new();
     ///
-    /// assert_eq!(parser.extract_time_ranges("Test"), vec![]);
-    /// assert_eq!(parser.extract_time_ranges("Test (10h-20h)"), vec![(h(10), h(20))]);
-    /// assert_eq!(parser.extract_time_ranges("Test (10h-20h, 12h-14h)"), vec![(h(10), h(20)), (h(12), h(14))]);
-    /// assert_eq!(parser.extract_time_ranges("Test (10h-20h, 12h-14h, 16h-18h)"), vec![(h(10), h(20)), (h(12), h(14)), (h(16), h(18))]);
+    /// let (start, end) = parser.extract_jittered_time_range("20h±20m-23h", "Test").unwrap();
+    /// assert!((h(20) - m(20)..=h(20) + m(20)).contains(&start));
+    /// assert_eq!(end, h(23));
     /// ```
-    pub fn extract_time_ranges(&self, str: &str) -> Vec<TimeRange> {
-        let Some(parsed) = self.regex_ranges.captures(str) else {
-            return vec![];
+    fn extract_jittered_time_range(&self, str: &str, key: &str) -> Option<TimeRange> {
+        // Scenes applied whenever their lights return, regardless of the time
+        if str.eq_ignore_ascii_case("always") {
+            return Some((0, SECONDS_OF_DAY));
+        }
+
+        if let Some(range) = self.range_variables.get(str) {
+            return Some(*range);
+        }
+
+        let shift = |seconds: u32, window: u32, segment: &str| {
+            utils::jitter(
+                seconds,
+                window,
+                &format!("{}/{}/{}", self.jitter_seed, key, segment),
+            )
         };
 
-        parsed["values"]
-            .split(",")
-            .into_iter()
-            .filter_map(|value| self.extract_time_range(value.trim()))
-            .collect::<Vec<TimeRange>>()
+        // The end of ranges like `sunset for 2h` follows from their start
+        if let Some((start, duration)) = str.split_once(" for ") {
+            let (start, window) = self.extract_jitter(start.trim())?;
+            let duration = self
+                .extract_duration(duration.trim())
+                .filter(|duration| *duration < SECONDS_OF_DAY)?;
+
+            let mut seconds = self.extract_time_segment(start)?;
+
+            // Overnight ranges that didn't end yet started with the variables of the previous day
+            if let Some(adjacent) = &self.adjacent_variables {
+                let end = seconds + duration;
+
+                if end > SECONDS_OF_DAY && adjacent.now < end - SECONDS_OF_DAY {
+                    seconds = self
+                        .extract_time_segment_with(start, &adjacent.previous, -1)
+                        .unwrap_or(seconds);
+                }
+            }
+
+            let seconds = shift(seconds, window, start);
+            return Some((seconds, (seconds + duration) % SECONDS_OF_DAY));
+        }
+
+        // Offsets contain dashes as well, use the first split where both sides are valid timestamps
+        let ((start, start_window), (end, end_window), range) =
+            str.match_indices("-").find_map(|(index, _)| {
+                let start = self.extract_jitter(str[..index].trim())?;
+                let end = self.extract_jitter(str[index + 1..].trim())?;
+                let range = (
+                    self.extract_time_segment(start.0)?,
+                    self.extract_time_segment(end.0)?,
+                );
+
+                Some((start, end, range))
+            })?;
+
+        self.validate(&range).ok()?;
+
+        let range = self.resolve_overnight(range, start, end);
+
+        let jittered = (
+            shift(range.0, start_window, start),
+            shift(range.1, end_window, end),
+        );
+
+        // Ranges within a day must not turn into overnight ones or collapse
+        if (range.0 < range.1 && jittered.0 >= jittered.1) || self.validate(&jittered).is_err() {
+            Some(range)
+        } else {
+            Some(jittered)
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Splits the window a timestamp is randomized within off it in seconds, e.g. 20 minutes for `sunset±20m`.
+    /// `+-` works as well, timestamps without a window have one of zero.
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert_eq!(parser.extract_jitter("sunset±20m"), Some(("sunset", m(20))));
+    /// assert_eq!(parser.extract_jitter("23h+-1h"), Some(("23h", h(1))));
+    /// assert_eq!(parser.extract_jitter("23h"), Some(("23h", 0)));
+    /// ```
+    fn extract_jitter<'a>(&self, str: &'a str) -> Option<(&'a str, u32)> {
+        let Some(parsed) = self.regex_jitter.captures(str) else {
+            return Some((str, 0));
+        };
 
-    #[test]
-    fn test_extract_time_range() {
-        let parser = TimeRangeParser::new();
-        let etr = |v: &str| parser.extract_time_range(v);
+        let window = self.extract_duration_parts(&parsed)?;
 
-        assert_eq!(etr("10h-20h"), Some((h(10), h(20))));
-        assert_eq!(etr("12:23h-20:59h"), Some((h(12) + 23, h(20) + 59)));
-        assert_eq!(etr("0:01h-0:00h"), Some((1, 0)));
-        assert_eq!(etr("0:00h-0:00h"), Some((0, 0)));
-        assert_eq!(etr("0:1h-0:0h"), None);
-        assert_eq!(etr("10h-20:60h"), None);
+        // Shifting by half a day or more could move a boundary anywhere
+        if window >= h(12) {
+            return None;
+        }
 
-        assert_eq!(etr("6AM-6PM"), Some((h(6), h(18))));
-        assert_eq!(etr("12AM-12PM"), Some((h(0), h(12))));
-        assert_eq!(etr("12:59AM-12:59PM"), Some((h(0) + 59, h(12) + 59)));
-        assert_eq!(etr("2:30PM-1:55PM"), Some((h(14) + 30, h(13) + 55)));
-        assert_eq!(etr("13PM-6PM"), None);
-        assert_eq!(etr("3AM-16:15h"), Some((h(3), h(16) + 15)));
+        Some((parsed.name("value")?.as_str(), window))
     }
 
-    #[test]
-    fn test_matches_time_range() {
-        let parser = TimeRangeParser::new();
-        let mtr = |r: &TimeRange, v: u32| parser.matches_time_range(&r, v);
+    /// Resolves the end of an overnight range that already started with the variables of the next day,
+    /// the start of one that didn't end yet with the ones of the previous day
+    fn resolve_overnight(&self, range: TimeRange, start: &str, end: &str) -> TimeRange {
+        let Some(adjacent) = &self.adjacent_variables else {
+            return range;
+        };
 
-        assert!(mtr(&(h(10), h(20)), h(12)));
-        assert!(mtr(&(h(10), h(20)), h(19)));
-        assert!(!mtr(&(h(10), h(20)), h(20)));
-        assert!(mtr(&(h(12), h(6)), h(20)));
-        assert!(!mtr(&(h(12), h(6)), h(8)));
-        assert!(!mtr(&(h(12), h(6)), h(8)));
-        assert!(mtr(&(h(12), h(6)), h(12)));
-        assert!(mtr(&(h(12), h(6)), h(18)));
-        assert!(!mtr(&(h(12), h(6)), h(6)));
-        assert!(mtr(&(h(12), h(6)), h(4)));
-        assert!(mtr(&(h(20), h(12)), h(21)));
-        assert!(mtr(&(h(20), h(12)), h(10)));
-        assert!(!mtr(&(h(20), h(12)), h(13)));
+        if range.0 <= range.1 {
+            return range;
+        }
+
+        let resolved = if adjacent.now < range.1 {
+            (
+                self.extract_time_segment_with(start, &adjacent.previous, -1)
+                    .unwrap_or(range.0),
+                range.1,
+            )
+        } else {
+            (
+                range.0,
+                self.extract_time_segment_with(end, &adjacent.next, 1)
+                    .unwrap_or(range.1),
+            )
+        };
+
+        // Values of adjacent days are only a few minutes apart, the range has to stay overnight though
+        if resolved.0 > resolved.1 {
+            resolved
+        } else {
+            range
+        }
     }
 
-    #[test]
-    fn test_time_ranges() {
-        let parser = TimeRangeParser::new();
-        let etrs = |v: &str| parser.extract_time_ranges(v);
+    /// Converts a date like `Dec1` or `Jan 6` to its month and day
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert_eq!(parser.extract_date("Dec1"), Some((12, 1)));
+    /// assert_eq!(parser.extract_date("Feb 29"), Some((2, 29)));
+    /// assert_eq!(parser.extract_date("Feb30"), None);
+    /// ```
+    fn extract_date(&self, str: &str) -> Option<(u32, u32)> {
+        let parsed = self.regex_date.captures(str.trim())?;
+        let month = parsed["month"].parse::<Month>().ok()?.number_from_month();
+        let day = parsed["day"].parse::<u32>().ok()?;
 
-        assert_eq!(etrs("Test"), vec![]);
-        assert_eq!(etrs("Test (10h-20h)"), vec![(h(10), h(20))]);
+        // Any leap year accepts every valid day
+        NaiveDate::from_ymd_opt(2000, month, day).map(|_| (month, day))
+    }
 
-        assert_eq!(
-            etrs("Test (10h-20h, 12h-14h)"),
-            vec![(h(10), h(20)), (h(12), h(14))]
-        );
+    /// Extracts a window of the year from a string, e.g. `Dec1-Jan6` or a single day like `Dec24`
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert_eq!(parser.extract_date_range("Dec1-Jan6"), Some(((12, 1), (1, 6))));
+    /// assert_eq!(parser.extract_date_range("Dec24"), Some(((12, 24), (12, 24))));
+    /// assert_eq!(parser.extract_date_range("10h-20h"), None);
+    /// ```
+    pub fn extract_date_range(&self, str: &str) -> Option<DateRange> {
+        match str.split_once("-") {
+            Some((from, to)) => Some((self.extract_date(from)?, self.extract_date(to)?)),
+            None => self.extract_date(str).map(|date| (date, date)),
+        }
+    }
 
-        assert_eq!(
-            etrs("Test (10h-20h, 12h-14h, 16h-18h)"),
-            vec![(h(10), h(20)), (h(12), h(14)), (h(16), h(18))]
-        );
+    /// Extracts all windows of the year from a string, a scene without any is active all year
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert_eq!(parser.extract_date_ranges("Xmas (Dec1-Jan6, 16h-23h)"), vec![((12, 1), (1, 6))]);
+    /// ```
+    pub fn extract_date_ranges(&self, str: &str) -> Vec<DateRange> {
+        self.extract_values(str)
+            .iter()
+            .filter_map(|value| self.extract_date_range(value))
+            .collect::<Vec<DateRange>>()
     }
 
-    #[test]
-    fn test_time_range_with_variables() {
-        let mut parser = TimeRangeParser::new();
+    /// Checks if a date is within a window of the year
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert!(parser.matches_date_range(&((12, 1), (1, 6)), NaiveDate::from_ymd_opt(2024, 1, 6).unwrap()));
+    /// assert!(!parser.matches_date_range(&((12, 1), (1, 6)), NaiveDate::from_ymd_opt(2024, 1, 7).unwrap()));
+    /// ```
+    pub fn matches_date_range(&self, range: &DateRange, date: NaiveDate) -> bool {
+        let value = (date.month(), date.day());
 
-        parser.define_variables(HashMap::from([
-            ("sunrise".to_string(), h(6)),
-            ("sunset".to_string(), h(20)),
-        ]));
+        if range.0 <= range.1 {
+            value >= range.0 && value <= range.1
+        } else {
+            value >= range.0 || value <= range.1
+        }
+    }
 
-        let etr = |v: &str| parser.extract_time_range(v);
+    /// Checks if a date is within any window of the year of a string, or if there are none
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert!(parser.matches_date_ranges("Xmas (Dec1-Jan6, 16h-23h)", NaiveDate::from_ymd_opt(2024, 12, 24).unwrap()));
+    /// assert!(parser.matches_date_ranges("Work (8h-17h)", NaiveDate::from_ymd_opt(2024, 12, 24).unwrap()));
+    /// ```
+    pub fn matches_date_ranges(&self, str: &str, date: NaiveDate) -> bool {
+        let date_ranges = self.extract_date_ranges(str);
 
-        assert_eq!(etr("sunrise-sunset"), Some((h(6), h(20))));
-        assert_eq!(etr("sunrise-20h"), Some((h(6), h(20))));
-        assert_eq!(etr("18:23h-sunset"), Some((h(18) + 23, h(20))));
-        assert_eq!(etr("18:23h-15h"), Some((h(18) + 23, h(15))));
+        date_ranges.is_empty()
+            || date_ranges
+                .iter()
+                .any(|range| self.matches_date_range(range, date))
+    }
+
+    /// Extracts the days of the week a time-range is restricted to, either a single day, a span of days,
+    /// `weekday` for Monday to Friday or `weekend` for Saturday and Sunday
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert_eq!(parser.extract_weekdays("Sat"), Some(vec![Weekday::Sat]));
+    /// assert_eq!(parser.extract_weekdays("Fri-Mon"), Some(vec![Weekday::Fri, Weekday::Sat, Weekday::Sun, Weekday::Mon]));
+    /// assert_eq!(parser.extract_weekdays("weekend"), Some(vec![Weekday::Sat, Weekday::Sun]));
+    /// assert_eq!(parser.extract_weekdays("night"), None);
+    /// ```
+    fn extract_weekdays(&self, str: &str) -> Option<Vec<Weekday>> {
+        match str.trim().to_lowercase().as_str() {
+            "weekday" | "weekdays" => return self.extract_weekdays("Mon-Fri"),
+            "weekend" | "weekends" => return self.extract_weekdays("Sat-Sun"),
+            _ => {}
+        }
+
+        let days = str
+            .split("-")
+            .map(|day| day.trim().parse::<Weekday>().ok())
+            .collect::<Option<Vec<Weekday>>>()?;
+
+        match days[..] {
+            [day] => Some(vec![day]),
+            [from, to] => {
+                let mut days = vec![from];
+
+                while days.last() != Some(&to) {
+                    days.push(days.last()?.succ());
+                }
+
+                Some(days)
+            }
+            _ => None,
+        }
+    }
+
+    /// Extracts the days a time-range is restricted to, days of the week, `holiday` or `!holiday`
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert_eq!(parser.extract_days("Sat").map(|days| days.weekdays), Some(vec![Weekday::Sat]));
+    /// assert_eq!(parser.extract_days("!holiday").map(|days| days.holiday), Some(Some(false)));
+    /// ```
+    fn extract_days(&self, str: &str) -> Option<DayRestriction> {
+        match str.trim() {
+            "holiday" => Some(DayRestriction {
+                weekdays: vec![],
+                holiday: Some(true),
+            }),
+            "!holiday" => Some(DayRestriction {
+                weekdays: vec![],
+                holiday: Some(false),
+            }),
+            days => Some(DayRestriction {
+                weekdays: self.extract_weekdays(days)?,
+                holiday: None,
+            }),
+        }
+    }
+
+    /// Extracts multiple time-ranges from a string together with the days they're restricted to.
+    /// Days listed on their own belong to the next range, e.g. `Sat` in `(Sat,Sun 9h-12h)`.
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert_eq!(parser.extract_weekday_ranges("Test (10h-20h)"), vec![(None, (h(10), h(20)))]);
+    /// assert_eq!(parser.extract_weekday_ranges("Test (holiday 9h-12h)")[0].0.unwrap().holiday, Some(true));
+    /// ```
+    pub fn extract_weekday_ranges(&self, str: &str) -> Vec<(Option<DayRestriction>, TimeRange)> {
+        let mut ranges = vec![];
+        let mut pending_days = DayRestriction::default();
+
+        for value in self.extract_values(str).iter().map(String::as_str) {
+            if let Some(days) = self.extract_days(value) {
+                pending_days.merge(days);
+                continue;
+            }
+
+            let (mut days, range) = match self.regex_weekdays.captures(value) {
+                Some(parsed) => match self.extract_days(&parsed["days"]) {
+                    Some(days) => (
+                        days,
+                        parsed.name("range").map_or(value, |range| range.as_str()),
+                    ),
+                    None => (DayRestriction::default(), value),
+                },
+                None => (DayRestriction::default(), value),
+            };
+
+            days.merge(std::mem::take(&mut pending_days));
+
+            // Windows to leave out follow the range, e.g. `10h-22h !13h-14h`
+            let mut parts = range.split("!").map(str::trim);
+            let range = parts.next().unwrap_or_default();
+
+            let Some(windows) = parts
+                .map(|window| self.extract_jittered_time_range(window, str))
+                .collect::<Option<Vec<TimeRange>>>()
+            else {
+                continue;
+            };
+
+            let included = match self.extract_interval(range, str) {
+                Some(included) => included,
+                None => match self.extract_jittered_time_range(range, str) {
+                    Some(range) if windows.is_empty() => {
+                        ranges.push(((!days.is_empty()).then_some(days), range));
+                        continue;
+                    }
+                    Some(range) => vec![self.unroll(&range)],
+                    None => continue,
+                },
+            };
+
+            for (range, next_day) in self
+                .exclude(included, &windows)
+                .iter()
+                .map(|range| self.roll_up(range))
+            {
+                let mut days = days.clone();
+
+                if next_day {
+                    days.weekdays = days.weekdays.iter().map(Weekday::succ).collect();
+                }
+
+                ranges.push(((!days.is_empty()).then_some(days), range));
+            }
+        }
+
+        ranges
+    }
+
+    /// Unrolls an overnight range into the next day, so the end may be up to 48h
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert_eq!(parser.unroll(&(h(10), h(22))), (h(10), h(22)));
+    /// assert_eq!(parser.unroll(&(h(22), h(6))), (h(22), h(30)));
+    /// ```
+    fn unroll(&self, range: &TimeRange) -> TimeRange {
+        match self.linearize(range).as_slice() {
+            [range] => *range,
+            _ => (range.0, range.1 + SECONDS_OF_DAY),
+        }
+    }
+
+    /// Turns an unrolled range back into a [TimeRange], ranges completely after midnight are marked as they belong to the next day
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert_eq!(parser.roll_up(&(h(22), h(30))), ((h(22), h(6)), false));
+    /// assert_eq!(parser.roll_up(&(h(26), h(30))), ((h(2), h(6)), true));
+    /// ```
+    fn roll_up(&self, range: &TimeRange) -> (TimeRange, bool) {
+        let (start, end) = *range;
+
+        if start >= SECONDS_OF_DAY {
+            ((start - SECONDS_OF_DAY, end - SECONDS_OF_DAY), true)
+        } else if end > SECONDS_OF_DAY {
+            ((start, end - SECONDS_OF_DAY), false)
+        } else {
+            ((start, end), false)
+        }
+    }
+
+    /// Leaves windows out of unrolled ranges, e.g. `10h-22h` without `13h-14h` turns into `10h-13h` and `14h-22h`
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert_eq!(parser.exclude(vec![(h(10), h(22))], &[(h(13), h(14))]), vec![(h(10), h(13)), (h(14), h(22))]);
+    /// assert_eq!(parser.exclude(vec![(h(22), h(30))], &[(h(1), h(2))]), vec![(h(22), h(25)), (h(26), h(30))]);
+    /// ```
+    fn exclude(&self, mut ranges: Vec<TimeRange>, windows: &[TimeRange]) -> Vec<TimeRange> {
+        for (start, end) in windows
+            .iter()
+            .flat_map(|window| self.linearize(window))
+            .flat_map(|(start, end)| [(start, end), (start + SECONDS_OF_DAY, end + SECONDS_OF_DAY)])
+        {
+            ranges = ranges
+                .into_iter()
+                .flat_map(|range| [(range.0, range.1.min(start)), (range.0.max(end), range.1)])
+                .filter(|range| range.0 < range.1)
+                .collect();
+        }
+
+        ranges
+    }
+
+    /// Converts a duration like `2h`, `15m`, `1h30m` or `90s` to seconds
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert_eq!(parser.extract_duration("1h30m"), Some(h(1) + m(30)));
+    /// assert_eq!(parser.extract_duration("0m"), None);
+    /// ```
+    fn extract_duration(&self, str: &str) -> Option<u32> {
+        let parsed = self.regex_duration.captures(str)?;
+
+        self.extract_duration_parts(&parsed)
+            .filter(|duration| *duration > 0)
+    }
+
+    /// Expands a repeating schedule like `every 2h for 15m between 8h-20h` into unrolled ranges,
+    /// without a window it repeats all day starting at midnight
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert_eq!(parser.extract_interval("every 4h for 15m between 8h-20h", ""), Some(vec![(h(8), h(8) + m(15)), (h(12), h(12) + m(15)), (h(16), h(16) + m(15))]));
+    /// assert_eq!(parser.extract_interval("every 1h for 1h", ""), None);
+    /// ```
+    fn extract_interval(&self, str: &str, key: &str) -> Option<Vec<TimeRange>> {
+        let parsed = self.regex_interval.captures(str)?;
+        let interval = self.extract_duration(&parsed["interval"])?;
+        let duration = self.extract_duration(&parsed["duration"])?;
+
+        // Anything longer would overlap with the next one and simply be on all the time
+        if duration >= interval {
+            return None;
+        }
+
+        let (start, end) = match parsed.name("range") {
+            Some(range) => self.unroll(&self.extract_jittered_time_range(range.as_str(), key)?),
+            None => (0, SECONDS_OF_DAY),
+        };
+
+        Some(
+            (start..end)
+                .step_by(interval as usize)
+                .map(|from| (from, (from + duration).min(end)))
+                .collect(),
+        )
+    }
+
+    /// Extracts multiple time-ranges from a string, regardless of the days they're restricted to
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert_eq!(parser.extract_time_ranges("Test"), vec![]);
+    /// assert_eq!(parser.extract_time_ranges("Test (10h-20h)"), vec![(h(10), h(20))]);
+    /// assert_eq!(parser.extract_time_ranges("Test (10h-20h, 12h-14h)"), vec![(h(10), h(20)), (h(12), h(14))]);
+    /// assert_eq!(parser.extract_time_ranges("Test (10h-20h, 12h-14h, 16h-18h)"), vec![(h(10), h(20)), (h(12), h(14)), (h(16), h(18))]);
+    /// ```
+    pub fn extract_time_ranges(&self, str: &str) -> Vec<TimeRange> {
+        self.extract_weekday_ranges(str)
+            .into_iter()
+            .map(|(_, range)| range)
+            .collect::<Vec<TimeRange>>()
+    }
+
+    /// Extracts the time-ranges that apply on a day of the week.
+    /// The part of an overnight range after midnight belongs to the day it started on, so it's cut off at midnight
+    /// or starts at midnight if only one of both days is included.
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert_eq!(parser.extract_time_ranges_on("Test (Mon-Fri 7h-18h)", Weekday::Mon), vec![(h(7), h(18))]);
+    /// assert_eq!(parser.extract_time_ranges_on("Test (Mon-Fri 7h-18h)", Weekday::Sat), vec![]);
+    /// assert_eq!(parser.extract_time_ranges_on("Test (Fri 22h-2h)", Weekday::Sat), vec![(h(0), h(2))]);
+    /// ```
+    #[cfg(test)]
+    pub fn extract_time_ranges_on(&self, str: &str, weekday: Weekday) -> Vec<TimeRange> {
+        self.extract_time_ranges_for(str, (weekday.pred(), false), (weekday, false))
+    }
+
+    /// Extracts the time-ranges that apply on a day given as day of the week and whether it's a holiday,
+    /// the previous day is needed for overnight ranges
+    fn extract_time_ranges_for(
+        &self,
+        str: &str,
+        previous: (Weekday, bool),
+        day: (Weekday, bool),
+    ) -> Vec<TimeRange> {
+        self.extract_weekday_ranges(str)
+            .into_iter()
+            .filter_map(|(days, range)| {
+                let Some(days) = days else {
+                    return Some(range);
+                };
+
+                if self.linearize(&range).len() == 1 {
+                    return days.includes(day.0, day.1).then_some(range);
+                }
+
+                match (
+                    days.includes(previous.0, previous.1),
+                    days.includes(day.0, day.1),
+                ) {
+                    (true, true) => Some(range),
+                    (true, false) => Some((0, range.1)),
+                    (false, true) => Some((range.0, 0)),
+                    (false, false) => None,
+                }
+            })
+            .collect::<Vec<TimeRange>>()
+    }
+
+    /// Extracts the time-ranges that apply on a date, none if it's outside of all windows of the year
+    /// # Examples
+    /// ```
+    /// let parser = TimeRangeParser::new();
+    ///
+    /// assert_eq!(parser.extract_time_ranges_at("Xmas (Dec1-Jan6, 16h-23h)", NaiveDate::from_ymd_opt(2024, 12, 24).unwrap()), vec![(h(16), h(23))]);
+    /// assert_eq!(parser.extract_time_ranges_at("Xmas (Dec1-Jan6, 16h-23h)", NaiveDate::from_ymd_opt(2024, 7, 1).unwrap()), vec![]);
+    /// ```
+    pub fn extract_time_ranges_at(&self, str: &str, date: NaiveDate) -> Vec<TimeRange> {
+        if !self.matches_date_ranges(str, date) {
+            return vec![];
+        }
+
+        let is_holiday = |date: NaiveDate| (date.weekday(), self.holidays.contains(&date));
+        let previous = date.pred_opt().unwrap_or(date);
+
+        let mut ranges = self.extract_time_ranges_for(str, is_holiday(previous), is_holiday(date));
+
+        if let Some(cron) = self.extract_cron(str) {
+            ranges.extend(cron.ranges_on(date));
+        }
+
+        ranges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_time_range() {
+        let parser = TimeRangeParser::new();
+        let etr = |v: &str| parser.extract_time_range(v);
+
+        assert_eq!(etr("10h-20h"), Some((h(10), h(20))));
+        assert_eq!(etr("12:23h-20:59h"), Some((h(12) + m(23), h(20) + m(59))));
+        assert_eq!(etr("0:01h-0:00h"), Some((m(1), 0)));
+        assert_eq!(etr("0:00h-0:00h"), Some((0, 0)));
+        assert_eq!(etr("0:1h-0:0h"), None);
+        assert_eq!(etr("10h-20:60h"), None);
+        assert_eq!(etr("0h-24h"), Some((0, h(24))));
+        assert_eq!(etr("14h-14h"), None);
+        assert_eq!(etr("20h-24:30h"), None);
+
+        assert_eq!(etr("6AM-6PM"), Some((h(6), h(18))));
+        assert_eq!(etr("12AM-12PM"), Some((h(0), h(12))));
+        assert_eq!(etr("12:59AM-12:59PM"), Some((h(0) + m(59), h(12) + m(59))));
+        assert_eq!(etr("2:30PM-1:55PM"), Some((h(14) + m(30), h(13) + m(55))));
+        assert_eq!(etr("13PM-6PM"), None);
+        assert_eq!(etr("3AM-16:15h"), Some((h(3), h(16) + m(15))));
+
+        assert_eq!(etr("5am-5:30 pm"), Some((h(5), h(17) + m(30))));
+        assert_eq!(etr("5 Am - 11PM"), Some((h(5), h(23))));
+        assert_eq!(etr("10h - 20h"), Some((h(10), h(20))));
+        assert_eq!(etr("5 a.m.-6pm"), None);
+        assert_eq!(etr("5 amm-6pm"), None);
+        assert_eq!(
+            parser.extract_time_ranges("Test ( 5 am - 6 pm ,7pm-  8 pm )"),
+            vec![(h(5), h(18)), (h(19), h(20))]
+        );
+    }
+
+    #[test]
+    fn test_time_ranges_with_seconds() {
+        let mut parser = TimeRangeParser::new();
+
+        parser.define_variables(HashMap::from([("sunset".to_string(), h(20))]));
+
+        let etr = |v: &str| parser.extract_time_range(v);
+
+        assert_eq!(etr("22:30:15h-23h"), Some((h(22) + m(30) + 15, h(23))));
+        assert_eq!(etr("10:30:15PM-11PM"), Some((h(22) + m(30) + 15, h(23))));
+        assert_eq!(etr("0:00:01h-0h"), Some((1, 0)));
+        assert_eq!(etr("23:59:59h-24h"), Some((h(24) - 1, h(24))));
+        assert_eq!(etr("22:30:60h-23h"), None);
+        assert_eq!(etr("24:00:01h-23h"), None);
+        assert_eq!(etr("sunset+30s-23h"), Some((h(20) + 30, h(23))));
+        assert_eq!(etr("sunset-1m30s-23h"), Some((h(20) - 90, h(23))));
+        assert_eq!(etr("sunset for 45s"), Some((h(20), h(20) + 45)));
+    }
+
+    #[test]
+    fn test_matches_time_range() {
+        let parser = TimeRangeParser::new();
+        let mtr = |r: &TimeRange, v: u32| parser.matches_time_range(r, v);
+
+        assert!(mtr(&(h(10), h(20)), h(12)));
+        assert!(mtr(&(h(10), h(20)), h(19)));
+        assert!(!mtr(&(h(10), h(20)), h(20)));
+        assert!(mtr(&(h(12), h(6)), h(20)));
+        assert!(!mtr(&(h(12), h(6)), h(8)));
+        assert!(!mtr(&(h(12), h(6)), h(8)));
+        assert!(mtr(&(h(12), h(6)), h(12)));
+        assert!(mtr(&(h(12), h(6)), h(18)));
+        assert!(!mtr(&(h(12), h(6)), h(6)));
+        assert!(mtr(&(h(12), h(6)), h(4)));
+        assert!(mtr(&(h(20), h(12)), h(21)));
+        assert!(mtr(&(h(20), h(12)), h(10)));
+        assert!(!mtr(&(h(20), h(12)), h(13)));
+
+        // Midnight to midnight covers the whole day
+        assert!(mtr(&(0, 0), 0));
+        assert!(mtr(&(0, 0), h(12)));
+        assert!(mtr(&(0, h(24)), h(24) - 1));
+        assert!(mtr(&(h(20), h(24)), h(23)));
+        assert!(!mtr(&(h(20), h(24)), h(1)));
+        assert!(mtr(&(h(24), h(6)), h(1)));
+    }
+
+    #[test]
+    fn test_linearize() {
+        let parser = TimeRangeParser::new();
+        let lin = |r: &TimeRange| parser.linearize(r);
+
+        assert_eq!(lin(&(h(10), h(20))), vec![(h(10), h(20))]);
+        assert_eq!(lin(&(h(20), h(6))), vec![(h(20), h(24)), (0, h(6))]);
+        assert_eq!(lin(&(h(20), 0)), vec![(h(20), h(24))]);
+        assert_eq!(lin(&(h(20), h(24))), vec![(h(20), h(24))]);
+        assert_eq!(lin(&(0, 0)), vec![(0, h(24))]);
+        assert_eq!(lin(&(0, h(24))), vec![(0, h(24))]);
+        assert_eq!(
+            lin(&(h(14) + m(30), h(13) + m(55))),
+            vec![(h(14) + m(30), h(24)), (0, h(13) + m(55))]
+        );
+    }
+
+    #[test]
+    fn test_time_ranges() {
+        let parser = TimeRangeParser::new();
+        let etrs = |v: &str| parser.extract_time_ranges(v);
+
+        assert_eq!(etrs("Test"), vec![]);
+        assert_eq!(etrs("Test (10h-20h)"), vec![(h(10), h(20))]);
+
+        assert_eq!(
+            etrs("Test (10h-20h, 12h-14h)"),
+            vec![(h(10), h(20)), (h(12), h(14))]
+        );
+
+        assert_eq!(
+            etrs("Test (10h-20h, 12h-14h, 16h-18h)"),
+            vec![(h(10), h(20)), (h(12), h(14)), (h(16), h(18))]
+        );
+    }
+
+    #[test]
+    fn test_time_range_with_variables() {
+        let mut parser = TimeRangeParser::new();
+
+        parser.define_variables(HashMap::from([
+            ("sunrise".to_string(), h(6)),
+            ("sunset".to_string(), h(20)),
+        ]));
+
+        let etr = |v: &str| parser.extract_time_range(v);
+
+        assert_eq!(etr("sunrise-sunset"), Some((h(6), h(20))));
+        assert_eq!(etr("sunrise-20h"), Some((h(6), h(20))));
+        assert_eq!(etr("18:23h-sunset"), Some((h(18) + m(23), h(20))));
+        assert_eq!(etr("18:23h-15h"), Some((h(18) + m(23), h(15))));
+    }
+
+    #[test]
+    fn test_time_range_with_custom_variables() {
+        let mut parser = TimeRangeParser::new();
+        let mut variables = HashMap::from([("sunset".to_string(), h(20))]);
+
+        for (name, value) in [
+            ("bedtime", "22:30h"),
+            ("winddown", "bedtime-1h"),
+            ("late", "sunset+2h"),
+        ] {
+            let value = parser
+                .extract_time_segment_with(value, &variables, 0)
+                .unwrap();
+            variables.insert(name.to_string(), value);
+        }
+
+        parser.define_variables(variables);
+
+        let etr = |v: &str| parser.extract_time_range(v);
+
+        assert_eq!(
+            etr("winddown-bedtime"),
+            Some((h(21) + m(30), h(22) + m(30)))
+        );
+        assert_eq!(etr("late-bedtime+30m"), Some((h(22), h(23))));
+        assert_eq!(etr("8h-workstart"), None);
+    }
+
+    #[test]
+    fn test_time_range_with_offsets() {
+        let mut parser = TimeRangeParser::new();
+
+        parser.define_variables(HashMap::from([
+            ("sunrise".to_string(), h(6)),
+            ("sunset".to_string(), h(20)),
+        ]));
+
+        let etr = |v: &str| parser.extract_time_range(v);
+
+        assert_eq!(etr("sunset+30m-23h"), Some((h(20) + m(30), h(23))));
+        assert_eq!(etr("sunrise-1h-9h"), Some((h(5), h(9))));
+        assert_eq!(
+            etr("sunset-1h30m-sunrise+15m"),
+            Some((h(18) + m(30), h(6) + m(15)))
+        );
+        assert_eq!(etr("18h-sunset-2h"), None);
+        assert_eq!(etr("17h-sunset-2h"), Some((h(17), h(18))));
+        assert_eq!(etr("sunset+5h-6h"), Some((h(1), h(6))));
+        assert_eq!(etr("sunset+-23h"), None);
+        assert_eq!(etr("sunset+24h-23h"), None);
+        assert_eq!(etr("moonrise+1h-23h"), None);
+        assert_eq!(etr("10h+1h-23h"), None);
+    }
+
+    #[test]
+    fn test_time_ranges_with_exclusions() {
+        let mut parser = TimeRangeParser::new();
+
+        parser.define_variables(HashMap::from([("sunset".to_string(), h(20))]));
+
+        let etrs = |v: &str| parser.extract_time_ranges(v);
+        let etrs_on = |v: &str, d: Weekday| parser.extract_time_ranges_on(v, d);
+
+        assert_eq!(
+            etrs("Test (10h-22h !13h-14h)"),
+            vec![(h(10), h(13)), (h(14), h(22))]
+        );
+        assert_eq!(
+            etrs("Test (8h-sunset !12h-13h !sunset-1h-sunset)"),
+            vec![(h(8), h(12)), (h(13), h(19))]
+        );
+        assert_eq!(etrs("Test (10h-22h !9h-11h)"), vec![(h(11), h(22))]);
+        assert_eq!(etrs("Test (10h-22h !0h-0h)"), vec![]);
+        assert_eq!(etrs("Test (10h-22h !13h)"), vec![]);
+        assert_eq!(
+            etrs("Test (22h-6h !23:30h-0:30h)"),
+            vec![(h(22), h(23) + m(30)), (m(30), h(6))]
+        );
+        assert_eq!(etrs("Test (0h-0h !3h-4h)"), vec![(0, h(3)), (h(4), h(24))]);
+
+        // Parts after midnight still belong to the day the range started on
+        assert_eq!(
+            etrs_on("Test (Fri 22h-6h !1h-2h)", Weekday::Fri),
+            vec![(h(22), 0)]
+        );
+        assert_eq!(
+            etrs_on("Test (Fri 22h-6h !1h-2h)", Weekday::Sat),
+            vec![(0, h(1)), (h(2), h(6))]
+        );
+    }
+
+    #[test]
+    fn test_time_ranges_with_intervals() {
+        let parser = TimeRangeParser::new();
+
+        let etrs = |v: &str| parser.extract_time_ranges(v);
+        let etrs_on = |v: &str, d: Weekday| parser.extract_time_ranges_on(v, d);
+
+        assert_eq!(
+            etrs("Plants (every 2h for 15m between 8h-14h)"),
+            vec![
+                (h(8), h(8) + m(15)),
+                (h(10), h(10) + m(15)),
+                (h(12), h(12) + m(15))
+            ]
+        );
+        assert_eq!(
+            etrs("Plants (every 5h for 2h between 8h-19h)"),
+            vec![(h(8), h(10)), (h(13), h(15)), (h(18), h(19))]
+        );
+        assert_eq!(etrs("Plants (every 6h for 1h)").len(), 4);
+        assert_eq!(
+            etrs("Plants (every 6h for 1h !6h-7h)"),
+            vec![(0, h(1)), (h(12), h(13)), (h(18), h(19))]
+        );
+        assert_eq!(etrs("Plants (every 1h for 1h)"), vec![]);
+        assert_eq!(etrs("Plants (every 0h for 1m)"), vec![]);
+        assert_eq!(etrs("Plants (every 2h for 15m between 8h)"), vec![]);
+
+        // Overnight windows continue into the next day
+        assert_eq!(
+            etrs("Aquarium (every 4h for 30m between 22h-6h)"),
+            vec![(h(22), h(22) + m(30)), (h(2), h(2) + m(30))]
+        );
+        assert_eq!(
+            etrs_on(
+                "Aquarium (Fri every 4h for 30m between 22h-6h)",
+                Weekday::Sat
+            ),
+            vec![(h(2), h(2) + m(30))]
+        );
+        assert_eq!(
+            etrs_on(
+                "Aquarium (Fri every 4h for 30m between 22h-6h)",
+                Weekday::Fri
+            ),
+            vec![(h(22), h(22) + m(30))]
+        );
+    }
+
+    #[test]
+    fn test_time_ranges_with_weekdays() {
+        let mut parser = TimeRangeParser::new();
+
+        parser.define_range_variables(HashMap::from([("night".to_string(), (h(20), h(6)))]));
+
+        let etrs = |v: &str, d: Weekday| parser.extract_time_ranges_on(v, d);
+
+        assert_eq!(
+            etrs("Office (Mon-Fri 7h-18h)", Weekday::Wed),
+            vec![(h(7), h(18))]
+        );
+        assert_eq!(etrs("Office (Mon-Fri 7h-18h)", Weekday::Sun), vec![]);
+        assert_eq!(
+            etrs("Brunch (Sat,Sun 9h-12h)", Weekday::Sat),
+            vec![(h(9), h(12))]
+        );
+        assert_eq!(
+            etrs("Brunch (Sat,Sun 9h-12h)", Weekday::Sun),
+            vec![(h(9), h(12))]
+        );
+        assert_eq!(etrs("Brunch (Sat,Sun 9h-12h)", Weekday::Fri), vec![]);
+        assert_eq!(
+            etrs("Weekend (Fri-Mon 10h-11h)", Weekday::Mon),
+            vec![(h(10), h(11))]
+        );
+        assert_eq!(etrs("Weekend (Fri-Mon 10h-11h)", Weekday::Tue), vec![]);
+        assert_eq!(
+            etrs("Mixed (Mon 7h-8h, 20h-21h)", Weekday::Tue),
+            vec![(h(20), h(21))]
+        );
+
+        // Overnight ranges continue into the next day
+        assert_eq!(etrs("Party (Fri 22h-2h)", Weekday::Fri), vec![(h(22), 0)]);
+        assert_eq!(etrs("Party (Fri 22h-2h)", Weekday::Sat), vec![(0, h(2))]);
+        assert_eq!(
+            etrs("Party (Fri,Sat 22h-2h)", Weekday::Sat),
+            vec![(h(22), h(2))]
+        );
+        assert_eq!(etrs("Party (Fri 22h-2h)", Weekday::Sun), vec![]);
+        assert_eq!(etrs("Porch (Sat night)", Weekday::Sun), vec![(0, h(6))]);
+
+        // Anything else isn't a day
+        assert_eq!(etrs("Test (Foo 7h-18h)", Weekday::Mon), vec![]);
+        assert_eq!(etrs("Test (Mon, <18C)", Weekday::Mon), vec![]);
+    }
+
+    #[test]
+    fn test_time_ranges_with_weekday_keywords() {
+        let parser = TimeRangeParser::new();
+
+        let etrs = |v: &str, d: Weekday| parser.extract_time_ranges_on(v, d);
+
+        assert_eq!(
+            etrs("Test (weekday 7h-9h)", Weekday::Mon),
+            vec![(h(7), h(9))]
+        );
+        assert_eq!(
+            etrs("Test (weekday 7h-9h)", Weekday::Fri),
+            vec![(h(7), h(9))]
+        );
+        assert_eq!(etrs("Test (weekday 7h-9h)", Weekday::Sat), vec![]);
+        assert_eq!(
+            etrs("Test (Weekends 9h-12h)", Weekday::Sun),
+            vec![(h(9), h(12))]
+        );
+        assert_eq!(etrs("Test (weekend 9h-12h)", Weekday::Mon), vec![]);
+
+        // Overnight ranges end the day after, combined with other days
+        assert_eq!(etrs("Test (weekend 22h-1h)", Weekday::Mon), vec![(0, h(1))]);
+        assert_eq!(
+            etrs("Test (Fri, weekend 22h-1h)", Weekday::Fri),
+            vec![(h(22), 0)]
+        );
+        assert_eq!(etrs("Test (weekend 22h-1h)", Weekday::Fri), vec![]);
+    }
+
+    #[test]
+    fn test_time_ranges_with_holidays() {
+        let mut parser = TimeRangeParser::new();
+        let date = |day: u32| NaiveDate::from_ymd_opt(2024, 12, day).unwrap();
+
+        // Christmas falls on a Wednesday
+        parser.define_holidays(HashSet::from([date(25), date(26)]));
+
+        let etrs = |v: &str, d: NaiveDate| parser.extract_time_ranges_at(v, d);
+
+        assert_eq!(etrs("Lazy (holiday 9h-23h)", date(25)), vec![(h(9), h(23))]);
+        assert_eq!(etrs("Lazy (holiday 9h-23h)", date(24)), vec![]);
+        assert_eq!(etrs("Alarm (!holiday 7h-9h)", date(25)), vec![]);
+        assert_eq!(etrs("Alarm (!holiday 7h-9h)", date(24)), vec![(h(7), h(9))]);
+
+        // Holidays count as Sunday
+        assert_eq!(etrs("Office (Mon-Fri 7h-18h)", date(25)), vec![]);
+        assert_eq!(
+            etrs("Brunch (Sat,Sun 9h-12h)", date(25)),
+            vec![(h(9), h(12))]
+        );
+        assert_eq!(etrs("Brunch (Sat,Sun 9h-12h)", date(24)), vec![]);
+        assert_eq!(
+            etrs("Office (Mon-Fri, !holiday 7h-18h)", date(27)),
+            vec![(h(7), h(18))]
+        );
+
+        // Overnight ranges continue into the day after the holiday
+        assert_eq!(etrs("Party (holiday 22h-2h)", date(27)), vec![(0, h(2))]);
+        assert_eq!(
+            etrs("Party (holiday 22h-2h)", date(26)),
+            vec![(h(22), h(2))]
+        );
+    }
+
+    #[test]
+    fn test_time_ranges_with_dates() {
+        let parser = TimeRangeParser::new();
+        let date = |m: u32, d: u32| NaiveDate::from_ymd_opt(2024, m, d).unwrap();
+        let etrs = |v: &str, d: NaiveDate| parser.extract_time_ranges_at(v, d);
+
+        assert_eq!(
+            etrs("Xmas (Dec1-Jan6, 16h-23h)", date(12, 1)),
+            vec![(h(16), h(23))]
+        );
+        assert_eq!(
+            etrs("Xmas (Dec1-Jan6, 16h-23h)", date(1, 6)),
+            vec![(h(16), h(23))]
+        );
+        assert_eq!(etrs("Xmas (Dec1-Jan6, 16h-23h)", date(1, 7)), vec![]);
+        assert_eq!(etrs("Xmas (Dec1-Jan6, 16h-23h)", date(11, 30)), vec![]);
+        assert_eq!(
+            etrs("Summer (Jun1-Aug31, 20h-22h)", date(7, 15)),
+            vec![(h(20), h(22))]
+        );
+        assert_eq!(etrs("Summer (Jun1-Aug31, 20h-22h)", date(9, 1)), vec![]);
+        assert_eq!(
+            etrs("Eve (Dec24, Dec31, 18h-23h)", date(12, 31)),
+            vec![(h(18), h(23))]
+        );
+        assert_eq!(etrs("Eve (Dec24, Dec31, 18h-23h)", date(12, 25)), vec![]);
+        assert_eq!(
+            etrs("Leap (Feb29, 18h-23h)", date(2, 29)),
+            vec![(h(18), h(23))]
+        );
+        assert_eq!(
+            etrs("Test (Feb30, 18h-23h)", date(2, 29)),
+            vec![(h(18), h(23))]
+        );
+        assert_eq!(etrs("Test (10h-20h)", date(3, 1)), vec![(h(10), h(20))]);
+    }
+
+    #[test]
+    fn test_time_ranges_with_cron() {
+        let parser = TimeRangeParser::new();
+        let date = |m: u32, d: u32| NaiveDate::from_ymd_opt(2024, m, d).unwrap();
+        let etrs = |v: &str, d: NaiveDate| parser.extract_time_ranges_at(v, d);
+
+        // 2024-01-01 is a Monday
+        assert_eq!(
+            etrs("Work (cron: * 7-8 * * 1-5)", date(1, 1)),
+            vec![(h(7), h(9))]
+        );
+        assert_eq!(etrs("Work (cron: * 7-8 * * 1-5)", date(1, 6)), vec![]);
+        assert_eq!(
+            etrs("Sun (cron: * 7 * * 0)", date(1, 7)),
+            vec![(h(7), h(8))]
+        );
+        assert_eq!(
+            etrs("Sun (cron: * 7 * * 7)", date(1, 7)),
+            vec![(h(7), h(8))]
+        );
+        assert_eq!(
+            etrs("Test (cron: 0,1,30 7 * * *)", date(1, 1)),
+            vec![(h(7), h(7) + m(2)), (h(7) + m(30), h(7) + m(31))]
+        );
+        assert_eq!(
+            etrs("Every other day (cron: * 20-21 */2 * *)", date(1, 3)),
+            vec![(h(20), h(22))]
+        );
+        assert_eq!(
+            etrs("Every other day (cron: * 20-21 */2 * *)", date(1, 2)),
+            vec![]
+        );
+        assert_eq!(
+            etrs("Late (cron: * 22-23 * * *, max=1)", date(1, 1)),
+            vec![(h(22), h(24))]
+        );
+        assert_eq!(
+            etrs("All day (cron: * * * * *)", date(1, 1)),
+            vec![(0, h(24))]
+        );
+        assert_eq!(etrs("Summer (cron: * 20 * 6-8 *)", date(1, 1)), vec![]);
+
+        // Either day field is enough if both are restricted, like in cron
+        assert_eq!(
+            etrs("Test (cron: * 7 15 * 1)", date(1, 15)),
+            vec![(h(7), h(8))]
+        );
+        assert_eq!(
+            etrs("Test (cron: * 7 14 * 1)", date(1, 14)),
+            vec![(h(7), h(8))]
+        );
+        assert_eq!(etrs("Test (cron: * 7 14 * 1)", date(1, 16)), vec![]);
+
+        // Combined with other ranges
+        assert_eq!(
+            etrs("Test (10h-11h, cron: * 7 * * *)", date(1, 1)),
+            vec![(h(10), h(11)), (h(7), h(8))]
+        );
+
+        assert_eq!(etrs("Test (cron: * 24 * * *)", date(1, 1)), vec![]);
+        assert_eq!(etrs("Test (cron: * 7 * *)", date(1, 1)), vec![]);
+        assert_eq!(etrs("Test (cron: * 7 * * 1/0)", date(1, 1)), vec![]);
+    }
+
+    #[test]
+    fn test_time_range_with_range_variables() {
+        let mut parser = TimeRangeParser::new();
+
+        parser.define_range_variables(HashMap::from([(
+            "night".to_string(),
+            (h(20) + m(12), h(6) + m(3)),
+        )]));
+
+        let etrs = |v: &str| parser.extract_time_ranges(v);
+
+        assert_eq!(etrs("Porch (night)"), vec![(h(20) + m(12), h(6) + m(3))]);
+        assert_eq!(
+            etrs("Porch (night, 12h-13h)"),
+            vec![(h(20) + m(12), h(6) + m(3)), (h(12), h(13))]
+        );
+        assert_eq!(etrs("Porch (nights)"), vec![]);
+        assert_eq!(etrs("Hallway (always)"), vec![(0, h(24))]);
+        assert_eq!(
+            etrs("Hallway (always !13h-14h)"),
+            vec![(0, h(13)), (h(14), h(24))]
+        );
+    }
+
+    #[test]
+    fn test_time_range_with_jitter() {
+        let mut parser = TimeRangeParser::new();
+
+        parser.define_variables(HashMap::from([("sunset".to_string(), h(20))]));
+
+        let etrs = |parser: &TimeRangeParser, v: &str| parser.extract_time_ranges(v);
+
+        // Shifted within the window, the same over the day
+        let mut starts = HashSet::new();
+
+        for day in 1..=20 {
+            parser.define_jitter_seed(format!("2024-01-{}", day));
+
+            let ranges = etrs(&parser, "Porch (sunset±20m-23h+-15m)");
+            assert_eq!(ranges, etrs(&parser, "Porch (sunset±20m-23h+-15m)"));
+
+            let (start, end) = ranges[0];
+            assert!((h(20) - m(20)..=h(20) + m(20)).contains(&start));
+            assert!((h(23) - m(15)..=h(23) + m(15)).contains(&end));
+            starts.insert(start);
+        }
+
+        assert!(starts.len() > 1);
+
+        // Without a window nothing is shifted
+        assert_eq!(etrs(&parser, "Porch (sunset-23h)"), vec![(h(20), h(23))]);
+        assert_eq!(etrs(&parser, "Day (0h-24h)"), vec![(0, h(24))]);
+
+        // Windows need a length below half a day
+        assert_eq!(etrs(&parser, "Porch (sunset±-23h)"), vec![]);
+        assert_eq!(etrs(&parser, "Porch (sunset±12h-23h)"), vec![]);
+    }
+
+    #[test]
+    fn test_time_range_with_sun_altitudes() {
+        let mut parser = TimeRangeParser::new();
+        let ets = |parser: &TimeRangeParser, v: &str| parser.extract_time_segment(v);
+
+        assert_eq!(ets(&parser, "sun<-6°"), None);
+
+        parser.define_location(
+            49.4875,
+            8.4660,
+            chrono_tz::Europe::Berlin,
+            NaiveDate::from_ymd_opt(2024, 6, 21).unwrap(),
+        );
+
+        let sunset = ets(&parser, "sun<0°").unwrap();
+        let dusk = ets(&parser, "sun<-6°").unwrap();
+        let morning = ets(&parser, "sun>10°").unwrap();
+
+        assert!((h(21)..h(22)).contains(&sunset));
+        assert!(dusk > sunset && dusk < sunset + h(1));
+        assert!((h(6)..h(8)).contains(&morning));
+        assert_eq!(ets(&parser, "sun<-6"), Some(dusk));
+        assert_eq!(ets(&parser, "sun<-6,0°"), Some(dusk));
+        assert_eq!(ets(&parser, "sun>80°"), None);
+        assert_eq!(ets(&parser, "sun<-91°"), None);
+        assert_eq!(ets(&parser, "sun=-6°"), None);
+
+        assert_eq!(
+            parser.extract_time_range("sun<-6°-23h"),
+            Some((dusk, h(23)))
+        );
+        assert_eq!(
+            parser
+                .extract_time_range("sun>10°-sun<10°")
+                .map(|range| range.0),
+            Some(morning)
+        );
+    }
+
+    #[test]
+    fn test_time_range_with_durations() {
+        let mut parser = TimeRangeParser::new();
+
+        parser.define_variables(HashMap::from([("sunset".to_string(), h(20))]));
+
+        let etr = |v: &str| parser.extract_time_range(v);
+
+        assert_eq!(etr("sunset for 2h"), Some((h(20), h(22))));
+        assert_eq!(etr("22h for 45m"), Some((h(22), h(22) + m(45))));
+        assert_eq!(etr("sunset+30m for 1h30m"), Some((h(20) + m(30), h(22))));
+        assert_eq!(etr("23h for 3h"), Some((h(23), h(2))));
+        assert_eq!(etr("23h for 1h"), Some((h(23), 0)));
+        assert_eq!(etr("0h for 24h"), None);
+        assert_eq!(etr("22h for 0m"), None);
+        assert_eq!(etr("moonrise for 1h"), None);
+        assert_eq!(
+            parser.extract_time_ranges("Test (Fri 23h for 3h, 8h for 1h !8:15h-8:30h)"),
+            vec![(h(23), h(2)), (h(8), h(8) + m(15)), (h(8) + m(30), h(9))]
+        );
+
+        // Overnight ranges that didn't end yet started with the previous sunset
+        parser.define_adjacent_variables(
+            h(1),
+            HashMap::from([("sunset".to_string(), h(20) + m(3))]),
+            HashMap::from([("sunset".to_string(), h(20) - m(3))]),
+        );
+
+        assert_eq!(
+            parser.extract_time_range("sunset for 6h"),
+            Some((h(20) + m(3), h(2) + m(3)))
+        );
+        assert_eq!(
+            parser.extract_time_range("sunset for 2h"),
+            Some((h(20), h(22)))
+        );
+    }
+
+    #[test]
+    fn test_time_range_with_adjacent_variables() {
+        let mut parser = TimeRangeParser::new();
+        let variables = |sunrise: u32, sunset: u32| {
+            HashMap::from([
+                ("sunrise".to_string(), sunrise),
+                ("sunset".to_string(), sunset),
+            ])
+        };
+
+        parser.define_variables(variables(h(6), h(20)));
+
+        let define_now = |parser: &mut TimeRangeParser, now: u32| {
+            parser.define_adjacent_variables(
+                now,
+                variables(h(6) + m(2), h(20) + m(3)),
+                variables(h(6) - m(2), h(20) - m(3)),
+            );
+        };
+
+        // Evening, the range ends with the next sunrise
+        define_now(&mut parser, h(22));
+        assert_eq!(
+            parser.extract_time_range("sunset-sunrise"),
+            Some((h(20), h(6) - m(2)))
+        );
+        assert_eq!(
+            parser.extract_time_range("sunset-sunrise+30m"),
+            Some((h(20), h(6) + m(28)))
+        );
+
+        // Morning, the range started with the previous sunset
+        define_now(&mut parser, h(5));
+        assert_eq!(
+            parser.extract_time_range("sunset-sunrise"),
+            Some((h(20) + m(3), h(6)))
+        );
+
+        // Noon, the next occurrence starts today
+        define_now(&mut parser, h(12));
+        assert_eq!(
+            parser.extract_time_range("sunset-sunrise"),
+            Some((h(20), h(6) - m(2)))
+        );
+
+        // Ranges within a day use today's values only
+        assert_eq!(
+            parser.extract_time_range("sunrise-sunset"),
+            Some((h(6), h(20)))
+        );
+        assert_eq!(parser.extract_time_range("22h-5h"), Some((h(22), h(5))));
     }
 }
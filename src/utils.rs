@@ -1,31 +1,99 @@
+use crate::conditions::{self, Condition, Environment};
 use crate::config::Config;
-use crate::time_range_parser::TimeRangeParser;
-use chrono::{DateTime, Local, Timelike, Utc};
-use huelib2::resource::{Light, Scene};
+use crate::debug;
+use crate::solar;
+use crate::time_range_parser::{TimeRange, TimeRangeParser};
+use chrono::{DateTime, Days, LocalResult, NaiveDate, NaiveTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use huelib2::resource::group::{CreatableKind, Kind};
+use huelib2::resource::{Group, Light, Scene};
+use std::cmp::Reverse;
 use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::fmt;
 use std::hash::{Hash, Hasher};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Seconds of a day, time-ranges are given in seconds since midnight
+const SECONDS_OF_DAY: u32 = 24 * 60 * 60;
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct ScheduledScene {
     pub scene_id: String,
     pub start: u32,
     pub end: u32,
+    pub conditions: usize,
+    pub weight: u32,
+    pub priority: Option<u32>,
 }
 
-/// Returns all scheduled scenes that are active right now
+/// Returns all scheduled scenes that are active right now and whose conditions are met,
+/// schedules can come from the scene name or from additional ranges per scene id.
+/// Among scenes for the same lights the one with the highest priority wins, then the one with the highest weight,
+/// then the one preferred by the `OVERLAP_STRATEGY`.
 pub fn get_scheduled_scenes(
     conf: &Config,
     parser: &TimeRangeParser,
     scenes: &Vec<Scene>,
+    extra_ranges: &HashMap<String, Vec<TimeRange>>,
+    environment: &Environment,
+    weights: &HashMap<String, u32>,
+) -> Vec<ScheduledScene> {
+    get_scheduled_scenes_at(
+        conf,
+        parser,
+        scenes,
+        extra_ranges,
+        environment,
+        weights,
+        &now_at_home(conf),
+    )
+}
+
+/// Same as [get_scheduled_scenes], but for any point in time
+pub fn get_scheduled_scenes_at(
+    conf: &Config,
+    parser: &TimeRangeParser,
+    scenes: &Vec<Scene>,
+    extra_ranges: &HashMap<String, Vec<TimeRange>>,
+    environment: &Environment,
+    weights: &HashMap<String, u32>,
+    at: &DateTime<Tz>,
 ) -> Vec<ScheduledScene> {
     let mut scheduled_scenes = HashMap::<u64, ScheduledScene>::new();
-    let date_time = DateTime::<Utc>::from(Local::now()).with_timezone(&conf.home_timezone);
-    let now = date_time.hour() * 60 + date_time.minute();
+    let now = seconds_of_day(at);
 
     // Group scenes by their lights
     for scene in scenes {
-        let time_ranges = parser.extract_time_ranges(&scene.name);
+        if is_manual_scene(conf, scene) {
+            continue;
+        }
+
+        let conditions = conditions::parse_conditions(&scene.name);
+
+        if !conditions
+            .iter()
+            .all(|condition| condition.is_met(environment))
+        {
+            continue;
+        }
+
+        // Scenes without a mode belong to the default one
+        if environment.mode != conditions::DEFAULT_MODE
+            && !conditions
+                .iter()
+                .any(|condition| matches!(condition, Condition::Mode(_)))
+        {
+            continue;
+        }
+
+        let time_ranges = scene_ranges(conf, parser, scene, extra_ranges, at.date_naive());
 
         let Some(time_range) = time_ranges
             .iter()
@@ -46,21 +114,25 @@ pub fn get_scheduled_scenes(
 
         let scene_id = hash.finish();
 
-        // Check if scene is closer to now than this one
+        let candidate = ScheduledScene {
+            scene_id: scene.id.clone(),
+            start: time_range.0,
+            end: time_range.1,
+            conditions: conditions.len(),
+            weight: weights.get(&scene.id).copied().unwrap_or(0),
+            priority: conditions::parse_priority(&scene.name),
+        };
+
+        // Check if a scene is preferred over this one, see [OverlapStrategy]
         if let Some(last_scene) = scheduled_scenes.get(&scene_id) {
-            if last_scene.start > time_range.0 {
+            if rank(conf.overlap_strategy, last_scene, now)
+                > rank(conf.overlap_strategy, &candidate, now)
+            {
                 continue;
             }
         }
 
-        scheduled_scenes.insert(
-            scene_id,
-            ScheduledScene {
-                scene_id: scene.id.clone(),
-                start: time_range.0,
-                end: time_range.1,
-            },
-        );
+        scheduled_scenes.insert(scene_id, candidate);
     }
 
     scheduled_scenes
@@ -69,16 +141,593 @@ pub fn get_scheduled_scenes(
         .collect::<Vec<ScheduledScene>>()
 }
 
-pub fn get_sunrise_sunset(latitude: f64, longitude: f64) -> Option<(u32, u32)> {
-    let (sunrise, sunset) =
-        sun_times::sun_times(Local::now().date_naive(), latitude, longitude, 0f64)?;
+/// Orders overlapping scenes, the greatest one wins.
+/// Priorities always come first, then preferences learned from manual selections unless only priorities count.
+fn rank(
+    strategy: OverlapStrategy,
+    scene: &ScheduledScene,
+    now: u32,
+) -> (Option<Reverse<u32>>, u32, i64, i64) {
+    let length = match (scene.end + SECONDS_OF_DAY - scene.start) % SECONDS_OF_DAY {
+        0 => SECONDS_OF_DAY,
+        length => length,
+    } as i64;
+    // Scenes scheduled all day, e.g. `(always)`, never started as far as others are concerned
+    let since_start = match length == SECONDS_OF_DAY as i64 {
+        true => length,
+        false => ((now + SECONDS_OF_DAY - scene.start % SECONDS_OF_DAY) % SECONDS_OF_DAY) as i64,
+    };
+    let conditions = scene.conditions as i64;
+
+    let (weight, tiebreak) = match strategy {
+        OverlapStrategy::Latest => (scene.weight, (-since_start, conditions)),
+        OverlapStrategy::Shortest => (scene.weight, (-length, conditions)),
+        OverlapStrategy::Specific => (scene.weight, (conditions, -length)),
+        OverlapStrategy::Priority => (0, (0, 0)),
+    };
+
+    (scene.priority.map(Reverse), weight, tiebreak.0, tiebreak.1)
+}
+
+/// Returns all ranges a scene is scheduled for on the given day, including the jitter of that day
+pub fn scene_ranges(
+    conf: &Config,
+    parser: &TimeRangeParser,
+    scene: &Scene,
+    extra_ranges: &HashMap<String, Vec<TimeRange>>,
+    date: NaiveDate,
+) -> Vec<TimeRange> {
+    let mut time_ranges = parser.extract_time_ranges_at(&scene.name, date);
+
+    // Windows of the year apply to additional ranges as well
+    if let Some(ranges) = extra_ranges
+        .get(&scene.id)
+        .filter(|_| parser.matches_date_ranges(&scene.name, date))
+    {
+        time_ranges.extend(ranges);
+    }
+
+    if conf.schedule_jitter > 0 {
+        for range in time_ranges.iter_mut() {
+            let jittered = jitter_range(range, conf.schedule_jitter, date, &scene.id);
+
+            debug!(
+                "Scene \"{}\": {} shifted to {} on {}",
+                scene.name,
+                format_range(range),
+                format_range(&jittered),
+                date
+            );
+
+            *range = jittered;
+        }
+    }
+
+    time_ranges
+}
+
+/// Returns when the next range of any of the scenes starts or ends, `None` if they're scheduled all day or never
+pub fn next_boundary(
+    conf: &Config,
+    parser: &TimeRangeParser,
+    scenes: &[Scene],
+    extra_ranges: &HashMap<String, Vec<TimeRange>>,
+    now: &DateTime<Tz>,
+) -> Option<DateTime<Tz>> {
+    // Schedules restricted to days of the week or the year may change at midnight
+    let today = now.date_naive();
+    let tomorrow = today.checked_add_days(Days::new(1))?;
+
+    let changes_at_midnight = scenes.iter().any(|scene| {
+        !is_manual_scene(conf, scene)
+            && parser.extract_time_ranges_at(&scene.name, today)
+                != parser.extract_time_ranges_at(&scene.name, tomorrow)
+    });
+
+    scenes
+        .iter()
+        .filter(|scene| !is_manual_scene(conf, scene))
+        .flat_map(|scene| scene_ranges(conf, parser, scene, extra_ranges, today))
+        .filter(|range| parser.linearize(range) != vec![(0, SECONDS_OF_DAY)])
+        .flat_map(|range| [range.0, range.1])
+        .chain(changes_at_midnight.then_some(0))
+        // Boundaries that already passed today, or are right now, are the ones of tomorrow
+        .filter_map(|boundary| {
+            [today, tomorrow].into_iter().find_map(|date| {
+                at_seconds_of_day(now.timezone(), date, boundary % SECONDS_OF_DAY)
+                    .filter(|at| at > now)
+            })
+        })
+        .min()
+}
+
+/// Shifts a boundary by up to ± `window` seconds, the offset only depends on `key` so it can be reproduced
+pub fn jitter(seconds: u32, window: u32, key: &str) -> u32 {
+    if window == 0 {
+        return seconds;
+    }
+
+    // FNV-1a, unlike the hasher of the standard library it is guaranteed to be stable
+    let hash = key.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+
+    let offset = (hash % (window as u64 * 2 + 1)) as i64 - window as i64;
+
+    // Boundaries stay within the day, wrapping around would turn e.g. `0h-23:59h` into a short range
+    (seconds as i64 + offset).clamp(0, SECONDS_OF_DAY as i64 - 1) as u32
+}
+
+/// Shifts both boundaries of a range by up to ± `jitter` minutes, boundaries are given in seconds.
+/// The offsets only depend on the day, scene and boundary, so they stay the same over the day and can be reproduced.
+pub fn jitter_range(range: &TimeRange, jitter: u32, date: NaiveDate, scene_id: &str) -> TimeRange {
+    let shift = |seconds: u32| {
+        self::jitter(
+            seconds,
+            jitter * 60,
+            &format!("{}/{}/{}", date, scene_id, seconds),
+        )
+    };
+
+    let jittered = (shift(range.0), shift(range.1));
+
+    // Ranges within a day must not turn into overnight ones, which would cover almost the whole day
+    if range.0 < range.1 && jittered.0 >= jittered.1 {
+        *range
+    } else {
+        jittered
+    }
+}
+
+/// Formats a range for the log, e.g. `20:00-23:30`, seconds are only shown if there are any
+pub fn format_range(range: &TimeRange) -> String {
+    let time = |seconds: u32| {
+        NaiveTime::from_num_seconds_from_midnight_opt(seconds % SECONDS_OF_DAY, 0)
+            .map(|time| format_time(&time, time.second() > 0))
+            .unwrap_or_default()
+    };
+
+    format!("{}-{}", time(range.0), time(range.1))
+}
+
+/// Whether times are shown with a 12-hour clock, set once on startup
+static TWELVE_HOUR_CLOCK: AtomicBool = AtomicBool::new(false);
+
+/// How times of day are shown in the log, the status and while watching
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TimeFormat {
+    H24,
+    H12,
+}
+
+impl FromStr for TimeFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "24h" => Ok(TimeFormat::H24),
+            "12h" => Ok(TimeFormat::H12),
+            _ => Err(format!("unknown value: {}", value.trim())),
+        }
+    }
+}
+
+pub fn set_time_format(format: TimeFormat) {
+    TWELVE_HOUR_CLOCK.store(format == TimeFormat::H12, Ordering::Relaxed);
+}
+
+/// Formats a time of day in the configured format, e.g. `21:05` or `9:05 PM`
+pub fn format_time(time: &impl Timelike, seconds: bool) -> String {
+    let seconds = if seconds {
+        format!(":{:0>2}", time.second())
+    } else {
+        String::new()
+    };
+
+    if TWELVE_HOUR_CLOCK.load(Ordering::Relaxed) {
+        let (pm, hour) = time.hour12();
+        let period = if pm { "PM" } else { "AM" };
+        format!("{}:{:0>2}{} {}", hour, time.minute(), seconds, period)
+    } else {
+        format!("{:0>2}:{:0>2}{}", time.hour(), time.minute(), seconds)
+    }
+}
+
+/// Virtual clock of a simulation, the instant and virtual time it started at and the virtual time it shows now
+type SimulatedTime = (Instant, DateTime<Utc>, DateTime<Utc>);
+
+#[cfg(not(test))]
+static SIMULATED_TIME: Mutex<Option<SimulatedTime>> = Mutex::new(None);
+
+/// Tests run side by side in one process, each of them follows a virtual clock of its own
+#[cfg(test)]
+thread_local! {
+    static SIMULATED_TIME: Mutex<Option<SimulatedTime>> = const { Mutex::new(None) };
+}
+
+fn with_simulated_time<T>(f: impl FnOnce(&mut Option<SimulatedTime>) -> T) -> T {
+    #[cfg(not(test))]
+    return f(&mut SIMULATED_TIME
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner));
+
+    #[cfg(test)]
+    SIMULATED_TIME.with(|time| f(&mut time.lock().unwrap_or_else(PoisonError::into_inner)))
+}
+
+/// Moves the virtual clock of a simulation forward, the first call starts it
+pub fn set_simulated_time(now: DateTime<Utc>) {
+    with_simulated_time(|simulated_time| {
+        let (started, start) = simulated_time
+            .map(|(started, start, _)| (started, start))
+            .unwrap_or((Instant::now(), now));
+
+        *simulated_time = Some((started, start, now));
+    });
+}
+
+fn simulated_time() -> Option<SimulatedTime> {
+    with_simulated_time(|simulated_time| *simulated_time)
+}
+
+/// Returns the current time in the home timezone
+pub fn now_at_home(conf: &Config) -> DateTime<Tz> {
+    let now = simulated_time()
+        .map(|(_, _, now)| now)
+        .unwrap_or_else(Utc::now);
+
+    now.with_timezone(&conf.home_timezone)
+}
+
+/// Same as [Instant::now], but following the virtual clock of a simulation
+pub fn instant_now() -> Instant {
+    match simulated_time() {
+        Some((started, start, now)) => started + (now - start).to_std().unwrap_or_default(),
+        None => Instant::now(),
+    }
+}
+
+/// Same as [Instant::elapsed], but following the virtual clock of a simulation
+pub fn elapsed(instant: Instant) -> Duration {
+    instant_now().saturating_duration_since(instant)
+}
+
+/// Returns the seconds since midnight of a timestamp, as schedules see it.
+/// When clocks are set back the repeated hour only counts once, the second time it stays at its end,
+/// so boundaries within it take effect the first time and don't flip back and forth.
+/// Boundaries within the hour skipped when clocks are set forward take effect once the clocks jump.
+pub fn seconds_of_day(date_time: &DateTime<Tz>) -> u32 {
+    let timezone = date_time.timezone();
+    let local = date_time.naive_local();
+
+    match timezone.from_local_datetime(&local) {
+        LocalResult::Ambiguous(first, _) if first != *date_time => {
+            // The repeated hour ends where wall-clock times become unique again
+            let mut end = local.with_second(0).unwrap_or(local);
+
+            while let LocalResult::Ambiguous(..) = timezone.from_local_datetime(&end) {
+                end += chrono::Duration::minutes(1);
+            }
+
+            match end.date() == local.date() {
+                true => end.num_seconds_from_midnight().saturating_sub(1),
+                false => SECONDS_OF_DAY - 1,
+            }
+        }
+        _ => date_time.num_seconds_from_midnight(),
+    }
+}
+
+/// Returns the point in time of a number of seconds since midnight on a day.
+/// Times skipped when clocks are set forward happen once the clocks jump, repeated ones happen the first time.
+pub fn at_seconds_of_day(timezone: Tz, date: NaiveDate, seconds: u32) -> Option<DateTime<Tz>> {
+    let mut local = date.and_time(NaiveTime::MIN) + chrono::Duration::seconds(seconds as i64);
+
+    // Clocks never jump by more than a day
+    for _ in 0..24 * 60 {
+        match timezone.from_local_datetime(&local) {
+            LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => return Some(time),
+            LocalResult::None => {
+                local = local.with_second(0)? + chrono::Duration::minutes(1);
+            }
+        }
+    }
+
+    None
+}
+
+/// Creates a parser with all variables resolved for the current day
+pub fn create_parser(conf: &Config) -> Option<TimeRangeParser> {
+    let now = now_at_home(conf);
+    let today = now.date_naive();
+
+    let mut parser = TimeRangeParser::new();
+    parser.define_location(
+        conf.home_latitude,
+        conf.home_longitude,
+        conf.home_timezone,
+        today,
+    );
+
+    let variables = get_variables(conf, &parser, today, 0);
+    let previous = get_variables(conf, &parser, today.checked_sub_days(Days::new(1))?, -1);
+    let next = get_variables(conf, &parser, today.checked_add_days(Days::new(1))?, 1);
+
+    // The night either started yesterday and ends with today's sunrise,
+    // or starts today and ends with tomorrow's sunrise. Both may be replaced by time variables of the same name.
+    let sunrise = *variables.get("sunrise")?;
+    let night = if seconds_of_day(&now) < sunrise {
+        (*previous.get("sunset")?, sunrise)
+    } else {
+        (*variables.get("sunset")?, *next.get("sunrise")?)
+    };
+
+    parser.define_variables(variables);
+    parser.define_adjacent_variables(seconds_of_day(&now), previous, next);
+    parser.define_jitter_seed(today.to_string());
+    parser.define_range_variables(HashMap::from([("night".to_string(), night)]));
+
+    Some(parser)
+}
+
+/// Returns the solar events and time variables of a day, `day` is how many days it's away from today.
+/// Time variables which can't be resolved are left undefined, ranges using them are skipped.
+fn get_variables(
+    conf: &Config,
+    parser: &TimeRangeParser,
+    date: NaiveDate,
+    day: i64,
+) -> HashMap<String, u32> {
+    let mut variables = HashMap::from_iter(solar::get_solar_events(conf, date));
+
+    for (name, value) in conf.time_variables.iter() {
+        if let Some(value) = parser.extract_time_segment_with(value, &variables, day) {
+            variables.insert(name.clone(), value);
+        }
+    }
+
+    variables
+}
+
+/// How to treat lights which don't report whether they're on, e.g. some third-party zigbee bulbs
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UnknownOnState {
+    On,
+    Off,
+    Ignore,
+}
+
+impl FromStr for UnknownOnState {
+    type Err = String;
 
-    Some((
-        sunrise.hour() * 60 + sunrise.minute(),
-        sunset.hour() * 60 + sunset.minute(),
-    ))
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "on" => Ok(UnknownOnState::On),
+            "off" => Ok(UnknownOnState::Off),
+            "ignore" => Ok(UnknownOnState::Ignore),
+            _ => Err(format!("unknown value: {}", value.trim())),
+        }
+    }
+}
+
+impl fmt::Display for UnknownOnState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnknownOnState::On => write!(f, "on"),
+            UnknownOnState::Off => write!(f, "off"),
+            UnknownOnState::Ignore => write!(f, "ignored"),
+        }
+    }
+}
+
+/// Which scene wins if several are scheduled for the same lights at the same time and none has a higher priority
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OverlapStrategy {
+    /// The scene that started last, e.g. `13h-4h` within `12h-6h` until 4:00
+    Latest,
+    /// The scene with the shortest range
+    Shortest,
+    /// The scene with the most conditions, then the one with the shortest range
+    Specific,
+    /// Only priorities count, scenes without one are picked by their order on the bridge
+    Priority,
+}
+
+impl FromStr for OverlapStrategy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "latest" => Ok(OverlapStrategy::Latest),
+            "shortest" => Ok(OverlapStrategy::Shortest),
+            "specific" => Ok(OverlapStrategy::Specific),
+            "priority" => Ok(OverlapStrategy::Priority),
+            _ => Err(format!("unknown value: {}", value.trim())),
+        }
+    }
+}
+
+/// What to do with lights that become reachable while no scene is scheduled for them
+#[derive(Clone, PartialEq, Debug)]
+pub enum UnscheduledPolicy {
+    /// Leave them in whatever state they stored
+    Ignore,
+    /// Turn them on at full brightness with a neutral white, like the "Bright" scene of the Hue app
+    Bright,
+    /// Apply a scene of the room, given by its name without the time-ranges
+    Scene(String),
+}
+
+impl FromStr for UnscheduledPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "" => Err("missing value".to_string()),
+            "ignore" => Ok(UnscheduledPolicy::Ignore),
+            "bright" => Ok(UnscheduledPolicy::Bright),
+            scene => Ok(UnscheduledPolicy::Scene(scene.to_string())),
+        }
+    }
+}
+
+/// What lights, groups and scenes are read from and commands are sent to, see `BRIDGE_BACKEND`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Backend {
+    Hue,
+    #[cfg(feature = "deconz")]
+    Deconz,
+    #[cfg(feature = "zigbee2mqtt")]
+    Zigbee2Mqtt,
+    #[cfg(feature = "home-assistant")]
+    HomeAssistant,
+    /// Fixtures replayed by `hue-scheduler simulate`, it can't be configured
+    Simulation,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "hue" => Ok(Backend::Hue),
+            #[cfg(feature = "deconz")]
+            "deconz" => Ok(Backend::Deconz),
+            #[cfg(feature = "zigbee2mqtt")]
+            "zigbee2mqtt" => Ok(Backend::Zigbee2Mqtt),
+            #[cfg(feature = "home-assistant")]
+            "homeassistant" => Ok(Backend::HomeAssistant),
+            #[cfg(not(feature = "deconz"))]
+            "deconz" => Err(without_feature("deconz")),
+            #[cfg(not(feature = "zigbee2mqtt"))]
+            "zigbee2mqtt" => Err(without_feature("zigbee2mqtt")),
+            #[cfg(not(feature = "home-assistant"))]
+            "homeassistant" => Err(without_feature("home-assistant")),
+            _ => Err(format!("unknown value: {}", value.trim())),
+        }
+    }
+}
+
+#[cfg(not(all(
+    feature = "deconz",
+    feature = "zigbee2mqtt",
+    feature = "home-assistant"
+)))]
+fn without_feature(feature: &str) -> String {
+    format!("hue-scheduler was built without the {} feature", feature)
+}
+
+impl Backend {
+    /// Whether lights are reached through a bridge, zigbee2mqtt and Home Assistant are connected to directly
+    pub fn is_bridge(self) -> bool {
+        match self {
+            #[cfg(feature = "zigbee2mqtt")]
+            Backend::Zigbee2Mqtt => false,
+            #[cfg(feature = "home-assistant")]
+            Backend::HomeAssistant => false,
+            _ => true,
+        }
+    }
+
+    /// Whether sensors can be read, only bridges have them
+    pub fn has_sensors(self) -> bool {
+        match self {
+            Backend::Hue => true,
+            #[cfg(feature = "deconz")]
+            Backend::Deconz => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Hue => write!(f, "the hue bridge"),
+            #[cfg(feature = "deconz")]
+            Backend::Deconz => write!(f, "deCONZ"),
+            #[cfg(feature = "zigbee2mqtt")]
+            Backend::Zigbee2Mqtt => write!(f, "zigbee2mqtt"),
+            #[cfg(feature = "home-assistant")]
+            Backend::HomeAssistant => write!(f, "Home Assistant"),
+            Backend::Simulation => write!(f, "the simulation"),
+        }
+    }
+}
+
+/// Returns the name of a scene without its time-ranges, e.g. `Relax` for `Relax (18h-22h)`
+pub fn scene_base_name(scene: &Scene) -> &str {
+    scene.name.split("(").next().unwrap_or_default().trim()
+}
+
+/// Returns whether a light is on, `None` if it doesn't report it and should be ignored
+pub fn is_light_on(conf: &Config, light: &Light) -> Option<bool> {
+    match (light.state.on, conf.unknown_on_state) {
+        (Some(on), _) => Some(on),
+        (None, UnknownOnState::On) => Some(true),
+        (None, UnknownOnState::Off) => Some(false),
+        (None, UnknownOnState::Ignore) => None,
+    }
 }
 
 pub fn is_attached_light(light: &Light) -> bool {
     light.name.ends_with("(att)")
 }
+
+/// Scenes marked with `(manual)` or matching `MANUAL_SCENES` are never applied by the scheduler
+pub fn is_manual_scene(conf: &Config, scene: &Scene) -> bool {
+    scene.name.contains("(manual)")
+        || conf
+            .manual_scenes
+            .as_ref()
+            .map(|pattern| pattern.is_match(&scene.name))
+            .unwrap_or(false)
+}
+
+/// Returns the type of a group as named by the bridge, e.g. `Room` or `Zone`
+pub fn group_type(group: &Group) -> String {
+    match group.kind {
+        Kind::Creatable(kind) => format!("{:?}", kind),
+        Kind::Immutable(kind) => format!("{:?}", kind),
+    }
+}
+
+/// Rooms and zones are set up by the user, other groups are created by apps or are entertainment areas
+pub fn is_room_or_zone(group: &Group) -> bool {
+    matches!(
+        group.kind,
+        Kind::Creatable(CreatableKind::Room) | Kind::Creatable(CreatableKind::Zone)
+    )
+}
+
+/// All lights of a room or zone marked with `(att)` count as attached
+pub fn is_attached_group(group: &Group) -> bool {
+    is_room_or_zone(group) && group.name.ends_with("(att)")
+}
+
+/// Checks if the scheduler may turn off a group, based on its type and name.
+/// Only rooms and zones are managed unless `MANAGED_GROUP_TYPES` says otherwise.
+pub fn is_managed_group(conf: &Config, group: &Group) -> bool {
+    conf.managed_group_types
+        .as_ref()
+        .map(|types| types.contains(&group_type(group)))
+        .unwrap_or(is_room_or_zone(group))
+        && conf
+            .managed_groups
+            .as_ref()
+            .map(|pattern| pattern.is_match(&group.name))
+            .unwrap_or(true)
+        && !conf
+            .ignored_groups
+            .as_ref()
+            .map(|pattern| pattern.is_match(&group.name))
+            .unwrap_or(false)
+}
+
+/// Checks if there's a unix socket at a path, without following symlinks
+pub fn is_socket(path: &Path) -> bool {
+    #[cfg(unix)]
+    return std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket());
+    #[cfg(not(unix))]
+    return false;
+}
@@ -0,0 +1,78 @@
+use crate::api_usage;
+use crate::debug;
+use huelib2::Bridge;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+/// Requests running at once, most of them hang in the background if there are this many
+const MAX_RUNNING: usize = 32;
+
+static TIMEOUT: OnceLock<Duration> = OnceLock::new();
+static AGENT: OnceLock<ureq::Agent> = OnceLock::new();
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets how long requests to the bridge may take, see `BRIDGE_TIMEOUT`
+pub fn init(timeout: Duration) {
+    let _ = TIMEOUT.set(timeout);
+}
+
+pub fn timeout() -> Duration {
+    TIMEOUT.get().copied().unwrap_or(Duration::from_secs(10))
+}
+
+/// Agent for requests sent without huelib2, they time out by themselves
+pub fn agent() -> &'static ureq::Agent {
+    AGENT.get_or_init(|| ureq::AgentBuilder::new().timeout(timeout()).build())
+}
+
+fn timed_out(message: String) -> huelib2::Error {
+    huelib2::Error::from(ureq::Error::from(io::Error::new(
+        io::ErrorKind::TimedOut,
+        message,
+    )))
+}
+
+/// Same as [api_usage::track] for requests of huelib2, which never time out if the connection stalls.
+/// The request runs in a separate thread with its own copy of the bridge and is abandoned once it hangs.
+pub fn track<T: Send + 'static>(
+    bridge: &Bridge,
+    request: impl FnOnce(Bridge) -> huelib2::Result<T> + Send + 'static,
+) -> huelib2::Result<T> {
+    let timeout = timeout();
+
+    if RUNNING.load(Ordering::Relaxed) >= MAX_RUNNING {
+        return Err(timed_out(format!(
+            "{} requests to the bridge are still hanging",
+            MAX_RUNNING
+        )));
+    }
+
+    let bridge = bridge.clone();
+    let (sender, receiver) = channel();
+
+    api_usage::track(|| {
+        RUNNING.fetch_add(1, Ordering::Relaxed);
+
+        thread::spawn(move || {
+            // Nobody is waiting for the result anymore if it took too long
+            let _ = sender.send(request(bridge));
+            RUNNING.fetch_sub(1, Ordering::Relaxed);
+        });
+
+        receiver.recv_timeout(timeout).unwrap_or_else(|_| {
+            debug!(
+                "Bridge: request didn't finish within {}ms, skipping it",
+                timeout.as_millis()
+            );
+
+            Err(timed_out(format!(
+                "no response within {}ms",
+                timeout.as_millis()
+            )))
+        })
+    })
+}
@@ -0,0 +1,53 @@
+use crate::config::Config;
+use crate::debug;
+use crate::events::Event;
+use crate::plugins::{Plugin, Sink};
+use serde::Serialize;
+use std::time::Duration;
+
+/// Body posted for every event, fits the `logbook.log` action of Home Assistant as-is.
+/// The event itself is flattened into it, e.g. `"event": "scene_applied", "scene": "Evening"`.
+#[derive(Serialize, Debug)]
+struct Payload<'a> {
+    name: &'static str,
+    message: String,
+    domain: &'static str,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+/// Posts all events to a webhook, e.g. `http://homeassistant.local:8123/api/webhook/hue-scheduler`
+pub struct Webhook {
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl Sink for Webhook {
+    fn handle(&mut self, event: &Event) -> Result<(), String> {
+        let payload = Payload {
+            name: "Hue Scheduler",
+            message: event.to_string(),
+            domain: "hue_scheduler",
+            event,
+        };
+
+        debug!("Webhook: POST {}", self.url);
+
+        // Failing deliveries are dropped, events are only informational
+        self.agent
+            .post(&self.url)
+            .send_json(&payload)
+            .map(|_| ())
+            .map_err(|err| format!("Failed to post event to webhook: {}", err))
+    }
+}
+
+/// Built-in plugin posting to `WEBHOOK_URL`
+pub fn plugin(conf: &Config) -> Result<Plugin, String> {
+    let url = conf.webhook_url.clone().ok_or("WEBHOOK_URL is missing")?;
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(5))
+        .build();
+
+    Ok(Plugin::Sink(Box::new(Webhook { url, agent })))
+}
@@ -0,0 +1,292 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+pub const TEXT: u8 = 0x1;
+pub const CLOSE: u8 = 0x8;
+pub const PING: u8 = 0x9;
+pub const PONG: u8 = 0xa;
+
+/// Largest message accepted, larger ones fail the connection instead of being allocated
+const MAX_MESSAGE_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Once a message started, the rest of it has to follow within this time
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(30);
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Encodes the key of the handshake, it's only needed once per connection
+fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+
+    for chunk in bytes.chunks(3) {
+        let value = chunk.iter().enumerate().fold(0u32, |value, (index, byte)| {
+            value | (*byte as u32) << (16 - 8 * index)
+        });
+
+        for index in 0..4 {
+            match index <= chunk.len() {
+                true => encoded.push(BASE64[(value >> (18 - 6 * index) & 0x3f) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Opens a plain WebSocket connection, e.g. to `homeassistant.local:8123` and `/api/websocket`
+pub fn connect(address: &str, path: &str) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(address)?;
+    let key = [random().to_be_bytes(), random().to_be_bytes()].concat();
+
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        path,
+        address,
+        base64(&key)
+    )?;
+
+    // Read the response byte by byte, the first frame may follow right after it
+    let mut response = vec![];
+
+    while !response.ends_with(b"\r\n\r\n") {
+        let mut byte = [0u8];
+        stream.read_exact(&mut byte)?;
+        response.push(byte[0]);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or_default();
+
+    match status.split_whitespace().nth(1) {
+        Some("101") => Ok(stream),
+        _ => Err(io::Error::other(format!("upgrade refused: {}", status))),
+    }
+}
+
+/// Builds a frame, everything sent by a client has to be masked
+pub fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+
+    match payload.len() {
+        length if length < 126 => frame.push(0x80 | length as u8),
+        length if length <= u16::MAX as usize => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(0x80 | 127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+
+    let mask = (random() as u32).to_be_bytes();
+    frame.extend_from_slice(&mask);
+    frame.extend(
+        payload
+            .iter()
+            .enumerate()
+            .map(|(index, byte)| byte ^ mask[index % 4]),
+    );
+
+    frame
+}
+
+/// Reads a message, fragmented ones are joined. Returns its opcode and payload.
+/// Pings are passed to `on_ping` and pongs skipped, also between the fragments of a message. A close frame is returned.
+/// The read timeout of the stream only applies until a message starts, it can't end up in the middle of one.
+pub fn read_message(
+    stream: &mut TcpStream,
+    mut on_ping: impl FnMut(&[u8]) -> io::Result<()>,
+) -> io::Result<(u8, Vec<u8>)> {
+    let timeout = stream.read_timeout()?;
+
+    loop {
+        let mut first = [0u8];
+
+        match stream.read(&mut first)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            _ => stream.set_read_timeout(Some(MESSAGE_TIMEOUT))?,
+        }
+
+        let message = read_frames(stream, first[0], &mut on_ping);
+        stream.set_read_timeout(timeout)?;
+
+        match message {
+            Ok(Some(message)) => return Ok(message),
+            Ok(None) => {}
+            // Callers take timeouts as the connection being idle
+            Err(err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                return Err(io::Error::other("message incomplete"))
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Reads frames until a message is complete, `first` is the first byte of the first one.
+/// Returns `None` if only control frames arrived that weren't in the middle of a message.
+fn read_frames(
+    stream: &mut TcpStream,
+    mut first: u8,
+    on_ping: &mut impl FnMut(&[u8]) -> io::Result<()>,
+) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut message: Option<(u8, Vec<u8>)> = None;
+
+    loop {
+        let mut second = [0u8];
+        stream.read_exact(&mut second)?;
+
+        let length = match second[0] & 0x7f {
+            126 => {
+                let mut length = [0u8; 2];
+                stream.read_exact(&mut length)?;
+                u16::from_be_bytes(length) as u64
+            }
+            127 => {
+                let mut length = [0u8; 8];
+                stream.read_exact(&mut length)?;
+                u64::from_be_bytes(length)
+            }
+            length => length as u64,
+        };
+
+        let fin = first & 0x80 != 0;
+        let opcode = first & 0x0f;
+        let received = message.as_ref().map(|(_, data)| data.len()).unwrap_or(0);
+
+        if opcode >= CLOSE && length > 125 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "control frame too large",
+            ));
+        }
+
+        if length > (MAX_MESSAGE_LENGTH - received) as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("message larger than {} bytes", MAX_MESSAGE_LENGTH),
+            ));
+        }
+
+        let mut mask = [0u8; 4];
+
+        if second[0] & 0x80 != 0 {
+            stream.read_exact(&mut mask)?;
+        }
+
+        let mut payload = vec![0u8; length as usize];
+        stream.read_exact(&mut payload)?;
+
+        for (index, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[index % 4];
+        }
+
+        // Control frames may arrive between the fragments of a message, which is kept
+        match opcode {
+            PING => on_ping(&payload)?,
+            PONG => {}
+            CLOSE => return Ok(Some((opcode, payload))),
+            _ => {
+                let (opcode, mut data) = message.take().unwrap_or((opcode, vec![]));
+                data.extend_from_slice(&payload);
+
+                if fin {
+                    return Ok(Some((opcode, data)));
+                }
+
+                message = Some((opcode, data));
+            }
+        }
+
+        if message.is_none() {
+            return Ok(None);
+        }
+
+        let mut next = [0u8];
+        stream.read_exact(&mut next)?;
+        first = next[0];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    /// Frames sent by a server aren't masked
+    fn server_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![(fin as u8) << 7 | opcode, payload.len() as u8];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (client, listener.accept().unwrap().0)
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b"hue"), "aHVl");
+        assert_eq!(base64(b"hues"), "aHVlcw==");
+        assert_eq!(base64(b"hue s"), "aHVlIHM=");
+    }
+
+    #[test]
+    fn test_fragments() {
+        let (mut client, mut server) = connection();
+
+        server.write_all(&server_frame(true, PING, b"a")).unwrap();
+        server
+            .write_all(&server_frame(false, TEXT, b"{\"type\":"))
+            .unwrap();
+        server.write_all(&server_frame(true, PING, b"b")).unwrap();
+        server.write_all(&server_frame(true, PONG, b"")).unwrap();
+        server
+            .write_all(&server_frame(false, 0, b"\"event\""))
+            .unwrap();
+        server.write_all(&server_frame(true, 0, b"}")).unwrap();
+        server.write_all(&server_frame(true, CLOSE, b"")).unwrap();
+
+        let mut pings = vec![];
+        let mut on_ping = |payload: &[u8]| {
+            pings.push(payload.to_vec());
+            Ok(())
+        };
+
+        assert_eq!(
+            read_message(&mut client, &mut on_ping).unwrap(),
+            (TEXT, b"{\"type\":\"event\"}".to_vec())
+        );
+        assert_eq!(
+            read_message(&mut client, &mut on_ping).unwrap(),
+            (CLOSE, vec![])
+        );
+        assert_eq!(pings, vec![b"a".to_vec(), b"b".to_vec()]);
+    }
+
+    #[test]
+    fn test_too_large() {
+        let (mut client, mut server) = connection();
+
+        let mut frame = vec![0x80 | TEXT, 127];
+        frame.extend_from_slice(&u64::MAX.to_be_bytes());
+        server.write_all(&frame).unwrap();
+
+        let err = read_message(&mut client, |_| Ok(())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,455 @@
+use crate::backend;
+use crate::debug;
+use crate::mqtt::{self, Client};
+use huelib2::resource::group::{
+    CreatableKind, Kind as GroupKind, State as GroupState, StateModifier as GroupStateModifier,
+};
+use huelib2::resource::light::{State as LightState, StateModifier as LightStateModifier};
+use huelib2::resource::scene::{AppData, Kind as SceneKind, Version};
+use huelib2::resource::{Adjust, ColorMode, Group, Light, Scene};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+
+#[derive(Clone, Deserialize)]
+struct Feature {
+    #[serde(default)]
+    name: String,
+}
+
+#[derive(Clone, Deserialize)]
+struct Expose {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    features: Vec<Feature>,
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+struct Definition {
+    model: String,
+    vendor: Option<String>,
+    description: Option<String>,
+    exposes: Vec<Expose>,
+}
+
+#[derive(Clone, Deserialize)]
+struct Device {
+    ieee_address: String,
+    friendly_name: String,
+    #[serde(default)]
+    definition: Option<Definition>,
+    #[serde(default)]
+    software_build_id: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+struct Member {
+    ieee_address: String,
+}
+
+#[derive(Clone, Deserialize)]
+struct GroupScene {
+    id: u32,
+    name: String,
+}
+
+#[derive(Clone, Deserialize)]
+struct DeviceGroup {
+    id: u32,
+    friendly_name: String,
+    #[serde(default)]
+    members: Vec<Member>,
+    #[serde(default)]
+    scenes: Vec<GroupScene>,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+struct Xy {
+    x: f32,
+    y: f32,
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+struct DeviceState {
+    state: Option<String>,
+    brightness: Option<u8>,
+    color_temp: Option<u16>,
+    color: Option<Xy>,
+    color_mode: Option<String>,
+}
+
+/// Everything zigbee2mqtt published about devices and groups, it's updated as messages arrive
+#[derive(Default)]
+struct Model {
+    devices: Vec<Device>,
+    groups: Vec<DeviceGroup>,
+    /// By friendly name
+    available: HashMap<String, bool>,
+    /// By friendly name
+    states: HashMap<String, DeviceState>,
+}
+
+impl Model {
+    /// Lights are devices exposing a light or a switch, e.g. smart plugs
+    fn lights(&self) -> impl Iterator<Item = (&Device, &Definition)> {
+        self.devices.iter().filter_map(|device| {
+            device
+                .definition
+                .as_ref()
+                .filter(|definition| {
+                    definition
+                        .exposes
+                        .iter()
+                        .any(|expose| expose.kind == "light" || expose.kind == "switch")
+                })
+                .map(|definition| (device, definition))
+        })
+    }
+
+    fn light_ids(&self, group: &DeviceGroup) -> Vec<String> {
+        group
+            .members
+            .iter()
+            .filter(|member| {
+                self.lights()
+                    .any(|(device, _)| device.ieee_address == member.ieee_address)
+            })
+            .map(|member| member.ieee_address.clone())
+            .collect()
+    }
+
+    /// Stores a message, `topic` is relative to the base topic of zigbee2mqtt
+    fn receive(&mut self, topic: &str, payload: &[u8]) {
+        match topic {
+            "bridge/devices" => match serde_json::from_slice(payload) {
+                Ok(devices) => self.devices = devices,
+                Err(err) => eprintln!("Failed to parse zigbee2mqtt devices: {}", err),
+            },
+            "bridge/groups" => match serde_json::from_slice(payload) {
+                Ok(groups) => self.groups = groups,
+                Err(err) => eprintln!("Failed to parse zigbee2mqtt groups: {}", err),
+            },
+            topic if topic.starts_with("bridge/") => {}
+            topic if topic.ends_with("/availability") => {
+                let name = topic.trim_end_matches("/availability");
+
+                // Either `{"state": "online"}` or, with the legacy format, just `online`
+                let available = serde_json::from_slice::<Value>(payload)
+                    .ok()
+                    .and_then(|value| value.get("state").cloned())
+                    .and_then(|state| state.as_str().map(String::from))
+                    .unwrap_or(String::from_utf8_lossy(payload).to_string());
+
+                self.available
+                    .insert(name.to_string(), available.trim() == "online");
+            }
+            topic => {
+                if let Ok(state) = serde_json::from_slice::<DeviceState>(payload) {
+                    self.states.insert(topic.to_string(), state);
+                }
+            }
+        }
+    }
+}
+
+/// Converts a modifier of the hue api into a payload of zigbee2mqtt, only absolute values are supported
+fn payload(
+    on: Option<bool>,
+    brightness: &Option<Adjust<u8>>,
+    color_space_coordinates: &Option<Adjust<(f32, f32)>>,
+    color_temperature: &Option<Adjust<u16>>,
+    transition_time: Option<u16>,
+) -> Map<String, Value> {
+    let mut payload = Map::new();
+
+    if let Some(on) = on {
+        payload.insert("state".to_string(), json!(if on { "ON" } else { "OFF" }));
+    }
+
+    if let Some(Adjust::Override(brightness)) = brightness {
+        payload.insert("brightness".to_string(), json!(brightness));
+    }
+
+    if let Some(Adjust::Override((x, y))) = color_space_coordinates {
+        payload.insert("color".to_string(), json!({ "x": x, "y": y }));
+    }
+
+    if let Some(Adjust::Override(color_temperature)) = color_temperature {
+        payload.insert("color_temp".to_string(), json!(color_temperature));
+    }
+
+    // The hue api counts in 100ms, zigbee2mqtt in seconds
+    if let Some(transition_time) = transition_time {
+        payload.insert(
+            "transition".to_string(),
+            json!(transition_time as f32 / 10.0),
+        );
+    }
+
+    payload
+}
+
+/// Backend talking to zigbee2mqtt through an MQTT broker instead of a bridge, see `BRIDGE_BACKEND`.
+/// Devices, groups and their states are kept up to date from the retained messages of zigbee2mqtt.
+pub struct Zigbee2Mqtt {
+    topic: String,
+    client: Arc<Client>,
+    model: Arc<Mutex<Model>>,
+}
+
+impl Zigbee2Mqtt {
+    pub fn connect(options: mqtt::Options, topic: String) -> Zigbee2Mqtt {
+        let model = Arc::new(Mutex::new(Model::default()));
+        let prefix = format!("{}/", topic);
+        let receiver = model.clone();
+
+        let client = Client::connect(
+            options,
+            vec![format!("{}/#", topic)],
+            move |topic, payload| {
+                if let Some(topic) = topic.strip_prefix(&prefix) {
+                    receiver
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .receive(topic, payload);
+                }
+            },
+        );
+
+        Zigbee2Mqtt {
+            topic,
+            client,
+            model,
+        }
+    }
+
+    fn model(&self) -> std::sync::MutexGuard<'_, Model> {
+        self.model.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn publish(&self, name: &str, payload: Map<String, Value>) -> Result<(), String> {
+        let topic = format!("{}/{}/set", self.topic, name);
+        let payload = Value::Object(payload).to_string();
+
+        debug!("zigbee2mqtt: {} {}", topic, payload);
+
+        self.client
+            .publish(&topic, &payload)
+            .map_err(|err| err.to_string())
+    }
+
+    /// Returns all lights in the shape of the hue api, identified by their IEEE address.
+    /// They're reachable unless zigbee2mqtt reports them as offline, which requires its availability feature.
+    pub fn get_all_lights(&self) -> Vec<Light> {
+        let model = self.model();
+
+        model
+            .lights()
+            .map(|(device, definition)| {
+                let state = model
+                    .states
+                    .get(&device.friendly_name)
+                    .cloned()
+                    .unwrap_or_default();
+                let features = definition
+                    .exposes
+                    .iter()
+                    .flat_map(|expose| expose.features.iter().map(|feature| feature.name.as_str()))
+                    .collect::<Vec<&str>>();
+
+                let kind = match (
+                    features.contains(&"color_xy"),
+                    features.contains(&"color_temp"),
+                    features.contains(&"brightness"),
+                ) {
+                    (true, true, _) => "Extended color light",
+                    (true, false, _) => "Color light",
+                    (false, true, _) => "Color temperature light",
+                    (false, false, true) => "Dimmable light",
+                    (false, false, false) => "On/Off plug-in unit",
+                };
+
+                let light_state = LightState {
+                    on: state.state.as_deref().map(|state| state == "ON"),
+                    brightness: state.brightness,
+                    hue: None,
+                    saturation: None,
+                    color_space_coordinates: state.color.map(|color| (color.x, color.y)),
+                    color_temperature: state.color_temp,
+                    alert: None,
+                    effect: None,
+                    color_mode: match state.color_mode.as_deref() {
+                        Some("color_temp") => Some(ColorMode::ColorTemperature),
+                        Some("xy") => Some(ColorMode::ColorSpaceCoordinates),
+                        Some("hs") => Some(ColorMode::HueAndSaturation),
+                        _ => None,
+                    },
+                    reachable: model
+                        .available
+                        .get(&device.friendly_name)
+                        .copied()
+                        .unwrap_or(true),
+                };
+
+                Light {
+                    model_id: definition.model.clone(),
+                    product_name: definition.description.clone(),
+                    manufacturer_name: definition.vendor.clone(),
+                    software_version: device.software_build_id.clone().unwrap_or_default(),
+                    ..backend::new_light(
+                        device.ieee_address.clone(),
+                        device.friendly_name.clone(),
+                        kind,
+                        light_state,
+                    )
+                }
+            })
+            .collect()
+    }
+
+    /// Returns a single light in the shape of the hue api
+    pub fn get_light(&self, id: &str) -> Result<Light, String> {
+        self.get_all_lights()
+            .into_iter()
+            .find(|light| light.id == id)
+            .ok_or(format!("unknown device {}", id))
+    }
+
+    /// Returns all groups in the shape of the hue api, they count as rooms
+    pub fn get_all_groups(&self) -> Vec<Group> {
+        let model = self.model();
+        let lights = self.get_all_lights_of(&model);
+
+        model
+            .groups
+            .iter()
+            .map(|group| {
+                let ids = model.light_ids(group);
+                let on = lights
+                    .iter()
+                    .filter(|(id, _)| ids.contains(id))
+                    .map(|(_, on)| *on)
+                    .collect::<Vec<bool>>();
+
+                Group {
+                    id: group.id.to_string(),
+                    name: group.friendly_name.clone(),
+                    lights: ids,
+                    sensors: vec![],
+                    kind: GroupKind::Creatable(CreatableKind::Room),
+                    class: None,
+                    state: Some(GroupState {
+                        any_on: on.iter().any(|on| *on),
+                        all_on: !on.is_empty() && on.iter().all(|on| *on),
+                    }),
+                    model_id: None,
+                    unique_id: None,
+                    recycle: None,
+                }
+            })
+            .collect()
+    }
+
+    /// Whether each light is on, by its IEEE address
+    fn get_all_lights_of(&self, model: &Model) -> Vec<(String, bool)> {
+        model
+            .lights()
+            .map(|(device, _)| {
+                let on = model
+                    .states
+                    .get(&device.friendly_name)
+                    .and_then(|state| state.state.as_deref())
+                    == Some("ON");
+
+                (device.ieee_address.clone(), on)
+            })
+            .collect()
+    }
+
+    /// Returns the scenes of all groups in the shape of the hue api, their ids are prefixed with the id of their group.
+    /// zigbee2mqtt doesn't tell which lights a scene covers, all lights of its group are assumed.
+    pub fn get_all_scenes(&self) -> Vec<Scene> {
+        let model = self.model();
+
+        model
+            .groups
+            .iter()
+            .flat_map(|group| {
+                let lights = model.light_ids(group);
+
+                group.scenes.iter().map(move |scene| Scene {
+                    id: format!("{}/{}", group.id, scene.id),
+                    name: scene.name.clone(),
+                    kind: SceneKind::GroupScene,
+                    group: Some(group.id.to_string()),
+                    lights: Some(lights.clone()),
+                    owner: None,
+                    recycle: false,
+                    locked: false,
+                    app_data: AppData::default(),
+                    picture: None,
+                    last_update: None,
+                    version: Version::Post,
+                })
+            })
+            .collect()
+    }
+
+    fn group_name(&self, group_id: &str) -> Result<String, String> {
+        self.model()
+            .groups
+            .iter()
+            .find(|group| group.id.to_string() == group_id)
+            .map(|group| group.friendly_name.clone())
+            .ok_or(format!("unknown group {}", group_id))
+    }
+
+    pub fn set_light_state(&self, id: &str, modifier: &LightStateModifier) -> Result<(), String> {
+        let name = self
+            .model()
+            .devices
+            .iter()
+            .find(|device| device.ieee_address == id)
+            .map(|device| device.friendly_name.clone())
+            .ok_or(format!("unknown device {}", id))?;
+
+        self.publish(
+            &name,
+            payload(
+                modifier.on,
+                &modifier.brightness,
+                &modifier.color_space_coordinates,
+                &modifier.color_temperature,
+                modifier.transition_time,
+            ),
+        )
+    }
+
+    pub fn set_group_state(
+        &self,
+        group_id: &str,
+        modifier: &GroupStateModifier,
+    ) -> Result<(), String> {
+        let mut payload = payload(
+            modifier.on,
+            &modifier.brightness,
+            &modifier.color_space_coordinates,
+            &modifier.color_temperature,
+            modifier.transition_time,
+        );
+
+        if let Some(scene_id) = &modifier.scene {
+            let (_, scene_id) = scene_id.split_once("/").unwrap_or(("", scene_id));
+            payload.insert(
+                "scene_recall".to_string(),
+                json!(scene_id.parse::<u32>().map_err(|err| err.to_string())?),
+            );
+        }
+
+        self.publish(&self.group_name(group_id)?, payload)
+    }
+}